@@ -0,0 +1,379 @@
+//! Locating base ROMs.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, bail};
+use flate2::read::GzDecoder;
+use pyo3::prelude::*;
+use serde::Deserialize;
+use temp_dir::TempDir;
+
+use crate::Error;
+use crate::emulator::{self, EmulatorKind};
+use crate::package::manifest::Region;
+use crate::rom::{ByteOrder, Rom, RomVerifyError};
+
+/// SHA1 hash of an unmodified US-release Paper Mario (N64) ROM in big-endian (`.z64`) byte order.
+pub const PAPERMARIO_US_SHA1: &str = "3837f44cda784b466c9a2d99df70d77c322b97a0";
+
+/// Returns the SHA1 hash of an unmodified Paper Mario ROM of `region`, in big-endian (`.z64`) byte order, if it is
+/// known ahead of time. Otherwise it is only known once the decomp is cloned, from `ver/<region>/splat.yaml`.
+pub fn expected_sha1(region: Region) -> Option<&'static str> {
+    match region {
+        Region::Us => Some(PAPERMARIO_US_SHA1),
+        Region::Jp | Region::Pal | Region::Ique => None,
+    }
+}
+
+/// File extensions of archives that a base ROM can be read from. See [`open`].
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "gz"];
+
+/// File extensions of N64 ROMs, in each byte order.
+pub const ROM_EXTENSIONS: &[&str] = &["z64", "n64", "v64"];
+
+/// Returns true if the file at the given path is an unmodified US-release Paper Mario ROM, or an archive containing
+/// one.
+#[pyfunction]
+pub fn is_papermario_us(path: PathBuf) -> bool {
+    path.is_file() && open(&path).is_ok_and(|rom| crate::rom::verify_papermario_us(&rom).is_ok())
+}
+
+/// Checks that the region in the base ROM's header is `target`, so that a ROM from the wrong region is reported as
+/// such, rather than as a modified ROM. ROMs whose header region can't be read are let through, to be caught by later
+/// checks if they really are wrong.
+pub fn check_region(rom: &Rom, target: Region) -> Result<(), RomVerifyError> {
+    match rom.header_region() {
+        Ok(Some(region)) if region != target => Err(RomVerifyError::WrongRegion { region, expected: target }),
+        Ok(_) => Ok(()),
+        Err(error) => Err(RomVerifyError::Unreadable(format!("{:#}", error))),
+    }
+}
+
+/// Opens a base ROM.
+///
+/// ROM dumps are often stored compressed, so if the file is a zip or gzip archive, going by its contents or else one
+/// of the [`ARCHIVE_EXTENSIONS`], the single ROM inside it is extracted to a temporary file, which is deleted when the
+/// returned ROM is dropped. It is an error for a zip archive to contain no ROMs or more than one.
+///
+/// ROMs dumped in `.v64` or `.n64` byte order are likewise converted to `.z64` in a temporary file, so the returned
+/// ROM is always big-endian. See [`Rom::normalized_to_z64`].
+pub fn open(path: &Path) -> Result<Rom> {
+    let rom = extract(path)?;
+    match rom.byte_order()? {
+        Some(ByteOrder::ByteSwapped | ByteOrder::LittleEndian) => {
+            let temp_dir = TempDir::new().context("failed to create temporary directory")?;
+            let file_name = rom.path().with_extension(crate::rom::EXTENSION);
+            let file_name = file_name.file_name().context("baserom has no file name")?;
+            let normalized = rom.normalized_to_z64(&temp_dir.path().join(file_name))?;
+            Ok(normalized.owning_temp_dir(temp_dir))
+        }
+        Some(ByteOrder::BigEndian) | None => Ok(rom),
+    }
+}
+
+/// Returns the ROM at `path`, or the one extracted from it if it is an archive. See [`open`].
+fn extract(path: &Path) -> Result<Rom> {
+    if !path.is_file() {
+        bail!(Error::Baserom(format!("baserom {:?} is not a file", path)));
+    }
+    let Some(archive_kind) = ArchiveKind::detect(path)? else {
+        return Ok(Rom::from(path.to_owned()));
+    };
+
+    let temp_dir = TempDir::new().context("failed to create temporary directory")?;
+    let rom = match archive_kind {
+        ArchiveKind::Zip => extract_zip(path, temp_dir.path())?,
+        ArchiveKind::Gzip => extract_gzip(path, temp_dir.path())?,
+    };
+    Ok(Rom::from(rom).owning_temp_dir(temp_dir))
+}
+
+/// Extracts the single ROM in the zip archive at `path` into `dir`, returning its path.
+fn extract_zip(path: &Path, dir: &Path) -> Result<PathBuf> {
+    let invalid = |error: zip::result::ZipError| {
+        Error::Baserom(format!("failed to extract baserom archive {}: {}", path.display(), error))
+    };
+    let file = fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(invalid)?;
+
+    let mut roms = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index).map_err(invalid)?;
+        if !entry.is_dir() && has_rom_extension(Path::new(entry.name())) {
+            roms.push((entry.name().to_owned(), index));
+        }
+    }
+    roms.sort();
+    let index = match roms.as_slice() {
+        [] => bail!(Error::Baserom(format!("baserom archive {} does not contain a ROM", path.display()))),
+        [(_, index)] => *index,
+        _ => {
+            let names: Vec<&str> = roms.iter().map(|(name, _)| name.as_str()).collect();
+            bail!(Error::Baserom(format!(
+                "baserom archive {} contains more than one ROM ({}); extract the one to use",
+                path.display(),
+                names.join(", "),
+            )))
+        }
+    };
+
+    let mut entry = archive.by_index(index).map_err(invalid)?;
+    let file_name = entry.enclosed_name()
+        .and_then(|name| name.file_name())
+        .map(PathBuf::from)
+        .with_context(|| format!("baserom archive {} has a ROM with an invalid name", path.display()))?;
+    let output = dir.join(file_name);
+    let mut file = fs::File::create(&output)
+        .with_context(|| format!("failed to create {}", output.display()))?;
+    io::copy(&mut entry, &mut file)
+        .map_err(|error| Error::Baserom(format!("failed to extract baserom archive {}: {}", path.display(), error)))?;
+    Ok(output)
+}
+
+/// Decompresses the gzip archive at `path` into `dir`, returning the path of the ROM.
+fn extract_gzip(path: &Path, dir: &Path) -> Result<PathBuf> {
+    // gzip holds a single file, named after the archive without its extension
+    let stem = PathBuf::from(path.file_stem().context("baserom has no file name")?);
+    let output = if has_rom_extension(&stem) { stem } else { stem.with_extension(crate::rom::EXTENSION) };
+    let output = dir.join(output);
+    let file = fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut decoder = GzDecoder::new(file);
+    let mut file = fs::File::create(&output)
+        .with_context(|| format!("failed to create {}", output.display()))?;
+    io::copy(&mut decoder, &mut file)
+        .map_err(|error| Error::Baserom(format!("failed to extract baserom archive {}: {}", path.display(), error)))?;
+    Ok(output)
+}
+
+/// A kind of archive that [`open`] can extract a base ROM from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Gzip,
+}
+
+impl ArchiveKind {
+    /// Detects the kind of archive from the first bytes of the file, or else its extension. Returns `None` if the
+    /// file isn't an archive.
+    fn detect(path: &Path) -> Result<Option<Self>> {
+        let mut magic = Vec::new();
+        fs::File::open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?
+            .take(4)
+            .read_to_end(&mut magic)?;
+        // Empty zips have only an end of central directory record
+        if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            return Ok(Some(Self::Zip));
+        }
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            return Ok(Some(Self::Gzip));
+        }
+
+        let extension = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        Ok(match extension.as_str() {
+            "zip" => Some(Self::Zip),
+            "gz" => Some(Self::Gzip),
+            _ => None,
+        })
+    }
+}
+
+/// Returns true if the path has one of the [`ROM_EXTENSIONS`].
+fn has_rom_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Looks for a US-release Paper Mario ROM in RetroArch's playlists and content directory.
+///
+/// Returns `None` if RetroArch isn't installed or no matching ROM is found. Only a ROM whose SHA1 matches
+/// [`PAPERMARIO_US_SHA1`] is ever returned.
+#[pyfunction]
+pub fn find_in_retroarch() -> Option<PathBuf> {
+    let is_retroarch = |path: &PathBuf| EmulatorKind::from_path(path) == EmulatorKind::RetroArch;
+    if !emulator::list_available().iter().any(is_retroarch) {
+        log::info!("RetroArch is not installed");
+        return None;
+    }
+
+    let config_dir = retroarch_config_dir()?;
+    let mut candidates = Vec::new();
+    if let Ok(entries) = fs::read_dir(config_dir.join("playlists")) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().map(|ext| ext == "lpl").unwrap_or(false) {
+                candidates.extend(read_playlist(&path));
+            }
+        }
+    }
+    if let Some(content_dir) = read_content_dir(&config_dir.join("retroarch.cfg")) {
+        if let Ok(entries) = fs::read_dir(content_dir) {
+            candidates.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+
+    let found = candidates.into_iter()
+        .filter(|path| path.extension().map(|ext| ext.eq_ignore_ascii_case("z64")).unwrap_or(false))
+        .find(|path| is_papermario_us(path.clone()));
+    if found.is_none() {
+        log::info!("no US-release Paper Mario ROM found in RetroArch content");
+    }
+    found
+}
+
+fn retroarch_config_dir() -> Option<PathBuf> {
+    if cfg!(target_os = "macos") {
+        dirs::home_dir().map(|home| home.join("Library/Application Support/RetroArch"))
+    } else if cfg!(windows) {
+        dirs::config_dir().map(|dir| dir.join("RetroArch"))
+    } else {
+        dirs::config_dir().map(|dir| dir.join("retroarch"))
+    }
+}
+
+/// Returns the content paths listed in a RetroArch playlist (`.lpl`) file.
+/// Content inside archives (`foo.zip#rom.z64`) is skipped.
+fn read_playlist(path: &Path) -> Vec<PathBuf> {
+    #[derive(Deserialize)]
+    struct Playlist {
+        items: Vec<Item>,
+    }
+    #[derive(Deserialize)]
+    struct Item {
+        path: String,
+    }
+
+    let playlist: Playlist = match fs::read_to_string(path).ok().and_then(|s| serde_json::from_str(&s).ok()) {
+        Some(playlist) => playlist,
+        None => {
+            log::debug!("failed to read RetroArch playlist {}", path.display());
+            return Vec::new();
+        }
+    };
+    playlist.items.into_iter()
+        .filter(|item| !item.path.contains('#'))
+        .map(|item| PathBuf::from(item.path))
+        .collect()
+}
+
+/// Reads the content browser directory from `retroarch.cfg`.
+fn read_content_dir(config_path: &Path) -> Option<PathBuf> {
+    let config = fs::read_to_string(config_path).ok()?;
+    config.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if key.trim() != "rgui_browser_directory" {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        if value.is_empty() || value == "default" {
+            None
+        } else {
+            Some(PathBuf::from(value))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn playlist_skips_archives() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("Nintendo - Nintendo 64.lpl");
+        fs::write(&path, r#"{
+            "version": "1.5",
+            "items": [
+                { "path": "/roms/Paper Mario (USA).z64", "label": "Paper Mario (USA)" },
+                { "path": "/roms/n64.zip#Paper Mario (USA).z64", "label": "Paper Mario (USA)" }
+            ]
+        }"#)?;
+        assert_eq!(read_playlist(&path), vec![PathBuf::from("/roms/Paper Mario (USA).z64")]);
+        Ok(())
+    }
+
+    #[test]
+    fn opens_rom_in_zip() -> Result<()> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        let rom = open(&dir.join("single.zip"))?;
+        assert!(rom.is_temporary());
+        assert_eq!(rom.path().file_name().unwrap(), "Paper Mario (USA).z64");
+        assert_eq!(rom.read_bytes()?, b"pretend this is a US ROM\n");
+
+        let path = rom.path().to_owned();
+        drop(rom);
+        assert!(!path.exists());
+
+        // Not an archive, so used as-is
+        let rom = open(&dir.join("../distributables/baserom.z64"))?;
+        assert!(!rom.is_temporary());
+        Ok(())
+    }
+
+    #[test]
+    fn archives_are_detected_by_contents() -> Result<()> {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+
+        // Decompressed, then converted from .v64
+        let rom = open(&fixtures.join("us-header.v64.gz"))?;
+        assert!(rom.is_temporary());
+        assert_eq!(rom.path().file_name().unwrap(), "us-header.z64");
+        assert_eq!(rom.byte_order()?, Some(crate::rom::ByteOrder::BigEndian));
+        assert_eq!(rom.header_region()?, Some(Region::Us));
+
+        // Archives without their usual extension
+        let dir = TempDir::new()?;
+        fs::copy(fixtures.join("single.zip"), dir.path().join("baserom"))?;
+        let rom = open(&dir.path().join("baserom"))?;
+        assert_eq!(rom.read_bytes()?, b"pretend this is a US ROM\n");
+        fs::copy(fixtures.join("us-header.v64.gz"), dir.path().join("baserom.z64"))?;
+        let rom = open(&dir.path().join("baserom.z64"))?;
+        assert_eq!(rom.path().file_name().unwrap(), "baserom.z64");
+        assert_eq!(rom.header_region()?, Some(Region::Us));
+        Ok(())
+    }
+
+    #[test]
+    fn zip_must_contain_one_rom() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        for (archive, message) in [("empty.zip", "does not contain a ROM"), ("multiple.zip", "more than one ROM")] {
+            let error = open(&dir.join(archive)).unwrap_err();
+            assert!(error.to_string().contains(message), "{}: {:#}", archive, error);
+            assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Baserom(_))));
+        }
+    }
+
+    #[test]
+    fn mismatched_header_region_is_rejected() -> Result<()> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        let jp = Rom::from(dir.join("jp-header.z64"));
+        let error = check_region(&jp, Region::Us).unwrap_err();
+        assert_eq!(error, RomVerifyError::WrongRegion { region: Region::Jp, expected: Region::Us });
+        assert_eq!(error.to_string(), "baserom is region JP but package targets US");
+        check_region(&jp, Region::Jp)?;
+        check_region(&Rom::from(dir.join("us-header.v64")), Region::Us)?;
+
+        // No header to go by
+        check_region(&Rom::from(dir.join("../distributables/baserom.z64")), Region::Us)?;
+        Ok(())
+    }
+
+    #[test]
+    fn only_matching_rom_is_papermario_us() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("Some Other Game.z64");
+        fs::write(&path, "not paper mario")?;
+        assert!(!is_papermario_us(path));
+        assert!(!is_papermario_us(dir.path().join("missing.z64")));
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! Persistent user settings.
+//!
+//! Settings are stored in `config.toml` in the Merlon config directory (e.g. `~/.config/merlon/config.toml` on
+//! Linux) and can be edited with `merlon config`. Command-line arguments always take precedence over them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::baserom;
+
+/// File name of the config file within [`dir`].
+pub const FILE_NAME: &str = "config.toml";
+
+/// The settings that can be read and written with [`Config::get`] and [`Config::set`].
+pub const KEYS: &[&str] = &["baserom", "emulator", "decomp_url", "update_check"];
+
+/// URL of the decomp repository that is cloned when no `decomp_url` is configured.
+pub const DEFAULT_DECOMP_URL: &str = "https://github.com/pmret/papermario.git";
+
+/// Merlon's settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Base ROM to use when one isn't given on the command line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baserom: Option<PathBuf>,
+
+    /// Emulator to run ROMs with when one isn't given on the command line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emulator: Option<PathBuf>,
+
+    /// Git URL of the decomp repository to clone, e.g. a local mirror.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decomp_url: Option<String>,
+
+    /// Whether to check GitHub for newer releases of Merlon. Off unless enabled, since it makes a network request.
+    pub update_check: bool,
+}
+
+/// Returns the directory Merlon stores its config and caches in, if the platform has one.
+pub fn dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("merlon"))
+}
+
+impl Config {
+    /// Loads the config file, or the default config if there isn't one.
+    pub fn load() -> Result<Self> {
+        match dir() {
+            Some(dir) => Self::load_from(&dir.join(FILE_NAME)),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Loads a config file from the given path, or the default config if it doesn't exist.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let string = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&string).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    /// Saves the config file, creating the config directory if needed.
+    pub fn save(&self) -> Result<()> {
+        let dir = dir().context("no config directory on this platform")?;
+        self.save_to(&dir.join(FILE_NAME))
+    }
+
+    /// Saves the config to the given path.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Returns the value of a setting as a string, or `None` if it isn't set.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(match key {
+            "baserom" => self.baserom.as_ref().map(|path| path.display().to_string()),
+            "emulator" => self.emulator.as_ref().map(|path| path.display().to_string()),
+            "decomp_url" => self.decomp_url.clone(),
+            "update_check" => Some(self.update_check.to_string()),
+            _ => bail!("unknown setting {} (valid settings: {})", key, KEYS.join(", ")),
+        })
+    }
+
+    /// Validates and changes a setting. An empty value unsets it, restoring the default.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "baserom" => self.baserom = non_empty(value).map(validate_baserom).transpose()?,
+            "emulator" => self.emulator = non_empty(value).map(validate_emulator).transpose()?,
+            "decomp_url" => self.decomp_url = non_empty(value).map(ToOwned::to_owned),
+            "update_check" => {
+                self.update_check = match non_empty(value) {
+                    Some(value) => value.parse().with_context(|| format!("{} is not true or false", value))?,
+                    None => false,
+                }
+            }
+            _ => bail!("unknown setting {} (valid settings: {})", key, KEYS.join(", ")),
+        }
+        Ok(())
+    }
+
+    /// Returns every setting and its value, in the order of [`KEYS`].
+    pub fn list(&self) -> Vec<(&'static str, Option<String>)> {
+        KEYS.iter()
+            .map(|key| (*key, self.get(key).expect("KEYS are all valid")))
+            .collect()
+    }
+
+    /// Returns the URL of the decomp repository to clone.
+    pub fn decomp_url(&self) -> &str {
+        self.decomp_url.as_deref().unwrap_or(DEFAULT_DECOMP_URL)
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn validate_baserom(value: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(value).canonicalize()
+        .with_context(|| format!("baserom {} does not exist", value))?;
+    if !baserom::is_papermario_us(path.clone()) {
+        bail!("{} is not an unmodified US-release Paper Mario ROM", path.display());
+    }
+    Ok(path)
+}
+
+fn validate_emulator(value: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(value).canonicalize()
+        .with_context(|| format!("emulator {} does not exist", value))?;
+    if !path.is_file() {
+        bail!("emulator {} is not a file", path.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if path.metadata()?.permissions().mode() & 0o111 == 0 {
+            bail!("emulator {} is not executable", path.display());
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn set_validates_and_round_trips() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut config = Config::default();
+
+        let not_a_rom = dir.path().join("not a rom.z64");
+        fs::write(&not_a_rom, "not paper mario")?;
+        assert!(config.set("baserom", &not_a_rom.display().to_string()).is_err());
+        assert!(config.set("emulator", &not_a_rom.display().to_string()).is_err());
+        assert!(config.set("update_check", "maybe").is_err());
+        assert!(config.set("colour", "blue").is_err());
+
+        config.set("decomp_url", "https://example.com/papermario.git")?;
+        config.set("update_check", "true")?;
+        let path = dir.path().join(FILE_NAME);
+        config.save_to(&path)?;
+        let loaded = Config::load_from(&path)?;
+        assert_eq!(loaded, config);
+        assert_eq!(loaded.get("update_check")?.as_deref(), Some("true"));
+        assert_eq!(loaded.decomp_url(), "https://example.com/papermario.git");
+
+        config.set("decomp_url", "")?;
+        assert_eq!(config.decomp_url(), DEFAULT_DECOMP_URL);
+        config.set("update_check", "")?;
+        assert!(!config.update_check);
+        assert_eq!(Config::load_from(&dir.path().join("missing.toml"))?, Config::default());
+        Ok(())
+    }
+}
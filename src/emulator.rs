@@ -1,40 +1,481 @@
 //! Utilities for interfacing with N64 emulators.
 
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use anyhow::{Result, bail};
+use std::time::{Duration, Instant};
+use anyhow::{Result, bail, Context};
+use clap::ValueEnum;
 use pyo3::prelude::*;
 
+use crate::config::Config;
 use crate::rom::Rom;
 
+/// Environment variable that overrides which emulator is used. See [`find_emulator`].
+pub const EMULATOR_ENV_VAR: &str = "MERLON_EMULATOR";
+
+/// Known emulator install locations that usually aren't on `PATH`, such as macOS app bundles.
+const EMULATOR_PATHS: &[&str] = &[
+    "/Applications/ares.app/Contents/MacOS/ares",
+    "/Applications/RetroArch.app/Contents/MacOS/RetroArch",
+    "C:\\Program Files (x86)\\Project64 2.3\\Project64.exe",
+    "C:\\Program Files (x86)\\Project64 3.0\\Project64.exe",
+];
+
+/// GDB executable names that can debug MIPS, searched for on `PATH` in order of preference.
+const GDB_NAMES: &[&str] = &[
+    "gdb-multiarch",
+    "mips-linux-gnu-gdb",
+    "gdb",
+];
+
+/// How long to wait for an emulator's GDB server to start before giving up on connecting GDB to it.
+const DEBUG_SERVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Known emulator executable names without an extension, searched for on `PATH` in order of preference.
+const EMULATOR_NAMES: &[&str] = &[
+    "ares",
+    "cen64",
+    "simple64",
+    "simple64-gui",
+    "mupen64plus",
+    "retroarch",
+    "Project64",
+];
+
+/// A known emulator. Each takes different command-line flags, so [`LaunchIntent`]s are translated per emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[pyclass(module = "merlon.emulator")]
+pub enum EmulatorKind {
+    /// https://github.com/n64dev/cen64
+    Cen64,
+    /// https://ares-emu.net
+    Ares,
+    /// https://simple64.github.io, formerly m64p
+    Simple64,
+    /// https://mupen64plus.org
+    Mupen64Plus,
+    /// https://www.retroarch.com
+    RetroArch,
+    /// https://www.pj64-emu.com
+    Project64,
+    /// An emulator Merlon doesn't know about. Only the ROM is passed to it.
+    Unknown,
+}
+
+/// How the user would like the emulator to run, independent of which emulator it is.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[pyclass(module = "merlon.emulator")]
+pub enum LaunchIntent {
+    /// Start in fullscreen.
+    Fullscreen,
+    /// Disable audio output.
+    NoAudio,
+}
+
+#[pymethods]
+impl EmulatorKind {
+    /// Classifies an emulator by the name of its executable.
+    #[staticmethod]
+    pub fn detect(path: PathBuf) -> Self {
+        Self::from_path(&path)
+    }
+
+    /// Returns the command-line flags that achieve the given intents, in addition to the ROM path.
+    /// Intents that the emulator has no flag for are skipped with a warning.
+    pub fn args_for(&self, intents: Vec<LaunchIntent>) -> Vec<String> {
+        let mut args = Vec::new();
+        for intent in intents {
+            let flags: &[&str] = match (self, intent) {
+                (Self::Ares | Self::Mupen64Plus | Self::RetroArch, LaunchIntent::Fullscreen) => &["--fullscreen"],
+                (Self::Cen64, LaunchIntent::NoAudio) => &["-noaudio"],
+                (Self::Mupen64Plus, LaunchIntent::NoAudio) => &["--audio", "dummy"],
+                _ => {
+                    log::warn!("{:?} does not support {:?}, ignoring", self, intent);
+                    &[]
+                }
+            };
+            args.extend(flags.iter().map(|flag| flag.to_string()));
+        }
+        args
+    }
+
+    /// Returns the command-line flags that make the emulator record gameplay footage to the given output path.
+    /// Errors if the emulator can't record from the command line.
+    pub fn record_args(&self, output: PathBuf) -> Result<Vec<String>> {
+        let output = output.to_string_lossy().into_owned();
+        match self {
+            // Uses RetroArch's FFmpeg recording, so the container is chosen by the output's extension
+            Self::RetroArch => Ok(vec!["--record".to_owned(), output]),
+            _ => bail!("recording is not supported for {:?}", self),
+        }
+    }
+
+    /// Returns the command-line flags that make the emulator start a GDB server on the given port, so that a
+    /// debugger can connect to it. Errors if the emulator has no GDB server.
+    pub fn debug_args(&self, port: u16) -> Result<Vec<String>> {
+        match self {
+            Self::Cen64 => Ok(vec!["-debug".to_owned(), format!("localhost:{}", port)]),
+            Self::Ares => Ok(vec![
+                "--setting".to_owned(),
+                "DebugServer/Enabled=true".to_owned(),
+                "--setting".to_owned(),
+                format!("DebugServer/Port={}", port),
+            ]),
+            _ => bail!("debugging is not supported for {:?}, try cen64 or ares", self),
+        }
+    }
+}
+
+impl EmulatorKind {
+    /// Classifies an emulator by the name of its executable.
+    pub fn from_path(path: &Path) -> Self {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+        match stem.as_str() {
+            "cen64" => Self::Cen64,
+            "ares" => Self::Ares,
+            "mupen64plus" => Self::Mupen64Plus,
+            "retroarch" => Self::RetroArch,
+            "m64p" => Self::Simple64,
+            _ if stem.starts_with("simple64") => Self::Simple64,
+            _ if stem.starts_with("project64") => Self::Project64,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 /// Runs the given ROM in an emulator.
+/// If no emulator is given, one is found with [`find_emulator`].
+///
+/// The intents are translated into flags for the emulator with [`EmulatorKind::args_for`]. If `record` is given, the
+/// emulator records gameplay to that path; see [`EmulatorKind::record_args`].
+///
+/// If `debug_port` is given, the emulator starts a GDB server on that port; see [`EmulatorKind::debug_args`]. If
+/// `gdb_symbols` is also given, GDB is started with that ELF once the server is up, and connects to it.
+///
+/// Any `args` are passed to the emulator as-is, after the ROM path.
+#[pyfunction]
+#[pyo3(signature = (
+    rom,
+    emulator = None,
+    intents = Vec::new(),
+    record = None,
+    debug_port = None,
+    gdb_symbols = None,
+    args = Vec::new(),
+))]
+pub fn run_rom(
+    rom: &Rom,
+    emulator: Option<PathBuf>,
+    intents: Vec<LaunchIntent>,
+    record: Option<PathBuf>,
+    debug_port: Option<u16>,
+    gdb_symbols: Option<PathBuf>,
+    args: Vec<String>,
+) -> Result<()> {
+    let (emulator, kind) = match emulator {
+        Some(emulator) => {
+            if !emulator.is_file() {
+                bail!("emulator {} does not exist", emulator.display());
+            }
+            let kind = EmulatorKind::from_path(&emulator);
+            (emulator, kind)
+        }
+        None => find_emulator()?,
+    };
+    // Check recording is supported before starting the emulator
+    let record_args = match record {
+        Some(output) => kind.record_args(output)?,
+        None => Vec::new(),
+    };
+    // Likewise for debugging
+    let debug_args = match debug_port {
+        Some(port) => kind.debug_args(port)?,
+        None => Vec::new(),
+    };
+    let gdb = match (&gdb_symbols, debug_port) {
+        (Some(symbols), Some(port)) => {
+            if !symbols.is_file() {
+                bail!("symbol file {} does not exist, build with symbols first", symbols.display());
+            }
+            Some((find_gdb()?, symbols, port))
+        }
+        (Some(_), None) => bail!("a debug port is needed to connect GDB to the emulator"),
+        (None, _) => None,
+    };
+    log::debug!("running {} ({:?})", emulator.display(), kind);
+    let flags = [kind.args_for(intents), record_args, debug_args].concat();
+    let mut child = emulator_command(&emulator, flags, rom.path(), &args)
+        .spawn()
+        .with_context(|| format!("failed to run {}", emulator.display()))?;
+
+    if let Some((gdb, symbols, port)) = gdb {
+        if let Err(error) = wait_for_debug_server(port) {
+            let _ = child.kill();
+            return Err(error);
+        }
+        log::debug!("running {} on {}", gdb.display(), symbols.display());
+        Command::new(&gdb)
+            .arg(symbols)
+            .arg("-ex")
+            .arg(format!("target remote localhost:{}", port))
+            .status()
+            .with_context(|| format!("failed to run {}", gdb.display()))?;
+    }
+
+    child.wait()?;
+    Ok(())
+}
+
+/// Returns every known emulator that is installed, either on `PATH` or at a known location, in that order.
 #[pyfunction]
-pub fn run_rom(rom: &Rom) -> Result<()> {
-    let emulator = find_emulator()?;
-    Command::new(emulator)
-        .arg(rom.path())
-        .status()
-        .map(|_| ())
-        .map_err(Into::into)
-}
-
-fn find_emulator() -> Result<PathBuf> {
-    const EMULATOR_PATHS: &[&str] = &[
-        "/usr/bin/cen64",
-        "/usr/bin/ares",
-        "/Applications/ares.app/Contents/MacOS/ares",
-        "/usr/bin/mupen64plus",
-        "/usr/bin/retroarch",
-        "C:\\Program Files (x86)\\Project64 2.3\\Project64.exe",
-        "C:\\Program Files (x86)\\Project64 3.0\\Project64.exe",
-    ];
-
-    for path in EMULATOR_PATHS {
+pub fn list_available() -> Vec<PathBuf> {
+    let mut emulators = search_path(&std::env::var_os("PATH").unwrap_or_default(), EMULATOR_NAMES);
+    for path in EMULATOR_PATHS.iter().map(PathBuf::from) {
+        if path.is_file() && !emulators.contains(&path) {
+            emulators.push(path);
+        }
+    }
+
+    emulators
+}
+
+/// Returns true if the emulator to use has been chosen explicitly, with the [`MERLON_EMULATOR`](EMULATOR_ENV_VAR)
+/// environment variable or the `emulator` setting, rather than being whichever installed emulator is found first.
+pub fn is_emulator_chosen() -> Result<bool> {
+    let env_var_set = std::env::var_os(EMULATOR_ENV_VAR).is_some_and(|path| !path.is_empty());
+    Ok(env_var_set || Config::load()?.emulator.is_some())
+}
+
+/// Asks the user which of the given emulators to use, numbered from 1, reading the answer from `input`. An empty
+/// answer picks the first. Asks again if the answer isn't one of the numbers. Returns `None` if `input` ends first.
+pub fn prompt_for_emulator(
+    emulators: &[PathBuf],
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<Option<PathBuf>> {
+    writeln!(output, "Multiple emulators are installed:")?;
+    for (i, emulator) in emulators.iter().enumerate() {
+        writeln!(output, "  {}) {} ({:?})", i + 1, emulator.display(), EmulatorKind::from_path(emulator))?;
+    }
+    loop {
+        write!(output, "Which one should be used? [1]: ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(emulators.first().cloned());
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=emulators.len()).contains(&number) => return Ok(Some(emulators[number - 1].clone())),
+            _ => writeln!(output, "Please enter a number from 1 to {}.", emulators.len())?,
+        }
+    }
+}
+
+/// Finds the emulator to run ROMs with when none is given. In order of precedence:
+///
+/// 1. The [`MERLON_EMULATOR`](EMULATOR_ENV_VAR) environment variable.
+/// 2. The `emulator` setting in the [config](crate::config).
+/// 3. The first installed emulator found by [`list_available`].
+///
+/// Returns the path to the emulator along with which emulator it is, so that its arguments can be chosen.
+#[pyfunction]
+pub fn find_emulator() -> Result<(PathBuf, EmulatorKind)> {
+    let path = find_emulator_path(std::env::var_os(EMULATOR_ENV_VAR))?;
+    let kind = EmulatorKind::from_path(&path);
+    Ok((path, kind))
+}
+
+/// Finds the emulator path for [`find_emulator`], given the value of the [`MERLON_EMULATOR`](EMULATOR_ENV_VAR)
+/// environment variable.
+fn find_emulator_path(env_var: Option<OsString>) -> Result<PathBuf> {
+    if let Some(path) = env_var.filter(|path| !path.is_empty()) {
         let path = PathBuf::from(path);
-        if path.exists() {
-            return Ok(path);
+        if !path.is_file() {
+            bail!("{} is set to {}, which does not exist", EMULATOR_ENV_VAR, path.display());
+        }
+        return Ok(path);
+    }
+    if let Some(path) = Config::load()?.emulator {
+        if !path.is_file() {
+            bail!("configured emulator {} does not exist, see `merlon config`", path.display());
+        }
+        return Ok(path);
+    }
+    match list_available().into_iter().next() {
+        Some(path) => Ok(path),
+        None => bail!("no known emulator installed, set {} or `merlon config set emulator <path>`", EMULATOR_ENV_VAR),
+    }
+}
+
+/// Builds the command to run an emulator: Merlon's flags for it, then the ROM, then the user's own arguments.
+fn emulator_command(emulator: &Path, flags: Vec<String>, rom: &Path, args: &[String]) -> Command {
+    let mut command = Command::new(emulator);
+    command.args(flags).arg(rom).args(args);
+    command
+}
+
+/// Finds the first GDB on `PATH` from [`GDB_NAMES`].
+fn find_gdb() -> Result<PathBuf> {
+    match search_path(&std::env::var_os("PATH").unwrap_or_default(), GDB_NAMES).into_iter().next() {
+        Some(path) => Ok(path),
+        None => bail!("no GDB installed, install gdb-multiarch to debug"),
+    }
+}
+
+/// Returns the executables with the given names, without an extension, in the directories of a `PATH`-like
+/// variable. They are ordered by name first, then by directory.
+fn search_path(path_var: &OsStr, names: &[&str]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for name in names {
+        for dir in std::env::split_paths(path_var) {
+            let path = dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+            if path.is_file() && !found.contains(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+/// Waits until something is listening on the given local port, i.e. the emulator's GDB server has started.
+fn wait_for_debug_server(port: u16) -> Result<()> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let start = Instant::now();
+    while TcpStream::connect_timeout(&address, Duration::from_millis(100)).is_err() {
+        if start.elapsed() > DEBUG_SERVER_TIMEOUT {
+            bail!("emulator did not start its GDB server on port {}", port);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intents_are_translated_per_emulator() {
+        let intents = vec![LaunchIntent::Fullscreen, LaunchIntent::NoAudio];
+        let mupen = EmulatorKind::from_path(Path::new("/usr/bin/mupen64plus"));
+        assert_eq!(mupen, EmulatorKind::Mupen64Plus);
+        assert_eq!(mupen.args_for(intents.clone()), vec!["--fullscreen", "--audio", "dummy"]);
+        assert_eq!(EmulatorKind::from_path(Path::new("/Applications/ares.app/Contents/MacOS/ares")), EmulatorKind::Ares);
+        assert_eq!(EmulatorKind::from_path(Path::new("Project64.exe")), EmulatorKind::Project64);
+        assert_eq!(EmulatorKind::from_path(Path::new("/opt/simple64/simple64-gui")), EmulatorKind::Simple64);
+        assert_eq!(EmulatorKind::from_path(Path::new("m64p")), EmulatorKind::Simple64);
+        let unknown = EmulatorKind::from_path(Path::new("/usr/local/bin/my-emulator"));
+        assert_eq!(unknown, EmulatorKind::Unknown);
+        assert!(unknown.args_for(intents).is_empty());
+    }
+
+    #[test]
+    fn only_some_emulators_can_record() -> Result<()> {
+        let output = PathBuf::from("footage.mkv");
+        assert_eq!(EmulatorKind::RetroArch.record_args(output.clone())?, vec!["--record", "footage.mkv"]);
+        for kind in [EmulatorKind::Cen64, EmulatorKind::Mupen64Plus, EmulatorKind::Unknown] {
+            let error = kind.record_args(output.clone()).unwrap_err();
+            assert_eq!(error.to_string(), format!("recording is not supported for {:?}", kind));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn only_some_emulators_can_debug() -> Result<()> {
+        assert_eq!(EmulatorKind::Cen64.debug_args(9123)?, vec!["-debug", "localhost:9123"]);
+        let ares = EmulatorKind::Ares.debug_args(9123)?;
+        assert!(ares.contains(&"DebugServer/Port=9123".to_owned()), "{:?}", ares);
+        let unsupported = [
+            EmulatorKind::Mupen64Plus,
+            EmulatorKind::RetroArch,
+            EmulatorKind::Project64,
+            EmulatorKind::Unknown,
+        ];
+        for kind in unsupported {
+            let error = kind.debug_args(9123).unwrap_err();
+            assert!(error.to_string().starts_with(&format!("debugging is not supported for {:?}", kind)));
         }
+        Ok(())
+    }
+
+    #[test]
+    fn debug_server_is_waited_for() -> Result<()> {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        wait_for_debug_server(listener.local_addr()?.port())?;
+        Ok(())
+    }
+
+    #[test]
+    fn extra_args_follow_rom() {
+        let flags = EmulatorKind::Mupen64Plus.args_for(vec![LaunchIntent::Fullscreen]);
+        let rom = Path::new("papermario.z64");
+        let args_of = |command: Command| -> Vec<String> {
+            command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect()
+        };
+
+        let command = emulator_command(Path::new("mupen64plus"), flags.clone(), rom, &[]);
+        assert_eq!(args_of(command), vec!["--fullscreen", "papermario.z64"]);
+        let extra = ["--set".to_owned(), "Input[Profile]=pad".to_owned()];
+        let command = emulator_command(Path::new("mupen64plus"), flags, rom, &extra);
+        assert_eq!(args_of(command), vec!["--fullscreen", "papermario.z64", "--set", "Input[Profile]=pad"]);
     }
 
-    bail!("no known emulator installed");
+    #[test]
+    fn path_is_searched_by_name() -> Result<()> {
+        let dir = temp_dir::TempDir::new()?;
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        for bin in [&first, &second] {
+            std::fs::create_dir(bin)?;
+        }
+        let exe = |dir: &Path, name: &str| dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+        for path in [exe(&first, "retroarch"), exe(&second, "ares"), exe(&second, "retroarch")] {
+            std::fs::write(path, "")?;
+        }
+
+        let path_var = std::env::join_paths([&first, &second, &first])?;
+        let found = search_path(&path_var, EMULATOR_NAMES);
+        assert_eq!(found, vec![exe(&second, "ares"), exe(&first, "retroarch"), exe(&second, "retroarch")]);
+        assert!(search_path(&path_var, GDB_NAMES).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn emulator_is_chosen_by_number() -> Result<()> {
+        let emulators = vec![PathBuf::from("/usr/bin/ares"), PathBuf::from("/usr/bin/mupen64plus")];
+        let mut output = Vec::new();
+        let chosen = prompt_for_emulator(&emulators, "3\nares\n2\n".as_bytes(), &mut output)?;
+        assert_eq!(chosen, Some(emulators[1].clone()));
+        let output = String::from_utf8(output)?;
+        assert!(output.contains("  2) /usr/bin/mupen64plus (Mupen64Plus)"), "{}", output);
+        assert_eq!(output.matches("Please enter a number from 1 to 2.").count(), 2, "{}", output);
+
+        assert_eq!(prompt_for_emulator(&emulators, "\n".as_bytes(), Vec::new())?, Some(emulators[0].clone()));
+        assert_eq!(prompt_for_emulator(&emulators, "".as_bytes(), Vec::new())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn env_var_overrides_emulator() -> Result<()> {
+        let dir = temp_dir::TempDir::new()?;
+        let script = dir.path().join("my-emulator.sh");
+        std::fs::write(&script, "#!/bin/sh\nexit 0\n")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+        }
+
+        assert_eq!(find_emulator_path(Some(script.clone().into()))?, script);
+        assert_eq!(EmulatorKind::from_path(&script), EmulatorKind::Unknown);
+        let error = find_emulator_path(Some(dir.path().join("missing").into())).unwrap_err();
+        assert!(error.to_string().starts_with("MERLON_EMULATOR is set to"), "{}", error);
+        Ok(())
+    }
 }
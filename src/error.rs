@@ -0,0 +1,31 @@
+//! Categories of errors that callers may want to handle differently.
+//!
+//! Most functions return an [`anyhow::Error`] with a human-readable message. Where the kind of failure matters,
+//! e.g. for the CLI's exit codes, an [`Error`] is somewhere in its context chain, and can be found with
+//! `error.downcast_ref::<merlon::Error>()`.
+
+use thiserror::Error;
+
+/// A categorised error. The message is the same as it would be for an uncategorised error.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// There is no package where one was expected, e.g. a command needing a package was run outside of one.
+    #[error("{0}")]
+    NotAPackage(String),
+
+    /// Configuring or building the decomp failed.
+    #[error("{0}")]
+    Build(String),
+
+    /// A base ROM is missing, or isn't the ROM that was expected.
+    #[error("{0}")]
+    Baserom(String),
+
+    /// A package has problems, such as invalid metadata.
+    #[error("{0}")]
+    Validation(String),
+
+    /// A package's dependencies could not be resolved.
+    #[error("{0}")]
+    Dependency(String),
+}
@@ -0,0 +1,41 @@
+//! Process exit codes.
+//!
+//! Scripts can rely on these to tell kinds of failure apart. They are derived from the [`merlon::Error`] category
+//! in an error's context chain; errors without a category exit with [`FAILURE`].
+
+use merlon::Error;
+use merlon::package::manifest::ValidationError;
+
+/// Any error without a more specific exit code.
+pub const FAILURE: i32 = 1;
+
+/// The command line was invalid, or a command needing a package was run outside of one. Clap also exits with this
+/// code for usage errors.
+pub const USAGE: i32 = 2;
+
+/// Configuring or building the decomp failed.
+pub const BUILD: i32 = 3;
+
+/// The package has problems, e.g. invalid metadata or `merlon check` found errors.
+pub const VALIDATION: i32 = 4;
+
+/// A base ROM is missing, or isn't an unmodified Paper Mario ROM of the package's region.
+pub const BASEROM: i32 = 5;
+
+/// The package's dependencies could not be resolved.
+pub const DEPENDENCY: i32 = 6;
+
+/// Returns the exit code to exit with because of the given error.
+pub fn for_error(error: &anyhow::Error) -> i32 {
+    if error.downcast_ref::<ValidationError>().is_some() {
+        return VALIDATION;
+    }
+    match error.downcast_ref::<Error>() {
+        Some(Error::NotAPackage(_)) => USAGE,
+        Some(Error::Build(_)) => BUILD,
+        Some(Error::Validation(_)) => VALIDATION,
+        Some(Error::Baserom(_)) => BASEROM,
+        Some(Error::Dependency(_)) => DEPENDENCY,
+        None => FAILURE,
+    }
+}
@@ -4,7 +4,12 @@
 //!
 //! Merlon is also available as a Python library: https://pypi.org/project/merlon/
 
+pub mod error;
+pub use error::Error;
+
 pub mod package;
+pub mod baserom;
+pub mod config;
 pub mod emulator;
 pub mod rom;
 
@@ -2,10 +2,21 @@
 
 use clap::Parser;
 use anyhow::{Result, Context, bail};
-use merlon::package::{Package, InitialisedPackage, Distributable};
-use std::path::PathBuf;
-
+use merlon::package::{Package, InitialisedPackage, Distributable, Registry, ProjectDefaults, rc};
+use merlon::package::init::InitialiseOptions;
+use merlon::package::distribute::BuildManifest;
+use merlon::config::Config;
+use merlon::emulator::LaunchIntent;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+mod exit_code;
 mod new;
+mod output;
+mod update_check;
+
+/// The path that means stdin or stdout instead of a file, e.g. `merlon export --output -`.
+const STDIO_PATH: &str = "-";
 
 /// Mod package manager for the Paper Mario (N64) decompilation.
 /// 
@@ -33,6 +44,18 @@ struct Args {
     /// If not set, a `merlon.toml` file will be searched for in the current directory and its parents.
     #[arg(short, long)]
     directory: Option<PathBuf>,
+
+    /// Show debug logs, including the output of git commands.
+    #[arg(short, long, global = true, overrides_with = "no_verbose")]
+    verbose: bool,
+
+    /// Don't show debug logs, even if the package's `.merlonrc` sets `verbose`.
+    #[arg(long, global = true, overrides_with = "verbose")]
+    no_verbose: bool,
+
+    /// When to use colors in output.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    color: clap::ColorChoice,
 }
 
 #[derive(Parser, Debug)]
@@ -41,7 +64,7 @@ enum SubCommand {
     New(new::Args),
 
     /// Initialise this package for editing and building.
-    Init(merlon::package::init::InitialiseOptions),
+    Init(InitArgs),
 
     /// Export this package as a `.merlon` file for distribution.
     Export(merlon::package::distribute::ExportOptions),
@@ -52,29 +75,228 @@ enum SubCommand {
     /// Open a distributable's source code.
     Open(OpenArgs),
 
+    /// Show information about a distributable, including the build it was verified with, if any.
+    Info(InfoArgs),
+
     /// Run the current package in an emulator.
-    Run(merlon::package::init::BuildRomOptions),
+    Run(RunArgs),
 
     /// Build the current package into a ROM.
-    Build(merlon::package::init::BuildRomOptions),
+    Build(BuildArgs),
+
+    /// Build the current package and run the decomp's test suite against it.
+    Test(merlon::package::init::BuildRomOptions),
 
     /// Update all dependencies, including packages and the decomp.
-    Update,
+    Update(UpdateArgs),
 
     /// Add a dependency to the current package.
     Add(merlon::package::init::AddDependencyOptions),
 
+    /// Remove a dependency from the current package.
+    Remove(RemoveArgs),
+
+    /// Check the current package for problems.
+    Check(CheckArgs),
+
+    /// Print the version of the current package, or bump it.
+    Version(VersionArgs),
+
+    /// Show whether the current package is initialised, and the state of its dependencies' branches.
+    Status,
+
+    /// Print the dependency tree of the current package.
+    List(ListArgs),
+
+    /// Check the current package's working state for problems, such as a dirty or out of sync decomp repository.
+    Doctor,
+
+    /// View and change persistent settings.
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+
     /// Launch the GUI.
     #[cfg(feature = "gui")]
     Gui,
 }
 
+#[derive(Parser, Debug)]
+struct InitArgs {
+    /// Path to an unmodified Paper Mario (N64) ROM of the package's region.
+    ///
+    /// If not set, the `baserom` setting is used (see `merlon config`).
+    #[arg(long)]
+    pub baserom: Option<PathBuf>,
+
+    /// Region of the base ROM. It becomes the region the package is built for, and is recorded in `merlon.toml`.
+    ///
+    /// If not provided, the package's region from `merlon.toml` is used, which is `us` unless it says otherwise.
+    #[arg(long, value_enum)]
+    pub region: Option<merlon::package::manifest::Region>,
+
+    /// Look for the base ROM in RetroArch's playlists and content directory instead of specifying `--baserom`.
+    #[arg(long, conflicts_with = "baserom")]
+    pub baserom_from_rom_manager: bool,
+
+    /// Git revision of decomp to use: a commit hash, a branch or tag name, or `latest-tag` for the newest release
+    /// tag. The commit it resolves to is recorded in `merlon.toml`.
+    ///
+    /// If not provided, the latest commit on `main` is used.
+    #[arg(long)]
+    pub rev: Option<String>,
+
+    /// Use the base ROM even if it isn't an unmodified Paper Mario ROM of the region.
+    #[arg(long)]
+    pub skip_baserom_check: bool,
+
+    /// An existing clone of the decomp, e.g. another package's `papermario` directory, to copy objects from instead
+    /// of downloading them.
+    ///
+    /// If not provided, the decomp clones of packages in the same directory as this one are used, if there are any.
+    #[arg(long)]
+    pub reference: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+struct VersionArgs {
+    /// Increment this component of the version, resetting lower components.
+    #[arg(long, value_enum)]
+    pub bump: Option<merlon::package::manifest::VersionBump>,
+
+    /// Bump the version even if dependent packages require a version it won't satisfy.
+    #[arg(long, requires = "bump")]
+    pub force: bool,
+}
+
+#[derive(Parser, Debug)]
+enum ConfigCommand {
+    /// Print the value of a setting.
+    Get {
+        /// The setting, e.g. `baserom`.
+        key: String,
+    },
+
+    /// Change a setting. Pass an empty value to restore the default.
+    Set {
+        /// The setting, e.g. `baserom`.
+        key: String,
+
+        /// The new value.
+        value: String,
+    },
+
+    /// Print every setting and its value.
+    List,
+}
+
+#[derive(Parser, Debug)]
+struct CheckArgs {
+    /// Also write a JSON snapshot of the package's dependency graph to this path.
+    ///
+    /// The snapshot contains no source code, so it can be attached to bug reports about dependency problems.
+    #[arg(long)]
+    pub snapshot: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+struct RemoveArgs {
+    /// ID of the dependency to remove, as shown by `merlon list`.
+    pub id: merlon::package::Id,
+}
+
+#[derive(Parser, Debug)]
+struct ListArgs {
+    /// Print the tree as JSON instead, for use by other tools.
+    #[arg(long)]
+    pub json: bool,
+}
+
 #[derive(Parser, Debug)]
 struct ApplyArgs {
     #[clap(flatten)]
     pub options: merlon::package::distribute::ApplyOptions,
 
-    pub distributable: PathBuf,
+    /// Distributables to apply. If more than one is given, they are all applied together. Use `-` to read one from
+    /// stdin.
+    #[arg(required = true)]
+    pub distributables: Vec<PathBuf>,
+
+    /// Don't keep the patched ROM, only check that it matches the given ROM, or if no ROM is given, the author's
+    /// build recorded in the distributable. Exits with an error if it doesn't match.
+    ///
+    /// Give the distributable before this option, e.g. `merlon apply mod.merlon --verify-only expected.z64`.
+    #[arg(long, value_name = "EXPECTED")]
+    pub verify_only: Option<Option<PathBuf>>,
+}
+
+#[derive(Parser, Debug)]
+struct RunArgs {
+    #[clap(flatten)]
+    pub options: merlon::package::init::BuildRomOptions,
+
+    /// Path to the emulator to run the ROM with.
+    ///
+    /// If not set, the `MERLON_EMULATOR` environment variable is used, then the `emulator` setting (see
+    /// `merlon config`), or else the first installed emulator found on `PATH` or in a common location.
+    #[arg(long)]
+    pub emulator: Option<PathBuf>,
+
+    /// Start the emulator in fullscreen, if it supports it.
+    #[arg(long, overrides_with = "no_fullscreen")]
+    pub fullscreen: bool,
+
+    /// Don't start the emulator in fullscreen, even if the package's `.merlonrc` sets `fullscreen`.
+    #[arg(long, overrides_with = "fullscreen")]
+    pub no_fullscreen: bool,
+
+    /// Disable the emulator's audio, if it supports it.
+    #[arg(long, overrides_with = "audio")]
+    pub no_audio: bool,
+
+    /// Don't disable the emulator's audio, even if the package's `.merlonrc` sets `no_audio`.
+    #[arg(long, overrides_with = "no_audio")]
+    pub audio: bool,
+
+    /// Record gameplay footage to this path. Only emulators that can record from the command line, such as
+    /// RetroArch, support this.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+
+    /// Start the emulator's GDB server on this port, for debugging. Only cen64 and ares support this.
+    #[arg(long, value_name = "PORT")]
+    pub debug_port: Option<u16>,
+
+    /// Also start GDB with the build's symbols, connected to the emulator's GDB server. Implies `--symbols`.
+    #[arg(long, requires = "debug_port")]
+    pub gdb: bool,
+
+    /// Extra arguments to pass to the emulator after the ROM path, e.g. `merlon run -- --setting Foo=1`.
+    #[arg(last = true, value_name = "EMULATOR_ARGS")]
+    pub emulator_args: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+struct UpdateArgs {
+    /// Switch the decomp to this revision instead of updating it to the latest commit.
+    ///
+    /// Package branches are rebuilt on top of the revision and the manifest is updated to pin it. Patches are
+    /// rewritten to follow any files that the decomp moved between the revisions.
+    #[arg(long)]
+    pub decomp_rev: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct BuildArgs {
+    #[clap(flatten)]
+    pub options: merlon::package::init::BuildRomOptions,
+
+    /// Keep running and rebuild whenever the package or decomp source changes.
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Build every region listed in the manifest's `regions`, rather than just `us`.
+    #[arg(long, conflicts_with_all = ["watch", "output"])]
+    pub all: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -85,10 +307,19 @@ struct OpenArgs {
     pub distributable: PathBuf,
 }
 
+#[derive(Parser, Debug)]
+struct InfoArgs {
+    /// The base ROM to decrypt the distributable with.
+    ///
+    /// If not set, the `baserom` setting is used (see `merlon config`).
+    #[arg(long)]
+    pub baserom: Option<PathBuf>,
+
+    pub distributable: PathBuf,
+}
+
 #[cfg(feature = "gui")]
 fn main() -> Result<()> {
-    pretty_env_logger::init();
-
     // If TERM is not set, or MERLON_GUI=1, run the GUI.
     let is_gui = std::env::var("TERM").is_err() || matches!(std::env::var("MERLON_GUI"), Ok(v) if v == "1");
 
@@ -101,13 +332,58 @@ fn main() -> Result<()> {
 
 #[cfg(not(feature = "gui"))]
 fn main() -> Result<()> {
-    pretty_env_logger::init();
     main_cli()
 }
 
 fn main_cli() -> Result<()> {
     let args = Args::parse();
-    args.run()
+    output::set_color_choice(args.color);
+    let result = args.with_project_defaults().and_then(|args| {
+        init_logger(args.verbose);
+        update_check::notify_if_outdated();
+        args.run()
+    });
+    if let Err(error) = result {
+        output::error(format!("{:?}", error));
+        std::process::exit(exit_code::for_error(&error));
+    }
+    Ok(())
+}
+
+/// Initialises logging from `RUST_LOG`, raising the level to debug if `verbose` is set.
+fn init_logger(verbose: bool) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    if verbose {
+        builder.filter_level(log::LevelFilter::Debug);
+    }
+    builder.init();
+}
+
+/// If several emulators are installed and none has been chosen, asks which to use and saves the answer as the
+/// `emulator` setting. Returns `None` to let [`merlon::emulator::find_emulator`] decide, e.g. when not interactive.
+fn choose_emulator() -> Result<Option<PathBuf>> {
+    if merlon::emulator::is_emulator_chosen()? || !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(None);
+    }
+    let emulators = merlon::emulator::list_available();
+    if emulators.len() < 2 {
+        return Ok(None);
+    }
+    let chosen = merlon::emulator::prompt_for_emulator(&emulators, std::io::stdin().lock(), std::io::stderr())?;
+    let Some(emulator) = chosen else {
+        return Ok(None);
+    };
+    let mut config = Config::load()?;
+    config.emulator = Some(emulator.clone());
+    config.save()?;
+    output::success_to_stderr(format!(
+        "Saved {} as the emulator to use, change it with `merlon config set emulator <path>`",
+        emulator.display(),
+    ));
+    Ok(Some(emulator))
 }
 
 #[cfg(feature = "gui")]
@@ -115,16 +391,44 @@ fn main_gui() -> Result<()> {
     use klask::Settings;
 
     klask::run_derived::<Args, _>(Settings::default(), |args| {
-        if let Err(error) = args.run() {
+        let result = args.with_project_defaults().and_then(|args| {
+            init_logger(args.verbose);
+            args.run()
+        });
+        if let Err(error) = result {
             // TODO: better error handling, e.g. nativefiledialog
             eprintln!("{}", error);
-            std::process::exit(1);
+            std::process::exit(exit_code::for_error(&error));
         }
     });
     Ok(())
 }
 
 impl Args {
+    /// Fills in options that weren't given on the command line from the package's `.merlonrc`, if it has one.
+    pub fn with_project_defaults(mut self) -> Result<Self> {
+        let package_dir = match self.directory.as_ref() {
+            Some(directory) => Some(directory.clone()),
+            None => Package::current()?.map(|package| package.path().to_owned()),
+        };
+        let Some(package_dir) = package_dir else {
+            return Ok(self);
+        };
+        let defaults = ProjectDefaults::load(&package_dir)?;
+        self.verbose = rc::flag(self.verbose, self.no_verbose, defaults.verbose);
+        match &mut self.subcmd {
+            SubCommand::Build(build_args) => defaults.apply_to_build_rom_options(&mut build_args.options),
+            SubCommand::Test(options) => defaults.apply_to_build_rom_options(options),
+            SubCommand::Run(run_args) => {
+                defaults.apply_to_build_rom_options(&mut run_args.options);
+                run_args.fullscreen = rc::flag(run_args.fullscreen, run_args.no_fullscreen, defaults.run.fullscreen);
+                run_args.no_audio = rc::flag(run_args.no_audio, run_args.audio, defaults.run.no_audio);
+            }
+            _ => {}
+        }
+        Ok(self)
+    }
+
     pub fn run(self) -> Result<()> {
         // Get package from args, or current directory if not specified.
         let mut package = if let Some(directory) = self.directory.as_ref() {
@@ -137,7 +441,7 @@ impl Args {
 
         if let Some(package) = &mut package {
             for warning in package.manifest()?.metadata().validate() {
-                eprintln!("warning: {}", warning);
+                output::diagnostic(&warning);
             }
         }
 
@@ -152,35 +456,115 @@ impl Args {
             },
             SubCommand::Init(init_args) => {
                 if let Some(package) = package {
-                    InitialisedPackage::initialise(package, init_args)?;
+                    let baserom = if init_args.baserom_from_rom_manager {
+                        let baserom = merlon::baserom::find_in_retroarch()
+                            .context("no US-release Paper Mario ROM found in RetroArch, specify --baserom instead")?;
+                        println!("Using base ROM from RetroArch: {}", baserom.display());
+                        baserom
+                    } else {
+                        init_args.baserom
+                            .or(Config::load()?.baserom)
+                            .context("no base ROM given, use --baserom or `merlon config set baserom <path>`")?
+                    };
+                    if let Some(header) = merlon::baserom::open(&baserom)?.header()? {
+                        println!("Base ROM: {}", header);
+                    }
+                    InitialisedPackage::initialise(package, InitialiseOptions {
+                        baserom,
+                        region: init_args.region,
+                        rev: init_args.rev,
+                        skip_baserom_check: init_args.skip_baserom_check,
+                        reference: init_args.reference,
+                    })?;
                     Ok(())
                 } else {
-                    bail!("cannot initialise package: not in a package directory.");
+                    bail!(merlon::Error::NotAPackage("cannot initialise package: not in a package directory.".to_owned()));
                 }
             },
             SubCommand::Export(export_args) => {
+                // Building writes to stdout, which would corrupt the distributable
+                if export_args.output.as_deref() == Some(Path::new(STDIO_PATH)) && export_args.verify_reproducible {
+                    bail!("--verify-reproducible cannot be used when writing to stdout");
+                }
                 if let Some(package) = package {
+                    if export_args.dry_run {
+                        let preview = package.preview_export()?;
+                        println!("{} patch(es):", preview.patches.len());
+                        for patch in &preview.patches {
+                            println!("  {}", patch);
+                            for file in &patch.files {
+                                println!("    {}", file);
+                            }
+                        }
+                        println!("{} dependency(ies):", preview.dependencies.len());
+                        for dependency in &preview.dependencies {
+                            println!("  {}", dependency);
+                        }
+                        println!("Total size before compression: {} bytes", preview.size);
+                        if preview.uncommitted_changes {
+                            output::warning("the decomp repo has uncommitted changes, which will not be exported");
+                        }
+                        return Ok(());
+                    }
+
                     // If the package is initialised, sync it so the patches dir updates
-                    if InitialisedPackage::is_initialised(&package)? {
+                    if package.is_initialised()? {
                         let initialised = InitialisedPackage::try_from(package.clone())?;
-                        initialised.setup_git_branches()?;
+                        initialised.sync_repo()?;
+                    }
+
+                    for warning in package.check_commit_subjects()? {
+                        output::diagnostic(&warning);
+                    }
+
+                    if export_args.output.as_deref() == Some(Path::new(STDIO_PATH)) {
+                        let mut stdout = std::io::stdout().lock();
+                        if stdout.is_terminal() {
+                            bail!("refusing to write a distributable to a terminal, redirect stdout to a file or pipe");
+                        }
+                        if export_args.split_size.is_some() {
+                            bail!("--split-size cannot be used when writing to stdout");
+                        }
+                        package.write_distributable(export_args, &mut stdout)?;
+                        output::success_to_stderr(format!("Exported distributable of {} to stdout", package));
+                        return Ok(());
                     }
 
                     let exported = package.export_distributable(export_args)?;
-                    println!("Exported distributable: {}", exported);
+                    output::success(format!("Exported distributable: {}", exported));
                     Ok(())
                 } else {
-                    bail!("cannot export package: not in a package directory.");
+                    bail!(merlon::Error::NotAPackage("cannot export package: not in a package directory.".to_owned()));
                 }
             },
             SubCommand::Apply(apply_args) => {
-                let distributable = Distributable::try_from(apply_args.distributable)?;
-                distributable.open_scoped(apply_args.options.baserom.clone(), |package| {
-                    println!("{}", package.copyright_notice()?);
-                    Ok(())
-                })?;
-                let rom = distributable.apply(apply_args.options)?;
-                println!("Patched: {}", rom);
+                let mut distributables = Vec::with_capacity(apply_args.distributables.len());
+                for path in apply_args.distributables {
+                    let distributable = if path == Path::new(STDIO_PATH) {
+                        Distributable::read_from(&mut std::io::stdin().lock())?
+                    } else {
+                        Distributable::try_from(path)?
+                    };
+                    distributable.open_scoped(apply_args.options.baserom.clone(), |package| {
+                        println!("{}", package.copyright_notice()?);
+                        Ok(())
+                    })?;
+                    distributables.push(distributable);
+                }
+                if let Some(expected) = apply_args.verify_only {
+                    let [distributable] = distributables.as_slice() else {
+                        bail!("--verify-only can only be used with a single distributable");
+                    };
+                    distributable.verify_apply(apply_args.options, expected)?;
+                    output::success("Patched ROM matches");
+                    return Ok(());
+                }
+                let rom = if let [distributable] = distributables.as_slice() {
+                    distributable.apply(apply_args.options)?
+                } else {
+                    Distributable::apply_many(&distributables, apply_args.options)?
+                };
+                output::success(format!("Patched: {}", rom));
                 Ok(())
             },
             SubCommand::Open(open_args) => {
@@ -192,36 +576,127 @@ impl Args {
                 println!("Opened {} to directory {}", package, package.path().display());
                 Ok(())
             },
-            SubCommand::Run(build_args) => {
+            SubCommand::Info(info_args) => {
+                let baserom = info_args.baserom
+                    .or(Config::load()?.baserom)
+                    .context("no base ROM given, use --baserom or `merlon config set baserom <path>`")?;
+                let distributable = Distributable::try_from(info_args.distributable)
+                    .context("failed to open distributable file")?;
+                distributable.open_scoped(baserom, |package| {
+                    println!("{}", package);
+                    let manifest = package.manifest()?;
+                    if let Some(long_description) = manifest.metadata().long_description() {
+                        println!("\n{}\n", long_description);
+                    }
+                    if let Some(repository) = manifest.metadata().repository() {
+                        println!("Repository: {}", output::hyperlink(repository));
+                    }
+                    if let Some(homepage) = manifest.metadata().homepage() {
+                        println!("Homepage: {}", output::hyperlink(homepage));
+                    }
+                    println!("Format version: {}", distributable.format_version());
+                    match BuildManifest::read_from_dir(package.path())? {
+                        Some(build_manifest) => println!("{}", build_manifest),
+                        None => println!("No build manifest (the package was not exported with --verify-reproducible)"),
+                    }
+                    Ok(())
+                })
+            },
+            SubCommand::Run(run_args) => {
                 if let Some(package) = package {
                     let initialised: InitialisedPackage = package.try_into()?;
-                    let rom = initialised.build_rom(build_args)?;
-                    merlon::emulator::run_rom(&rom)?;
+                    let mut options = run_args.options;
+                    options.with_symbols |= run_args.gdb;
+                    let rom = initialised.build_rom(options)?;
+                    let mut intents = Vec::new();
+                    if run_args.fullscreen {
+                        intents.push(LaunchIntent::Fullscreen);
+                    }
+                    if run_args.no_audio {
+                        intents.push(LaunchIntent::NoAudio);
+                    }
+                    // The ELF is next to the ROM, whether in the build directory or copied with --symbols
+                    let gdb_symbols = run_args.gdb.then(|| rom.path().with_extension("elf"));
+                    let emulator = match run_args.emulator {
+                        Some(emulator) => Some(emulator),
+                        None => choose_emulator()?,
+                    };
+                    merlon::emulator::run_rom(
+                        &rom,
+                        emulator,
+                        intents,
+                        run_args.record,
+                        run_args.debug_port,
+                        gdb_symbols,
+                        run_args.emulator_args,
+                    )?;
                     Ok(())
                 } else {
-                    bail!("cannot run package: not in a package directory.");
+                    bail!(merlon::Error::NotAPackage("cannot run package: not in a package directory.".to_owned()));
                 }
             },
             SubCommand::Build(build_args) => {
                 if let Some(package) = package {
                     let initialised: InitialisedPackage = package.try_into()?;
-                    let rom = initialised.build_rom(build_args)?;
-                    println!("Built: {}", rom);
+                    if build_args.watch {
+                        println!("Watching for changes. Press Ctrl+C to stop.");
+                        return initialised.watch_build(build_args.options, |result| {
+                            match result {
+                                Ok(rom) => output::success(format!("Built: {}", rom)),
+                                Err(error) => output::error(format!("{:?}", error)),
+                            }
+                            Ok(())
+                        });
+                    }
+                    if build_args.all {
+                        for (region, rom) in initialised.build_all_regions(build_args.options)? {
+                            output::success(format!("[{}] Built: {} (SHA1 {})", region, rom, rom.sha1_string()?));
+                        }
+                        output::warning("do not distribute these ROMs. To distribute this package, use `merlon export`.");
+                        return Ok(());
+                    }
+                    let rom = initialised.build_rom(build_args.options)?;
+                    output::success(format!("Built: {}", rom));
                     println!("You can run this ROM with `merlon run`.");
-                    println!("Warning: do not distribute this ROM. To distribute this package, use `merlon export`.");
+                    output::warning("do not distribute this ROM. To distribute this package, use `merlon export`.");
                     Ok(())
                 } else {
-                    bail!("cannot build package: not in a package directory.");
+                    bail!(merlon::Error::NotAPackage("cannot build package: not in a package directory.".to_owned()));
                 }
             },
-            SubCommand::Update => {
+            SubCommand::Test(options) => {
                 if let Some(package) = package {
                     let initialised: InitialisedPackage = package.try_into()?;
-                    initialised.update_decomp()?;
-                    initialised.setup_git_branches()?;
+                    if initialised.run_tests(options)? {
+                        output::success("Tests passed.");
+                        Ok(())
+                    } else {
+                        bail!("tests failed, see {} for details", initialised.test_log_path().display());
+                    }
+                } else {
+                    bail!(merlon::Error::NotAPackage("cannot test package: not in a package directory.".to_owned()));
+                }
+            },
+            SubCommand::Update(update_args) => {
+                if let Some(package) = package {
+                    let mut initialised: InitialisedPackage = package.try_into()?;
+                    let before = initialised.registry().to_snapshot()?;
+                    if let Some(rev) = update_args.decomp_rev {
+                        initialised.switch_decomp_rev(&rev)?;
+                    } else {
+                        initialised.update_git_dependencies()?;
+                        initialised.update_decomp()?;
+                        initialised.sync_repo()?;
+                    }
+                    let diff = before.diff(&initialised.registry().to_snapshot()?);
+                    if diff.is_empty() {
+                        println!("Already up to date.");
+                    } else {
+                        output::success(format!("Updated: {}", diff));
+                    }
                     Ok(())
                 } else {
-                    bail!("cannot update package: not in a package directory.");
+                    bail!(merlon::Error::NotAPackage("cannot update package: not in a package directory.".to_owned()));
                 }
             }
             SubCommand::Add(add_args) => {
@@ -236,13 +711,214 @@ impl Args {
                     // Add the dependency
                     let id = initialised.add_dependency(add_args)?;
                     let package = initialised.registry().get_or_error(id)?;
-                    println!("Added dependency: {}", package);
-                    initialised.setup_git_branches()
+                    output::success(format!("Added dependency: {}", package));
+                    initialised.sync_repo()
                         .context("failed to setup git branches with dependency, there might be a merge issue")
                 } else {
-                    bail!("cannot add dependency: not in a package directory.");
+                    bail!(merlon::Error::NotAPackage("cannot add dependency: not in a package directory.".to_owned()));
+                }
+            },
+            SubCommand::Remove(remove_args) => {
+                let Some(package) = package else {
+                    bail!(merlon::Error::NotAPackage("cannot remove: not in a package directory.".to_owned()));
+                };
+                let mut initialised: InitialisedPackage = package.try_into()?;
+                if initialised.is_git_dirty()? {
+                    bail!("papermario repo has uncommitted changes, please commit or stash them first");
+                }
+                let name = initialised.registry().get(remove_args.id)?
+                    .map_or_else(|| remove_args.id.to_string(), ToString::to_string);
+                initialised.remove_dependency(remove_args.id)?;
+                output::success(format!("Removed dependency: {}", name));
+                Ok(())
+            },
+            SubCommand::Check(check_args) => {
+                if let Some(package) = package {
+                    if let Some(snapshot_path) = check_args.snapshot {
+                        let registry = if package.is_initialised()? {
+                            InitialisedPackage::try_from(package.clone())?.registry().clone()
+                        } else {
+                            let mut registry = Registry::new();
+                            registry.register(package.clone())?;
+                            registry
+                        };
+                        std::fs::write(&snapshot_path, registry.to_snapshot()?.to_json()?)
+                            .with_context(|| format!("failed to write {}", snapshot_path.display()))?;
+                        println!("Wrote registry snapshot to {}", snapshot_path.display());
+                    }
+
+                    // Metadata warnings were already printed above
+                    let metadata_warnings = package.manifest()?.metadata().validate();
+                    let mut warning_count = metadata_warnings.len();
+                    let mut error_count = metadata_warnings.iter().filter(|warning| warning.is_error()).count();
+
+                    for warning in package.check_commit_subjects()? {
+                        output::diagnostic(&warning);
+                        warning_count += 1;
+                    }
+
+                    if package.is_initialised()? {
+                        let initialised: InitialisedPackage = package.try_into()?;
+                        for error in initialised.registry().validate_all(initialised.package().id()?) {
+                            output::error(error);
+                            warning_count += 1;
+                            error_count += 1;
+                        }
+                        let inconsistencies = initialised.registry()
+                            .is_consistent_with_disk(initialised.package().path())?;
+                        for inconsistency in inconsistencies {
+                            output::error(inconsistency);
+                            warning_count += 1;
+                            error_count += 1;
+                        }
+                        for warning in initialised.check_decomp_revs()? {
+                            output::warning(warning);
+                            warning_count += 1;
+                        }
+                        for warning in initialised.check_assets()? {
+                            output::diagnostic(&warning);
+                            warning_count += 1;
+                            error_count += warning.is_error() as usize;
+                        }
+                    }
+
+                    if warning_count == 0 {
+                        output::success("No problems found.");
+                    } else if error_count > 0 {
+                        bail!(merlon::Error::Validation(format!(
+                            "found {} warning(s), of which {} are errors",
+                            warning_count, error_count,
+                        )));
+                    } else {
+                        println!("Found {} warning(s).", warning_count);
+                    }
+                    Ok(())
+                } else {
+                    bail!(merlon::Error::NotAPackage("cannot check package: not in a package directory.".to_owned()));
+                }
+            },
+            SubCommand::Status => {
+                if let Some(package) = package {
+                    println!("{}", package);
+                    if !package.is_initialised()? {
+                        if package.has_incomplete_initialisation() {
+                            println!("Initialisation incomplete, run `merlon init` to resume");
+                        } else {
+                            println!("Not initialised, run `merlon init` to initialise");
+                        }
+                        return Ok(());
+                    }
+                    let initialised = InitialisedPackage::try_from(package)?;
+                    println!("Branches:");
+                    for (id, exists) in initialised.list_package_branches()? {
+                        let package = initialised.registry().get_or_error(id)?;
+                        if exists {
+                            println!("  {} ({})", package, id);
+                        } else {
+                            println!("  {} ({}) - missing, run `merlon update` to sync", package, id);
+                        }
+                    }
+                    Ok(())
+                } else {
+                    bail!(merlon::Error::NotAPackage("cannot show status: not in a package directory.".to_owned()));
+                }
+            },
+            SubCommand::List(list_args) => {
+                let Some(package) = package else {
+                    bail!(merlon::Error::NotAPackage("cannot list: not in a package directory.".to_owned()));
+                };
+                // Without the dependencies directory, only direct dependencies are known
+                let tree = if package.is_initialised()? {
+                    let initialised = InitialisedPackage::try_from(package)?;
+                    initialised.registry().dependency_tree(initialised.package().id()?)?
+                } else {
+                    let mut registry = Registry::new();
+                    let id = registry.register(package)?;
+                    registry.dependency_tree(id)?
+                };
+                if list_args.json {
+                    println!("{}", tree.to_json()?);
+                } else {
+                    print!("{}", tree);
+                }
+                Ok(())
+            },
+            SubCommand::Doctor => {
+                let Some(package) = package else {
+                    bail!(merlon::Error::NotAPackage("cannot diagnose package: not in a package directory.".to_owned()));
+                };
+                if !package.is_initialised()? {
+                    bail!("package is not initialised, run `merlon init` to initialise it");
+                }
+                let diagnostics = InitialisedPackage::try_from(package)?.doctor()?;
+                for diagnostic in &diagnostics {
+                    output::warning(diagnostic);
+                }
+                if diagnostics.is_empty() {
+                    output::success("No problems found");
+                    Ok(())
+                } else {
+                    bail!("found {} problem(s)", diagnostics.len());
+                }
+            },
+            SubCommand::Version(version_args) => {
+                if let Some(package) = package {
+                    let Some(bump) = version_args.bump else {
+                        println!("{}", package.manifest()?.metadata().version());
+                        return Ok(());
+                    };
+                    let mut metadata = package.manifest()?.metadata().clone();
+                    let old_version = metadata.version().clone();
+                    let new_version = metadata.bump_version(bump).clone();
+
+                    // Dependents may pin a requirement that the new version doesn't satisfy
+                    let dependents = package.dependents_incompatible_with(&new_version)?;
+                    if !dependents.is_empty() {
+                        let names: Vec<String> = dependents.iter().map(ToString::to_string).collect();
+                        if !version_args.force {
+                            bail!(
+                                "{} would no longer satisfy the requirements of {}; use --force to bump anyway",
+                                new_version,
+                                names.join(", "),
+                            );
+                        }
+                        output::warning(format!(
+                            "{} no longer satisfies the requirements of {}",
+                            new_version,
+                            names.join(", "),
+                        ));
+                    }
+
+                    package.edit_manifest(|manifest| {
+                        manifest.metadata_mut().bump_version(bump);
+                        Ok(())
+                    })?;
+                    output::success(format!("Bumped version from {} to {}", old_version, new_version));
+                    Ok(())
+                } else {
+                    bail!(merlon::Error::NotAPackage("cannot bump version: not in a package directory.".to_owned()));
                 }
             },
+            SubCommand::Config(config_command) => {
+                let mut config = Config::load()?;
+                match config_command {
+                    ConfigCommand::Get { key } => {
+                        if let Some(value) = config.get(&key)? {
+                            println!("{}", value);
+                        }
+                    }
+                    ConfigCommand::Set { key, value } => {
+                        config.set(&key, &value)?;
+                        config.save()?;
+                    }
+                    ConfigCommand::List => {
+                        for (key, value) in config.list() {
+                            println!("{} = {}", key, value.unwrap_or_default());
+                        }
+                    }
+                }
+                Ok(())
+            },
             #[cfg(feature = "gui")]
             SubCommand::Gui => main_gui(),
         }
@@ -254,3 +930,32 @@ fn verify_cli() {
     use clap::CommandFactory;
     Args::command().debug_assert()
 }
+
+#[test]
+fn cli_overrides_project_defaults() -> Result<()> {
+    let dir = temp_dir::TempDir::new()?;
+    std::fs::write(
+        dir.path().join(rc::FILE_NAME),
+        "verbose = true\n\n[build]\nsymbols = true\n\n[run]\nfullscreen = true\nno_audio = true\n",
+    )?;
+    let parse = |args: &[&str]| {
+        let directory = dir.path().to_str().unwrap();
+        Args::try_parse_from(["merlon", "--directory", directory].iter().chain(args))?.with_project_defaults()
+    };
+
+    let args = parse(&["run"])?;
+    assert!(args.verbose);
+    let SubCommand::Run(run_args) = args.subcmd else { unreachable!() };
+    assert!(run_args.options.with_symbols && run_args.fullscreen && run_args.no_audio);
+
+    let args = parse(&["run", "--no-verbose", "--no-symbols", "--no-fullscreen", "--audio"])?;
+    assert!(!args.verbose);
+    let SubCommand::Run(run_args) = args.subcmd else { unreachable!() };
+    assert!(!run_args.options.with_symbols && !run_args.fullscreen && !run_args.no_audio);
+
+    // The last of a flag and its counterpart wins
+    let args = parse(&["run", "--no-fullscreen", "--fullscreen"])?;
+    let SubCommand::Run(run_args) = args.subcmd else { unreachable!() };
+    assert!(run_args.fullscreen);
+    Ok(())
+}
@@ -1,21 +1,50 @@
 use std::path::PathBuf;
 use clap::Parser;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use heck::AsKebabCase;
-use merlon::package::Package;
+use merlon::package::{Package, Template, template::{TEMPLATES, DEFAULT_TEMPLATE_NAME}};
 
 #[derive(Parser, Debug)]
 pub struct Args {
     /// The name of the mod. This will be used as the mod's directory name.
     /// It is recommended that mods be named in the snake-case format.
-    name: String,
+    #[arg(required_unless_present = "list_templates")]
+    name: Option<String>,
+
+    /// Starter template to create the package from.
+    ///
+    /// Use `--list-templates` to see the available templates.
+    #[arg(long, default_value = DEFAULT_TEMPLATE_NAME)]
+    template: String,
+
+    /// Also create a CHANGELOG.md, which is bundled with exported distributables.
+    #[arg(long)]
+    changelog: bool,
+
+    /// List the available starter templates and exit.
+    #[arg(long)]
+    list_templates: bool,
 }
 
 pub fn run(dir: Option<PathBuf>, args: Args) -> Result<()> {
+    if args.list_templates {
+        for template in TEMPLATES {
+            println!("{:<12} {}", template.name, template.description);
+        }
+        return Ok(());
+    }
+
+    let name = args.name.ok_or_else(|| anyhow!("a package name is required"))?;
+    let template = Template::find(&args.template)
+        .ok_or_else(|| anyhow!("unknown template {}, see `merlon new --list-templates`", args.template))?;
+
     // Create the package
     let current_dir = std::env::current_dir()?;
-    let dir = dir.unwrap_or_else(|| current_dir.join(format!("{}", AsKebabCase(&args.name))));
-    let package = Package::new(args.name, dir)?;
+    let dir = dir.unwrap_or_else(|| current_dir.join(format!("{}", AsKebabCase(&name))));
+    let package = Package::new_from_template(name, dir, template)?;
+    if args.changelog {
+        package.create_changelog()?;
+    }
 
     // Try and make path relative to current directory, but if that fails, just use the absolute path
     let path_relative_to_current = package.path()
@@ -24,7 +53,7 @@ pub fn run(dir: Option<PathBuf>, args: Args) -> Result<()> {
 
     // Done!
     println!("");
-    println!("Created package: {}", &package);
+    crate::output::success(format!("Created package: {}", &package));
     println!("To build and run this package, run the following commands:");
     println!("");
     println!("    cd {:?}", path_relative_to_current);
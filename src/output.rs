@@ -0,0 +1,83 @@
+//! Styled terminal output.
+//!
+//! Colors are only used when writing to a terminal, and never when the `NO_COLOR` environment variable is set
+//! (see https://no-color.org). `--color always` or `--color never` overrides both.
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use anstyle::{AnsiColor, Style};
+use clap::ColorChoice;
+use merlon::package::warning::{Warning, Severity};
+
+const ERROR: Style = AnsiColor::Red.on_default().bold();
+const WARNING: Style = AnsiColor::Yellow.on_default().bold();
+const SUCCESS: Style = AnsiColor::Green.on_default();
+
+static COLOR_CHOICE: OnceLock<ColorChoice> = OnceLock::new();
+
+/// Sets whether to use colors. Only the first call has any effect.
+pub fn set_color_choice(choice: ColorChoice) {
+    let _ = COLOR_CHOICE.set(choice);
+}
+
+fn use_color(is_terminal: bool) -> bool {
+    match COLOR_CHOICE.get().copied().unwrap_or_default() {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && std::env::var_os("NO_COLOR").unwrap_or_default().is_empty(),
+    }
+}
+
+/// Returns `style`, or no style at all if colors are disabled for the stream.
+fn styled(style: Style, is_terminal: bool) -> Style {
+    if use_color(is_terminal) {
+        style
+    } else {
+        Style::new()
+    }
+}
+
+/// Prints an error to stderr.
+pub fn error(message: impl Display) {
+    let style = styled(ERROR, std::io::stderr().is_terminal());
+    eprintln!("{}error{:#}: {}", style, style, message);
+}
+
+/// Prints a warning to stderr.
+pub fn warning(message: impl Display) {
+    let style = styled(WARNING, std::io::stderr().is_terminal());
+    eprintln!("{}warning{:#}: {}", style, style, message);
+}
+
+/// Prints a package validation warning to stderr, colored by its severity.
+pub fn diagnostic(warning: &Warning) {
+    let (style, severity) = match warning.severity() {
+        Severity::Error => (ERROR, "error"),
+        Severity::Warning => (WARNING, "warning"),
+    };
+    let style = styled(style, std::io::stderr().is_terminal());
+    eprintln!("{}{}[{}]{:#}: {}", style, severity, warning.code(), style, warning.message());
+}
+
+/// Prints a message saying that something succeeded to stdout.
+pub fn success(message: impl Display) {
+    let style = styled(SUCCESS, std::io::stdout().is_terminal());
+    println!("{}{}{:#}", style, message, style);
+}
+
+/// Prints a message saying that something succeeded to stderr, for when stdout is being used for output.
+pub fn success_to_stderr(message: impl Display) {
+    let style = styled(SUCCESS, std::io::stderr().is_terminal());
+    eprintln!("{}{}{:#}", style, message, style);
+}
+
+/// Returns `url` as a hyperlink that terminals which support them make clickable, or plain text if colors are disabled
+/// for stdout.
+pub fn hyperlink(url: &str) -> String {
+    if use_color(std::io::stdout().is_terminal()) {
+        format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, url)
+    } else {
+        url.to_owned()
+    }
+}
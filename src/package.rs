@@ -6,18 +6,22 @@ const ROOT_DIR_NAME: &str = "merlon_v1";
 const MANIFEST_FILE_NAME: &str = "merlon.toml";
 const README_FILE_NAME: &str = "README.md";
 const LICENSE_FILE_NAME: &str = "LICENSE";
+const CHANGELOG_FILE_NAME: &str = "CHANGELOG.md";
 const PATCHES_DIR_NAME: &str = "patches";
 
 use std::{
+    collections::HashMap,
     fs,
-    process::Command,
+    process::{Command, ExitStatus, Stdio},
     path::{Path, PathBuf},
     fmt::{self, Display, Formatter},
     hash::Hash,
     cmp::{Eq, PartialEq},
     io::prelude::*,
 };
-use anyhow::{Result, bail};
+use anyhow::{Result, Context, bail};
+use scopeguard::defer;
+use sha1::{Sha1, Digest};
 use pyo3::prelude::*;
 
 pub mod manifest;
@@ -33,9 +37,26 @@ pub use registry::Registry;
 pub mod init;
 pub use init::InitialisedPackage;
 
+pub mod decomp_repo;
+
 pub mod distribute;
 pub use distribute::Distributable;
 
+pub mod template;
+pub use template::Template;
+
+pub mod warning;
+pub use warning::Warning;
+
+pub mod patch;
+pub use patch::PatchInfo;
+
+pub mod asset;
+pub mod readme;
+
+pub mod rc;
+pub use rc::ProjectDefaults;
+
 /// Returns true if the given directory is probably a Merlon package.
 pub fn is_unexported_package(path: &Path) -> bool {
     path.is_dir() && path.join(MANIFEST_FILE_NAME).is_file()
@@ -55,14 +76,23 @@ impl TryFrom<PathBuf> for Package {
         if is_unexported_package(&path) {
             Ok(Self { path })
         } else {
-            bail!("{} is not an unexported Merlon package", path.display());
+            bail!(crate::Error::NotAPackage(format!("{} is not an unexported Merlon package", path.display())));
         }
     }
 }
 
 impl Package {
-    /// Create a new package at the given path. The path must not exist.
+    /// Create a new package at the given path. The path must not exist, and its parent must be a writable directory
+    /// that isn't inside a decomp repository.
     pub fn new<N>(name: N, path: PathBuf) -> Result<Self>
+    where
+        N: manifest::name::TryIntoName,
+    {
+        Self::new_from_template(name, path, Template::default_template())
+    }
+
+    /// Create a new package at the given path, pre-populated from a starter template. The path must not exist.
+    pub fn new_from_template<N>(name: N, path: PathBuf, template: &Template) -> Result<Self>
     where
         N: manifest::name::TryIntoName,
     {
@@ -72,6 +102,7 @@ impl Package {
         if path.exists() {
             bail!("{} already exists", path.display());
         }
+        check_new_package_parent(&path)?;
 
         let path_clone = path.clone();
 
@@ -82,6 +113,9 @@ impl Package {
             let manifest = manifest::Manifest::new(name)?;
             manifest.write_to_file(&path.join(MANIFEST_FILE_NAME))?;
             fs::write(&path.join(LICENSE_FILE_NAME), generate_license(&manifest))?;
+            for (file_name, contents) in template.patches {
+                fs::write(path.join(PATCHES_DIR_NAME).join(file_name), contents)?;
+            }
 
             debug_assert!(Package::try_from(path.clone()).is_ok());
             Ok(Self { path })
@@ -99,23 +133,100 @@ impl Package {
 
     /// Edit the package manifest. The given function will be called with a mutable reference to the manifest,
     /// and after the function returns the manifest will be written back to disk.
+    ///
+    /// If the edited metadata has validation errors, nothing is written and a [`manifest::ValidationError`] is
+    /// returned. Warnings that aren't errors don't prevent writing.
+    ///
+    /// A legacy manifest is written back in the current format. Reading a package's manifest never changes the file.
     pub fn edit_manifest<F>(&self, f: F) -> Result<()>
     where
         F: FnOnce(&mut Manifest) -> Result<()>,
     {
-        let path = self.path.join(MANIFEST_FILE_NAME);
         let mut manifest = self.manifest()?;
         f(&mut manifest)?;
-        manifest.write_to_file(&path)
+        let errors: Vec<_> = manifest.metadata().validate()
+            .into_iter()
+            .filter(Warning::is_error)
+            .collect();
+        if !errors.is_empty() {
+            return Err(manifest::ValidationError { errors }.into());
+        }
+        self.write_manifest(&manifest)
+    }
+
+    /// Like [`Package::edit_manifest`], but writes the manifest even if it fails validation.
+    ///
+    /// Used for edits that don't touch metadata, such as dependency bookkeeping, which shouldn't be blocked by
+    /// unrelated metadata problems.
+    pub fn edit_manifest_unchecked<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Manifest) -> Result<()>,
+    {
+        let mut manifest = self.manifest()?;
+        f(&mut manifest)?;
+        self.write_manifest(&manifest)
+    }
+
+    /// Returns the packages that depend on this one with a version requirement that `version` doesn't satisfy, i.e.
+    /// the packages that would break if this package's version was changed to `version`.
+    ///
+    /// Packages don't know their dependents, so the packages looked at are those next to this one, and, if this
+    /// package is a dependency of an initialised package, that package.
+    pub fn dependents_incompatible_with(&self, version: &manifest::Version) -> Result<Vec<Package>> {
+        let mut registry = Registry::new();
+        let id = registry.register(self.clone())?;
+        let mut candidates = Vec::new();
+        let parent = self.path.parent().unwrap_or(Path::new("."));
+        for entry in fs::read_dir(parent).with_context(|| format!("failed to read {}", parent.display()))? {
+            candidates.push(entry?.path());
+        }
+        if parent.ends_with(init::DEPENDENCIES_DIR_NAME) {
+            if let Some(root) = parent.ancestors().nth(Path::new(init::DEPENDENCIES_DIR_NAME).components().count()) {
+                candidates.push(root.to_owned());
+            }
+        }
+        for path in candidates {
+            // Anything that isn't a readable package can't depend on this one
+            let Ok(package) = Package::try_from(path) else {
+                continue;
+            };
+            if package.id().is_ok_and(|candidate_id| !registry.has(candidate_id)) {
+                registry.register(package)?;
+            }
+        }
+        registry.dependents_incompatible_with(id, version)?
+            .into_iter()
+            .map(|dependent| registry.take(dependent))
+            .collect()
+    }
+
+    /// Writes the package manifest, migrating a legacy manifest to the current format.
+    fn write_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.path.join(MANIFEST_FILE_NAME);
+        let legacy = Manifest::is_legacy(&path);
+        manifest.write_to_file(&path)?;
+        if legacy {
+            log::info!("migrated legacy manifest {} to the current format", path.display());
+        }
+        Ok(())
     }
 }
 
 #[pymethods]
 impl Package {
     /// Creates a new package at the given path. The path must not exist.
+    /// Optionally, the name of a starter template to create the package from.
     #[new]
-    fn py_new(name: Name, path: PathBuf) -> Result<Self> {
-        Self::new(name, path)
+    #[pyo3(signature = (name, path, template = None))]
+    fn py_new(name: Name, path: PathBuf, template: Option<String>) -> Result<Self> {
+        let template = match template {
+            Some(template) => match Template::find(&template) {
+                Some(template) => template,
+                None => bail!("unknown template {}", template),
+            },
+            None => Template::default_template(),
+        };
+        Self::new_from_template(name, path, template)
     }
 
     /// Gets the current package, if any, by looking for `merlon.toml` in the current directory and its parents.
@@ -147,6 +258,32 @@ impl Package {
         fs::read_to_string(self.path.join(README_FILE_NAME)).map_err(Into::into)
     }
 
+    /// Returns the README.md file in the package rendered to HTML. Unsafe HTML and link URLs are removed, so the result
+    /// is safe to display.
+    pub fn readme_html(&self) -> Result<String> {
+        self.readme().map(|markdown| readme::render_html(&markdown))
+    }
+
+    /// Returns the text content of the CHANGELOG.md file in the package, or `None` if the package has no changelog.
+    pub fn changelog(&self) -> Result<Option<String>> {
+        let path = self.path.join(CHANGELOG_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(path)?))
+    }
+
+    /// Creates a CHANGELOG.md file in the package with an entry for the current version.
+    /// Errors if the package already has a changelog.
+    pub fn create_changelog(&self) -> Result<()> {
+        let path = self.path.join(CHANGELOG_FILE_NAME);
+        if path.exists() {
+            bail!("{} already exists", path.display());
+        }
+        fs::write(path, generate_changelog(&self.manifest()?))?;
+        Ok(())
+    }
+
     /// Returns the manifest of the package by parsing the `merlon.toml` file.
     pub fn manifest(&self) -> Result<Manifest> {
         let path = self.path.join(MANIFEST_FILE_NAME);
@@ -170,6 +307,81 @@ impl Package {
         Ok(notice)
     }
 
+    /// Checks the subjects of the package's patches against the commit convention in the manifest, returning a
+    /// warning for each patch that doesn't conform. If there is no convention, there are no warnings.
+    ///
+    /// Patches are read from the patches directory, so commits that haven't been synced to it aren't checked.
+    pub fn check_commit_subjects(&self) -> Result<Vec<Warning>> {
+        let manifest = self.manifest()?;
+        let convention = match manifest.commit_convention() {
+            Some(convention) => convention,
+            None => return Ok(Vec::new()),
+        };
+        if manifest.metadata().allows(warning::Code::CommitSubject) {
+            return Ok(Vec::new());
+        }
+        let mut warnings = Vec::new();
+        for patch in self.patches()? {
+            for problem in convention.check(&patch.subject) {
+                warnings.push(Warning::new(
+                    warning::Code::CommitSubject,
+                    format!("commit subject \"{}\" {}", patch.subject, problem),
+                ));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Parses the package's patch files, in the order they are applied.
+    pub fn patches(&self) -> Result<Vec<PatchInfo>> {
+        if !self.path.join(PATCHES_DIR_NAME).is_dir() {
+            return Ok(Vec::new());
+        }
+        self.patch_files()?
+            .into_iter()
+            .map(PatchInfo::read_from_path)
+            .collect()
+    }
+
+    /// Rewrites the package's patch files so that they apply after the decomp moves files around, e.g. when a new
+    /// decomp revision restructures `src/`. Returns the number of patch files that changed.
+    ///
+    /// The keys of `renames` are old paths and the values are new paths, relative to the decomp repo. A key may be a
+    /// directory, in which case everything in it is renamed. See [`patch::rename_paths`].
+    pub fn migrate_patches(&self, renames: HashMap<String, String>) -> Result<usize> {
+        if renames.is_empty() || !self.path.join(PATCHES_DIR_NAME).is_dir() {
+            return Ok(0);
+        }
+        let mut migrated = 0;
+        for path in self.patch_files()? {
+            let patch = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read patch {}", path.display()))?;
+            if let Some(patch) = patch::rename_paths(&patch, &renames) {
+                fs::write(&path, patch)
+                    .with_context(|| format!("failed to write patch {}", path.display()))?;
+                migrated += 1;
+            }
+        }
+        Ok(migrated)
+    }
+
+    /// Returns a SHA1 hash of the package's manifest and patches, which changes whenever either does.
+    ///
+    /// Each file is hashed separately and the hash of the package is taken over those, so the hash doesn't depend on
+    /// anything but the content and names of the files, e.g. not the package's path or file modification times.
+    pub fn content_hash(&self) -> Result<String> {
+        let mut hasher = Sha1::new();
+        hasher.update(Sha1::digest(fs::read(self.path.join(MANIFEST_FILE_NAME))?));
+        if self.path.join(PATCHES_DIR_NAME).is_dir() {
+            for path in self.patch_files()? {
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                hasher.update(Sha1::digest(name.as_bytes()));
+                hasher.update(Sha1::digest(fs::read(&path)?));
+            }
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
     fn __str__(&self) -> String {
         format!("{}", self)
     }
@@ -182,27 +394,22 @@ impl Package {
             log::warn!("{} directory does not exist", PATCHES_DIR_NAME);
             return Ok(())
         }
-        let mut patch_files = fs::read_dir(patches_path)?
-            .map(|entry| entry.unwrap().path())
-            .filter(|path| path.extension().map(|ext| ext == "patch").unwrap_or(false))
-            .map(|path| path.canonicalize())
-            .collect::<Result<Vec<_>, _>>()?;
-        patch_files.sort_unstable();
+        let patch_files = self.patch_files()?;
         if patch_files.is_empty() {
             return Ok(())
         }
-        let status = Command::new("git")
-            .arg("am")
-            .arg("--3way")
-            .args(patch_files.iter().map(|path| path.to_string_lossy().to_string()))
-            .current_dir(&repo)
-            .status()?;
+        let status = git_am(&patch_files, repo)?;
         if !status.success() {
             log::warn!("Failed to merge. Resolve merge conflicts (either using `git mergetool` or vscode's merge editor) and commit them to continue.");
         }   
         Ok(())
     }
 
+    /// Returns the paths of the package's patch files, in the order they are applied.
+    pub(crate) fn patch_files(&self) -> Result<Vec<PathBuf>> {
+        patch_files_in(&self.path.join(PATCHES_DIR_NAME))
+    }
+
     /// Copies the package to the given path and updates. The path must not exist.
     /// Effectively a set_path method.
     pub fn clone_to_dir(&self, path: PathBuf) -> Result<Self> {
@@ -258,6 +465,57 @@ impl Hash for Package {
     }
 }
 
+/// Checks that a package can be created at `path`, so that [`Package::new`] fails before creating anything.
+///
+/// The parent directory must exist and be writable, and must not be inside a clone of the decomp, such as an
+/// initialised package's subrepo. A package there would be nested in the repository that packages are applied to.
+fn check_new_package_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let metadata = fs::metadata(parent)
+        .with_context(|| format!("cannot create {}: {} does not exist", path.display(), parent.display()))?;
+    if !metadata.is_dir() {
+        bail!("cannot create {}: {} is not a directory", path.display(), parent.display());
+    }
+
+    // Try writing rather than checking the permission bits, which don't say whether this user can write to it
+    let probe = parent.join(format!(".merlon-write-test-{}", std::process::id()));
+    match fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+        }
+        // Left behind by a process that was killed
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(error) => {
+            return Err(anyhow::Error::new(error)
+                .context(format!("cannot create {}: {} is not writable", path.display(), parent.display())));
+        }
+    }
+
+    let parent = parent.canonicalize()
+        .with_context(|| format!("failed to resolve {}", parent.display()))?;
+    if let Some(decomp_dir) = parent.ancestors().find(|dir| is_decomp_dir(dir)) {
+        bail!(
+            "cannot create {} inside the decomp repository {}, create it somewhere else",
+            path.display(),
+            decomp_dir.display(),
+        );
+    }
+    Ok(())
+}
+
+/// Returns true if the given directory is a clone of the decomp, either an initialised package's subrepo or one
+/// cloned by hand.
+fn is_decomp_dir(dir: &Path) -> bool {
+    let is_subrepo = dir.file_name() == Some(init::SUBREPO_DIR_NAME.as_ref())
+        && dir.parent().is_some_and(|package_dir| package_dir.join(init::MERLON_DIR_NAME).is_dir());
+    let is_clone = dir.join(".git").exists()
+        && (dir.file_name() == Some(init::SUBREPO_DIR_NAME.as_ref()) || dir.join("ver/us/splat.yaml").is_file());
+    is_subrepo || is_clone
+}
+
 /// Finds the nearest git root, starting from the current directory.
 /// If within a submodule, returns the root of the parent repo.
 /// Otherwise, returns the root of the current repo.
@@ -291,21 +549,702 @@ fn nearest_git_root() -> Result<PathBuf> {
     bail!("not in a git repository");
 }
 
+/// Applies patch files to a repo with `git am`.
+///
+/// The patches' authors and author dates are kept, so that patches written again with `git format-patch` are
+/// attributed the same way no matter how many times they have been synced.
+pub(crate) fn git_am(patch_files: &[PathBuf], repo: &Path) -> Result<ExitStatus> {
+    run_git(Command::new("git")
+        .arg("am")
+        .arg("--3way")
+        // Only strip the `[PATCH]` prefix from subjects. Otherwise, any other bracketed prefix (e.g. `[ui] tweak menu`)
+        // would be stripped too, and the subject would change every time the patches are synced.
+        .arg("--keep-non-patch")
+        // Patches authored on Windows may have CRLF line endings. Always strip CRs (regardless of the user's
+        // am.keepcr config) and ignore whitespace in context lines so patches round-trip across platforms.
+        .arg("--no-keep-cr")
+        .arg("--ignore-whitespace")
+        .args(patch_files.iter().map(|path| path.to_string_lossy().to_string()))
+        .current_dir(repo))
+        .map_err(Into::into)
+}
+
+/// Checks that the given patch files apply cleanly, in order, on top of revision `base` of `repo`.
+/// The patches are applied in a temporary worktree, so the repo's own working tree is untouched.
+pub(crate) fn check_patches_apply(patch_files: &[PathBuf], repo: &Path, base: &str) -> Result<()> {
+    let temp_dir = temp_dir::TempDir::new()?;
+    let worktree = temp_dir.path().join("worktree");
+    let status = run_git(Command::new("git")
+        .arg("worktree")
+        .arg("add")
+        .arg("--detach")
+        .arg(&worktree)
+        .arg(base)
+        .current_dir(repo))?;
+    if !status.success() {
+        bail!("failed to create worktree of {} at {}", repo.display(), base);
+    }
+    defer! {
+        let _ = run_git(Command::new("git")
+            .arg("worktree")
+            .arg("remove")
+            .arg("--force")
+            .arg(&worktree)
+            .current_dir(repo));
+    }
+
+    if !patch_files.is_empty() && !git_am(patch_files, &worktree)?.success() {
+        bail!("patches do not apply cleanly on top of {}", base);
+    }
+    Ok(())
+}
+
+/// Runs a git command, hiding its routine output unless debug logging is enabled (e.g. with `merlon -v`), in which
+/// case it is written to stderr.
+/// If the command fails, any captured output is printed to stderr, since it's usually needed to diagnose why.
+pub(crate) fn run_git(command: &mut Command) -> std::io::Result<ExitStatus> {
+    if log::log_enabled!(log::Level::Debug) {
+        // Not stdout, which may be a distributable being exported
+        return command.stdout(std::io::stderr()).status();
+    }
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        let mut stderr = std::io::stderr();
+        stderr.write_all(&output.stdout)?;
+        stderr.write_all(&output.stderr)?;
+    }
+    Ok(output.status)
+}
+
 fn generate_readme(package_name: &Name) -> String {
     include_str!("../templates/README.md")
         .replace("{{package_name}}", &format!("{}", package_name))
 }
 
+fn generate_changelog(manifest: &Manifest) -> String {
+    let metadata = manifest.metadata();
+    include_str!("../templates/CHANGELOG.md")
+        .replace("{{package_name}}", &format!("{}", metadata.name()))
+        .replace("{{version}}", &format!("{}", metadata.version()))
+}
+
+/// Returns the paths of the patch files in the given directory, sorted by name.
+fn patch_files_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut patch_files = fs::read_dir(dir)?
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "patch").unwrap_or(false))
+        .map(|path| path.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    patch_files.sort_unstable();
+    Ok(patch_files)
+}
+
 fn generate_license(manifest: &Manifest) -> String {
-    let authors = manifest.metadata().authors();
+    let authors = manifest.metadata().parsed_authors();
     let author_names;
     if authors.is_empty() {
         author_names = "Unknown Author(s)".to_string();
     } else {
-        author_names = authors.join(", ");
+        author_names = authors.into_iter().map(|author| author.name).collect::<Vec<_>>().join(", ");
     }
 
     include_str!("../templates/CC-BY-SA-4.0.txt")
         .replace("{{year}}", &chrono::Utc::now().format("%Y").to_string())
         .replace("{{author_names}}", &author_names)
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use temp_dir::TempDir;
+    use anyhow::Result;
+
+    use super::{Package, InitialisedPackage, Template, MANIFEST_FILE_NAME, PATCHES_DIR_NAME};
+    use super::distribute::{ExportOptions, OpenOptions};
+    use super::init::{MERLON_DIR_NAME, SUBREPO_DIR_NAME};
+    use super::manifest::{CommitConvention, Region, VersionBump};
+    use super::warning::{Warning, Code};
+
+    /// Runs git in `repo`, asserting that it succeeds, and returns its output with surrounding whitespace trimmed.
+    pub(crate) fn git(repo: &Path, args: &[&str]) -> Result<String> {
+        let output = Command::new("git").args(args).current_dir(repo).output()?;
+        assert!(output.status.success(), "git {:?} failed", args);
+        Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+    }
+
+    /// Creates a git repository at `path` to stand in for the decomp, with a single commit on `main` adding `file.c`.
+    pub(crate) fn init_upstream(path: &Path) -> Result<()> {
+        fs::create_dir_all(path)?;
+        git(path, &["init", "--quiet", "--initial-branch", "main"])?;
+        fs::write(path.join("file.c"), "int x = 0;\n")?;
+        git(path, &["add", "file.c"])?;
+        git(path, &["commit", "--quiet", "-m", "initial"])?;
+        Ok(())
+    }
+
+    /// Clones `upstream` as the package's decomp repository, so the package is initialised without `merlon init`.
+    pub(crate) fn clone_upstream(package: &Package, upstream: &Path) -> Result<InitialisedPackage> {
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        git(upstream, &["clone", "--quiet", &upstream.to_string_lossy(), &subrepo.to_string_lossy()])?;
+        fs::create_dir(package.path().join(MERLON_DIR_NAME))?;
+        InitialisedPackage::from_initialised(package.clone())
+    }
+
+    #[test]
+    fn new_package_location_is_checked() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        // Inside a hand-made clone of the decomp
+        let clone = dir.path().join("decomp");
+        fs::create_dir_all(clone.join("ver/us"))?;
+        fs::write(clone.join("ver/us/splat.yaml"), "")?;
+        git(&clone, &["init", "--quiet"])?;
+        fs::create_dir(clone.join("src"))?;
+        let error = Package::new("Nested", clone.join("src").join("nested")).unwrap_err();
+        assert!(error.to_string().contains("inside the decomp repository"), "{}", error);
+        assert!(!clone.join("src/nested").exists());
+
+        // Inside an initialised package's subrepo, even before it is a git repository
+        let subrepo = dir.path().join("package/papermario");
+        fs::create_dir_all(&subrepo)?;
+        fs::create_dir(dir.path().join("package/.merlon"))?;
+        let error = Package::new("Nested", subrepo.join("nested")).unwrap_err();
+        assert!(error.to_string().contains("inside the decomp repository"), "{}", error);
+
+        // Missing or read-only parent
+        let error = Package::new("Orphan", dir.path().join("missing/orphan")).unwrap_err();
+        assert!(error.to_string().contains("does not exist"), "{}", error);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let read_only = dir.path().join("read-only");
+            fs::create_dir(&read_only)?;
+            fs::set_permissions(&read_only, fs::Permissions::from_mode(0o555))?;
+            // Unless running as root, which can write to it anyway
+            if fs::write(read_only.join("probe"), "").is_err() {
+                let error = Package::new("Locked", read_only.join("locked")).unwrap_err();
+                assert!(error.to_string().contains("is not writable"), "{}", error);
+                assert!(!read_only.join("locked").exists());
+            }
+            fs::set_permissions(&read_only, fs::Permissions::from_mode(0o755))?;
+        }
+
+        assert!(Package::new("Fine", dir.path().join("fine")).is_ok());
+        let probes = fs::read_dir(dir.path())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(".merlon"))
+            .count();
+        assert_eq!(probes, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn edit_manifest_refuses_invalid_metadata() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Valid", dir.path().join("package"))?;
+        let manifest_path = package.path().join("merlon.toml");
+        let before = fs::read_to_string(&manifest_path)?;
+
+        let result = package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_description(String::new());
+            Ok(())
+        });
+        let error = result.unwrap_err().downcast::<crate::package::manifest::ValidationError>()?;
+        assert_eq!(error.errors, vec![Warning::new(Code::EmptyDescription, "description cannot be empty")]);
+        assert_eq!(fs::read_to_string(&manifest_path)?, before);
+
+        package.edit_manifest_unchecked(|manifest| {
+            manifest.metadata_mut().set_description(String::new());
+            Ok(())
+        })?;
+        assert_eq!(package.manifest()?.metadata().description(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn editing_manifest_keeps_comments() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Commented", dir.path().join("package"))?;
+        let base = Package::new("Base", dir.path().join("base"))?;
+        let manifest_path = package.path().join(MANIFEST_FILE_NAME);
+        let manifest = fs::read_to_string(&manifest_path)?
+            .replace("[package]\n", "# Keep me\n[package]\n")
+            .replace("\ndescription = ", "\n# The one-liner\ndescription = ");
+        fs::write(&manifest_path, &manifest)?;
+
+        package.edit_manifest(|manifest| {
+            manifest.upsert_decomp_dependency("0".repeat(40))?;
+            manifest.declare_direct_dependency(super::manifest::Dependency::from(base.manifest()?.metadata()))
+        })?;
+        let edited = fs::read_to_string(&manifest_path)?;
+        assert!(edited.starts_with(&manifest.replace("dependencies = []\n", "")), "{}", edited);
+        assert!(edited.contains(&base.manifest()?.metadata().id().to_string()), "{}", edited);
+        assert_eq!(package.manifest()?.iter_direct_dependencies().count(), 2);
+        assert!(edited.contains("\n[[dependencies]]\n"), "{}", edited);
+        Ok(())
+    }
+
+    #[test]
+    fn declared_dependencies_are_upgraded() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Dependent", dir.path().join("package"))?;
+        let id = Package::new("Base", dir.path().join("base"))?.manifest()?.metadata().id();
+        let mut manifest = package.manifest()?;
+        let requirement = |manifest: &super::manifest::Manifest| {
+            manifest.iter_package_dependencies().next().map(|(_, version)| version.to_string())
+        };
+        let declare = |manifest: &mut super::manifest::Manifest, version: &str| {
+            manifest.declare_direct_dependency(super::manifest::Dependency::Package { id, version: version.parse()? })
+        };
+
+        declare(&mut manifest, "~0.1.0")?;
+        declare(&mut manifest, "~0.2.0")?;
+        assert_eq!(requirement(&manifest).as_deref(), Some("~0.2.0"));
+        // Already satisfied by the newer requirement
+        declare(&mut manifest, ">=0.1.5")?;
+        assert_eq!(requirement(&manifest).as_deref(), Some("~0.2.0"));
+        let error = declare(&mut manifest, "~0.1.0").unwrap_err();
+        assert!(error.to_string().contains("incompatible version ~0.2.0 (adding ~0.1.0)"), "{}", error);
+        assert!(declare(&mut manifest, "<1.0.0").is_err());
+        assert_eq!(manifest.iter_direct_dependencies().count(), 1);
+
+        // Decomp history is a -> b -> c, and x is on another branch
+        let history = ["a", "b", "c"];
+        let is_ancestor = |ancestor: &str, descendant: &str| -> Result<bool> {
+            let position = |rev| history.iter().position(|&other| other == rev);
+            Ok(matches!((position(ancestor), position(descendant)), (Some(a), Some(d)) if a < d))
+        };
+        manifest.upgrade_decomp_dependency("b".to_owned(), is_ancestor)?;
+        manifest.upgrade_decomp_dependency("c".to_owned(), is_ancestor)?;
+        assert_eq!(manifest.get_direct_decomp_dependency_rev(), Some("c"));
+        manifest.upgrade_decomp_dependency("a".to_owned(), is_ancestor)?;
+        assert_eq!(manifest.get_direct_decomp_dependency_rev(), Some("c"));
+        assert!(manifest.upgrade_decomp_dependency("x".to_owned(), is_ancestor).is_err());
+        let unrelated = super::manifest::Dependency::Decomp { rev: "d".to_owned() };
+        assert!(manifest.declare_direct_dependency(unrelated).is_err());
+        manifest.upsert_decomp_dependency("a".to_owned())?;
+        assert_eq!(manifest.get_direct_decomp_dependency_rev(), Some("a"));
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_manifests_are_migrated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("legacy");
+        fs::create_dir(&path)?;
+        let legacy = r#"[package]
+name = "My Mod"
+version = "0.3.0"
+authors = ["Alice"]
+description = "An old mod"
+license = "MIT"
+keywords = []
+base_commit = "7a9df943ad079e7b19df0f8690bdc92e2beed964"
+
+[dependencies]
+"Base Mod" = "0.2.0"
+"#;
+        fs::write(path.join(MANIFEST_FILE_NAME), legacy)?;
+
+        // Reading doesn't change the file, and gives the same ID each time
+        let package = Package::try_from(path.clone())?;
+        let manifest = package.manifest()?;
+        assert_eq!(manifest.metadata().id(), super::manifest::Id::from_legacy_name("My Mod"));
+        assert_eq!(package.id()?, manifest.metadata().id().clone());
+        assert_eq!(manifest.metadata().name().to_string(), "My Mod");
+        assert_eq!(manifest.metadata().version().to_string(), "0.3.0");
+        assert_eq!(manifest.get_direct_decomp_dependency_rev(), Some("7a9df943ad079e7b19df0f8690bdc92e2beed964"));
+        let dependencies: Vec<_> = manifest.iter_package_dependencies().collect();
+        assert_eq!(dependencies, vec![(super::manifest::Id::from_legacy_name("Base Mod"), &"0.2.0".parse()?)]);
+        assert!(manifest.metadata().is_valid());
+        assert_eq!(fs::read_to_string(path.join(MANIFEST_FILE_NAME))?, legacy);
+
+        // Editing writes it in the current format
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_version("0.3.1".parse()?);
+            Ok(())
+        })?;
+        assert!(!super::manifest::Manifest::is_legacy(&path.join(MANIFEST_FILE_NAME)));
+        let manifest = package.manifest()?;
+        assert_eq!(manifest.metadata().id(), super::manifest::Id::from_legacy_name("My Mod"));
+        assert_eq!(manifest.metadata().version().to_string(), "0.3.1");
+        assert_eq!(manifest.iter_package_dependencies().count(), 1);
+
+        // Dependencies that aren't valid package names have no ID to refer to them by
+        fs::write(path.join(MANIFEST_FILE_NAME), legacy.replace("\"Base Mod\"", "\"Base/Mod\""))?;
+        let error = package.manifest().unwrap_err();
+        assert!(format!("{:#}", error).contains("cannot map dependency \"Base/Mod\" to a package ID"), "{:#}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn allowed_warnings_are_suppressed() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Allow", dir.path().join("package"))?;
+        let manifest_path = package.path().join("merlon.toml");
+        let manifest = fs::read_to_string(&manifest_path)?
+            .replace("keywords = []", "keywords = [\"bogus\"]");
+        fs::write(&manifest_path, &manifest)?;
+        let warnings = package.manifest()?.metadata().validate();
+        assert_eq!(warnings.iter().map(Warning::code).collect::<Vec<_>>(), vec![Code::InvalidKeyword]);
+        assert!(package.manifest()?.metadata().is_valid());
+
+        fs::write(&manifest_path, manifest.replace("[package]\n", "[package]\nallow = [\"W005\"]\n"))?;
+        assert!(package.manifest()?.metadata().validate().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn long_description_round_trips_and_is_validated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Detailed", dir.path().join("package"))?;
+        assert_eq!(package.manifest()?.metadata().long_description(), None);
+        assert!(!fs::read_to_string(package.path().join(MANIFEST_FILE_NAME))?.contains("long_description"));
+
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_long_description(Some("  Adds a new area.\n\nIt has secrets.\n".to_owned()));
+            Ok(())
+        })?;
+        let manifest = package.manifest()?;
+        assert_eq!(manifest.metadata().long_description(), Some("Adds a new area.\n\nIt has secrets."));
+        assert!(manifest.metadata().validate().is_empty());
+
+        let mut metadata = manifest.metadata().clone();
+        metadata.set_long_description(Some("a".repeat(super::manifest::MAX_LONG_DESCRIPTION_LEN + 1)));
+        let warnings = metadata.validate();
+        assert_eq!(warnings.iter().map(Warning::code).collect::<Vec<_>>(), vec![Code::LongLongDescription]);
+        assert!(metadata.is_valid());
+
+        metadata.set_long_description(Some("   ".to_owned()));
+        assert_eq!(metadata.long_description(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn links_round_trip_and_are_validated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Linked", dir.path().join("package"))?;
+        assert_eq!(package.manifest()?.metadata().repository(), None);
+        assert!(!fs::read_to_string(package.path().join(MANIFEST_FILE_NAME))?.contains("repository"));
+
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_repository(Some("https://github.com/example/linked".to_owned()));
+            manifest.metadata_mut().set_homepage(Some("http://example.com/linked".to_owned()));
+            Ok(())
+        })?;
+        let manifest = package.manifest()?;
+        assert_eq!(manifest.metadata().repository(), Some("https://github.com/example/linked"));
+        assert_eq!(manifest.metadata().homepage(), Some("http://example.com/linked"));
+        assert!(manifest.metadata().validate().is_empty());
+
+        let mut metadata = manifest.metadata().clone();
+        for url in ["github.com/example/linked", "git@github.com:example/linked.git", "file:///home/linked"] {
+            metadata.set_homepage(Some(url.to_owned()));
+            let warnings = metadata.validate();
+            assert_eq!(warnings.iter().map(Warning::code).collect::<Vec<_>>(), vec![Code::InvalidUrl], "{}", url);
+            assert!(warnings[0].message().starts_with("homepage"), "{}", warnings[0]);
+            assert!(!metadata.is_valid());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn version_is_validated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Versioned", dir.path().join("package"))?;
+        let mut metadata = package.manifest()?.metadata().clone();
+        let codes = |metadata: &super::manifest::Metadata| {
+            metadata.validate().iter().map(Warning::code).collect::<Vec<_>>()
+        };
+        assert_eq!(codes(&metadata), vec![]);
+
+        metadata.set_version("0.0.0".parse()?);
+        assert_eq!(codes(&metadata), vec![Code::InvalidVersion]);
+        metadata.set_version("0.0.3".parse()?);
+        assert_eq!(codes(&metadata), vec![Code::UnstableVersion]);
+        assert!(metadata.is_valid());
+        metadata.set_version("1.0.0-rc-1.2".parse()?);
+        assert_eq!(codes(&metadata), vec![]);
+
+        // Pre-release identifiers must be ASCII alphanumerics and hyphens
+        let manifest_path = package.path().join(MANIFEST_FILE_NAME);
+        let manifest = fs::read_to_string(&manifest_path)?;
+        fs::write(&manifest_path, manifest.replace("version = \"0.1.0\"", "version = \"1.0.0-beta_1\""))?;
+        assert!(package.manifest().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn required_merlon_version_is_checked() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Versioned", dir.path().join("package"))?;
+        let manifest_path = package.path().join("merlon.toml");
+        let manifest = fs::read_to_string(&manifest_path)?;
+
+        let with_requirement = |req: &str| manifest.replace("[package]\n", &format!("[package]\nrequired_merlon_version = \"{}\"\n", req));
+        fs::write(&manifest_path, with_requirement(&format!("<={}", env!("CARGO_PKG_VERSION"))))?;
+        package.manifest()?.metadata().check_merlon_version()?;
+
+        fs::write(&manifest_path, with_requirement(">=999.0.0"))?;
+        let error = package.manifest()?.metadata().check_merlon_version().unwrap_err();
+        assert!(error.to_string().contains("requires Merlon >=999.0.0"));
+
+        fs::write(&manifest_path, with_requirement("not a version"))?;
+        assert!(package.manifest().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bump_version_follows_semver() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Bumpy", dir.path().join("package"))?;
+        let mut metadata = package.manifest()?.metadata().clone();
+        let mut bump = |version: &str, bump: VersionBump| {
+            metadata.set_version(version.parse().unwrap());
+            metadata.bump_version(bump).to_string()
+        };
+        assert_eq!(bump("1.2.3", VersionBump::Major), "2.0.0");
+        assert_eq!(bump("1.2.3", VersionBump::Minor), "1.3.0");
+        assert_eq!(bump("1.2.3+build", VersionBump::Patch), "1.2.4");
+        assert_eq!(bump("2.0.0-rc1", VersionBump::Major), "2.0.0");
+        assert_eq!(bump("1.3.0-rc1", VersionBump::Major), "2.0.0");
+        assert_eq!(bump("1.3.0-rc1", VersionBump::Minor), "1.3.0");
+        assert_eq!(bump("1.2.4-rc1", VersionBump::Minor), "1.3.0");
+        assert_eq!(bump("1.2.4-rc1", VersionBump::Patch), "1.2.4");
+        assert_eq!(metadata.bump_patch(), super::manifest::Version::new(1, 2, 5));
+        assert_eq!(metadata.bump_minor(), super::manifest::Version::new(1, 3, 0));
+        assert_eq!(metadata.bump_major(), super::manifest::Version::new(2, 0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn dependents_are_found_next_to_the_package() -> Result<()> {
+        let dir = TempDir::new()?;
+        let base = Package::new("Base", dir.path().join("base"))?;
+        let dependent = Package::new("Dependent", dir.path().join("dependent"))?;
+        dependent.edit_manifest(|manifest| {
+            manifest.declare_direct_dependency(super::manifest::Dependency::from(base.manifest()?.metadata()))
+        })?;
+        Package::new("Unrelated", dir.path().join("unrelated"))?;
+        fs::create_dir(dir.path().join("not-a-package"))?;
+
+        assert!(base.dependents_incompatible_with(&"0.1.1".parse()?)?.is_empty());
+        let dependents = base.dependents_incompatible_with(&"0.2.0".parse()?)?;
+        assert_eq!(dependents.iter().map(Package::id).collect::<Result<Vec<_>>>()?, vec![dependent.id()?]);
+
+        // A dependency of an initialised package
+        let root = Package::new("Root", dir.path().join("root"))?;
+        let dependencies_dir = root.path().join(super::init::DEPENDENCIES_DIR_NAME);
+        fs::create_dir_all(&dependencies_dir)?;
+        let dependency = Package::new("Dependency", dependencies_dir.join("dependency"))?;
+        root.edit_manifest(|manifest| {
+            manifest.declare_direct_dependency(super::manifest::Dependency::from(dependency.manifest()?.metadata()))
+        })?;
+        let dependents = dependency.dependents_incompatible_with(&"1.0.0".parse()?)?;
+        assert_eq!(dependents.iter().map(Package::id).collect::<Result<Vec<_>>>()?, vec![root.id()?]);
+        Ok(())
+    }
+
+    #[test]
+    fn patches_round_trip_authorship() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let headers = |package: &Package| -> Result<Vec<String>> {
+            let mut headers = Vec::new();
+            for path in package.patch_files()? {
+                headers.extend(fs::read_to_string(path)?
+                    .lines()
+                    .filter(|line| ["From: ", "Date: ", "Subject: "].iter().any(|prefix| line.starts_with(prefix)))
+                    .map(ToOwned::to_owned));
+            }
+            Ok(headers)
+        };
+        let export = |package: &Package, output: &str| -> Result<Package> {
+            let distributable = package.export_distributable(ExportOptions {
+                output: Some(dir.path().join(format!("{}.merlon", output))),
+                baserom: Some(baserom.clone()),
+                if_exists: Default::default(),
+                dry_run: false,
+                exclude_patches: vec![],
+                verify_reproducible: false,
+                patch_context: None,
+                split_size: None,
+            })?;
+            distributable.open_to_dir(OpenOptions {
+                output: Some(dir.path().join(output)),
+                baserom: baserom.clone(),
+            })
+        };
+
+        // The author's decomp repo
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+        git(&upstream, &["checkout", "--quiet", "-b", "feature"])?;
+        fs::create_dir(upstream.join("src"))?;
+        fs::write(upstream.join("src/two.c"), "int two = 2;\n")?;
+        git(&upstream, &["add", "src/two.c"])?;
+        git(&upstream, &[
+            "commit", "--quiet", "-m", "[ui] say two",
+            "--author", "Original Author <original@example.com>", "--date", "2020-01-02T03:04:05+00:00",
+        ])?;
+        git(&upstream, &["checkout", "--quiet", "main"])?;
+        let package = Package::new("Round Trip", dir.path().join("package"))?;
+        let patches_dir = package.path().join(PATCHES_DIR_NAME).canonicalize()?;
+        git(&upstream, &[
+            "format-patch", "--quiet", "--keep-subject", "main..feature", "-o", &patches_dir.to_string_lossy(),
+        ])?;
+
+        // Someone else opens the distributable, syncs it so the patches are applied with `git am`, and exports it
+        let opened = export(&package, "opened")?;
+        let initialised = clone_upstream(&opened, &upstream)?;
+        initialised.sync_repo()?;
+        initialised.update_patches_dir()?;
+        let reopened = export(&opened, "reopened")?;
+
+        let original_headers = headers(&package)?;
+        assert_eq!(original_headers.len(), 3);
+        assert!(original_headers.contains(&"Subject: [ui] say two".to_owned()), "{:?}", original_headers);
+        assert_eq!(headers(&reopened)?, original_headers);
+        Ok(())
+    }
+
+    #[test]
+    fn migrated_patches_apply_after_directory_rename() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        // A decomp with a package patch on top
+        let decomp = dir.path().join("decomp");
+        fs::create_dir_all(decomp.join("src/world"))?;
+        git(&decomp, &["init", "--quiet"])?;
+        fs::write(decomp.join("src/world/area.c"), "a\nb\nc\nd\ne\nf\ng\n")?;
+        git(&decomp, &["add", "."])?;
+        git(&decomp, &["commit", "--quiet", "-m", "initial"])?;
+        git(&decomp, &["tag", "base"])?;
+        fs::write(decomp.join("src/world/area.c"), "a\nb\nc\nD\ne\nf\ng\n")?;
+        git(&decomp, &["commit", "--quiet", "-am", "capitalise d"])?;
+        let package = Package::new("Migrating", dir.path().join("package"))?;
+        let patches_dir = package.path().join("patches");
+        git(&decomp, &[
+            "format-patch", "--quiet", "--keep-subject", "base..HEAD", "-o", &patches_dir.to_string_lossy(),
+        ])?;
+
+        // A later decomp revision that has moved the directory. Its history isn't available, so `git am --3way`
+        // can't follow the rename by itself.
+        let restructured = dir.path().join("restructured");
+        fs::create_dir_all(restructured.join("src/areas"))?;
+        git(&restructured, &["init", "--quiet"])?;
+        fs::write(restructured.join("src/areas/area.c"), "a\nb\nc\nd\ne\nf\ng\nh\n")?;
+        git(&restructured, &["add", "."])?;
+        git(&restructured, &["commit", "--quiet", "-m", "restructured"])?;
+        assert!(!super::git_am(&package.patch_files()?, &restructured)?.success());
+        git(&restructured, &["am", "--abort"])?;
+
+        let renames = HashMap::from([("src/world/".to_owned(), "src/areas/".to_owned())]);
+        assert_eq!(package.migrate_patches(renames.clone())?, 1);
+        assert_eq!(package.patches()?[0].files, vec!["src/areas/area.c"]);
+        assert!(super::git_am(&package.patch_files()?, &restructured)?.success());
+        assert_eq!(fs::read_to_string(restructured.join("src/areas/area.c"))?, "a\nb\nc\nD\ne\nf\ng\nh\n");
+
+        // Already migrated
+        assert_eq!(package.migrate_patches(renames)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn regions_default_to_us() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Regional", dir.path().join("package"))?;
+        assert_eq!(package.manifest()?.metadata().regions(), vec![Region::Us]);
+        assert_eq!(package.manifest()?.metadata().region(), Region::Us);
+        assert!(!fs::read_to_string(package.path().join(MANIFEST_FILE_NAME))?.contains("regions"));
+
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_regions(vec![Region::Us, Region::Jp]);
+            Ok(())
+        })?;
+        assert!(fs::read_to_string(package.path().join(MANIFEST_FILE_NAME))?.contains(r#""jp""#));
+        assert_eq!(package.manifest()?.metadata().regions(), vec![Region::Us, Region::Jp]);
+
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_regions(vec![Region::Jp, Region::Us]);
+            Ok(())
+        })?;
+        assert_eq!(package.manifest()?.metadata().region(), Region::Jp);
+        Ok(())
+    }
+
+    #[test]
+    fn commit_convention_is_checked() -> Result<()> {
+        let dir = TempDir::new()?;
+        let template = Template::find("skip-intro").unwrap();
+        let package = Package::new_from_template("Conventional", dir.path().join("package"), template)?;
+        assert!(package.check_commit_subjects()?.is_empty());
+
+        package.edit_manifest(|manifest| {
+            manifest.set_commit_convention(Some(CommitConvention {
+                prefixes: vec!["feat: ".to_owned(), "fix: ".to_owned()],
+                max_length: Some(10),
+            }));
+            Ok(())
+        })?;
+        let warnings = package.check_commit_subjects()?;
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().all(|warning| warning.code() == Code::CommitSubject));
+        Ok(())
+    }
+
+    #[test]
+    fn crlf_patch_applies_to_lf_repo() -> Result<()> {
+        let dir = TempDir::new()?;
+
+        // Repo with LF line endings, configured such that git am would normally keep CRs
+        let repo = dir.path().join("repo");
+        fs::create_dir(&repo)?;
+        git(&repo, &["init", "--quiet"])?;
+        git(&repo, &["config", "core.autocrlf", "false"])?;
+        git(&repo, &["config", "am.keepcr", "true"])?;
+        fs::write(repo.join("hello.txt"), "one\ntwo\nthree\n")?;
+        git(&repo, &["add", "hello.txt"])?;
+        git(&repo, &["commit", "--quiet", "-m", "initial"])?;
+
+        // Package with a patch authored on Windows
+        let package = Package::new("CRLF", dir.path().join("package"))?;
+        let patch = concat!(
+            "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001\n",
+            "From: Merlon test <merlontest@nanaian.town>\n",
+            "Date: Wed, 26 Apr 2023 22:40:19 +0100\n",
+            "Subject: translate two\n",
+            "\n",
+            "---\n",
+            " hello.txt | 2 +-\n",
+            " 1 file changed, 1 insertion(+), 1 deletion(-)\n",
+            "\n",
+            "diff --git a/hello.txt b/hello.txt\n",
+            "--- a/hello.txt\n",
+            "+++ b/hello.txt\n",
+            "@@ -1,3 +1,3 @@\n",
+            " one\n",
+            "-two\n",
+            "+deux\n",
+            " three\n",
+            "-- \n",
+            "2.39.0\n",
+        ).replace('\n', "\r\n");
+        fs::write(package.path().join("patches/0001-translate-two.patch"), patch)?;
+
+        package.apply_patches_to_decomp_repo(&repo)?;
+
+        assert_eq!(git(&repo, &["log", "-1", "--pretty=format:%s"])?, "translate two");
+        assert_eq!(fs::read_to_string(repo.join("hello.txt"))?, "one\ndeux\nthree\n");
+        assert!(git(&repo, &["status", "--porcelain"])?.is_empty());
+        Ok(())
+    }
+}
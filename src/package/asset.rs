@@ -0,0 +1,169 @@
+//! Validation of custom assets.
+//!
+//! The decomp expects each category of asset in a specific format, and a malformed asset otherwise only fails deep
+//! inside splat or the build. Assets are checked by reading their headers, so problems can be reported before
+//! building.
+//!
+//! Custom assets live in `papermario/assets/<package_id>/`, in the same layout as the extracted assets in
+//! `papermario/assets/us/`. For example, sprites are PNGs under `sprite/`.
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use pyo3::prelude::*;
+
+use super::warning::{Warning, Code};
+
+/// The signature that every PNG file starts with.
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG color type of images that use a palette.
+const PNG_COLOR_TYPE_INDEXED: u8 = 3;
+
+/// A kind of asset with a format that the decomp expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[pyclass(module = "merlon.package.asset")]
+pub enum AssetCategory {
+    /// A sprite raster. Must be a color-indexed PNG (CI4 or CI8) with power-of-two dimensions.
+    Sprite,
+}
+
+impl AssetCategory {
+    /// Returns the category of the asset at the given path, relative to an assets directory, or `None` if the
+    /// asset isn't one that can be validated.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let is_png = path.extension().map(|ext| ext.eq_ignore_ascii_case("png")).unwrap_or(false);
+        match path.components().next()?.as_os_str().to_str()? {
+            "sprite" if is_png => Some(AssetCategory::Sprite),
+            _ => None,
+        }
+    }
+
+    /// Checks the asset at the given path against the format expected for this category, returning a warning for
+    /// each problem found.
+    pub fn validate(&self, path: &Path) -> Result<Vec<Warning>> {
+        match self {
+            AssetCategory::Sprite => validate_sprite(path),
+        }
+    }
+}
+
+/// Validates every asset in an assets directory that has a known category, recursively.
+pub fn validate_assets_in(dir: &Path) -> Result<Vec<Warning>> {
+    let mut warnings = Vec::new();
+    for path in files_in(dir)? {
+        let relative = path.strip_prefix(dir)?;
+        if let Some(category) = AssetCategory::from_path(relative) {
+            warnings.extend(category.validate(&path)?);
+        }
+    }
+    Ok(warnings)
+}
+
+fn files_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(files_in(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// The parts of a PNG header that matter for validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PngHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl PngHeader {
+    /// Reads the signature and IHDR chunk of a PNG file. Returns `None` if the file isn't a PNG.
+    fn read_from_path(path: &Path) -> Result<Option<Self>> {
+        // Signature (8), IHDR length (4), "IHDR" (4), width (4), height (4), bit depth (1), color type (1)
+        let mut header = [0; 26];
+        let mut file = fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        if file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        if &header[0..8] != PNG_SIGNATURE || &header[12..16] != b"IHDR" {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            width: u32::from_be_bytes(header[16..20].try_into()?),
+            height: u32::from_be_bytes(header[20..24].try_into()?),
+            bit_depth: header[24],
+            color_type: header[25],
+        }))
+    }
+}
+
+fn validate_sprite(path: &Path) -> Result<Vec<Warning>> {
+    let invalid = |problem: String| Warning::new(Code::InvalidAsset, format!("sprite {} {}", path.display(), problem));
+
+    let Some(header) = PngHeader::read_from_path(path)? else {
+        return Ok(vec![invalid("is not a PNG file; re-export it as a PNG".to_owned())]);
+    };
+    let mut warnings = Vec::new();
+    if !header.width.is_power_of_two() || !header.height.is_power_of_two() {
+        warnings.push(invalid(format!(
+            "is {}x{}, but sprite dimensions must be powers of two (e.g. 32x64); resize or pad the image",
+            header.width, header.height,
+        )));
+    }
+    if header.color_type != PNG_COLOR_TYPE_INDEXED || !matches!(header.bit_depth, 4 | 8) {
+        warnings.push(invalid(
+            "must use an indexed color palette of 16 or 256 colors (CI4 or CI8); convert it in your image editor"
+                .to_owned(),
+        ));
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn png(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        bytes.extend(13u32.to_be_bytes());
+        bytes.extend(b"IHDR");
+        bytes.extend(width.to_be_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes.extend([bit_depth, color_type, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn sprites_are_validated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let sprite_dir = dir.path().join("sprite/npc");
+        fs::create_dir_all(&sprite_dir)?;
+        fs::write(sprite_dir.join("good.png"), png(32, 64, 4, PNG_COLOR_TYPE_INDEXED))?;
+        fs::write(sprite_dir.join("odd.png"), png(30, 64, 8, PNG_COLOR_TYPE_INDEXED))?;
+        fs::write(sprite_dir.join("rgba.png"), png(32, 32, 8, 6))?;
+        fs::write(sprite_dir.join("jpeg.png"), b"\xff\xd8\xff\xe0 not a png")?;
+        fs::write(dir.path().join("readme.png"), b"not a sprite, so not checked")?;
+
+        let warnings = validate_assets_in(dir.path())?;
+        let messages: Vec<_> = warnings.iter().map(Warning::message).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("jpeg.png is not a PNG"));
+        assert!(messages[1].contains("odd.png is 30x64"));
+        assert!(messages[2].contains("rgba.png must use an indexed color palette"));
+        assert!(warnings.iter().all(Warning::is_error));
+        Ok(())
+    }
+}
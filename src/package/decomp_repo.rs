@@ -0,0 +1,93 @@
+//! Existing clones of the decomp on this machine, which new clones can share objects with rather than downloading
+//! the whole history again. See [`InitialiseOptions::reference`](super::init::InitialiseOptions::reference).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use anyhow::{Result, bail};
+
+use super::init::SUBREPO_DIR_NAME;
+
+/// A file that every revision of the decomp has, used to tell decomp clones from other git repositories.
+const DECOMP_MARKER_FILE: &str = "ver/us/splat.yaml";
+
+/// A clone of the decomp repository on this machine, such as the `papermario` directory of an initialised package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalDecompRepo {
+    path: PathBuf,
+}
+
+impl LocalDecompRepo {
+    /// Checks that `path` is a git clone of the decomp.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        if !path.join(DECOMP_MARKER_FILE).is_file() {
+            bail!("{} is not a clone of the decomp, it has no {}", path.display(), DECOMP_MARKER_FILE);
+        }
+        // Not just inside one, e.g. a package that is itself a git repository
+        let output = Command::new("git")
+            .arg("rev-parse")
+            .arg("--show-toplevel")
+            .current_dir(&path)
+            .stderr(Stdio::null())
+            .output()?;
+        let toplevel = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+        if !output.status.success() || toplevel.canonicalize().ok() != path.canonicalize().ok() {
+            bail!("{} is not a git repository", path.display());
+        }
+        Ok(Self { path })
+    }
+
+    /// Looks for a decomp clone in a package next to `package_path`, as there is when several packages are kept in
+    /// the same directory.
+    pub fn find_near(package_path: &Path) -> Option<Self> {
+        let parent = package_path.parent()?;
+        let mut siblings: Vec<PathBuf> = fs::read_dir(parent).ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path != package_path && path.is_dir())
+            .collect();
+        siblings.sort();
+        siblings.into_iter().find_map(|sibling| Self::new(sibling.join(SUBREPO_DIR_NAME)).ok())
+    }
+
+    /// The path of the clone.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{create_dir, create_dir_all, write};
+    use temp_dir::TempDir;
+
+    use super::*;
+    use crate::package::test::git;
+
+    #[test]
+    fn only_decomp_clones_are_found() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package_path = dir.path().join("new-mod");
+        create_dir(&package_path)?;
+        assert_eq!(LocalDecompRepo::find_near(&package_path), None);
+
+        // Has the marker file, but isn't a git repository
+        let not_git = dir.path().join("a-mod").join(SUBREPO_DIR_NAME);
+        create_dir_all(not_git.join("ver/us"))?;
+        write(not_git.join(DECOMP_MARKER_FILE), "")?;
+        assert!(LocalDecompRepo::new(not_git).is_err());
+
+        // A git repository, but not of the decomp
+        let not_decomp = dir.path().join("b-mod").join(SUBREPO_DIR_NAME);
+        create_dir_all(&not_decomp)?;
+        git(&not_decomp, &["init", "--quiet"])?;
+        assert!(LocalDecompRepo::new(not_decomp.clone()).is_err());
+        assert_eq!(LocalDecompRepo::find_near(&package_path), None);
+
+        create_dir_all(not_decomp.join("ver/us"))?;
+        write(not_decomp.join(DECOMP_MARKER_FILE), "")?;
+        let found = LocalDecompRepo::find_near(&package_path).unwrap();
+        assert_eq!(found.path(), not_decomp);
+        Ok(())
+    }
+}
@@ -10,38 +10,101 @@
 //!  │   └── 0003-Add-baz.patch
 //!  ├── merlon.toml             - Manifest
 //!  ├── LICENSE                 - License
+//!  ├── ATTRIBUTION.md          - Authors of the package and its dependencies
 //!  └── README.md               - Documentation
 //!
+//! Distributables start with a header of [`HEADER_MAGIC`] followed by a single [`FormatVersion`] byte, so that files
+//! exported by older versions of Merlon can still be recognised and opened. See [`FormatVersion`] for the formats.
+//!
 
 use std::path::{Path, PathBuf};
 use std::fmt;
 use std::fs;
+use std::io::{Read, Write};
 use std::process::{Command, Stdio};
 use clap::Parser;
 use anyhow::{Result, bail, Context};
 use temp_dir::TempDir;
 use pyo3::prelude::*;
 
-use crate::package::InitialisedPackage;
-use crate::rom::Rom;
+use crate::package::{InitialisedPackage, Registry, Id};
+use crate::package::manifest::{PatchContext, Region};
+use crate::package::PatchInfo;
+use crate::baserom;
+use crate::Error;
+use crate::rom::{self, Rom, OverwritePolicy};
 
-use super::init::{InitialiseOptions, BuildRomOptions};
+use super::init::{InitialiseOptions, BuildRomOptions, AddDependencyOptions};
 use super::{
     Package,
     ROOT_DIR_NAME,
     MANIFEST_FILE_NAME,
     README_FILE_NAME,
     LICENSE_FILE_NAME,
+    CHANGELOG_FILE_NAME,
     PATCHES_DIR_NAME, Manifest,
+    check_patches_apply,
 };
 
+pub mod build_manifest;
+pub use build_manifest::BuildManifest;
+use build_manifest::BUILD_MANIFEST_FILE_NAME;
+pub mod volumes;
+
 const EXTENSION: &str = "merlon";
+const ATTRIBUTION_FILE_NAME: &str = "ATTRIBUTION.md";
+
+/// The bytes that versioned distributables start with, before the [`FormatVersion`] byte.
+pub const HEADER_MAGIC: &[u8; 6] = b"MERLON";
+
+/// The bytes that files encrypted by `openssl enc -salt` start with. Distributables from before there was a header
+/// start with these instead of [`HEADER_MAGIC`].
+const OPENSSL_SALT_MAGIC: &[u8; 8] = b"Salted__";
+
+/// A version of the distributable file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[pyclass(module = "merlon.package.distribute")]
+pub enum FormatVersion {
+    /// A bzip2 tar with the package files in `merlon_v1/`, encrypted with aes-256-cbc and PBKDF2 keyed on the
+    /// baserom. There is no header.
+    V1 = 1,
+    /// The same as [`FormatVersion::V1`], after a header giving the format version.
+    V2 = 2,
+}
+
+impl FormatVersion {
+    /// The format that distributables are exported in.
+    pub const CURRENT: FormatVersion = FormatVersion::V2;
+
+    /// Returns the format version with the given header byte, if it is one that has a header.
+    fn from_header_byte(byte: u8) -> Option<Self> {
+        match byte {
+            2 => Some(FormatVersion::V2),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatVersion::V1 => f.write_str("v1"),
+            FormatVersion::V2 => f.write_str("v2"),
+        }
+    }
+}
 
 /// A package in the form of a distributable file.
 #[derive(Debug)]
 #[pyclass(module = "merlon.package.distribute")]
 pub struct Distributable {
     path: PathBuf,
+    /// The format version, from the header if the file has one.
+    format_version: FormatVersion,
+    /// The temporary directory containing the file, if the distributable owns it. See [`Distributable::read_from`].
+    temp_dir: Option<TempDir>,
+    /// The index of the volumes that the file was joined from, if the distributable was split. See [`volumes`].
+    volume_index: Option<PathBuf>,
 }
 
 /// Options for [`Package::export_distributable`].
@@ -52,23 +115,93 @@ pub struct ExportOptions {
     ///
     /// If not specified, the default is `NAME VERSION.merlon`, where `NAME` is the name of the package
     /// and `VERSION` is the package version as specified in `merlon.toml`.
+    ///
+    /// On the command line, `-` writes the distributable to stdout. See [`Package::write_distributable`]. This can't
+    /// be combined with `--verify-reproducible`, since building writes to stdout.
     #[arg(short, long)]
     #[pyo3(get, set)]
     pub output: Option<PathBuf>,
 
     /// The base ROM to use as the encryption key.
     ///
-    /// If not specified and the package is initialised, `papermario/ver/us/baserom.z64` will be used.
+    /// If not specified and the package is initialised, the base ROM of the package's region in `papermario/ver/` will
+    /// be used.
     #[arg(long)]
     #[pyo3(get, set)]
     pub baserom: Option<PathBuf>,
+
+    /// What to do if a file already exists at the output path.
+    #[arg(long, value_enum, default_value_t)]
+    #[pyo3(get, set)]
+    pub if_exists: OverwritePolicy,
+
+    /// Show what would be exported without writing the distributable.
+    ///
+    /// Use [`Package::preview_export`] for this rather than [`Package::export_distributable`].
+    #[arg(long)]
+    #[pyo3(get, set)]
+    pub dry_run: bool,
+
+    /// Leave out patches, e.g. work in progress. Matches a patch's file name or part of its commit subject.
+    ///
+    /// If the package is initialised, the remaining patches are checked to still apply without the excluded ones.
+    #[arg(long = "exclude-patch", value_name = "PATCH")]
+    #[pyo3(get, set)]
+    pub exclude_patches: Vec<String>,
+
+    /// Check that building the patches on a fresh clone of the decomp gives the same ROM as the package's decomp
+    /// repo, so that no uncommitted changes are missing from the distributable. Requires the package to be
+    /// initialised, and builds the ROM twice.
+    ///
+    /// The SHA1 of the ROM is then embedded in the distributable as a [`BuildManifest`], so that people applying it
+    /// are warned if their build differs.
+    #[arg(long)]
+    #[pyo3(get, set)]
+    pub verify_reproducible: bool,
+
+    /// How much context to write patches with: `minimal`, `function`, or `lines=N`.
+    ///
+    /// The choice is recorded in `merlon.toml`, so later syncs of the patches directory use it too. If the package
+    /// is initialised, the patches directory is rewritten with it before exporting.
+    #[arg(long, value_name = "MODE")]
+    #[pyo3(get, set)]
+    pub patch_context: Option<PatchContext>,
+
+    /// Split the distributable into numbered volumes of at most this many bytes if it is larger, e.g. for upload
+    /// limits. The volumes are written as `OUTPUT.001`, `OUTPUT.002`, and so on, with an index `OUTPUT.volumes`.
+    ///
+    /// Opening or applying any of these joins the volumes back together. See [`volumes`].
+    #[arg(long, value_name = "BYTES")]
+    #[pyo3(get, set)]
+    pub split_size: Option<u64>,
+}
+
+/// What [`Package::export_distributable`] would export. See [`Package::preview_export`].
+#[derive(Debug, Clone)]
+#[pyclass(module = "merlon.package.distribute")]
+pub struct ExportPreview {
+    /// The patches that would be included, in application order.
+    #[pyo3(get)]
+    pub patches: Vec<PatchInfo>,
+
+    /// The resolved dependencies, e.g. `Foo 1.2.0` or `decomp 7a9df94`, in patch order.
+    #[pyo3(get)]
+    pub dependencies: Vec<String>,
+
+    /// Total size in bytes of the files that would be packed, before compression.
+    #[pyo3(get)]
+    pub size: u64,
+
+    /// Whether the decomp repo has uncommitted changes, which would not be exported.
+    #[pyo3(get)]
+    pub uncommitted_changes: bool,
 }
 
 /// Options for [`Distributable::apply`].
 #[derive(Parser, Debug, Clone)]
 #[pyclass(module = "merlon.package.distribute")]
 pub struct ApplyOptions {
-    /// The base ROM path.
+    /// The base ROM path. This may also be a `.zip` or `.gz` archive containing the ROM.
     #[arg(long)]
     #[pyo3(get, set)]
     pub baserom: PathBuf,
@@ -91,7 +224,7 @@ pub struct OpenOptions {
     #[pyo3(get, set)]
     pub output: Option<PathBuf>,
 
-    /// The base ROM path.
+    /// The base ROM path. This may also be a `.zip` or `.gz` archive containing the ROM.
     #[arg(long)]
     #[pyo3(get, set)]
     pub baserom: PathBuf,
@@ -101,30 +234,85 @@ pub struct OpenOptions {
 impl Package {
     /// Exports the package as a distributable `.merlon` file.
     pub fn export_distributable(&self, options: ExportOptions) -> Result<Distributable> {
-        let baserom_path = match options.baserom {
-            Some(baserom) => baserom,
+        if options.dry_run {
+            bail!("dry run requested, use preview_export instead");
+        }
+        let baserom = self.export_baserom(&options)?;
+
+        let output_path = match &options.output {
+            Some(output) => output.clone(),
+            None => {
+                let manifest = self.manifest()?;
+                let metadata = manifest.metadata();
+                PathBuf::from(format!("{} {}.{}", metadata.name(), metadata.version(), EXTENSION))
+            },
+        };
+        let output_path = Distributable::extension_normalized(&output_path, options.if_exists)?;
+
+        // Compress, encrypt using baserom as hash, and write to output
+        let tempdir = self.stage_export(&options)?;
+        rom::write_atomically(&output_path, |temp_path| {
+            let mut file = fs::File::create(temp_path)
+                .with_context(|| format!("failed to create {}", temp_path.display()))?;
+            pack(tempdir.path(), &mut file, baserom.path())
+        })?;
+        if let Some(split_size) = options.split_size {
+            volumes::split(&output_path, split_size)?;
+        }
+
+        Distributable::try_from(output_path)
+    }
+}
+
+impl Package {
+    /// Exports the package like [`Package::export_distributable`], but writes the distributable to `sink` instead
+    /// of a file, e.g. to stdout. `options.output`, `options.if_exists`, and `options.split_size` are ignored.
+    pub fn write_distributable<W: Write>(&self, options: ExportOptions, sink: &mut W) -> Result<()> {
+        if options.dry_run {
+            bail!("dry run requested, use preview_export instead");
+        }
+        let baserom = self.export_baserom(&options)?;
+        let tempdir = self.stage_export(&options)?;
+        pack(tempdir.path(), sink, baserom.path())
+    }
+
+    /// Opens the base ROM to use as the encryption key, converted to `.z64` so that the key is the same whichever
+    /// byte order the ROM was dumped in.
+    fn export_baserom(&self, options: &ExportOptions) -> Result<Rom> {
+        let baserom_path = match &options.baserom {
+            Some(baserom) => baserom.clone(),
             None => {
                 InitialisedPackage::try_from(self.clone())
                     .context("failed to get baserom from package, try specifying --baserom or running `merlon init`")?
-                    .baserom_path()
+                    .baserom_path()?
             },
         };
         if !baserom_path.is_file() {
-            bail!("baserom {:?} is not a file", baserom_path);
+            bail!(Error::Baserom(format!("baserom {:?} is not a file", baserom_path)));
         }
+        baserom::open(&baserom_path)
+    }
 
-        let output_path = match options.output {
-            Some(output) => output,
-            None => {
-                let manifest = self.manifest()?;
-                let metadata = manifest.metadata();
-                PathBuf::from(format!("{} {}.merlon", metadata.name(), metadata.version()))
-            },
+    /// Copies the files to export into `merlon_v1/` in a temporary directory, ready for [`pack`].
+    fn stage_export(&self, options: &ExportOptions) -> Result<TempDir> {
+        if let Some(patch_context) = options.patch_context {
+            self.set_patch_context(patch_context)?;
+        }
+        let build_manifest = if options.verify_reproducible {
+            let initialised = InitialisedPackage::try_from(self.clone())
+                .context("package must be initialised to verify that it builds reproducibly")?;
+            let rom_sha1 = initialised.verify_reproducible_build()?;
+            Some(BuildManifest {
+                region: initialised.region()?,
+                decomp_rev: self.manifest()?.get_direct_decomp_dependency_rev().map(str::to_owned),
+                closure_hash: initialised.registry().closure_hash(self.id()?)?,
+                rom_sha1,
+            })
+        } else {
+            None
         };
 
         let tempdir = temp_dir::TempDir::new()?;
-        let tar_path = tempdir.path().join("package.tar.bz2");
-        let encrypted_path = tempdir.path().join("package.merlon");
 
         // TODO: include a binary patch for the baserom so basic users dont have to build from source
 
@@ -134,48 +322,136 @@ impl Package {
         fs::copy(&self.path.join(MANIFEST_FILE_NAME), &root_dir.join(MANIFEST_FILE_NAME))?;
         fs::copy(&self.path.join(README_FILE_NAME), &root_dir.join(README_FILE_NAME))?;
         fs::copy(&self.path.join(LICENSE_FILE_NAME), &root_dir.join(LICENSE_FILE_NAME))?;
+        if self.path.join(CHANGELOG_FILE_NAME).is_file() {
+            fs::copy(self.path.join(CHANGELOG_FILE_NAME), root_dir.join(CHANGELOG_FILE_NAME))?;
+        }
+        fs::write(root_dir.join(ATTRIBUTION_FILE_NAME), self.generate_attribution()?)?;
+        if let Some(build_manifest) = build_manifest {
+            build_manifest.write_to_dir(&root_dir)?;
+        }
         fs::create_dir(&root_dir.join(PATCHES_DIR_NAME))?;
+        let (kept, excluded) = exclude_patches(self.patch_files()?, &options.exclude_patches)?;
+        if !excluded.is_empty() {
+            self.check_remaining_patches_apply(&kept)?;
+        }
         for entry in fs::read_dir(&self.path.join(PATCHES_DIR_NAME))? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_file() {
+            if path.is_file() && !excluded.iter().any(|excluded| excluded.file_name() == path.file_name()) {
                 fs::copy(&path, &root_dir.join(PATCHES_DIR_NAME).join(path.file_name().unwrap()))?;
             }
         }
 
-        // Compress directory into a tar
-        let status = Command::new("tar")
-            .arg("--no-xattrs") // Avoid com.apple.provenance
-            .arg("-cjvf")
-            .arg(&tar_path)
-            .arg("-C").arg(tempdir.path())
-            .arg(ROOT_DIR_NAME)
-            .stderr(Stdio::null())
-            .status()?;
-        if !status.success() {
-            bail!("failed to compress to tar {}", tar_path.display());
-        }
-
-        // Encrypt the tar using baserom as hash
-        let status = Command::new("openssl")
-            .arg("enc")
-            .arg("-aes-256-cbc")
-            .arg("-md").arg("sha512")
-            .arg("-pbkdf2")
-            .arg("-iter").arg("100000")
-            .arg("-salt")
-            .arg("-in").arg(&tar_path)
-            .arg("-out").arg(&encrypted_path)
-            .arg("-pass").arg(format!("file:{}", baserom_path.display()))
-            .status()?;
-        if !status.success() {
-            bail!("failed to encrypt tar to {}", encrypted_path.display());
+        Ok(tempdir)
+    }
+}
+
+#[pymethods]
+impl Package {
+    /// Previews what [`Package::export_distributable`] would export, without writing anything to the package.
+    ///
+    /// If the package is initialised, patches are generated from the package branch's commits into a temporary
+    /// directory, so it needn't be synced first.
+    pub fn preview_export(&self) -> Result<ExportPreview> {
+        let tempdir = TempDir::new()?;
+        let manifest = self.manifest()?;
+        let mut dependencies = Vec::new();
+        let mut uncommitted_changes = false;
+
+        let patches_dir = if self.is_initialised()? {
+            let initialised = InitialisedPackage::try_from(self.clone())?;
+            let patches_dir = tempdir.path().join(PATCHES_DIR_NAME);
+            fs::create_dir(&patches_dir)?;
+            initialised.write_patches_to(&patches_dir)?;
+            uncommitted_changes = initialised.is_git_dirty()?;
+
+            let registry = initialised.registry();
+            for id in registry.calc_dependency_patch_order(initialised.package_id())? {
+                if id != initialised.package_id() {
+                    let metadata = registry.get_or_error(id)?.manifest()?.metadata().clone();
+                    dependencies.push(format!("{} {}", metadata.name(), metadata.version()));
+                }
+            }
+            patches_dir
+        } else {
+            for (id, version) in manifest.iter_package_dependencies() {
+                dependencies.push(format!("{} {}", id, version));
+            }
+            self.path.join(PATCHES_DIR_NAME)
+        };
+        if let Some(rev) = manifest.get_direct_decomp_dependency_rev() {
+            dependencies.push(format!("decomp {}", rev));
         }
 
-        // Copy encrypted tar to output
-        fs::copy(&encrypted_path, &output_path)?;
+        let mut size = 0;
+        for file in [MANIFEST_FILE_NAME, README_FILE_NAME, LICENSE_FILE_NAME] {
+            size += fs::metadata(self.path.join(file))?.len();
+        }
+        if let Ok(metadata) = fs::metadata(self.path.join(CHANGELOG_FILE_NAME)) {
+            size += metadata.len();
+        }
+        let mut patches = Vec::new();
+        if patches_dir.is_dir() {
+            for path in super::patch_files_in(&patches_dir)? {
+                size += fs::metadata(&path)?.len();
+                let mut patch = PatchInfo::read_from_path(path.clone())?;
+                // Point at where the patch will be, rather than the tempdir
+                patch.path = self.path.join(PATCHES_DIR_NAME).join(path.file_name().unwrap_or_default());
+                patches.push(patch);
+            }
+        }
 
-        Distributable::try_from(output_path)
+        Ok(ExportPreview {
+            patches,
+            dependencies,
+            size,
+            uncommitted_changes,
+        })
+    }
+}
+
+impl Package {
+    /// Credits the authors of this package and every package in its dependency tree, grouped by package.
+    fn generate_attribution(&self) -> Result<String> {
+        if self.is_initialised()? {
+            let initialised = InitialisedPackage::try_from(self.clone())?;
+            generate_attribution(initialised.registry(), initialised.package_id())
+        } else {
+            let mut registry = Registry::new();
+            let id = registry.register(self.clone())?;
+            generate_attribution(&registry, id)
+        }
+    }
+
+    /// Records the patch context in the manifest, and rewrites the patches with it if the package is initialised.
+    fn set_patch_context(&self, patch_context: PatchContext) -> Result<()> {
+        if self.manifest()?.metadata().patch_context() == patch_context {
+            return Ok(());
+        }
+        self.edit_manifest_unchecked(|manifest| {
+            manifest.metadata_mut().set_patch_context(patch_context);
+            Ok(())
+        })?;
+        if self.is_initialised()? {
+            InitialisedPackage::try_from(self.clone())?
+                .update_patches_dir()
+                .context("failed to rewrite patches with the new patch context")?;
+        } else {
+            log::warn!("package is not initialised, so patches will use the new context once they are next synced");
+        }
+        Ok(())
+    }
+
+    /// Checks that a subset of the package's patches applies without the others. This can only be checked if the
+    /// package is initialised, since the patches apply on top of the decomp and the package's dependencies.
+    fn check_remaining_patches_apply(&self, kept: &[PathBuf]) -> Result<()> {
+        if !self.is_initialised()? {
+            log::warn!("package is not initialised, so cannot check that the remaining patches apply");
+            return Ok(());
+        }
+        let initialised = InitialisedPackage::try_from(self.clone())?;
+        check_patches_apply(kept, &initialised.subrepo_path(), &initialised.patch_base_branch()?)
+            .context("the remaining patches depend on the excluded ones")
     }
 }
 
@@ -185,42 +461,31 @@ impl Distributable {
     pub fn open_to_dir(&self, options: OpenOptions) -> Result<Package> {
         let temp_dir = TempDir::new()
             .context("failed to create temporary directory")?;
-        let tar_path = temp_dir.path().join("package.tar.bz2");
-
-        // Check baserom exists
-        if !options.baserom.is_file() {
-            bail!("baserom {:?} is not a file", options.baserom);
-        }
-
-        // Decrypt tar using baserom as hash
-        let status = Command::new("openssl")
-            .arg("enc")
-            .arg("-d") // decrypt
-            .arg("-aes-256-cbc")
-            .arg("-md").arg("sha512")
-            .arg("-pbkdf2")
-            .arg("-iter").arg("100000")
-            .arg("-salt")
-            .arg("-in").arg(&self.path)
-            .arg("-out").arg(&tar_path)
-            .arg("-pass").arg(format!("file:{}", options.baserom.display()))
-            .status()
-            .context("failed run openssl")?;
-        if !status.success() {
-            bail!("failed to decrypt {}", self.path.display());
-        }
 
-        // Decompress tar into temp dir
-        let status = Command::new("tar")
-            .arg("-xjvf")
-            .arg(&tar_path)
-            .arg("-C").arg(&temp_dir.path())
-            .arg(ROOT_DIR_NAME)
-            .stderr(Stdio::null())
-            .status()
-            .context("failed run tar")?;
-        if !status.success() {
-            bail!("failed to decompress {}", tar_path.display());
+        // Check baserom exists, extracting it if it is in an archive
+        let baserom = baserom::open(&options.baserom)?;
+
+        if let Err(error) = self.unpack(temp_dir.path(), baserom.path()) {
+            // The most likely cause is a ROM that has already been patched, e.g. by applying another distributable
+            let region = baserom.header_region().ok().flatten().unwrap_or_default();
+            if let Some(expected_sha1) = baserom::expected_sha1(region) {
+                if !matches!(baserom.sha1_string(), Ok(sha1) if sha1 == expected_sha1) {
+                    return Err(error.context(Error::Baserom(format!(
+                        "{} is not an unmodified {}-release Paper Mario ROM. Distributables must be applied to a \
+                        clean base ROM, not one that already has mods applied; to combine several mods, apply them \
+                        together with `merlon apply <first.merlon> <second.merlon> ...`",
+                        options.baserom.display(),
+                        region.as_str().to_uppercase(),
+                    ))));
+                }
+            } else {
+                return Err(error.context(Error::Baserom(format!(
+                    "failed to open with {}-release base ROM {}; make sure the distributable was made for that region",
+                    region.as_str().to_uppercase(),
+                    options.baserom.display(),
+                ))));
+            }
+            return Err(error);
         }
 
         // Validate structure
@@ -268,6 +533,23 @@ impl Distributable {
             .context("failed to copy manifest")?;
         fs::copy(&root_dir.join(README_FILE_NAME), &output_dir.join(README_FILE_NAME))
             .context("failed to copy readme")?;
+        fs::copy(root_dir.join(LICENSE_FILE_NAME), output_dir.join(LICENSE_FILE_NAME))
+            .context("failed to copy license")?;
+        if root_dir.join(CHANGELOG_FILE_NAME).is_file() {
+            // Changelogs are optional
+            fs::copy(root_dir.join(CHANGELOG_FILE_NAME), output_dir.join(CHANGELOG_FILE_NAME))
+                .context("failed to copy changelog")?;
+        }
+        if root_dir.join(ATTRIBUTION_FILE_NAME).is_file() {
+            // Not present in distributables exported by older versions of Merlon
+            fs::copy(root_dir.join(ATTRIBUTION_FILE_NAME), output_dir.join(ATTRIBUTION_FILE_NAME))
+                .context("failed to copy attribution")?;
+        }
+        if root_dir.join(BUILD_MANIFEST_FILE_NAME).is_file() {
+            // Only present if the package was verified when exported
+            fs::copy(root_dir.join(BUILD_MANIFEST_FILE_NAME), output_dir.join(BUILD_MANIFEST_FILE_NAME))
+                .context("failed to copy build manifest")?;
+        }
         fs::create_dir(&output_dir.join(PATCHES_DIR_NAME))
             .context("failed to create patches directory")?;
         for entry in fs::read_dir(&root_dir.join(PATCHES_DIR_NAME)).context("failed to read patches directory")? {
@@ -291,12 +573,26 @@ impl Distributable {
         &self.path
     }
 
+    /// Returns the format version of the distributable.
+    pub fn format_version(&self) -> FormatVersion {
+        self.format_version
+    }
+
     /// Applies the distributable to a base ROM, and returns the output ROM.
+    /// If applying fails, no output ROM is written.
+    ///
+    /// The base ROM must be of the package's [region](crate::package::manifest::Metadata::region), going by its
+    /// header, since that is what the package is built for. If the distributable has a [`BuildManifest`], a warning
+    /// is logged if the ROM differs from the author's.
     pub fn apply(&self, mut options: ApplyOptions) -> Result<Rom> {
         self.open_scoped(options.baserom.clone(), |package| {
+            let build_manifest = BuildManifest::read_from_dir(package.path())?;
             let initialised = package.to_initialised(InitialiseOptions {
                 baserom: options.baserom,
+                region: None,
                 rev: None,
+                skip_baserom_check: false,
+                reference: None,
             })?;
 
             // Default output path - since we're using a open_scoped tempdir, we need to set output to Some because
@@ -304,34 +600,283 @@ impl Distributable {
             if options.build_rom_options.output.is_none() {
                 let manifest = initialised.package().manifest()?;
                 let metadata = manifest.metadata();
-                let output_path = PathBuf::from(format!("{} {}.z64", metadata.name(), metadata.version()));
-                options.build_rom_options.output = Some(output_path)
+                let file_name = format!("{} {}.{}", metadata.name(), metadata.version(), rom::EXTENSION);
+                options.build_rom_options.output = Some(PathBuf::from(file_name))
             }
 
-            initialised.build_rom(options.build_rom_options)
+            let rom = initialised.build_rom(options.build_rom_options)?;
+            if let Some(build_manifest) = build_manifest {
+                if !build_manifest.matches_rom(&rom)? {
+                    log::warn!(
+                        "your build differs from the author's (SHA1 {} rather than {}); you may have a toolchain \
+                        difference",
+                        rom.sha1_string()?,
+                        build_manifest.rom_sha1,
+                    );
+                }
+            }
+            Ok(rom)
         })
     }
 
+    /// Applies the distributable to a base ROM without keeping the output, and checks that the patched ROM is the
+    /// one expected. It is compared against the ROM at `expected` if given, or else the SHA1 recorded in the
+    /// distributable's [`BuildManifest`]. Returns an error describing the difference if it isn't a match.
+    ///
+    /// The output path in `options` is ignored.
+    #[pyo3(signature = (options, expected = None))]
+    pub fn verify_apply(&self, options: ApplyOptions, expected: Option<PathBuf>) -> Result<()> {
+        let expected = match expected {
+            Some(path) => {
+                if !path.is_file() {
+                    bail!("expected ROM {} does not exist", path.display());
+                }
+                Some(Rom::from(path))
+            }
+            None => None,
+        };
+        let build_manifest = match expected {
+            Some(_) => None,
+            None => Some(self.build_manifest(options.baserom.clone())?.context(
+                "no expected ROM given, and the distributable has no build manifest to check against (it was not \
+                exported with --verify-reproducible)"
+            )?),
+        };
+
+        let rom = Rom::from_distributable_apply(self, options)?;
+        if let Some(expected) = expected {
+            if !rom.content_equals(&expected)? {
+                bail!(
+                    "patched ROM does not match {} (SHA1 {} rather than {})\n{}",
+                    expected,
+                    rom.sha1_string()?,
+                    expected.sha1_string()?,
+                    rom.diff_summary(&expected)?,
+                );
+            }
+        }
+        if let Some(build_manifest) = build_manifest {
+            if !build_manifest.matches_rom(&rom)? {
+                bail!(
+                    "patched ROM does not match the author's build (SHA1 {} rather than {})",
+                    rom.sha1_string()?,
+                    build_manifest.rom_sha1,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the [`BuildManifest`] embedded in the distributable, if it has one.
+    pub fn build_manifest(&self, baserom: PathBuf) -> Result<Option<BuildManifest>> {
+        self.open_scoped(baserom, |package| BuildManifest::read_from_dir(package.path()))
+    }
+
+    /// Applies the distributable to a base ROM like [`Distributable::apply`], and returns the patched ROM's bytes
+    /// without keeping the output ROM. The output path in `options` is ignored.
+    pub fn apply_to_bytes(&self, options: ApplyOptions) -> Result<Vec<u8>> {
+        let rom = Rom::from_distributable_apply(self, options)?;
+        rom.read_bytes()
+            .with_context(|| format!("failed to read {}", rom.path().display()))
+    }
+
     /// Opens the distributable into a temporary directory and reads the package manifest. 
     pub fn manifest(&self, baserom: PathBuf) -> Result<Manifest> {
         self.open_scoped(baserom, |package| {
             package.manifest()
         })
     }
+
+    /// Opens the distributable into a temporary directory and reads the package changelog, if it has one.
+    pub fn changelog(&self, baserom: PathBuf) -> Result<Option<String>> {
+        self.open_scoped(baserom, |package| {
+            package.changelog()
+        })
+    }
+
+    /// Re-encrypts the distributable against a different base ROM, such as one from another region, and writes
+    /// it to the given output path. Distributables in older formats are written in the current format.
+    ///
+    /// Only packages that declare `region_portable = true` in their manifest can be repacked, since the patches of
+    /// other packages are not guaranteed to apply to a different base ROM.
+    ///
+    /// Like the base ROMs of [`Package::export_distributable`], either base ROM may be in an archive or another byte
+    /// order.
+    pub fn repack(&self, from_baserom: PathBuf, to_baserom: PathBuf, output: PathBuf) -> Result<Distributable> {
+        if !to_baserom.is_file() {
+            bail!(Error::Baserom(format!("baserom {:?} is not a file", to_baserom)));
+        }
+        let output = Distributable::extension_normalized(&output, OverwritePolicy::Error)?;
+
+        let manifest = self.manifest(from_baserom.clone())
+            .context("failed to open distributable with the original baserom")?;
+        if !manifest.metadata().region_portable() {
+            bail!(
+                "package {} is not marked as region_portable, so it cannot be repacked for a different baserom",
+                manifest.metadata().name(),
+            );
+        }
+
+        let temp_dir = TempDir::new()
+            .context("failed to create temporary directory")?;
+        let encrypted_path = temp_dir.path().join("package.merlon");
+        self.unpack(temp_dir.path(), baserom::open(&from_baserom)?.path())?;
+        pack(temp_dir.path(), &mut fs::File::create(&encrypted_path)?, baserom::open(&to_baserom)?.path())?;
+        fs::copy(&encrypted_path, &output)?;
+
+        Distributable::try_from(output)
+    }
 }
 
 impl Distributable {
+    /// Applies several independent distributables to a base ROM together, and returns the output ROM.
+    ///
+    /// The distributables' packages, which must include every dependency they need, are added as dependencies of an
+    /// empty package which is then built, for the region of the first package.
+    pub fn apply_many(distributables: &[Distributable], mut options: ApplyOptions) -> Result<Rom> {
+        let temp_dir = TempDir::new()
+            .context("failed to create temporary directory")?;
+        let (registry, ids) = Registry::from_distributable_closure(
+            distributables,
+            &options.baserom,
+            &temp_dir.path().join("packages"),
+        )?;
+
+        // Build against the decomp revision pinned by the first package
+        let mut revs = Vec::new();
+        for id in &ids {
+            let manifest = registry.get_or_error(*id)?.manifest()?;
+            if let Some(rev) = manifest.get_direct_decomp_dependency_rev() {
+                if !revs.iter().any(|other| other == rev) {
+                    revs.push(rev.to_owned());
+                }
+            }
+        }
+        if revs.len() > 1 {
+            log::warn!("packages use different decomp revisions, using {}", revs[0]);
+        }
+        let region = match ids.first() {
+            Some(id) => registry.get_or_error(*id)?.manifest()?.metadata().region(),
+            None => Region::default(),
+        };
+
+        let mut names = Vec::with_capacity(ids.len());
+        for id in &ids {
+            names.push(registry.get_or_error(*id)?.manifest()?.metadata().name().to_string());
+        }
+
+        let stack = Package::new("Stack", temp_dir.path().join("stack"))?;
+        let mut initialised = stack.to_initialised(InitialiseOptions {
+            baserom: options.baserom,
+            region: Some(region),
+            rev: revs.into_iter().next(),
+            skip_baserom_check: false,
+            reference: None,
+        })?;
+        for id in registry.package_ids() {
+            initialised.add_dependency(AddDependencyOptions {
+                path: Some(registry.get_or_error(id)?.path().to_owned()),
+                git: None,
+                rev: None,
+            })?;
+        }
+        initialised.sync_repo()?;
+
+        // The stack package is in the tempdir, so the output ROM has to go somewhere else
+        if options.build_rom_options.output.is_none() {
+            options.build_rom_options.output = Some(PathBuf::from(names.join(" + ")));
+        }
+        initialised.build_rom(options.build_rom_options)
+    }
+
+    /// Prepares a path to write a distributable to. See [`rom::prepare_output_path`].
+    pub fn extension_normalized(path: &Path, policy: OverwritePolicy) -> Result<PathBuf> {
+        rom::prepare_output_path(path, EXTENSION, policy)
+    }
+
     /// Opens the package into a temporary directory, and calls the given closure with the package.
     pub fn open_scoped<F, R>(&self, baserom: PathBuf, f: F) -> Result<R>
     where
         F: FnOnce(Package) -> Result<R>,
     {
-        let temp_dir = TempDir::new()?;
+        let (_temp_dir, package) = self.into_temp_package(baserom)?;
+        f(package)
+    }
+
+    /// Opens the package into a temporary directory, and returns the package along with the directory.
+    /// The directory, and the package with it, is deleted when the returned [`TempDir`] is dropped.
+    pub fn into_temp_package(&self, baserom: PathBuf) -> Result<(TempDir, Package)> {
+        let temp_dir = TempDir::new()
+            .context("failed to create temporary directory")?;
         let package = self.open_to_dir(OpenOptions {
             output: Some(temp_dir.path().to_owned()),
             baserom,
         })?;
-        f(package)
+        Ok((temp_dir, package))
+    }
+}
+
+impl Distributable {
+    /// Reads a distributable from `reader`, e.g. stdin, into a temporary file that is deleted when the distributable
+    /// is dropped.
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let temp_dir = TempDir::new()
+            .context("failed to create temporary directory")?;
+        let path = temp_dir.path().join(format!("package.{}", EXTENSION));
+        let mut file = fs::File::create(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        std::io::copy(reader, &mut file).context("failed to read distributable")?;
+        drop(file);
+        let mut distributable = Distributable::try_from(path)?;
+        distributable.temp_dir = Some(temp_dir);
+        Ok(distributable)
+    }
+
+    /// Joins a split distributable's volumes into a temporary file that is deleted when the distributable is
+    /// dropped. See [`volumes`].
+    fn join_volumes(index_path: PathBuf) -> Result<Self> {
+        let temp_dir = TempDir::new()
+            .context("failed to create temporary directory")?;
+        let file_name = volumes::distributable_path(&index_path)
+            .file_name()
+            .context("volume index has no file name")?
+            .to_owned();
+        let path = temp_dir.path().join(file_name);
+        volumes::join(&index_path, &path)?;
+        let mut distributable = Distributable::try_from(path)?;
+        distributable.temp_dir = Some(temp_dir);
+        distributable.volume_index = Some(index_path);
+        Ok(distributable)
+    }
+
+    /// Decrypts and decompresses the distributable into `dir/merlon_v1`.
+    fn unpack(&self, dir: &Path, baserom: &Path) -> Result<()> {
+        let encrypted_path = dir.join("package.tar.bz2.enc");
+        let tar_path = dir.join("package.tar.bz2");
+
+        // Strip the header, if any, leaving what openssl wrote
+        let contents = fs::read(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        let payload = match self.format_version {
+            FormatVersion::V1 => &contents[..],
+            FormatVersion::V2 => &contents[HEADER_MAGIC.len() + 1..],
+        };
+        fs::write(&encrypted_path, payload)?;
+        decrypt(&encrypted_path, &tar_path, baserom)
+            .with_context(|| format!("failed to decrypt {}", self.path.display()))?;
+
+        // Decompress tar into the root directory
+        let status = Command::new("tar")
+            .arg("-xjf").arg(&tar_path)
+            .arg("-C").arg(dir)
+            .arg(ROOT_DIR_NAME)
+            .stderr(Stdio::null())
+            .status()
+            .context("failed run tar")?;
+        if !status.success() {
+            bail!("failed to decompress {}", tar_path.display());
+        }
+        Ok(())
     }
 }
 
@@ -339,21 +884,612 @@ impl TryFrom<PathBuf> for Distributable {
     type Error = anyhow::Error;
 
     fn try_from(path: PathBuf) -> Result<Self> {
-        if is_distributable_package(&path) {
-            Ok(Self { path })
-        } else {
+        if let Some(index_path) = volumes::find_index(&path) {
+            return Distributable::join_volumes(index_path);
+        }
+        if !is_distributable_package(&path) {
             bail!("{} is not a Merlon distributable", path.display());
         }
+        let format_version = read_format_version(&path)?;
+        Ok(Self { path, format_version, temp_dir: None, volume_index: None })
     }
 }
 
+/// Reads the format version from the header of a distributable. Files from before there was a header are
+/// [`FormatVersion::V1`].
+fn read_format_version(path: &Path) -> Result<FormatVersion> {
+    let mut header = Vec::new();
+    fs::File::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .take(OPENSSL_SALT_MAGIC.len() as u64)
+        .read_to_end(&mut header)?;
+    if header.len() > HEADER_MAGIC.len() && header.starts_with(HEADER_MAGIC) {
+        let byte = header[HEADER_MAGIC.len()];
+        match FormatVersion::from_header_byte(byte) {
+            Some(version) => Ok(version),
+            None if byte > FormatVersion::CURRENT as u8 => bail!(
+                "{} is format version {}, which is newer than this version of Merlon supports; try updating Merlon",
+                path.display(),
+                byte,
+            ),
+            None => bail!("{} has an invalid format version {}", path.display(), byte),
+        }
+    } else if header == OPENSSL_SALT_MAGIC {
+        Ok(FormatVersion::V1)
+    } else {
+        bail!("{} is not a Merlon distributable", path.display());
+    }
+}
+
+/// Compresses `dir/merlon_v1`, encrypts it using the given baserom as the key, and writes it to `sink` in the
+/// current format.
+fn pack<W: Write + ?Sized>(dir: &Path, sink: &mut W, baserom: &Path) -> Result<()> {
+    let tar_path = dir.join("package.tar.bz2");
+    let encrypted_path = dir.join("package.tar.bz2.enc");
+
+    // Compress directory into a tar
+    let status = Command::new("tar")
+        .arg("--no-xattrs") // Avoid com.apple.provenance
+        .arg("-cjf")
+        .arg(&tar_path)
+        .arg("-C").arg(dir)
+        .arg(ROOT_DIR_NAME)
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!("failed to compress to tar {}", tar_path.display());
+    }
+
+    // Encrypt the tar using baserom as hash
+    encrypt(&tar_path, &encrypted_path, baserom)?;
+
+    // Write header and encrypted tar to output
+    sink.write_all(HEADER_MAGIC)?;
+    sink.write_all(&[FormatVersion::CURRENT as u8])?;
+    sink.write_all(&fs::read(&encrypted_path)?)?;
+    sink.flush()?;
+    Ok(())
+}
+
 impl fmt::Display for Distributable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} (distributable)", self.path.display())
+        match &self.volume_index {
+            Some(index_path) => write!(f, "{} (split distributable)", index_path.display()),
+            None => write!(f, "{} (distributable)", self.path.display()),
+        }
+    }
+}
+
+#[pymethods]
+impl Rom {
+    /// Applies a distributable to a base ROM like [`Distributable::apply`], but writes the output ROM to a temporary
+    /// file that is deleted when the returned ROM is dropped. The output path in `options` is ignored.
+    ///
+    /// This is for tooling that only needs the patched ROM briefly, e.g. to hash it or pass it to an emulator.
+    #[staticmethod]
+    pub fn from_distributable_apply(distributable: &Distributable, mut options: ApplyOptions) -> Result<Rom> {
+        let temp_dir = TempDir::new()
+            .context("failed to create temporary directory")?;
+        options.build_rom_options.output = Some(temp_dir.path().join(format!("patched.{}", rom::EXTENSION)));
+        options.build_rom_options.if_exists = OverwritePolicy::Overwrite;
+        let rom = distributable.apply(options)?;
+        Ok(rom.owning_temp_dir(temp_dir))
     }
 }
 
+/// Splits patch files into those to keep and those excluded by `exclude`, which matches patches by file name or by
+/// part of their commit subject. Errors if a pattern matches no patches, since it's probably a typo.
+fn exclude_patches(patch_files: Vec<PathBuf>, exclude: &[String]) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    if exclude.is_empty() {
+        return Ok((patch_files, Vec::new()));
+    }
+    let mut matched = vec![false; exclude.len()];
+    let mut kept = Vec::new();
+    let mut excluded = Vec::new();
+    for path in patch_files {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let subject = PatchInfo::read_from_path(path.clone())?.subject;
+        let mut is_excluded = false;
+        for (pattern, matched) in exclude.iter().zip(matched.iter_mut()) {
+            if file_name == *pattern || subject.contains(pattern.as_str()) {
+                *matched = true;
+                is_excluded = true;
+            }
+        }
+        if is_excluded {
+            excluded.push(path);
+        } else {
+            kept.push(path);
+        }
+    }
+    if let Some((pattern, _)) = exclude.iter().zip(matched).find(|(_, matched)| !matched) {
+        bail!("no patch matches {:?}", pattern);
+    }
+    Ok((kept, excluded))
+}
+
+/// Encrypts a file using the given baserom as the key.
+fn encrypt(input: &Path, output: &Path, baserom: &Path) -> Result<()> {
+    let status = Command::new("openssl")
+        .arg("enc")
+        .arg("-aes-256-cbc")
+        .arg("-md").arg("sha512")
+        .arg("-pbkdf2")
+        .arg("-iter").arg("100000")
+        .arg("-salt")
+        .arg("-in").arg(input)
+        .arg("-out").arg(output)
+        .arg("-pass").arg(format!("file:{}", baserom.display()))
+        .status()
+        .context("failed run openssl")?;
+    if !status.success() {
+        bail!("failed to encrypt tar to {}", output.display());
+    }
+    Ok(())
+}
+
+/// Decrypts a file encrypted by [`encrypt`] using the same baserom.
+fn decrypt(input: &Path, output: &Path, baserom: &Path) -> Result<()> {
+    let status = Command::new("openssl")
+        .arg("enc")
+        .arg("-d") // decrypt
+        .arg("-aes-256-cbc")
+        .arg("-md").arg("sha512")
+        .arg("-pbkdf2")
+        .arg("-iter").arg("100000")
+        .arg("-salt")
+        .arg("-in").arg(input)
+        .arg("-out").arg(output)
+        .arg("-pass").arg(format!("file:{}", baserom.display()))
+        .status()
+        .context("failed run openssl")?;
+    if !status.success() {
+        bail!("failed to decrypt {}", input.display());
+    }
+    Ok(())
+}
+
+fn generate_attribution(registry: &Registry, root: Id) -> Result<String> {
+    // Walk the dependency tree, skipping packages we don't have (e.g. when exporting an uninitialised package)
+    let mut dependencies = Vec::new();
+    let mut visited = vec![root];
+    let mut stack = vec![root];
+    while let Some(id) = stack.pop() {
+        for dependency in registry.get_direct_dependencies(id)? {
+            if let Some(id) = dependency.package_id() {
+                if visited.contains(&id) {
+                    continue;
+                }
+                visited.push(id);
+                match registry.get(id)? {
+                    Some(package) => {
+                        dependencies.push(package.manifest()?);
+                        stack.push(id);
+                    }
+                    None => log::warn!("cannot credit authors of dependency {}, it is not available locally", id),
+                }
+            }
+        }
+    }
+    dependencies.sort_by_key(|manifest| manifest.metadata().name().to_string());
+
+    let mut attribution = String::from("# Attribution\n");
+    let root_manifest = registry.get_or_error(root)?.manifest()?;
+    for manifest in std::iter::once(&root_manifest).chain(dependencies.iter()) {
+        let metadata = manifest.metadata();
+        attribution.push_str(&format!("\n## {} {}\n\n", metadata.name(), metadata.version()));
+        let authors = metadata.parsed_authors();
+        if authors.is_empty() {
+            attribution.push_str("- Unknown Author(s)\n");
+        }
+        for author in authors {
+            attribution.push_str(&format!("- {}\n", author));
+        }
+    }
+    Ok(attribution)
+}
+
 /// Returns true if the given path is probably a distributable package.
 pub fn is_distributable_package(path: &Path) -> bool {
-    path.is_file() && path.extension().unwrap_or_default() == EXTENSION
+    (path.is_file() && path.extension().unwrap_or_default() == EXTENSION) || volumes::find_index(path).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use temp_dir::TempDir;
+    use anyhow::Result;
+
+    use super::*;
+    use crate::package::test::git;
+
+    #[test]
+    fn attribution_credits_dependency_authors() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        let dependency = registry.register(Package::new("Dependency", dir.path().join("dependency"))?)?;
+        registry.get_or_error(root)?.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_authors(vec!["Root Author".to_owned()]);
+            Ok(())
+        })?;
+        registry.get_or_error(dependency)?.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_authors(vec!["Dependency Author".to_owned()]);
+            Ok(())
+        })?;
+        registry.add_direct_dependency(root, dependency)?;
+
+        let attribution = generate_attribution(&registry, root)?;
+        let root_section = attribution.find("## Root").unwrap();
+        let dependency_section = attribution.find("## Dependency").unwrap();
+        assert!(root_section < dependency_section);
+        assert!(attribution[root_section..dependency_section].contains("- Root Author"));
+        assert!(attribution[dependency_section..].contains("- Dependency Author"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_export_lists_patches() -> Result<()> {
+        let dir = TempDir::new()?;
+        let template = crate::package::Template::find("skip-intro").unwrap();
+        let package = Package::new_from_template("Preview", dir.path().join("package"), template)?;
+
+        let preview = package.preview_export()?;
+        assert_eq!(preview.patches.len(), 1);
+        assert_eq!(preview.patches[0].subject, "set bSkipIntro to true");
+        assert!(preview.dependencies.is_empty());
+        assert!(preview.size > fs::metadata(&preview.patches[0].path)?.len());
+        assert!(!preview.uncommitted_changes);
+        Ok(())
+    }
+
+    #[test]
+    fn repack_requires_region_portable() -> Result<()> {
+        let dir = TempDir::new()?;
+        let us_baserom = dir.path().join("us.z64");
+        let jp_baserom = dir.path().join("jp.z64");
+        fs::write(&us_baserom, "pretend this is a US ROM")?;
+        fs::write(&jp_baserom, "pretend this is a JP ROM")?;
+
+        let package = Package::new("Portable", dir.path().join("package"))?;
+        let export = |output: &str| package.export_distributable(ExportOptions {
+            output: Some(dir.path().join(output)),
+            baserom: Some(us_baserom.clone()),
+            if_exists: OverwritePolicy::Error,
+            dry_run: false,
+            exclude_patches: vec![],
+            verify_reproducible: false,
+            patch_context: None,
+            split_size: None,
+        });
+
+        // Not portable by default
+        let distributable = export("default.merlon")?;
+        let repacked_path = dir.path().join("repacked.merlon");
+        assert!(distributable.repack(us_baserom.clone(), jp_baserom.clone(), repacked_path.clone()).is_err());
+        assert!(!repacked_path.exists());
+
+        // Portable packages can be repacked, and then only open with the new baserom
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_region_portable(true);
+            Ok(())
+        })?;
+        let distributable = export("portable.merlon")?;
+        let repacked = distributable.repack(us_baserom.clone(), jp_baserom.clone(), repacked_path)?;
+        assert_eq!(repacked.manifest(jp_baserom)?.metadata().name().to_string(), "Portable");
+        assert!(repacked.manifest(us_baserom).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repack_normalizes_baseroms() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let v64_baserom = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms/us-header.v64");
+        let z64_baserom = dir.path().join("us-header.z64");
+        fs::copy(baserom::open(&v64_baserom)?.path(), &z64_baserom)?;
+
+        let package = Package::new("Portable", dir.path().join("package"))?;
+        package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_region_portable(true);
+            Ok(())
+        })?;
+        let distributable = package.export_distributable(ExportOptions {
+            output: Some(dir.path().join("portable.merlon")),
+            baserom: Some(baserom.clone()),
+            if_exists: OverwritePolicy::Error,
+            dry_run: false,
+            exclude_patches: vec![],
+            verify_reproducible: false,
+            patch_context: None,
+            split_size: None,
+        })?;
+
+        // The key is the same whichever byte order the new baserom is given in
+        let output = dir.path().join("repacked.merlon");
+        let repacked = distributable.repack(baserom.clone(), v64_baserom.clone(), output)?;
+        assert_eq!(repacked.manifest(v64_baserom.clone())?.metadata().name().to_string(), "Portable");
+        assert_eq!(repacked.manifest(z64_baserom)?.metadata().name().to_string(), "Portable");
+
+        // And whichever byte order the original baserom is given in
+        let repacked_back = repacked.repack(v64_baserom, baserom.clone(), dir.path().join("back.merlon"))?;
+        assert_eq!(repacked_back.manifest(baserom)?.metadata().name().to_string(), "Portable");
+        Ok(())
+    }
+
+    #[test]
+    fn temp_package_is_deleted_on_drop() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let distributable = Package::new("Temporary", dir.path().join("package"))?
+            .export_distributable(ExportOptions {
+                output: Some(dir.path().join("temporary.merlon")),
+                baserom: Some(baserom.clone()),
+                if_exists: OverwritePolicy::Error,
+                dry_run: false,
+                exclude_patches: vec![],
+                verify_reproducible: false,
+                patch_context: None,
+                split_size: None,
+            })?;
+
+        let (temp_dir, package) = distributable.into_temp_package(baserom)?;
+        let path = package.path().to_owned();
+        assert_eq!(package.manifest()?.metadata().name().to_string(), "Temporary");
+        drop(temp_dir);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn changelog_is_bundled() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let package = Package::new("Changes", dir.path().join("package"))?;
+        let export = |output: &str| package.export_distributable(ExportOptions {
+            output: Some(dir.path().join(output)),
+            baserom: Some(baserom.clone()),
+            if_exists: OverwritePolicy::Error,
+            dry_run: false,
+            exclude_patches: vec![],
+            verify_reproducible: false,
+            patch_context: None,
+            split_size: None,
+        });
+
+        assert_eq!(export("without.merlon")?.changelog(baserom.clone())?, None);
+
+        package.create_changelog()?;
+        assert!(package.create_changelog().is_err());
+        let changelog = export("with.merlon")?.changelog(baserom)?.unwrap();
+        assert_eq!(Some(&changelog), package.changelog()?.as_ref());
+        assert!(changelog.contains("## 0.1.0"));
+        Ok(())
+    }
+
+    #[test]
+    fn opens_every_format_version() -> Result<()> {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/distributables");
+        let baserom = fixtures.join("baserom.z64");
+        for (file_name, version) in [
+            ("v1.merlon", FormatVersion::V1),
+            ("v2.merlon", FormatVersion::V2),
+        ] {
+            let distributable = Distributable::try_from(fixtures.join(file_name))?;
+            assert_eq!(distributable.format_version(), version, "{}", file_name);
+
+            let (_dir, package) = distributable.into_temp_package(baserom.clone())?;
+            assert_eq!(package.manifest()?.metadata().name().to_string(), "Fixture", "{}", file_name);
+            assert_eq!(package.patches()?.len(), 1, "{}", file_name);
+            assert!(fs::read_to_string(package.path().join(README_FILE_NAME))?.contains("Fixture"), "{}", file_name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn exports_current_format_version() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let distributable = Package::new("Current", dir.path().join("package"))?
+            .export_distributable(ExportOptions {
+                output: Some(dir.path().join("current.merlon")),
+                baserom: Some(baserom.clone()),
+                if_exists: OverwritePolicy::Error,
+                dry_run: false,
+                exclude_patches: vec![],
+                verify_reproducible: false,
+                patch_context: None,
+                split_size: None,
+            })?;
+        assert_eq!(distributable.format_version(), FormatVersion::CURRENT);
+
+        // Unknown versions are rejected before trying to decrypt
+        let mut newer = HEADER_MAGIC.to_vec();
+        newer.push(FormatVersion::CURRENT as u8 + 1);
+        fs::write(dir.path().join("newer.merlon"), newer)?;
+        let error = Distributable::try_from(dir.path().join("newer.merlon")).unwrap_err();
+        assert!(error.to_string().contains("try updating Merlon"));
+        fs::write(dir.path().join("garbage.merlon"), "not a distributable")?;
+        assert!(Distributable::try_from(dir.path().join("garbage.merlon")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn build_manifest_is_embedded_in_distributable() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let package = Package::new("Verified", dir.path().join("package"))?;
+        let options = ExportOptions {
+            output: None,
+            baserom: None,
+            if_exists: OverwritePolicy::Error,
+            dry_run: false,
+            exclude_patches: vec![],
+            verify_reproducible: false,
+            patch_context: None,
+            split_size: None,
+        };
+
+        // Verifying needs a build, so stage the export and add the manifest that verification would write
+        let staged = package.stage_export(&options)?;
+        assert!(!staged.path().join(ROOT_DIR_NAME).join(BUILD_MANIFEST_FILE_NAME).exists());
+        let build_manifest = BuildManifest {
+            region: Region::Us,
+            decomp_rev: None,
+            closure_hash: "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_owned(),
+            rom_sha1: "3837f44cda784b466c9a2d99df70d77c322b97a0".to_owned(),
+        };
+        build_manifest.write_to_dir(&staged.path().join(ROOT_DIR_NAME))?;
+        let path = dir.path().join("verified.merlon");
+        pack(staged.path(), &mut fs::File::create(&path)?, &baserom)?;
+
+        let distributable = Distributable::try_from(path)?;
+        assert_eq!(distributable.build_manifest(baserom.clone())?, Some(build_manifest));
+        let unverified = package.export_distributable(ExportOptions {
+            output: Some(dir.path().join("unverified.merlon")),
+            baserom: Some(baserom.clone()),
+            ..options
+        })?;
+        assert_eq!(unverified.build_manifest(baserom)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn distributable_round_trips_through_streams() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let package = Package::new("Streamed", dir.path().join("package"))?;
+
+        let mut bytes = Vec::new();
+        package.write_distributable(ExportOptions {
+            output: None,
+            baserom: Some(baserom.clone()),
+            if_exists: OverwritePolicy::Error,
+            dry_run: false,
+            exclude_patches: vec![],
+            verify_reproducible: false,
+            patch_context: None,
+            split_size: None,
+        }, &mut bytes)?;
+        assert!(bytes.starts_with(HEADER_MAGIC));
+
+        let distributable = Distributable::read_from(&mut bytes.as_slice())?;
+        let path = distributable.path().to_owned();
+        assert_eq!(distributable.manifest(baserom)?.metadata().name().to_string(), "Streamed");
+        drop(distributable);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn split_distributable_round_trips() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        let package = Package::new("Large", dir.path().join("package"))?;
+
+        // Pseudorandom, so that compression doesn't shrink it below the split size
+        let mut state = 0x9e3779b9u32;
+        let readme: String = (0..64 * 1024).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            char::from(b'a' + (state % 26) as u8)
+        }).collect();
+        fs::write(package.path().join(README_FILE_NAME), &readme)?;
+
+        let output = dir.path().join("large.merlon");
+        let distributable = package.export_distributable(ExportOptions {
+            output: Some(output.clone()),
+            baserom: Some(baserom.clone()),
+            if_exists: OverwritePolicy::Error,
+            dry_run: false,
+            exclude_patches: vec![],
+            verify_reproducible: false,
+            patch_context: None,
+            split_size: Some(16 * 1024),
+        })?;
+        assert!(!output.exists());
+        assert!(dir.path().join("large.merlon.volumes").is_file());
+        assert!(dir.path().join("large.merlon.002").is_file());
+        assert!(distributable.to_string().contains("split distributable"));
+        drop(distributable);
+
+        for given in ["large.merlon", "large.merlon.001"] {
+            let (_dir, opened) = Distributable::try_from(dir.path().join(given))?.into_temp_package(baserom.clone())?;
+            assert_eq!(fs::read_to_string(opened.path().join(README_FILE_NAME))?, readme, "{}", given);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn modified_baserom_is_explained() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        let patched = dir.path().join("patched.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+        fs::write(&patched, "pretend this is a ROM with a mod applied")?;
+        let distributable = Package::new("Layered", dir.path().join("package"))?
+            .export_distributable(ExportOptions {
+                output: Some(dir.path().join("layered.merlon")),
+                baserom: Some(baserom),
+                if_exists: OverwritePolicy::Error,
+                dry_run: false,
+                exclude_patches: vec![],
+                verify_reproducible: false,
+                patch_context: None,
+                split_size: None,
+            })?;
+
+        let error = distributable.manifest(patched).unwrap_err();
+        assert!(format!("{}", error).contains("clean base ROM"));
+        Ok(())
+    }
+
+    #[test]
+    fn excluded_patches_are_checked() -> Result<()> {
+        let dir = TempDir::new()?;
+        let repo = dir.path().join("repo");
+        fs::create_dir(&repo)?;
+        git(&repo, &["init", "--quiet"])?;
+        fs::write(repo.join("numbers.txt"), "one\ntwo\nthree\n")?;
+        git(&repo, &["add", "numbers.txt"])?;
+        git(&repo, &["commit", "--quiet", "-m", "initial"])?;
+        git(&repo, &["tag", "base"])?;
+        for (from, to) in [("one", "uno"), ("uno", "eins"), ("three", "tres")] {
+            let numbers = fs::read_to_string(repo.join("numbers.txt"))?.replace(from, to);
+            fs::write(repo.join("numbers.txt"), numbers)?;
+            git(&repo, &["commit", "--quiet", "-am", &format!("say {}", to)])?;
+        }
+
+        let package = Package::new("Partial", dir.path().join("package"))?;
+        let patches_dir = package.path().join(PATCHES_DIR_NAME).canonicalize()?;
+        git(&repo, &["format-patch", "--quiet", "base..HEAD", "-o", &patches_dir.to_string_lossy()])?;
+
+        // The last patch is independent of the others
+        let (kept, excluded) = exclude_patches(package.patch_files()?, &["say tres".to_owned()])?;
+        assert_eq!((kept.len(), excluded.len()), (2, 1));
+        check_patches_apply(&kept, &repo, "base")?;
+
+        // The second patch needs the first
+        let first = excluded_file_name(&package, "say uno")?;
+        let (kept, _) = exclude_patches(package.patch_files()?, &[first])?;
+        assert!(check_patches_apply(&kept, &repo, "base").is_err());
+
+        assert!(exclude_patches(package.patch_files()?, &["say nothing".to_owned()]).is_err());
+        Ok(())
+    }
+
+    fn excluded_file_name(package: &Package, subject: &str) -> Result<String> {
+        let patch = package.patches()?.into_iter().find(|patch| patch.subject == subject).unwrap();
+        Ok(patch.path.file_name().unwrap().to_string_lossy().into_owned())
+    }
 }
@@ -0,0 +1,111 @@
+//! Build manifests, which record the ROM that a distributable's author built.
+//!
+//! When a package is exported with [`ExportOptions::verify_reproducible`](super::ExportOptions), the SHA1 of the ROM
+//! built from its patches is known, so it is embedded in the distributable along with what it was built from. After
+//! [`Distributable::apply`](super::Distributable::apply), the ROM that was built is compared against it.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, Context};
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::package::manifest::Region;
+use crate::rom::Rom;
+
+/// File name of the build manifest in a distributable.
+pub(super) const BUILD_MANIFEST_FILE_NAME: &str = "build.toml";
+
+/// What a distributable's author built. See the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[pyclass(module = "merlon.package.distribute")]
+pub struct BuildManifest {
+    /// The region of the ROM that was built.
+    #[pyo3(get)]
+    pub region: Region,
+
+    /// The decomp revision that the package pins, if any.
+    #[pyo3(get)]
+    pub decomp_rev: Option<String>,
+
+    /// The [closure hash](crate::package::Registry::closure_hash) of the package's dependency tree.
+    #[pyo3(get)]
+    pub closure_hash: String,
+
+    /// SHA1 of the ROM that was built.
+    #[pyo3(get)]
+    pub rom_sha1: String,
+}
+
+#[pymethods]
+impl BuildManifest {
+    /// Returns true if the given ROM is the one the author built.
+    pub fn matches_rom(&self, rom: &Rom) -> Result<bool> {
+        Ok(rom.sha1_string()? == self.rom_sha1)
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl BuildManifest {
+    /// Reads the build manifest in the given directory, if there is one. Distributables exported without
+    /// verification, or by older versions of Merlon, don't have one.
+    pub fn read_from_dir(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(BUILD_MANIFEST_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let string = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&string)
+            .map(Some)
+            .with_context(|| format!("invalid build manifest {}", path.display()))
+    }
+
+    /// Writes the build manifest into the given directory.
+    pub fn write_to_dir(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(BUILD_MANIFEST_FILE_NAME);
+        fs::write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+impl fmt::Display for BuildManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Region: {}", self.region)?;
+        writeln!(f, "Decomp revision: {}", self.decomp_rev.as_deref().unwrap_or("latest"))?;
+        writeln!(f, "Dependency closure hash: {}", self.closure_hash)?;
+        write!(f, "ROM SHA1: {}", self.rom_sha1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn round_trips_through_dir() -> Result<()> {
+        let dir = TempDir::new()?;
+        assert_eq!(BuildManifest::read_from_dir(dir.path())?, None);
+
+        let manifest = BuildManifest {
+            region: Region::Us,
+            decomp_rev: Some("7a9df94".to_owned()),
+            closure_hash: "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_owned(),
+            rom_sha1: "3837f44cda784b466c9a2d99df70d77c322b97a0".to_owned(),
+        };
+        manifest.write_to_dir(dir.path())?;
+        assert!(fs::read_to_string(dir.path().join(BUILD_MANIFEST_FILE_NAME))?.contains("region = \"us\""));
+        assert_eq!(BuildManifest::read_from_dir(dir.path())?, Some(manifest.clone()));
+
+        let rom_path = dir.path().join("rom.z64");
+        fs::write(&rom_path, "not the same ROM")?;
+        assert!(!manifest.matches_rom(&Rom::from(rom_path))?);
+        Ok(())
+    }
+}
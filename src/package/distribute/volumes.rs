@@ -0,0 +1,233 @@
+//! Distributables split into volumes, for distribution channels with a file size limit.
+//!
+//! When a package is exported with [`ExportOptions::split_size`](super::ExportOptions) and the distributable is
+//! larger than that, it is split into numbered volumes next to where it would have been written, along with an index
+//! of the volumes:
+//!
+//!  mod.merlon.001
+//!  mod.merlon.002
+//!  mod.merlon.003
+//!  mod.merlon.volumes         - Volume index, with the size and SHA1 of each volume
+//!
+//! Any of these paths, or `mod.merlon` itself, can be given to [`Distributable::try_from`](super::Distributable),
+//! which checks and joins the volumes back into a single distributable.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail, Context};
+use serde::{Deserialize, Serialize};
+use sha1::{Sha1, Digest};
+
+/// Extension of the volume index, after the distributable's own extension.
+const INDEX_EXTENSION: &str = "volumes";
+
+/// The most volumes a distributable can be split into, so that volume numbers stay three digits.
+pub const MAX_VOLUMES: usize = 999;
+
+/// The volumes that a distributable was split into. See the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeIndex {
+    /// The volumes, in order.
+    pub volumes: Vec<Volume>,
+}
+
+/// A part of a split distributable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Volume {
+    /// File name of the volume, in the same directory as the index.
+    pub file_name: String,
+
+    /// Size of the volume in bytes.
+    pub size: u64,
+
+    /// SHA1 of the volume.
+    pub sha1: String,
+}
+
+/// Splits the distributable at `path` into volumes of at most `max_size` bytes, if it is larger than that. Returns
+/// the path of the volume index, or `None` if the distributable was small enough to leave as it is.
+///
+/// The volumes and index are written next to `path`, replacing any from an earlier split, and `path` is removed.
+pub fn split(path: &Path, max_size: u64) -> Result<Option<PathBuf>> {
+    if max_size == 0 {
+        bail!("split size must be greater than zero");
+    }
+    let size = fs::metadata(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .len();
+    if size <= max_size {
+        return Ok(None);
+    }
+    let count = size.div_ceil(max_size);
+    if count > MAX_VOLUMES as u64 {
+        bail!(
+            "splitting {} into {}-byte volumes would need {} volumes, but at most {} are supported",
+            path.display(),
+            max_size,
+            count,
+            MAX_VOLUMES,
+        );
+    }
+
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let mut volumes = Vec::new();
+    for number in 1..=count {
+        let mut bytes = Vec::new();
+        (&mut file).take(max_size).read_to_end(&mut bytes)?;
+        let volume_path = volume_path(path, number as usize);
+        fs::write(&volume_path, &bytes)
+            .with_context(|| format!("failed to write {}", volume_path.display()))?;
+        volumes.push(Volume {
+            file_name: volume_path.file_name().unwrap().to_string_lossy().into_owned(),
+            size: bytes.len() as u64,
+            sha1: sha1_string(&bytes),
+        });
+    }
+    drop(file);
+
+    let index_path = index_path(path);
+    fs::write(&index_path, toml::to_string(&VolumeIndex { volumes })?)
+        .with_context(|| format!("failed to write {}", index_path.display()))?;
+    fs::remove_file(path)
+        .with_context(|| format!("failed to remove {}", path.display()))?;
+    Ok(Some(index_path))
+}
+
+/// Joins the volumes listed in the index at `index_path` into a single file at `output`, checking each volume's size
+/// and SHA1 against the index.
+pub fn join(index_path: &Path, output: &Path) -> Result<()> {
+    let string = fs::read_to_string(index_path)
+        .with_context(|| format!("failed to read {}", index_path.display()))?;
+    let index: VolumeIndex = toml::from_str(&string)
+        .with_context(|| format!("invalid volume index {}", index_path.display()))?;
+    if index.volumes.is_empty() {
+        bail!("volume index {} lists no volumes", index_path.display());
+    }
+
+    let dir = index_path.parent().unwrap_or(Path::new("."));
+    let mut sink = fs::File::create(output)
+        .with_context(|| format!("failed to create {}", output.display()))?;
+    for volume in &index.volumes {
+        let volume_path = dir.join(&volume.file_name);
+        if !volume_path.is_file() {
+            bail!("volume {} is missing", volume_path.display());
+        }
+        let bytes = fs::read(&volume_path)
+            .with_context(|| format!("failed to read {}", volume_path.display()))?;
+        if bytes.len() as u64 != volume.size || sha1_string(&bytes) != volume.sha1 {
+            bail!("volume {} is corrupt or incomplete, try downloading it again", volume_path.display());
+        }
+        sink.write_all(&bytes)?;
+    }
+    sink.flush()?;
+    Ok(())
+}
+
+/// Returns the volume index for `path`, if `path` is the index itself, one of the volumes, or a distributable that
+/// doesn't exist because it was split.
+pub fn find_index(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let index_path = if extension == INDEX_EXTENSION {
+        path.to_owned()
+    } else if extension.len() == 3 && extension.chars().all(|c| c.is_ascii_digit()) {
+        index_path(&path.with_extension(""))
+    } else if !path.exists() {
+        index_path(path)
+    } else {
+        return None;
+    };
+    index_path.is_file().then_some(index_path)
+}
+
+/// Returns the path that a distributable's volumes were joined from, i.e. the distributable's path without the
+/// index extension.
+pub fn distributable_path(index_path: &Path) -> PathBuf {
+    index_path.with_extension("")
+}
+
+fn index_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(INDEX_EXTENSION);
+    path.with_file_name(file_name)
+}
+
+fn volume_path(path: &Path, number: usize) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(format!(".{:03}", number));
+    path.with_file_name(file_name)
+}
+
+fn sha1_string(bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for byte in Sha1::digest(bytes).iter() {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn split_volumes_join_to_original() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("mod.merlon");
+
+        // Small enough to leave alone
+        fs::write(&path, "tiny")?;
+        assert_eq!(split(&path, 4)?, None);
+        assert!(path.is_file());
+        assert_eq!(find_index(&path), None);
+
+        // Pseudorandom, so that it looks like compressed data
+        let mut state = 0x2545f491u32;
+        let original: Vec<u8> = (0..10_000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect();
+        fs::write(&path, &original)?;
+        let index_path = split(&path, 4096)?.unwrap();
+        assert!(!path.exists());
+        assert_eq!(index_path, dir.path().join("mod.merlon.volumes"));
+        for file_name in ["mod.merlon.001", "mod.merlon.002", "mod.merlon.003"] {
+            assert!(dir.path().join(file_name).is_file(), "{}", file_name);
+        }
+        assert!(!dir.path().join("mod.merlon.004").exists());
+
+        for given in ["mod.merlon", "mod.merlon.002", "mod.merlon.volumes"] {
+            assert_eq!(find_index(&dir.path().join(given)), Some(index_path.clone()), "{}", given);
+        }
+        assert_eq!(distributable_path(&index_path), path);
+        let joined = dir.path().join("joined.merlon");
+        join(&index_path, &joined)?;
+        assert_eq!(fs::read(&joined)?, original);
+
+        // Corrupt volumes are rejected
+        fs::write(dir.path().join("mod.merlon.002"), vec![0; 4096])?;
+        let error = join(&index_path, &joined).unwrap_err();
+        assert!(error.to_string().contains("mod.merlon.002 is corrupt"), "{}", error);
+        fs::remove_file(dir.path().join("mod.merlon.002"))?;
+        let error = join(&index_path, &joined).unwrap_err();
+        assert!(error.to_string().contains("is missing"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_volumes_are_rejected() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("mod.merlon");
+        fs::write(&path, vec![0; MAX_VOLUMES + 1])?;
+        assert!(split(&path, 1).is_err());
+        assert!(split(&path, 0).is_err());
+        assert!(path.is_file());
+        Ok(())
+    }
+}
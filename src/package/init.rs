@@ -7,6 +7,7 @@
 //! │   │   └── <other_package_id>/
 //! ├── papermario/                 - git clone of decomp (not submodule)
 //! │   ├── assets/
+//! │   │   ├── us/                 - extracted from baserom during initialisation
 //! │   │   └── <package_id>/
 //! │   ├── src/
 //! │   └── ver/
@@ -23,23 +24,52 @@
 //! Being initialised means that the package is ready to be built.
 
 use anyhow::{anyhow, bail, Context, Error, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use git2::{BranchType, ErrorCode, Repository, Signature, StatusOptions};
+use git2::build::CheckoutBuilder;
 use pyo3::prelude::*;
 use scopeguard::defer;
-use semver::VersionReq;
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::fs::{copy, create_dir, create_dir_all, remove_dir_all, remove_file, write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-
-use super::manifest::Dependency;
-use super::{Distributable, Id, Package, Registry, PATCHES_DIR_NAME};
-use crate::rom::Rom;
-
-const MERLON_DIR_NAME: &str = ".merlon";
-const DEPENDENCIES_DIR_NAME: &str = ".merlon/dependencies";
-const SUBREPO_DIR_NAME: &str = "papermario";
+use std::sync::mpsc;
+use std::time::Duration;
+use notify::{Watcher, RecursiveMode, Event, EventKind, event::ModifyKind};
+
+use super::manifest::{Dependency, Region};
+use super::{Distributable, Id, Package, Registry, Warning, PATCHES_DIR_NAME, MANIFEST_FILE_NAME, run_git};
+use super::registry::{Inconsistency, Lockfile, rename_to_id};
+use super::decomp_repo::LocalDecompRepo;
+use crate::config::Config;
+use crate::rom::{self, Rom, OverwritePolicy, RomVerifyError};
+
+pub(super) const MERLON_DIR_NAME: &str = ".merlon";
+pub(super) const DEPENDENCIES_DIR_NAME: &str = ".merlon/dependencies";
+pub(crate) const SUBREPO_DIR_NAME: &str = "papermario";
 const VSCODE_DIR_NAME: &str = ".vscode";
 const GITIGNORE_FILE_NAME: &str = ".gitignore";
+const TEST_LOG_FILE_NAME: &str = ".merlon/test.log";
+
+/// The path of the decomp's splat config for `region`, which lists the asset directories the build takes assets from
+/// and the SHA1 of the region's base ROM. See [`InitialisedPackage::sync_repo`].
+fn splat_config_path(region: Region) -> String {
+    format!("ver/{}/splat.yaml", region.as_str())
+}
+
+/// The decomp revision that resolves to the newest release tag. See [`InitialiseOptions::rev`].
+pub const LATEST_TAG_REV: &str = "latest-tag";
+
+/// Marks a package whose initialisation failed after cloning the decomp, so that it can be resumed.
+const INIT_INCOMPLETE_FILE_NAME: &str = ".merlon/init-incomplete";
+
+/// The ninja target that runs the decomp's test suite, if the decomp revision has one.
+const TEST_TARGET: &str = "test";
+
+/// How long to wait for changes to settle before rebuilding in [`InitialisedPackage::watch_build`].
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
 
 /// An initialised package. Initialised packages are ready to be built.
 #[derive(Debug)]
@@ -53,17 +83,101 @@ pub struct InitialisedPackage {
 #[derive(Parser, Debug, Clone)]
 #[pyclass(module = "merlon.package.init")]
 pub struct InitialiseOptions {
-    /// Path to an unmodified US-release Paper Mario (N64) ROM.
+    /// Path to an unmodified Paper Mario (N64) ROM of the package's region, or a `.zip` or `.gz` archive containing
+    /// one.
     #[arg(long)]
     #[pyo3(get, set)]
     pub baserom: PathBuf,
 
-    /// Git revision of decomp to use.
+    /// Region of the base ROM, which the package is developed against. It is recorded as the package's first
+    /// region in the manifest, see [`Metadata::region`](super::manifest::Metadata::region).
+    ///
+    /// If not provided, the package's region from the manifest is used, which is `us` unless it says otherwise.
+    #[arg(long, value_enum)]
+    #[pyo3(get, set)]
+    pub region: Option<Region>,
+
+    /// Git revision of decomp to use: a commit hash, a branch or tag name, or `latest-tag` for the newest release
+    /// tag. Branches and tags are resolved to a commit hash, which is recorded in the manifest.
     ///
     /// If not provided, the latest commit on `main` is used.
     #[arg(long)]
     #[pyo3(get, set)]
     pub rev: Option<String>,
+
+    /// Use the base ROM even if it isn't an unmodified Paper Mario ROM of the region.
+    #[arg(long)]
+    #[pyo3(get, set)]
+    pub skip_baserom_check: bool,
+
+    /// An existing clone of the decomp, e.g. another package's `papermario` directory, to copy objects from instead
+    /// of downloading them. If it isn't a decomp clone, the decomp is downloaded as usual.
+    ///
+    /// If not provided, the decomp clones of packages in the same directory as this one are used, if there are any.
+    #[arg(long)]
+    #[pyo3(get, set)]
+    pub reference: Option<PathBuf>,
+}
+
+#[pymethods]
+impl InitialiseOptions {
+    /// Checks that the base ROM exists, has a supported extension, is for the region, and (unless
+    /// `skip_baserom_check` is set) is an unmodified Paper Mario ROM, and that the revision, if any, exists in the
+    /// decomp repository.
+    ///
+    /// [`InitialisedPackage::initialise`] does this before it downloads anything, so this only needs to be called to
+    /// find problems ahead of time.
+    pub fn validate(&self) -> Result<()> {
+        self.check(Config::load()?.decomp_url(), self.region.unwrap_or_default()).map(|_| ())
+    }
+}
+
+impl InitialiseOptions {
+    /// Validates the options against the decomp repository at `decomp_url`, for a package of `region`. Returns the
+    /// base ROM, extracted if it is in an archive, and the commit that the revision resolves to.
+    fn check(&self, decomp_url: &str, region: Region) -> Result<(Rom, Option<String>)> {
+        let baserom_path = &self.baserom;
+        if !baserom_path.is_file() {
+            bail!(crate::Error::Baserom(format!("baserom {:?} is not a file", baserom_path)));
+        }
+        let extension = baserom_path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        let is_rom = crate::baserom::ROM_EXTENSIONS.contains(&extension.as_str());
+        if !is_rom && !crate::baserom::ARCHIVE_EXTENSIONS.contains(&extension.as_str()) {
+            let list = |extensions: &[&str]| {
+                extensions.iter().map(|ext| format!(".{ext}")).collect::<Vec<_>>().join(" or ")
+            };
+            bail!(crate::Error::Baserom(format!(
+                "baserom {} has an unsupported extension, expected a {} ROM or a {} archive",
+                baserom_path.display(),
+                list(crate::baserom::ROM_EXTENSIONS),
+                list(crate::baserom::ARCHIVE_EXTENSIONS),
+            )));
+        }
+
+        let baserom = crate::baserom::open(baserom_path)?;
+        // The decomp is set up for the region of the base ROM
+        match rom::verify_papermario(&baserom, region) {
+            Ok(()) => {}
+            Err(RomVerifyError::WrongHash { .. }) if self.skip_baserom_check => {}
+            Err(RomVerifyError::WrongHash { got, .. }) => bail!(crate::Error::Baserom(format!(
+                "{} is not an unmodified {}-release Paper Mario ROM (its SHA1 is {}), use --skip-baserom-check to \
+                use it anyway",
+                baserom_path.display(),
+                region.as_str().to_uppercase(),
+                got,
+            ))),
+            Err(error) => bail!(crate::Error::Baserom(error.to_string())),
+        }
+
+        let rev = match &self.rev {
+            Some(rev) => Some(resolve_decomp_rev(decomp_url, rev)?),
+            None => None,
+        };
+        Ok((baserom, rev))
+    }
 }
 
 /// Options for [`InitialisedPackage::build_rom`].
@@ -86,6 +200,23 @@ pub struct BuildRomOptions {
     #[arg(long)]
     #[pyo3(get, set)]
     pub clean: bool,
+
+    /// Whether to also copy the ELF and symbol map next to the output ROM.
+    ///
+    /// Useful for loading symbols into an emulator's debugger.
+    #[arg(long = "symbols", overrides_with = "no_symbols")]
+    #[pyo3(get, set)]
+    pub with_symbols: bool,
+
+    /// Don't copy the ELF and symbol map, even if the package's `.merlonrc` sets `symbols`.
+    #[arg(long, overrides_with = "with_symbols")]
+    #[pyo3(get, set)]
+    pub no_symbols: bool,
+
+    /// What to do if a file already exists at the output path.
+    #[arg(long, value_enum, default_value_t)]
+    #[pyo3(get, set)]
+    pub if_exists: OverwritePolicy,
 }
 
 /// Options for [`InitialisedPackage::add_dependency`].
@@ -93,19 +224,128 @@ pub struct BuildRomOptions {
 #[pyclass(module = "merlon.package.init")]
 pub struct AddDependencyOptions {
     /// Path to the package to add as a dependency.
+    #[arg(long, required_unless_present = "git", conflicts_with = "git")]
+    #[pyo3(get, set)]
+    pub path: Option<PathBuf>,
+
+    /// URL of a git repository containing the package to add as a dependency. The repository is cloned, and the
+    /// manifest records the URL and commit so that the dependency can be fetched again.
     #[arg(long)]
     #[pyo3(get, set)]
-    pub path: PathBuf,
+    pub git: Option<String>,
+
+    /// Revision of the git repository to use, e.g. a branch, tag, or commit hash. Defaults to the default branch.
+    ///
+    /// Updating the dependency resolves this again, so a branch is followed, whereas a tag or commit hash pins it.
+    #[arg(long, requires = "git")]
+    #[pyo3(get, set)]
+    pub rev: Option<String>,
+}
+
+/// A kind of problem found by [`InitialisedPackage::doctor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[pyclass(module = "merlon.package.init")]
+pub enum DiagnosticKind {
+    /// The decomp repository has uncommitted changes.
+    DirtyTree,
+    /// A branch other than the package's is checked out in the decomp repository.
+    WrongBranch,
+    /// A package in the dependency tree has no branch in the decomp repository.
+    MissingBranch,
+    /// The decomp repository has a branch for a package that isn't in the registry.
+    StaleBranch,
+    /// The decomp's main branch isn't at the revision the manifest pins.
+    DecompRevDrift,
+    /// A dependency pins a different decomp revision to the package.
+    DecompRevSkew,
+    /// A dependency directory holds a package that nothing depends on.
+    OrphanedDependency,
+    /// A package in the dependency tree has no assets directory in the decomp repository.
+    MissingAssetsDir,
+    /// The dependency directories don't match the declared dependencies. See
+    /// [`Registry::is_consistent_with_disk`].
+    InconsistentDependencies,
+}
+
+/// A problem with the working state of an initialised package, and how to fix it. See
+/// [`InitialisedPackage::doctor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[pyclass(module = "merlon.package.init")]
+pub struct Diagnostic {
+    /// What kind of problem this is.
+    #[pyo3(get)]
+    pub kind: DiagnosticKind,
+
+    /// Description of the problem.
+    #[pyo3(get)]
+    pub message: String,
+
+    /// Suggested fix, e.g. a command to run.
+    #[pyo3(get)]
+    pub fix: String,
+}
+
+impl Diagnostic {
+    fn new(kind: DiagnosticKind, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}; {}", self.message, self.fix)
+    }
 }
 
 #[pymethods]
 impl Package {
+    /// Checks whether this package is initialised, i.e. it has a decomp subrepo and a `.merlon` directory.
+    pub fn is_initialised(&self) -> Result<bool> {
+        let path = self.path();
+
+        // Check subrepo exists and is a git repo
+        if !path.join(SUBREPO_DIR_NAME).is_dir() {
+            return Ok(false);
+        }
+        let status = run_git(Command::new("git")
+            .arg("status")
+            .current_dir(path.join(SUBREPO_DIR_NAME))
+            .stdout(Stdio::null()))?;
+        if !status.success() {
+            return Ok(false);
+        }
+
+        // Check merlon dir exists
+        if !path.join(MERLON_DIR_NAME).is_dir() {
+            return Ok(false);
+        }
+
+        // Check initialisation finished
+        if self.has_incomplete_initialisation() {
+            return Ok(false);
+        }
+
+        // VSCODE_DIR_NAME and GITIGNORE_FILE_NAME are optional
+
+        Ok(true)
+    }
+
+    /// Returns true if initialising this package failed after the decomp was cloned. Initialising the package again
+    /// will resume from installing the decomp's dependencies, rather than cloning it again.
+    pub fn has_incomplete_initialisation(&self) -> bool {
+        self.path().join(INIT_INCOMPLETE_FILE_NAME).is_file()
+    }
+
     /// Initialises this package if needed, and returns an InitialisedPackage.
     pub fn to_initialised(
         &self,
         initialise_options: InitialiseOptions,
     ) -> Result<InitialisedPackage> {
-        if InitialisedPackage::is_initialised(self)? {
+        if self.is_initialised()? {
             InitialisedPackage::from_initialised(self.clone())
         } else {
             InitialisedPackage::initialise(self.clone(), initialise_options)
@@ -118,29 +358,10 @@ impl InitialisedPackage {
     /// If the given package is initialised, returns it as an [`InitialisedPackage`].
     #[staticmethod]
     pub fn from_initialised(package: Package) -> Result<Self> {
-        if !Self::is_initialised(&package)? {
+        if !package.is_initialised()? {
             bail!("package is not initialised");
         }
-
-        let dependencies_dir_path = package.path().join(DEPENDENCIES_DIR_NAME);
-
-        // Create registry of this package and .merlon/dependencies/*
-        let mut registry = Registry::new();
-        let package_id = registry.register(package)?;
-        if dependencies_dir_path.is_dir() {
-            for path in dependencies_dir_path.read_dir()? {
-                let path = path?;
-                if path.file_type()?.is_dir() {
-                    let package = Package::try_from(path.path())?;
-                    registry.register(package)?;
-                }
-            }
-        }
-
-        Ok(Self {
-            registry,
-            package_id,
-        })
+        Self::load(package)
     }
 
     /// The package that this InitialisedPackage was created from.
@@ -154,9 +375,14 @@ impl InitialisedPackage {
         self.package_id
     }
 
-    /// The path to base ROM.
-    pub fn baserom_path(&self) -> PathBuf {
-        self.subrepo_path().join("ver/us/baserom.z64")
+    /// The path to the base ROM of the package's [region](super::manifest::Metadata::region).
+    pub fn baserom_path(&self) -> Result<PathBuf> {
+        Ok(self.region_baserom_path(self.region()?))
+    }
+
+    /// The region the package is developed against. See [`Metadata::region`](super::manifest::Metadata::region).
+    pub fn region(&self) -> Result<Region> {
+        Ok(self.package().manifest()?.metadata().region())
     }
 
     /// The path to the papermario repository used to build this package.
@@ -177,93 +403,133 @@ impl InitialisedPackage {
 
     /// Initialises a package. Errors if it is already initialised.
     /// This will clone the papermario repository, and create the .merlon directory.
+    ///
+    /// If initialisation fails after cloning, e.g. in the decomp's install.sh, the clone is kept and initialising
+    /// again resumes from there. See [`Package::has_incomplete_initialisation`].
     #[staticmethod]
     pub fn initialise(package: Package, options: InitialiseOptions) -> Result<Self> {
-        if Self::is_initialised(&package)? {
+        if package.is_initialised()? {
             bail!("package is already initialised, delete .merlon directory and try again to force reinitialisation");
         }
+        let resuming = package.has_incomplete_initialisation();
         // https://github.com/nanaian/merlon/issues/25
-        if package.path().join(SUBREPO_DIR_NAME).exists() {
+        if !resuming && package.path().join(SUBREPO_DIR_NAME).exists() {
             bail!(
                 "there is already a decomp clone here - delete the {} directory and try again",
                 SUBREPO_DIR_NAME
             );
         }
 
-        // If rev not provided on command line, use the one in the manifest, otherwise use latest
         let manifest = package.manifest()?;
-        let rev = match &options.rev {
+        manifest.metadata().check_merlon_version()?;
+
+        // Check options before downloading anything, so there's nothing to clean up if they are invalid
+        let region = options.region.unwrap_or(manifest.metadata().region());
+        let (baserom, resolved_rev) = options.check(Config::load()?.decomp_url(), region)?;
+
+        // If rev not provided on command line, use the one in the manifest, otherwise use latest
+        let rev = match &resolved_rev {
             Some(rev) => Some(rev.as_str()),
             None => manifest.get_direct_decomp_dependency_rev(),
         };
 
         let path_clone = package.path().to_owned();
         let error_context = format!("failed to initialise package {}", &package);
-        let do_it = || {
-            let package_id_string = package.id()?.to_string();
-
-            // Clone decomp subrepo
-            let mut command = Command::new("git");
-            command.arg("clone");
-            // If we're not using a specific revision, only clone the latest commit
-            if rev.is_none() {
-                command.arg("--depth=1");
-            }
-            // TODO: if existing clone, reference that
-            /*if let Some(repo) = local_decomp_repo.as_ref() {
-                command.arg("--reference").arg(repo.path());
-            }*/
-            let status = command
-                .arg("https://github.com/pmret/papermario.git")
-                .arg(SUBREPO_DIR_NAME)
-                .current_dir(package.path())
-                .status()?;
-            if !status.success() {
-                bail!("failed to clone decomp repository");
+
+        let local_decomp_repo = match &options.reference {
+            Some(path) => match LocalDecompRepo::new(path.clone()) {
+                Ok(repo) => Some(repo),
+                Err(error) => {
+                    log::warn!("not using {} as a reference: {:#}", path.display(), error);
+                    None
+                }
+            },
+            None => LocalDecompRepo::find_near(package.path()),
+        };
+
+        // Clone decomp subrepo. If this fails, there is nothing worth keeping.
+        if resuming {
+            log::info!("resuming initialisation of {}, the decomp is already cloned", &package);
+        } else {
+            let clone = || {
+                let mut command = Command::new("git");
+                command.arg("clone");
+                // If we're not using a specific revision, only clone the latest commit
+                if rev.is_none() {
+                    command.arg("--depth=1");
+                }
+                // Copy what we can from an existing clone, dissociating so this one still works if that is deleted
+                if let Some(repo) = &local_decomp_repo {
+                    log::info!("copying decomp objects from {}", repo.path().display());
+                    command.arg("--reference-if-able").arg(repo.path()).arg("--dissociate");
+                }
+                let status = run_git(command
+                    .arg(Config::load()?.decomp_url())
+                    .arg(SUBREPO_DIR_NAME)
+                    .current_dir(package.path()))?;
+                if !status.success() {
+                    bail!("failed to clone decomp repository");
+                }
+
+                // Create merlon dir, marking initialisation as incomplete until the end
+                create_dir(package.path().join(MERLON_DIR_NAME))
+                    .with_context(|| format!("failed to create {MERLON_DIR_NAME} directory"))?;
+                write(package.path().join(INIT_INCOMPLETE_FILE_NAME), "")
+                    .with_context(|| format!("failed to create {INIT_INCOMPLETE_FILE_NAME}"))?;
+                Ok(())
+            };
+            if let Err(e) = clone() {
+                // Cleanup
+                let _ = remove_dir_all(path_clone.join(SUBREPO_DIR_NAME));
+                let _ = remove_dir_all(path_clone.join(MERLON_DIR_NAME));
+                return Err(e).context(error_context);
             }
+        }
+
+        // From here on, failures (e.g. a flaky toolchain download in install.sh) keep the clone so that
+        // initialising again can resume
+        let do_it = || {
+            let package_id = package.id()?;
 
             if let Some(rev) = &rev {
                 // Reset to revision
-                let status = Command::new("git")
+                let status = run_git(Command::new("git")
                     .arg("reset")
                     .arg("--hard")
                     .arg(rev)
-                    .current_dir(package.path().join(SUBREPO_DIR_NAME))
-                    .status()?;
+                    .current_dir(package.path().join(SUBREPO_DIR_NAME)))?;
                 if !status.success() {
                     bail!("failed to checkout revision");
                 }
             }
 
+            // Record the region first, so that the package is built for it from now on
+            if manifest.metadata().region() != region {
+                package.edit_manifest_unchecked(|manifest| {
+                    let mut regions = manifest.metadata().regions();
+                    regions.retain(|&other| other != region);
+                    regions.insert(0, region);
+                    manifest.metadata_mut().set_regions(regions);
+                    Ok(())
+                })?;
+            }
+
             // Create assets dir for this mod
-            create_dir_all(
-                package
-                    .path()
-                    .join(SUBREPO_DIR_NAME)
-                    .join("assets")
-                    .join(&package_id_string),
-            )
-            .context("failed to create assets subdirectory")?;
+            create_assets_dir(&package.path().join(SUBREPO_DIR_NAME), package_id)?;
 
             // Copy baserom
-            if !options.baserom.is_file() {
-                bail!("baserom {:?} is not a file", options.baserom);
-            }
-            // TODO: check baserom sha1 is valid
             let baserom_path = package
                 .path()
                 .join(SUBREPO_DIR_NAME)
-                .join("ver/us/baserom.z64");
-            copy(options.baserom, &baserom_path)
+                .join("ver")
+                .join(region.as_str())
+                .join("baserom.z64");
+            copy(baserom.path(), &baserom_path)
                 .with_context(|| format!("failed to copy baserom to {:?}", baserom_path))?;
 
-            // Create merlon dir
-            create_dir(package.path().join(MERLON_DIR_NAME))
-                .with_context(|| format!("failed to create {MERLON_DIR_NAME} directory"))?;
-
             // Create vscode dir and copy files
             let vscode_dir = package.path().join(VSCODE_DIR_NAME);
-            create_dir(&vscode_dir)
+            create_dir_all(&vscode_dir)
                 .with_context(|| format!("failed to create {} directory", vscode_dir.display()))?;
             write(
                 vscode_dir.join("c_cpp_properties.json"),
@@ -324,13 +590,16 @@ impl InitialisedPackage {
                 }
             }
 
-            let initialised = Self::from_initialised(package)?;
+            let initialised = Self::load(package)?;
+
+            // Split the baserom into assets. This is where a bad baserom would fail, so report it distinctly.
+            initialised.extract_baserom_assets()?;
 
             // Add decomp as dependency
             let main_head = initialised.git_head_commit()?;
             initialised
                 .package()
-                .edit_manifest(|manifest| manifest.upsert_decomp_dependency(main_head))?;
+                .edit_manifest_unchecked(|manifest| manifest.upsert_decomp_dependency(main_head))?;
 
             // In case there are patches in the package already, apply them
             // i.e. sync patches ---> repo
@@ -342,59 +611,181 @@ impl InitialisedPackage {
                 .apply_patches_to_decomp_repo(&initialised.subrepo_path())?;
 
             // Load dependency patches
-            initialised.setup_git_branches()?;
+            initialised.sync_repo()?;
 
+            remove_file(path_clone.join(INIT_INCOMPLETE_FILE_NAME))
+                .with_context(|| format!("failed to remove {INIT_INCOMPLETE_FILE_NAME}"))?;
             Ok(initialised)
         };
-        match do_it() {
-            Err(e) => {
-                // Cleanup
-                let _ = remove_dir_all(path_clone.join(SUBREPO_DIR_NAME));
-                let _ = remove_dir_all(path_clone.join(MERLON_DIR_NAME));
-                let _ = remove_dir_all(path_clone.join(VSCODE_DIR_NAME));
-                let _ = remove_file(path_clone.join(GITIGNORE_FILE_NAME));
-                Err(e).context(error_context)
-            }
-            result => result,
-        }
+        do_it()
+            .context("the decomp clone was kept, so run `merlon init` again to resume")
+            .context(error_context)
     }
 
-    /// Checks whether a package is initialised.
+    /// Checks whether a package is initialised. Equivalent to [`Package::is_initialised`].
     #[staticmethod]
     pub fn is_initialised(package: &Package) -> Result<bool> {
-        let path = package.path();
+        package.is_initialised()
+    }
 
-        // Check subrepo exists and is a git repo
-        if !path.join(SUBREPO_DIR_NAME).is_dir() {
-            return Ok(false);
+    /// Lists every package in the registry, dependencies first, along with whether the package's branch exists in
+    /// the decomp repository. Each package's commits are kept on a branch named after its ID, which
+    /// [`InitialisedPackage::sync_repo`] recreates, so a missing branch means the repository is out of sync.
+    pub fn list_package_branches(&self) -> Result<Vec<(Id, bool)>> {
+        self.registry
+            .topological_ordering()?
+            .into_iter()
+            .map(|id| Ok((id, self.git_branch_exists(&id.to_string())?)))
+            .collect()
+    }
+
+    /// Checks the working state of the package for problems, such as the wrong branch being checked out in the decomp
+    /// repository or dependency directories that nothing uses. Returns a diagnostic with a suggested fix for each.
+    ///
+    /// Unlike [`Metadata::validate`](super::manifest::Metadata::validate), this is about the package on this machine,
+    /// not what would be exported.
+    pub fn doctor(&self) -> Result<Vec<Diagnostic>> {
+        use DiagnosticKind::*;
+
+        let mut diagnostics = Vec::new();
+        let package_id_string = self.package_id.to_string();
+
+        if self.is_git_dirty()? {
+            diagnostics.push(Diagnostic::new(
+                DirtyTree,
+                format!("{} has uncommitted changes", SUBREPO_DIR_NAME),
+                "commit or stash them",
+            ));
         }
-        let status = Command::new("git")
-            .arg("status")
-            .current_dir(path.join(SUBREPO_DIR_NAME))
-            .stdout(Stdio::null())
-            .status()?;
-        if !status.success() {
-            return Ok(false);
+
+        let current_branch = self.git_current_branch()?;
+        if current_branch != package_id_string && self.git_branch_exists(&package_id_string)? {
+            diagnostics.push(Diagnostic::new(
+                WrongBranch,
+                format!("{} is on branch {} rather than the package's branch", SUBREPO_DIR_NAME, current_branch),
+                format!("run `git checkout {}` in {}", package_id_string, SUBREPO_DIR_NAME),
+            ));
         }
 
-        // Check merlon dir exists
-        if !path.join(MERLON_DIR_NAME).is_dir() {
-            return Ok(false);
+        // The remaining checks assume that the dependency directories match what is declared
+        let inconsistencies: Vec<Inconsistency> = self.registry.is_consistent_with_disk(self.package().path())?
+            .into_iter()
+            .filter(|inconsistency| match inconsistency {
+                // Reported as orphans below
+                Inconsistency::UndeclaredDirectory { path } => !path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.parse::<Id>().ok())
+                    .is_some_and(|id| self.registry.has(id)),
+                _ => true,
+            })
+            .collect();
+        if !inconsistencies.is_empty() {
+            for inconsistency in inconsistencies {
+                let fix = match inconsistency {
+                    Inconsistency::MissingDirectory { .. } | Inconsistency::DeletedDirectory { .. } => {
+                        "add the dependency again with `merlon add`, or remove it from merlon.toml"
+                    }
+                    Inconsistency::UndeclaredDirectory { .. } => {
+                        "remove the directory, or add it as a dependency with `merlon add`"
+                    }
+                    Inconsistency::VersionMismatch { .. } => {
+                        "add a compatible version with `merlon add`, or change the requirement in merlon.toml"
+                    }
+                    Inconsistency::IdMismatch { .. } | Inconsistency::Manifest { .. } => {
+                        "remove the directory and add the dependency again with `merlon add`"
+                    }
+                };
+                diagnostics.push(Diagnostic::new(InconsistentDependencies, inconsistency.to_string(), fix));
+            }
+            return Ok(diagnostics);
         }
 
-        // VSCODE_DIR_NAME and GITIGNORE_FILE_NAME are optional
+        // Not the patch order, which can't be resolved while there are orphans
+        let mut tree: Vec<Id> = self.registry.get_dependencies(self.package_id)?
+            .iter()
+            .filter_map(Dependency::package_id)
+            .collect();
+        tree.sort();
+        tree.insert(0, self.package_id);
+        for id in &tree {
+            let package = self.registry.get_or_error(*id)?;
+            if !self.git_branch_exists(&id.to_string())? {
+                diagnostics.push(Diagnostic::new(
+                    MissingBranch,
+                    format!("package {} has no branch in {}", package, SUBREPO_DIR_NAME),
+                    "run `merlon update` to sync",
+                ));
+            }
+            if !self.subrepo_path().join("assets").join(id.to_string()).is_dir() {
+                diagnostics.push(Diagnostic::new(
+                    MissingAssetsDir,
+                    format!("package {} has no assets directory in {}", package, SUBREPO_DIR_NAME),
+                    "run `merlon update` to sync",
+                ));
+            }
+        }
 
-        Ok(true)
+        for branch in self.git_branches()? {
+            if let Ok(id) = branch.parse::<Id>() {
+                if !self.registry.has(id) {
+                    diagnostics.push(Diagnostic::new(
+                        StaleBranch,
+                        format!("branch {} in {} is for a package that is not a dependency", branch, SUBREPO_DIR_NAME),
+                        format!("run `git branch -D {}` in {}", branch, SUBREPO_DIR_NAME),
+                    ));
+                }
+            }
+        }
+
+        let manifest = self.package().manifest()?;
+        if let Some(pinned_rev) = manifest.get_direct_decomp_dependency_rev() {
+            let main_rev = self.git_rev_parse("main")?;
+            if !main_rev.starts_with(pinned_rev) {
+                diagnostics.push(Diagnostic::new(
+                    DecompRevDrift,
+                    format!("the decomp's main branch is at {} but the manifest pins {}", main_rev, pinned_rev),
+                    format!(
+                        "run `merlon update` to pin the latest revision, or `merlon update --decomp-rev {}` to go back",
+                        pinned_rev,
+                    ),
+                ));
+            }
+        }
+        for (id, rev) in self.registry.get_decomp_rev_skew(self.package_id)? {
+            diagnostics.push(Diagnostic::new(
+                DecompRevSkew,
+                format!("dependency {} pins decomp revision {}", self.registry.get_or_error(id)?, rev),
+                "check that it still builds on this package's revision, or update the dependency",
+            ));
+        }
+
+        let mut orphans: Vec<Id> = self.registry.get_orphans(self.package_id)?.into_iter().collect();
+        orphans.sort();
+        for id in orphans {
+            let package = self.registry.get_or_error(id)?;
+            diagnostics.push(Diagnostic::new(
+                OrphanedDependency,
+                format!("dependency directory {} holds {}, which nothing depends on", package.path().display(), package),
+                "remove the directory, or add it as a dependency with `merlon add`",
+            ));
+        }
+
+        Ok(diagnostics)
     }
 
-    /// Update the decomp repository so that all dependencies and patches are applied.
-    /// Also updates the patches directory if needed.
+    /// Syncs the decomp repository with the package and its dependencies. Call this after changing dependencies,
+    /// e.g. with [`InitialisedPackage::add_dependency`], so that their patches are applied.
     ///
-    /// This will recreate the dependency tree in the subrepo, where each dependency has a branch.
-    /// Additionally, a branch will be created for this package if it doesn't exist, and the branch will be off of
-    /// the branches that this package directly depends on.
-    /// Each branch will have the respective package patches applied to it.
-    pub fn setup_git_branches(&self) -> Result<()> {
+    /// A sync does the following to the subrepo:
+    /// 1. If this package's branch exists, its commits are first saved to `patches/`, so no work is lost.
+    /// 2. `main` is checked out, and the branches of this package and all of its dependencies are deleted.
+    /// 3. For each package in dependency order, a branch named after its ID is created on top of the previous one,
+    ///    and the package's patches are applied to it with `git am`. This package's branch comes last.
+    ///
+    /// Afterwards, this package's branch is checked out, the `asset_stack` of the decomp's `splat.yaml` is set so
+    /// that each package's assets override those of its dependencies, and the resolved dependencies are written to the
+    /// lockfile. Commits on `main` and other branches are left alone.
+    pub fn sync_repo(&self) -> Result<()> {
         // Make sure commits are saved to patches/
         let package_id_string = self.package_id.to_string();
         if self.git_branch_exists(&package_id_string)? {
@@ -405,6 +796,9 @@ impl InitialisedPackage {
 
         log::info!("starting repo sync");
 
+        // Take out the asset stack, in case patches change splat.yaml
+        self.remove_asset_stack()?;
+
         // Switch to main so we can delete branches
         self.git_checkout_branch("main")?;
 
@@ -428,7 +822,7 @@ impl InitialisedPackage {
             deps
         };
         for dependency in dependencies_including_self {
-            if let Dependency::Package { id, .. } = dependency {
+            if let Some(id) = dependency.package_id() {
                 let id_string = id.to_string();
                 if self.git_branch_exists(&id_string)? {
                     self.git_delete_branch(&id_string)?;
@@ -439,12 +833,14 @@ impl InitialisedPackage {
         // Create dependency tree as branches
         let patch_order = self.registry.calc_dependency_patch_order(self.package_id)?;
         let repo = self.subrepo_path();
-        for id in patch_order {
+        for &id in &patch_order {
             let package = self.registry.get_or_error(id)?;
             log::info!("applying patches of package: {}", &package);
             let id_string = id.to_string();
             self.git_create_branch(&id_string)?;
             self.git_checkout_branch(&id_string)?;
+            // Packages from before assets were kept per-package may never have had their directory created
+            create_assets_dir(&repo, id)?;
             let package = self.registry.get_or_error(id)?;
             package.apply_patches_to_decomp_repo(&repo)?;
         }
@@ -452,66 +848,165 @@ impl InitialisedPackage {
             bail!("patch order was incorrect");
         }
 
-        Ok(())
+        self.update_asset_stack(&patch_order)?;
+        self.registry.write_lockfile(self.package_id, &self.lockfile_path())
+    }
+
+    /// The path to the package's lockfile, which [`InitialisedPackage::sync_repo`] writes and building checks.
+    /// See [`lockfile`](super::registry::lockfile).
+    pub fn lockfile_path(&self) -> PathBuf {
+        self.package().path().join(super::registry::lockfile::FILE_NAME)
+    }
+
+    /// Alias of [`InitialisedPackage::sync_repo`], kept for compatibility.
+    pub fn setup_git_branches(&self) -> Result<()> {
+        self.sync_repo()
     }
 
-    /// Returns true if the decomp repository has uncommitted changes.
+    /// Returns true if the decomp repository has uncommitted changes, including untracked files. The asset stack
+    /// that [`InitialisedPackage::sync_repo`] adds to `splat.yaml` doesn't count, since git is told to ignore it.
     pub fn is_git_dirty(&self) -> Result<bool> {
-        let output = Command::new("git")
-            .arg("status")
-            .arg("--porcelain")
-            .current_dir(self.subrepo_path())
-            .output()?;
-        if !output.status.success() {
-            bail!("failed to run git status");
+        let repo = self.git_repo()?;
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).include_ignored(false);
+        let statuses = repo.statuses(Some(&mut options)).context("failed to get git status")?;
+        Ok(!statuses.is_empty())
+    }
+
+    /// The path to the directory that this package's custom assets are in.
+    pub fn assets_path(&self) -> PathBuf {
+        self.subrepo_path().join("assets").join(self.package_id.to_string())
+    }
+
+    /// Checks the package's custom assets against the formats the decomp expects, returning a warning for each
+    /// problem found. See [`super::asset`].
+    pub fn check_assets(&self) -> Result<Vec<Warning>> {
+        super::asset::validate_assets_in(&self.assets_path())
+    }
+
+    /// Checks whether dependencies were made against a different decomp revision than this package uses,
+    /// returning a warning for each one. Such dependencies may have patches that don't apply cleanly.
+    pub fn check_decomp_revs(&self) -> Result<Vec<String>> {
+        let manifest = self.package().manifest()?;
+        let root_rev = match manifest.get_direct_decomp_dependency_rev() {
+            Some(rev) => rev.to_owned(),
+            None => return Ok(vec!["package does not pin a decomp revision".to_owned()]),
+        };
+        let mut warnings = Vec::new();
+        for (id, rev) in self.registry.get_decomp_rev_skew(self.package_id)? {
+            let package = self.registry.get_or_error(id)?;
+            let relation = if self.git_is_ancestor(&rev, &root_rev)? {
+                "older than"
+            } else if self.git_is_ancestor(&root_rev, &rev)? {
+                "newer than"
+            } else {
+                "unrelated to"
+            };
+            warnings.push(format!(
+                "dependency {} uses decomp revision {}, which is {} this package's {}",
+                package, rev, relation, root_rev,
+            ));
         }
-        Ok(!output.stdout.is_empty())
+        Ok(warnings)
     }
 
     /// Builds the ROM and returns the path to the output ROM.
     pub fn build_rom(&self, options: BuildRomOptions) -> Result<Rom> {
-        let dir = self.subrepo_path();
+        let ninja = self.check_can_build()?;
 
         // Configure
         // TODO: only do this if we have to (i.e. file tree changed) - maybe ask git?
         if !options.skip_configure {
-            let mut command = Command::new("./configure");
-            command
-                //.arg("--non-matching")
-                //.arg("--debug")
-                .arg("--shift")
-                .arg("us");
-            if options.clean {
-                command.arg("--clean");
-            }
-            let status = command.current_dir(&dir).status()?;
-            if !status.success() {
-                bail!("failed to configure");
-            }
+            self.configure(options.clean)?;
         }
 
         // Build
-        let status = Command::new("ninja").current_dir(&dir).status()?;
-        if !status.success() {
-            bail!("failed to build");
-        }
+        let rom = self.ninja_build(&ninja, self.region()?)?;
 
         // Copy output file if needed
-        let rom = dir.join("ver/us/build/papermario.z64");
         if let Some(output) = options.output {
-            std::fs::copy(rom, &output)?;
+            let output = Rom::extension_normalized(&output, options.if_exists)?;
+            rom::write_atomically(&output, |temp_path| {
+                std::fs::copy(&rom, temp_path)
+                    .with_context(|| format!("failed to copy ROM to {}", output.display()))?;
+                Ok(())
+            })?;
+            if options.with_symbols {
+                self.copy_symbols_next_to(&output)?;
+            }
             Ok(output.into())
         } else {
+            // Symbols are already next to the ROM in the build directory
             Ok(rom.into())
         }
     }
 
+    /// Returns true if the decomp has a test target to run with [`InitialisedPackage::run_tests`].
+    /// Older decomp revisions don't have one. The decomp must have been configured, e.g. by building.
+    pub fn has_test_target(&self) -> Result<bool> {
+        let output = Command::new(ninja_program())
+            .arg("-t")
+            .arg("targets")
+            .arg("all")
+            .current_dir(self.subrepo_path())
+            .output()?;
+        if !output.status.success() {
+            bail!("failed to list ninja targets: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.split(':').next() == Some(TEST_TARGET)))
+    }
+
+    /// The path that the output of the last [`InitialisedPackage::run_tests`] is written to.
+    pub fn test_log_path(&self) -> PathBuf {
+        self.package().path().join(TEST_LOG_FILE_NAME)
+    }
+
+    /// Builds the ROM, then runs the decomp's test suite against the modified tree.
+    /// Returns whether the tests passed. Their output is written to [`InitialisedPackage::test_log_path`].
+    pub fn run_tests(&self, options: BuildRomOptions) -> Result<bool> {
+        self.build_rom(options)?;
+        if !self.has_test_target()? {
+            bail!(
+                "this decomp revision has no `{}` target, so there are no tests to run - try `merlon update`",
+                TEST_TARGET,
+            );
+        }
+
+        let log_path = self.test_log_path();
+        let log = std::fs::File::create(&log_path)
+            .with_context(|| format!("failed to create {}", log_path.display()))?;
+        let status = Command::new(ninja_program())
+            .arg(TEST_TARGET)
+            .current_dir(self.subrepo_path())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .status()?;
+        log::info!("wrote test output to {}", log_path.display());
+        Ok(status.success())
+    }
+
+    /// Builds the ROM and copies the decomp's ELF and symbol map next to it.
+    /// Equivalent to [`InitialisedPackage::build_rom`] with `with_symbols` set.
+    pub fn export_rom_with_symbols(&self, mut options: BuildRomOptions) -> Result<Rom> {
+        options.with_symbols = true;
+        self.build_rom(options)
+    }
+
     /// Writes the patches required to take the repo from the nearest dependency to this package's branch into the patches dir.
     pub fn update_patches_dir(&self) -> Result<()> {
         let package_id_str = self.package_id.to_string();
         if self.git_current_branch()? != package_id_str {
             bail!("repo is not on package branch {}", package_id_str);
         }
+
+        // Git ignores splat.yaml while it has the asset stack, so take it out to see any changes the user made to it
+        self.remove_asset_stack()?;
+        let patch_order = self.registry.calc_dependency_patch_order(self.package_id)?;
+        defer! {
+            warn_if_err(self.update_asset_stack(&patch_order));
+        }
         if self.is_git_dirty()? {
             bail!("repo is dirty, commit changes and try again");
         }
@@ -522,81 +1017,130 @@ impl InitialisedPackage {
         create_dir(&dir)
             .with_context(|| format!("failed to create patches dir {}", dir.display()))?;
 
-        // Figure out which branch to diff against.
-        // We want to diff against the nearest dependency, but if that doesn't exist, we want to diff against main.
-        let branch_order = std::iter::once("main".to_string()).chain(
-            self.registry()
-                .calc_dependency_patch_order(self.package_id)?
-                .into_iter()
-                .map(|id| id.to_string()),
-        );
-        let mut diff_against = None;
-        for branch in branch_order.rev() {
-            if branch != package_id_str && self.git_branch_exists(&branch)? {
-                diff_against = Some(branch);
-                break;
+        let count = self.write_patches_to(&dir)?;
+        log::info!("saved {} patches", count);
+        Ok(())
+    }
+
+    /// Adds a dependency by copying it into the dependencies directory and registering it.
+    /// If the dependency already exists, it will be updated.
+    /// Specifically, it will be copied into `.merlon/dependencies/<package_id>`.
+    /// If adding the dependency fails after it was copied, the copy is removed again.
+    pub fn add_dependency(&mut self, options: AddDependencyOptions) -> Result<Id> {
+        if let Some(url) = options.git {
+            return self.add_git_dependency(&url, options.rev.as_deref());
+        }
+        let path = options.path.context("no path or git repository given for the dependency")?;
+        self.add_dependency_from_path(path, None)
+    }
+
+    /// Removes a direct dependency: the inverse of [`InitialisedPackage::add_dependency`]. The dependency is
+    /// undeclared in the manifest and the decomp repository is synced without it. Only then is it unregistered and
+    /// its directory in `.merlon/dependencies` deleted, along with any of its own dependencies that nothing else
+    /// needs. If the sync fails, the dependency is declared again and nothing is deleted.
+    ///
+    /// Errors if another dependency still requires it.
+    pub fn remove_dependency(&mut self, id: Id) -> Result<()> {
+        let is_direct = self.package().manifest()?
+            .iter_direct_dependencies()
+            .any(|dependency| dependency.package_id() == Some(id));
+        if !is_direct {
+            bail!("{} is not a direct dependency of {}", id, self.package());
+        }
+        let mut dependents = Vec::new();
+        for dependency in self.registry.get_dependencies(self.package_id)? {
+            if let Some(dependent) = dependency.package_id() {
+                if dependent != id && self.registry.has(dependent) && self.registry.has_dependency(dependent, id)? {
+                    dependents.push(self.registry.get_or_error(dependent)?.to_string());
+                }
             }
         }
-        let diff_against = diff_against.ok_or_else(|| anyhow!("no branch to diff against"))?;
-        let diff_against_package_name = match diff_against.as_str() {
-            "main" => "Paper Mario (N64) decompilation".to_string(),
-            _ => {
-                let package = self.registry.get_or_error(diff_against.parse()?)?;
-                format!("{}", package)
+        if !dependents.is_empty() {
+            dependents.sort();
+            bail!("cannot remove {}, because it is required by: {}", id, dependents.join(", "));
+        }
+
+        let orphans_before = self.registry.get_orphans(self.package_id)?;
+        let mut undeclared = None;
+        self.package().edit_manifest(|manifest| {
+            undeclared = Some(manifest.undeclare_direct_dependency(id)?);
+            Ok(())
+        })?;
+        let mut removed = vec![id];
+        removed.extend(self.registry.get_orphans(self.package_id)?.difference(&orphans_before));
+        let mut taken = Vec::new();
+        for &id in &removed {
+            if self.registry.has(id) {
+                taken.push((id, self.registry.take(id)?));
             }
-        };
-        log::info!(
-            "saving patches since dependency: {}",
-            &diff_against_package_name
-        );
+        }
 
-        // Create patches
-        let status = Command::new("git")
-            .arg("format-patch")
-            .arg(format!("{}..HEAD", diff_against))
-            .arg("-o")
-            .arg(&dir.canonicalize()?)
-            .arg("--minimal")
-            .arg("--binary")
-            .arg("--ignore-cr-at-eol")
-            .arg("--function-context") // Maybe?
-            .arg("--keep-subject")
-            .arg("--no-merges")
-            .arg("--no-stdout")
-            .arg("--")
-            .arg("src")
-            .arg("include")
-            .arg("assets") //.arg(format!("assets/{}", package_name))
-            .arg("ver/us")
-            .arg("--no-track") // Don't track the branch on origin, since origin is the original decomp repo
-            .current_dir(self.subrepo_path())
-            .status()?;
-        if !status.success() {
-            bail!("failed git format-patch");
+        if let Err(error) = self.sync_repo() {
+            for (id, package) in taken {
+                self.registry.register_lazily(id, package)?;
+            }
+            if let Some(dependency) = undeclared {
+                self.package().edit_manifest(|manifest| manifest.declare_direct_dependency(dependency))
+                    .context("failed to declare the dependency again")?;
+            }
+            return Err(error.context(format!("failed to sync repo without {}", id)));
         }
 
-        // List patches
-        let patches = std::fs::read_dir(&dir)?
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                let path = entry.path();
-                if path.extension()? == "patch" {
-                    Some(path)
-                } else {
-                    None
+        for (_, package) in taken {
+            log::info!("removing dependency: {}", package);
+            remove_dir_all(package.path())
+                .with_context(|| format!("failed to remove dependency directory {}", package.path().display()))?;
+        }
+        for id in removed {
+            let id_string = id.to_string();
+            if self.git_branch_exists(&id_string)? {
+                self.git_delete_branch(&id_string)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the requested revision of every git dependency of this package again, or the default branch if none
+    /// was requested, and updates the dependencies and the manifest to it. Dependencies pinned to a tag or commit
+    /// hash therefore stay where they are. Returns the IDs of the dependencies that changed revision.
+    pub fn update_git_dependencies(&mut self) -> Result<Vec<Id>> {
+        let git_dependencies: Vec<(Id, String, String, Option<String>)> = self.package().manifest()?
+            .iter_direct_dependencies()
+            .filter_map(|dependency| match dependency {
+                Dependency::Git { id, url, rev, requested_rev } => {
+                    Some((*id, url.clone(), rev.clone(), requested_rev.clone()))
                 }
+                _ => None,
             })
-            .collect::<Vec<_>>();
-        log::info!("saved {} patches", patches.len());
+            .collect();
+        let mut updated = Vec::new();
+        for (id, url, rev, requested_rev) in git_dependencies {
+            log::info!("fetching git dependency {}", url);
+            let (_clone, new_rev, path) = clone_git_package(&url, requested_rev.as_deref())?;
+            if new_rev == rev {
+                continue;
+            }
+            let new_id = self.add_dependency_from_path(path, Some((url.clone(), new_rev, requested_rev)))?;
+            if new_id != id {
+                bail!("git dependency {} now contains package {} instead of {}", url, new_id, id);
+            }
+            updated.push(id);
+        }
+        Ok(updated)
+    }
+}
 
-        Ok(())
+impl InitialisedPackage {
+    /// Clones a git repository and adds the package in it as a dependency, pinned to the commit that `rev` (or the
+    /// default branch) resolves to.
+    fn add_git_dependency(&mut self, url: &str, requested_rev: Option<&str>) -> Result<Id> {
+        let (_clone, rev, path) = clone_git_package(url, requested_rev)?;
+        self.add_dependency_from_path(path, Some((url.to_owned(), rev, requested_rev.map(str::to_owned))))
     }
 
-    /// Adds a dependency by copying it into the dependencies directory and registering it.
-    /// If the dependency already exists, it will be updated.
-    /// Specifically, it will be copied into `.merlon/dependencies/<package_id>`.
-    pub fn add_dependency(&mut self, options: AddDependencyOptions) -> Result<Id> {
-        let path = options.path;
+    /// Adds the package or distributable at `path` as a dependency. If `git` is given, as a URL, commit hash and the
+    /// revision that was asked for, the dependency is declared as a git dependency rather than by version.
+    fn add_dependency_from_path(&mut self, path: PathBuf, git: Option<(String, String, Option<String>)>) -> Result<Id> {
         let dependencies_dir = self.package().path().join(DEPENDENCIES_DIR_NAME);
         create_dir_all(&dependencies_dir).with_context(|| {
             format!(
@@ -604,6 +1148,17 @@ impl InitialisedPackage {
                 dependencies_dir.display()
             )
         })?;
+
+        // If anything fails after the dependency directory is created, remove it again so that a failed add leaves
+        // no trace. Otherwise, the half-added dependency would be registered the next time the package is loaded.
+        let mut rollback = scopeguard::guard(None::<PathBuf>, |dir| {
+            if let Some(dir) = dir {
+                if let Err(error) = remove_dir_all(&dir) {
+                    log::warn!("failed to remove dependency directory {}: {}", dir.display(), error);
+                }
+            }
+        });
+
         let package = if super::is_unexported_package(&path) {
             let package =
                 Package::try_from(path).context("failed to open dependency as package")?;
@@ -614,6 +1169,7 @@ impl InitialisedPackage {
                 log::info!("dependency directory already exists, updating it");
                 remove_dir_all(&path)?;
             }
+            *rollback = Some(path.clone());
             let package = package
                 .clone_to_dir(path)
                 .context("failed to clone package to dependencies dir")?;
@@ -623,9 +1179,7 @@ impl InitialisedPackage {
                 log::info!("copying dependencies of new dependency to this package");
                 for id in initialised.registry().package_ids() {
                     if !self.registry.has(id) {
-                        self.add_dependency(AddDependencyOptions {
-                            path: self.registry.get_or_error(id)?.path().to_owned(),
-                        })?;
+                        self.add_dependency_from_path(self.registry.get_or_error(id)?.path().to_owned(), None)?;
                     }
                 }
             }
@@ -635,7 +1189,7 @@ impl InitialisedPackage {
             let distributable = Distributable::try_from(path)
                 .context("failed to open dependency as distributable")?;
             let manifest = distributable
-                .manifest(self.baserom_path())
+                .manifest(self.baserom_path()?)
                 .context("failed to read dependency manifest")?;
             let package_id = manifest.metadata().id().to_string();
             let path = dependencies_dir.join(package_id);
@@ -643,10 +1197,11 @@ impl InitialisedPackage {
                 log::info!("dependency directory already exists, updating it");
                 remove_dir_all(&path).context("failed to remove existing dependency directory")?;
             }
+            *rollback = Some(path.clone());
             distributable
                 .open_to_dir(super::distribute::OpenOptions {
                     output: Some(path),
-                    baserom: self.baserom_path(),
+                    baserom: self.baserom_path()?,
                 })
                 .context("failed to open distributable to dependencies dir")?
         } else {
@@ -657,26 +1212,59 @@ impl InitialisedPackage {
         };
         log::info!("adding dependency: {}", package);
         let id = package.id()?;
-        let id = match self.registry.has(id) {
-            true => id,
-            false => self.registry.register(package)?,
+        let newly_registered = !self.registry.has(id);
+        let id = match newly_registered {
+            true => self.registry.register(package)?,
+            false => id,
         };
-        let dependency: Dependency = self
-            .registry
-            .get_or_error(id)
-            .context("dependency not added to registry correctly")?
-            .try_into()?;
-        self.package()
-            .edit_manifest(move |manifest| manifest.declare_direct_dependency(dependency))?;
+        let declare = || -> Result<()> {
+            let dependency: Dependency = match git {
+                Some((url, rev, requested_rev)) => Dependency::Git { id, url, rev, requested_rev },
+                None => self
+                    .registry
+                    .get_or_error(id)
+                    .context("dependency not added to registry correctly")?
+                    .try_into()?,
+            };
+            self.package()
+                .edit_manifest_unchecked(move |manifest| manifest.declare_direct_dependency(dependency))
+        };
+        if let Err(error) = declare() {
+            if newly_registered {
+                let _ = self.registry.take(id);
+            }
+            return Err(error);
+        }
+
+        // Keep the dependency directory
+        scopeguard::ScopeGuard::into_inner(rollback);
         Ok(id)
     }
 }
 
 impl InitialisedPackage {
+    /// Loads a package and its dependencies without checking that it is initialised, e.g. partway through
+    /// initialising it.
+    fn load(package: Package) -> Result<Self> {
+        let dependencies_dir_path = package.path().join(DEPENDENCIES_DIR_NAME);
+
+        // Create registry of this package and .merlon/dependencies/*
+        let mut registry = Registry::new();
+        let package_id = registry.register(package)?;
+        if dependencies_dir_path.is_dir() {
+            register_dependencies_dir(&mut registry, &dependencies_dir_path)?;
+        }
+
+        Ok(Self {
+            registry,
+            package_id,
+        })
+    }
+
     /// The package that this InitialisedPackage was created from.
     pub fn package(&self) -> &Package {
         self.registry
-            .get(self.package_id)
+            .get_or_error(self.package_id)
             .expect("package somehow removed from registry")
     }
 
@@ -685,106 +1273,492 @@ impl InitialisedPackage {
         &self.registry
     }
 
-    fn git_create_branch(&self, branch_name: &str) -> Result<()> {
-        let status = Command::new("git")
-            .arg("branch")
-            .arg(&branch_name)
+    /// The path to the base ROM of the given region.
+    pub fn region_baserom_path(&self, region: Region) -> PathBuf {
+        self.subrepo_path().join("ver").join(region.as_str()).join("baserom.z64")
+    }
+
+    /// Builds the ROM for every region in the manifest, reconfiguring the decomp for each, and returns the built
+    /// ROMs by region. See [`Metadata::regions`](super::manifest::Metadata::regions).
+    ///
+    /// Every region must have its base ROM in the decomp. Per-region ROMs are left in the decomp's build
+    /// directories, so `options.output` is not supported.
+    pub fn build_all_regions(&self, options: BuildRomOptions) -> Result<Vec<(Region, Rom)>> {
+        if options.output.is_some() {
+            bail!("cannot copy ROMs of every region to a single output path");
+        }
+        let regions = self.package().manifest()?.metadata().regions();
+
+        // Check every region is set up before spending time building any of them
+        for region in &regions {
+            let baserom = self.region_baserom_path(*region);
+            if !baserom.is_file() {
+                bail!(
+                    "the decomp is not set up for region {}: {} is missing. Copy an unmodified {} base ROM there, \
+                    or re-initialise the package",
+                    region,
+                    baserom.display(),
+                    region,
+                );
+            }
+            crate::baserom::check_region(&Rom::from(baserom), *region)
+                .map_err(|error| crate::Error::Baserom(error.to_string()))?;
+        }
+
+        let ninja = self.check_can_build()?;
+        let mut roms = Vec::new();
+        for region in regions {
+            log::info!("building region {}", region);
+            configure_decomp(&self.subrepo_path(), region, options.clean)
+                .with_context(|| format!("failed to configure region {}", region))?;
+            let rom = self.ninja_build(&ninja, region)
+                .with_context(|| format!("failed to build region {}", region))?;
+            roms.push((region, rom.into()));
+        }
+        Ok(roms)
+    }
+
+    /// Checks that the package's patches fully describe its ROM, by building the ROM both from the package branch and
+    /// from a fresh clone of the decomp with every package's patches applied, and comparing the two.
+    ///
+    /// This catches changes that the patches don't capture, such as uncommitted or untracked files. It's slow, since
+    /// it builds from scratch. Returns the SHA1 of the ROM.
+    pub fn verify_reproducible_build(&self) -> Result<String> {
+        let live_rom = self.build_rom(BuildRomOptions::default())?;
+        let live_sha1 = live_rom.sha1_string()?;
+
+        let temp_dir = temp_dir::TempDir::new()
+            .context("failed to create temporary directory")?;
+        let clone = temp_dir.path().join(SUBREPO_DIR_NAME);
+        log::info!("building from patches in {}", clone.display());
+        let status = run_git(Command::new("git")
+            .arg("clone")
+            .arg("--shared")
+            .arg("--no-checkout")
+            .arg(self.subrepo_path())
+            .arg(&clone))?;
+        if !status.success() {
+            bail!("failed to clone decomp repository");
+        }
+        let status = run_git(Command::new("git")
+            .arg("checkout")
+            .arg("--detach")
+            .arg("origin/main")
+            .current_dir(&clone))?;
+        if !status.success() {
+            bail!("failed to check out decomp");
+        }
+        for id in self.registry().calc_dependency_patch_order(self.package_id)? {
+            let package = self.registry().get_or_error(id)?;
+            let patch_files = package.patch_files()?;
+            if !patch_files.is_empty() && !super::git_am(&patch_files, &clone)?.success() {
+                bail!("patches of {} do not apply to a fresh clone of the decomp", package);
+            }
+        }
+        let region = self.region()?;
+        let region_dir = clone.join("ver").join(region.as_str());
+        copy(self.baserom_path()?, region_dir.join("baserom.z64"))?;
+        configure_decomp(&clone, region, false)?;
+        let status = Command::new(ninja_program()).current_dir(&clone).status()?;
+        if !status.success() {
+            bail!(crate::Error::Build("failed to build from patches".to_owned()));
+        }
+        let clean_sha1 = Rom::from(region_dir.join("build/papermario.z64")).sha1_string()?;
+
+        if clean_sha1 != live_sha1 {
+            let dirty_files = Command::new("git")
+                .arg("status")
+                .arg("--porcelain")
+                .current_dir(self.subrepo_path())
+                .output()?
+                .stdout;
+            bail!(
+                "build is not reproducible from the package's patches: the decomp repo builds a ROM with SHA1 {}, \
+                but its patches build a ROM with SHA1 {}. Make sure all changes are committed.\n\
+                Uncommitted changes:\n{}",
+                live_sha1,
+                clean_sha1,
+                String::from_utf8_lossy(&dirty_files),
+            );
+        }
+        log::info!("build is reproducible (SHA1 {})", live_sha1);
+        Ok(live_sha1)
+    }
+
+    /// Returns the branch that this package's patches apply on top of: the branch of the nearest dependency, or
+    /// `main` if there are no dependencies.
+    pub(crate) fn patch_base_branch(&self) -> Result<String> {
+        let package_id_str = self.package_id.to_string();
+        let branch_order = std::iter::once("main".to_string()).chain(
+            self.registry()
+                .calc_dependency_patch_order(self.package_id)?
+                .into_iter()
+                .map(|id| id.to_string()),
+        );
+        for branch in branch_order.rev() {
+            if branch != package_id_str && self.git_branch_exists(&branch)? {
+                return Ok(branch);
+            }
+        }
+        Err(anyhow!("no branch to diff against"))
+    }
+
+    /// Writes the patches for this package's branch into the given existing directory, and returns how many were
+    /// written. Unlike [`InitialisedPackage::update_patches_dir`], uncommitted changes are allowed (and ignored).
+    pub fn write_patches_to(&self, dir: &Path) -> Result<usize> {
+        let package_id_str = self.package_id.to_string();
+        if self.git_current_branch()? != package_id_str {
+            bail!("repo is not on package branch {}", package_id_str);
+        }
+
+        let diff_against = self.patch_base_branch()?;
+        let diff_against_package_name = match diff_against.as_str() {
+            "main" => "Paper Mario (N64) decompilation".to_string(),
+            _ => {
+                let package = self.registry.get_or_error(diff_against.parse()?)?;
+                format!("{}", package)
+            }
+        };
+        log::info!(
+            "saving patches since dependency: {}",
+            &diff_against_package_name
+        );
+
+        // Create patches
+        let patch_context = self.package().manifest()?.metadata().patch_context();
+        let status = run_git(Command::new("git")
+            .arg("format-patch")
+            .arg(format!("{}..HEAD", diff_against))
+            .arg("-o")
+            .arg(&dir.canonicalize()?)
+            .arg("--minimal")
+            .arg("--binary")
+            .arg("--ignore-cr-at-eol")
+            .args(patch_context.format_patch_args())
+            .arg("--keep-subject")
+            .arg("--no-merges")
+            .arg("--no-stdout")
+            .arg("--")
+            .arg("src")
+            .arg("include")
+            .arg("assets") //.arg(format!("assets/{}", package_name))
+            .arg("ver")
             .arg("--no-track") // Don't track the branch on origin, since origin is the original decomp repo
-            .current_dir(self.subrepo_path())
-            .status()?;
+            .current_dir(self.subrepo_path()))?;
         if !status.success() {
-            bail!("failed to create git branch {}", branch_name);
+            bail!("failed git format-patch");
         }
-        Ok(())
+
+        // Count patches
+        let count = std::fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let path = entry.path();
+                if path.extension()? == "patch" {
+                    Some(path)
+                } else {
+                    None
+                }
+            })
+            .count();
+        Ok(count)
     }
 
-    fn git_current_branch(&self) -> Result<String> {
-        let output = Command::new("git")
-            .arg("rev-parse")
-            .arg("--abbrev-ref")
-            .arg("HEAD")
-            .current_dir(self.subrepo_path())
-            .output()?;
-        if !output.status.success() {
-            panic!("failed to run git rev-parse");
+    /// Builds the ROM, then watches the package patches, manifest, and decomp source for changes and rebuilds
+    /// whenever they change. The given callback is called with the result of every build; returning an error
+    /// from it stops watching. Otherwise, runs until interrupted.
+    ///
+    /// Rebuilds only reconfigure if files were created, removed, or renamed, since edits alone don't require it.
+    pub fn watch_build<F>(&self, options: BuildRomOptions, mut on_build: F) -> Result<()>
+    where
+        F: FnMut(Result<Rom>) -> Result<()>,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .context("failed to create file watcher")?;
+        let subrepo = self.subrepo_path();
+        let watched_dirs = [
+            self.package().path().join(PATCHES_DIR_NAME),
+            subrepo.join("src"),
+            subrepo.join("include"),
+            subrepo.join("assets"),
+        ];
+        for dir in watched_dirs.iter().filter(|dir| dir.is_dir()) {
+            watcher.watch(dir, RecursiveMode::Recursive)
+                .with_context(|| format!("failed to watch {}", dir.display()))?;
+        }
+        watcher.watch(&self.package().path().join(MANIFEST_FILE_NAME), RecursiveMode::NonRecursive)
+            .context("failed to watch manifest")?;
+
+        let mut options = options;
+        let first_build = self.build_rom(options.clone());
+        if let (Some(_), Ok(rom)) = (&options.output, &first_build) {
+            // Keep writing to wherever the first build went, in case it was renamed
+            options.output = Some(rom.path().to_owned());
+        }
+        on_build(first_build)?;
+        loop {
+            // Wait for a change, then keep collecting changes until they settle
+            let event = receiver.recv().context("file watcher stopped")?;
+            let mut file_tree_changed = changes_file_tree(&event?);
+            while let Ok(event) = receiver.recv_timeout(WATCH_DEBOUNCE) {
+                file_tree_changed |= changes_file_tree(&event?);
+            }
+
+            log::info!("change detected, rebuilding");
+            let mut options = options.clone();
+            options.clean = false;
+            options.if_exists = OverwritePolicy::Overwrite; // Replace the ROM from the previous build
+            options.skip_configure |= !file_tree_changed;
+            on_build(self.build_rom(options))?;
+
+            // Ignore changes made by the build itself, e.g. asset extraction during configure
+            while receiver.try_recv().is_ok() {}
         }
-        String::from_utf8(output.stdout)
-            .map(|s| s.trim().to_string())
-            .map_err(Into::into)
     }
 
-    fn git_checkout_branch(&self, branch_name: &str) -> Result<()> {
-        let status = Command::new("git")
-            .arg("checkout")
-            .arg(&branch_name)
-            .current_dir(self.subrepo_path())
-            .status()?;
+    /// Runs the decomp's configure script for the package's region, which also splits assets out of the baserom.
+    fn configure(&self, clean: bool) -> Result<()> {
+        configure_decomp(&self.subrepo_path(), self.region()?, clean)
+    }
+
+    /// Checks that the package can be built, returning the ninja executable to build with.
+    fn check_can_build(&self) -> Result<OsString> {
+        // The package or any of its dependencies may need features of a newer Merlon
+        for id in self.registry().package_ids() {
+            self.registry().get_or_error(id)?.manifest()?.metadata().check_merlon_version()?;
+        }
+
+        // Build against the dependencies that were locked, if any
+        let lockfile_path = self.lockfile_path();
+        if lockfile_path.is_file() {
+            let lockfile = Lockfile::read_from_path(&lockfile_path)?;
+            self.registry.verify_against_lockfile(self.package_id, &lockfile)
+                .context("dependencies have changed since they were locked, run `merlon update` to sync them")?;
+        }
+
+        // Check for build tools up front, since a missing one otherwise fails with an unhelpful IO error
+        let ninja = ninja_program();
+        require_program(&ninja, if cfg!(target_os = "macos") {
+            "ninja is required to build; install it with `brew install ninja`, or set MERLON_NINJA to its path"
+        } else {
+            "ninja is required to build; install it with your package manager (e.g. `sudo apt install ninja-build`), or set MERLON_NINJA to its path"
+        })?;
+        Ok(ninja)
+    }
+
+    /// Runs ninja in the configured decomp and returns the path of the ROM built for the given region.
+    fn ninja_build(&self, ninja: &OsStr, region: Region) -> Result<PathBuf> {
+        let dir = self.subrepo_path();
+        let status = Command::new(ninja).current_dir(&dir).status()?;
         if !status.success() {
-            bail!("failed to checkout git branch {}", branch_name);
+            bail!(crate::Error::Build("failed to build".to_owned()));
+        }
+        Ok(dir.join("ver").join(region.as_str()).join("build/papermario.z64"))
+    }
+
+    /// Extracts assets from the baserom and checks that they were extracted for the expected region.
+    fn extract_baserom_assets(&self) -> Result<()> {
+        log::info!("extracting assets from baserom");
+        let region = self.region()?;
+        let region_name = region.as_str().to_uppercase();
+        let baserom = Rom::from(self.baserom_path()?);
+        self.configure(false).with_context(|| crate::Error::Baserom(format!(
+            "failed to extract assets from baserom {}; make sure it is an unmodified {} Paper Mario ROM",
+            baserom.path().display(),
+            region_name,
+        )))?;
+
+        let assets_dir = self.subrepo_path().join("assets").join(region.as_str());
+        let has_assets = assets_dir.is_dir() && assets_dir.read_dir()?.next().is_some();
+        if !has_assets {
+            bail!(
+                "asset extraction did not produce {}; make sure the baserom is an unmodified {} Paper Mario ROM",
+                assets_dir.display(),
+                region_name,
+            );
+        }
+
+        // The decomp records the SHA1 of the ROM it expects in each region's splat config
+        let splat_config = self.subrepo_path().join(splat_config_path(region));
+        let expected_sha1 = std::fs::read_to_string(&splat_config)
+            .with_context(|| format!("failed to read {}", splat_config.display()))?
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("sha1:").map(|sha1| sha1.trim().to_owned()));
+        match expected_sha1 {
+            Some(expected_sha1) => {
+                let actual_sha1 = baserom.sha1_string()?;
+                if actual_sha1 != expected_sha1 {
+                    bail!(
+                        "baserom is not the region the decomp expects (expected SHA1 {}, got {})",
+                        expected_sha1,
+                        actual_sha1,
+                    );
+                }
+            }
+            None => log::warn!("{} has no sha1, cannot verify baserom region", splat_config.display()),
         }
         Ok(())
     }
 
-    fn git_stash(&self) -> Result<()> {
-        let status = Command::new("git")
-            .arg("stash")
-            .current_dir(self.subrepo_path())
-            .status()?;
-        if !status.success() {
-            bail!("failed to run git stash");
+    /// Copies `papermario.elf` and `papermario.map` from the build directory next to the given ROM path.
+    /// Artifacts that the build did not produce are skipped with a warning.
+    fn copy_symbols_next_to(&self, rom_path: &Path) -> Result<()> {
+        let build_dir = self.subrepo_path().join("ver").join(self.region()?.as_str()).join("build");
+        for extension in ["elf", "map"] {
+            let artifact = build_dir.join("papermario").with_extension(extension);
+            if !artifact.is_file() {
+                log::warn!("build did not produce {}, skipping", artifact.display());
+                continue;
+            }
+            let destination = rom_path.with_extension(extension);
+            copy(&artifact, &destination)
+                .with_context(|| format!("failed to copy {} to {}", artifact.display(), destination.display()))?;
+            log::info!("copied symbols to {}", destination.display());
         }
         Ok(())
     }
 
+    /// Opens the decomp repository. Branch bookkeeping is done in-process with it, whereas commands that libgit2
+    /// has no equivalent of, such as `git format-patch` and `git am`, are run with [`run_git`]. Errors from it have
+    /// the [`git2::Error`] as their source.
+    fn git_repo(&self) -> Result<Repository> {
+        let path = self.subrepo_path();
+        Repository::open(&path).with_context(|| format!("failed to open git repository {}", path.display()))
+    }
+
+    fn git_create_branch(&self, branch_name: &str) -> Result<()> {
+        let repo = self.git_repo()?;
+        // Not tracking a branch on origin, since origin is the original decomp repo
+        repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .and_then(|commit| repo.branch(branch_name, &commit, false))
+            .with_context(|| format!("failed to create git branch {}", branch_name))?;
+        Ok(())
+    }
+
+    /// Returns the name of the checked out branch, or `HEAD` if it is detached.
+    fn git_current_branch(&self) -> Result<String> {
+        let repo = self.git_repo()?;
+        let head = repo.head().context("failed to get git HEAD")?;
+        if !head.is_branch() {
+            return Ok("HEAD".to_owned());
+        }
+        head.shorthand()
+            .map(str::to_owned)
+            .context("git branch name is not valid UTF-8")
+    }
+
+    /// Checks out a local branch, keeping uncommitted changes to files that are the same on both branches. Any
+    /// other revision is checked out with a detached HEAD.
+    fn git_checkout_branch(&self, branch_name: &str) -> Result<()> {
+        let repo = self.git_repo()?;
+        let checkout = || -> std::result::Result<(), git2::Error> {
+            match repo.find_branch(branch_name, BranchType::Local) {
+                Ok(branch) => {
+                    let reference = branch.into_reference();
+                    let commit = reference.peel_to_commit()?;
+                    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+                    repo.set_head(reference.name().unwrap_or_default())
+                }
+                Err(error) if error.code() == ErrorCode::NotFound => {
+                    let commit = repo.revparse_single(branch_name)?.peel_to_commit()?;
+                    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+                    repo.set_head_detached(commit.id())
+                }
+                Err(error) => Err(error),
+            }
+        };
+        checkout().with_context(|| format!("failed to checkout git branch {}", branch_name))
+    }
+
+    /// Stashes uncommitted changes to tracked files, like `git stash`. Does nothing if there are none.
+    fn git_stash(&self) -> Result<()> {
+        let mut repo = self.git_repo()?;
+        // The stash is popped straight away, so who made it doesn't matter if the user has no identity configured
+        let stasher = repo.signature()
+            .or_else(|_| Signature::now("Merlon", "merlon@localhost"))
+            .context("failed to create git signature")?;
+        match repo.stash_save2(&stasher, None, None) {
+            Ok(_) => Ok(()),
+            Err(error) if error.code() == ErrorCode::NotFound => Ok(()),
+            Err(error) => Err(error).context("failed to stash changes"),
+        }
+    }
+
     fn git_stash_pop(&self) -> Result<()> {
-        let status = Command::new("git")
-            .arg("stash")
-            .arg("pop")
-            .current_dir(self.subrepo_path())
-            .status()
-            .expect("failed to run git stash pop");
-        if !status.success() {
-            bail!("failed to run git stash pop");
+        let mut repo = self.git_repo()?;
+        repo.stash_pop(0, None).context("failed to pop stashed changes")
+    }
+
+    /// Returns the paths of files with unresolved merge conflicts.
+    fn git_conflicted_files(&self) -> Result<Vec<String>> {
+        let repo = self.git_repo()?;
+        let index = repo.index().context("failed to read git index")?;
+        let mut paths = Vec::new();
+        for conflict in index.conflicts().context("failed to list conflicted files")? {
+            let conflict = conflict.context("failed to list conflicted files")?;
+            if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+                paths.push(String::from_utf8(entry.path)?);
+            }
         }
-        Ok(())
+        Ok(paths)
+    }
+
+    /// Lists the local branches of the decomp repository, sorted by name.
+    fn git_branches(&self) -> Result<Vec<String>> {
+        let repo = self.git_repo()?;
+        let mut names = Vec::new();
+        for branch in repo.branches(Some(BranchType::Local)).context("failed to list branches")? {
+            let (branch, _) = branch.context("failed to list branches")?;
+            if let Some(name) = branch.name().context("failed to list branches")? {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
     }
 
     fn git_branch_exists(&self, branch_name: &str) -> Result<bool> {
-        let output = Command::new("git")
-            .arg("branch")
-            .arg("--list")
-            .arg(&branch_name)
-            .current_dir(self.subrepo_path())
-            .output()?;
-        if !output.status.success() {
-            bail!("failed to run git branch --list {}", branch_name);
+        match self.git_repo()?.find_branch(branch_name, BranchType::Local) {
+            Ok(_) => Ok(true),
+            Err(error) if error.code() == ErrorCode::NotFound => Ok(false),
+            Err(error) => Err(error).with_context(|| format!("failed to look up git branch {}", branch_name)),
         }
-        Ok(!output.stdout.is_empty())
     }
 
     fn git_delete_branch(&self, branch_name: &str) -> Result<()> {
-        let status = Command::new("git")
-            .arg("branch")
-            .arg("-D")
-            .arg(&branch_name)
-            .current_dir(self.subrepo_path())
-            .status()?;
-        if !status.success() {
-            bail!("failed to run git branch -D {}", branch_name);
-        }
-        Ok(())
+        let repo = self.git_repo()?;
+        repo.find_branch(branch_name, BranchType::Local)
+            .and_then(|mut branch| branch.delete())
+            .with_context(|| format!("failed to delete git branch {}", branch_name))
     }
 
     /// Stashes if needed, switches to the main branch, pulls, then switches back, merges, and pops stash.
-    /// Also updates the decomp dependency in the package manifest to the main's HEAD commit.
+    /// Also updates the decomp dependency in the package manifest to the main's HEAD commit, unless it is pinned to a
+    /// newer one; see [`Manifest::upgrade_decomp_dependency`](super::manifest::Manifest::upgrade_decomp_dependency).
     pub fn update_decomp(&self) -> Result<()> {
         let main_branch = "main";
         let prev_branch = self.git_current_branch()?;
 
-        // Stash if needed
-        if self.is_git_dirty()? {
+        // The decomp may have changed splat.yaml, so take out the asset stack before anything else. This way it isn't
+        // stashed with the user's changes either.
+        self.remove_asset_stack()?;
+        let patch_order = self.registry.calc_dependency_patch_order(self.package_id)?;
+        defer! {
+            warn_if_err(self.update_asset_stack(&patch_order));
+        }
+
+        // Stash if needed. The stash is popped when this function returns, before the asset stack is put back, so
+        // any failure below must leave the working tree clean for the pop to succeed.
+        let stashed = self.is_git_dirty()?;
+        if stashed {
             self.git_stash()?;
-            defer!(warn_if_err(self.git_stash_pop()));
+        }
+        defer! {
+            if stashed {
+                warn_if_err(self.git_stash_pop());
+            }
         }
 
         // Switch to main branch
@@ -793,10 +1767,9 @@ impl InitialisedPackage {
         }
 
         // Pull
-        let status = Command::new("git")
+        let status = run_git(Command::new("git")
             .arg("pull")
-            .current_dir(self.subrepo_path())
-            .status()?;
+            .current_dir(self.subrepo_path()))?;
         if !status.success() {
             bail!("failed to run git pull");
         }
@@ -807,34 +1780,242 @@ impl InitialisedPackage {
 
             // Merge main into package branch
             // TODO: and/or sync_to_repo?
-            let status = Command::new("git")
+            let status = run_git(Command::new("git")
                 .arg("merge")
                 .arg(main_branch)
-                .current_dir(self.subrepo_path())
-                .status()?;
+                .current_dir(self.subrepo_path()))?;
             if !status.success() {
-                bail!("failed to run git merge");
+                let conflicts = self.git_conflicted_files()?;
+                if conflicts.is_empty() {
+                    bail!("failed to run git merge");
+                }
+                let status = run_git(Command::new("git")
+                    .arg("merge")
+                    .arg("--abort")
+                    .current_dir(self.subrepo_path()))?;
+                if !status.success() {
+                    bail!("failed to abort conflicting merge of {} into {}", main_branch, prev_branch);
+                }
+                bail!(
+                    "decomp update produced conflicts in {}; the merge was aborted and your changes were restored. \
+                    Resolve manually by running `git merge {}` in {}, or re-run after committing",
+                    conflicts.join(", "),
+                    main_branch,
+                    self.subrepo_path().display(),
+                );
             }
         }
 
         // Update decomp dependency in manifest
-        let main_head = self.git_head_commit()?;
+        let main_head = self.git_rev_parse(&format!("{main_branch}^{{commit}}"))?;
+        self.package().edit_manifest_unchecked(|manifest| {
+            manifest.upgrade_decomp_dependency(main_head, |ancestor, descendant| {
+                self.git_is_ancestor(ancestor, descendant)
+            })
+        })
+    }
+
+    /// Moves the decomp `main` branch to the given revision, rebuilds the package branches on top of it, and pins
+    /// the revision in the package manifest. The revision is fetched if the subrepo doesn't have it yet.
+    ///
+    /// Patches are saved to the patches directory first, so switching back to the previous revision (which is
+    /// logged) restores the original state.
+    pub fn switch_decomp_rev(&self, rev: &str) -> Result<()> {
+        if self.is_git_dirty()? {
+            bail!("repo is dirty, commit changes and try again");
+        }
+
+        // Make sure the revision exists before touching any branches
+        if !self.git_commit_exists(rev)? {
+            log::info!("fetching decomp revision {}", rev);
+            let status = run_git(Command::new("git")
+                .arg("fetch")
+                .arg("origin")
+                .arg(rev)
+                .current_dir(self.subrepo_path()))?;
+            if !status.success() || !self.git_commit_exists(rev)? {
+                bail!("decomp revision {} does not exist", rev);
+            }
+        }
+        let rev = self.git_rev_parse(&format!("{rev}^{{commit}}"))?;
+
+        // Save commits to patches/, since the package branch is about to be rebuilt
+        self.remove_asset_stack()?;
+        let package_id_string = self.package_id.to_string();
+        if self.git_branch_exists(&package_id_string)? {
+            self.git_checkout_branch(&package_id_string)?;
+            self.update_patches_dir()
+                .context("failed to update patches dir for backup")?;
+            // Which puts the asset stack back
+            self.remove_asset_stack()?;
+        }
+
+        // Move main to the revision
+        self.git_checkout_branch("main")?;
+        let previous_rev = self.git_head_commit()?;
+        let status = run_git(Command::new("git")
+            .arg("reset")
+            .arg("--hard")
+            .arg(&rev)
+            .current_dir(self.subrepo_path()))?;
+        if !status.success() {
+            bail!("failed to reset main to {}", rev);
+        }
+        log::info!("switched decomp from {} to {}", previous_rev, rev);
+
+        // Patches don't follow files that the decomp moves, so rewrite them to use the new paths
+        let migrated = self.package().migrate_patches(self.git_renames(&previous_rev, &rev)?)?;
+        if migrated > 0 {
+            log::info!("rewrote {} patch(es) to use paths moved by the decomp", migrated);
+        }
+
+        // The package branch was backed up above, so sync_repo doesn't need to do it again
+        if self.git_branch_exists(&package_id_string)? {
+            self.git_delete_branch(&package_id_string)?;
+        }
         self.package()
-            .edit_manifest(|manifest| manifest.upsert_decomp_dependency(main_head))
+            .edit_manifest_unchecked(|manifest| manifest.upsert_decomp_dependency(rev.clone()))?;
+        self.sync_repo()
+            .with_context(|| format!(
+                "failed to rebuild package branches on decomp revision {}, \
+                use `merlon update --decomp-rev {}` to switch back",
+                rev, previous_rev,
+            ))
     }
 
-    fn git_head_commit(&self) -> Result<String> {
+    /// Adds the assets directories of the packages to the `asset_stack` in the decomp's `splat.yaml` of each region
+    /// the package targets, latest in patch order first so that packages override the assets of their dependencies,
+    /// followed by the decomp's own. This is done after the packages' patches are applied, and the rest of the file is
+    /// left alone.
+    ///
+    /// The file is then marked `--skip-worktree`, so git ignores the asset stack: it is never committed or saved as a
+    /// patch, and isn't uncommitted work. A file with uncommitted changes is left as it is, so that marking it doesn't
+    /// hide them; its asset stack is added by the first sync after they are committed.
+    fn update_asset_stack(&self, patch_order: &[Id]) -> Result<()> {
+        let repo = self.git_repo()?;
+        let packages: Vec<Id> = patch_order.iter().rev().copied().collect();
+        for region in self.package().manifest()?.metadata().regions() {
+            let config_path = splat_config_path(region);
+            let path = self.subrepo_path().join(&config_path);
+            if !path.is_file() {
+                log::warn!("{} does not exist, cannot set asset stack", path.display());
+                continue;
+            }
+            let config = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if !self.differs_only_by_asset_stack(&repo, &config_path) {
+                log::warn!(
+                    "{} has uncommitted changes, so package assets won't be used until they are committed",
+                    path.display(),
+                );
+                continue;
+            }
+            let updated = with_asset_stack(&config, &packages, region);
+            if updated != config {
+                write(&path, updated).with_context(|| format!("failed to write {}", path.display()))?;
+                self.git_set_skip_worktree(&config_path, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes [`InitialisedPackage::update_asset_stack`], for every region in case the package's regions changed.
+    /// Only the package entries are removed, so git sees any other changes made to the file.
+    fn remove_asset_stack(&self) -> Result<()> {
+        for region in Region::value_variants() {
+            let config_path = splat_config_path(*region);
+            let path = self.subrepo_path().join(&config_path);
+            if !path.is_file() {
+                continue;
+            }
+            let config = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let stripped = without_package_assets(&config);
+            if stripped != config {
+                self.git_set_skip_worktree(&config_path, false)?;
+                write(&path, stripped).with_context(|| format!("failed to write {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets or clears the `--skip-worktree` bit of a tracked file, which makes git ignore changes to it.
+    fn git_set_skip_worktree(&self, path: &str, skip: bool) -> Result<()> {
+        let status = run_git(Command::new("git")
+            .arg("update-index")
+            .arg(if skip { "--skip-worktree" } else { "--no-skip-worktree" })
+            .arg("--")
+            .arg(path)
+            .current_dir(self.subrepo_path()))?;
+        if !status.success() {
+            bail!("failed to run git update-index on {}", path);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the file at `path` in the decomp is the same as in the index, apart from the asset stack.
+    fn differs_only_by_asset_stack(&self, repo: &Repository, path: &str) -> bool {
+        let Ok(config) = std::fs::read_to_string(self.subrepo_path().join(path)) else {
+            return false;
+        };
+        let committed = repo.index().ok()
+            .and_then(|index| index.get_path(Path::new(path), 0))
+            .and_then(|entry| repo.find_blob(entry.id).ok());
+        committed.is_some_and(|blob| blob.content() == without_package_assets(&config).as_bytes())
+    }
+
+    /// Returns true if `ancestor` is an ancestor of `descendant` in the subrepo's history.
+    /// Returns false if either commit is unknown to the subrepo.
+    fn git_is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        let repo = self.git_repo()?;
+        let commit = |rev: &str| repo.revparse_single(rev).and_then(|object| object.peel_to_commit()).ok();
+        let (Some(ancestor), Some(descendant)) = (commit(ancestor), commit(descendant)) else {
+            return Ok(false);
+        };
+        if ancestor.id() == descendant.id() {
+            return Ok(true);
+        }
+        repo.graph_descendant_of(descendant.id(), ancestor.id())
+            .context("failed to compare git commits")
+    }
+
+    /// Returns the files renamed between two revisions of the subrepo, as a map of old paths to new paths.
+    fn git_renames(&self, from: &str, to: &str) -> Result<HashMap<String, String>> {
         let output = Command::new("git")
-            .arg("rev-parse")
-            .arg("HEAD")
+            .arg("diff")
+            .arg("--name-status")
+            .arg("--find-renames")
+            .arg("--diff-filter=R")
+            .arg("-z")
+            .arg(from)
+            .arg(to)
             .current_dir(self.subrepo_path())
             .output()?;
         if !output.status.success() {
-            bail!("failed to run git rev-parse");
+            bail!("failed to run git diff {} {}", from, to);
+        }
+        // Each rename is a status (e.g. R100), then the old and new paths, all NUL-terminated
+        let output = String::from_utf8(output.stdout)?;
+        let mut fields = output.split('\0');
+        let mut renames = HashMap::new();
+        while let (Some(_status), Some(old), Some(new)) = (fields.next(), fields.next(), fields.next()) {
+            renames.insert(old.to_owned(), new.to_owned());
         }
-        String::from_utf8(output.stdout)
-            .map(|s| s.trim().to_string())
-            .map_err(Into::into)
+        Ok(renames)
+    }
+
+    fn git_head_commit(&self) -> Result<String> {
+        self.git_rev_parse("HEAD")
+    }
+
+    fn git_commit_exists(&self, rev: &str) -> Result<bool> {
+        Ok(self.git_repo()?.revparse_single(&format!("{rev}^{{commit}}")).is_ok())
+    }
+
+    fn git_rev_parse(&self, rev: &str) -> Result<String> {
+        let repo = self.git_repo()?;
+        let object = repo.revparse_single(rev).with_context(|| format!("failed to resolve git revision {}", rev))?;
+        Ok(object.id().to_string())
     }
 }
 
@@ -846,8 +2027,1032 @@ impl TryFrom<Package> for InitialisedPackage {
     }
 }
 
+/// Resolves a decomp revision to a commit hash using the repository at `url`. Commit hashes are returned as-is, and
+/// branch and tag names are looked up with `git ls-remote`. [`LATEST_TAG_REV`] resolves to the tag with the highest
+/// semver version, e.g. `v1.2.0`.
+fn resolve_decomp_rev(url: &str, rev: &str) -> Result<String> {
+    if (7..=40).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(rev.to_owned());
+    }
+
+    let refs = if rev == LATEST_TAG_REV {
+        ls_remote(url, &[])?
+    } else {
+        ls_remote(url, &[rev, &format!("{rev}^{{}}")])?
+    };
+    // Annotated tags point to tag objects; the entry ending `^{}` is the commit they point to
+    let commit_of = |name: &str| {
+        refs.iter().find(|(_, r)| *r == format!("{name}^{{}}"))
+            .or_else(|| refs.iter().find(|(_, r)| r == name))
+            .map(|(hash, _)| hash.clone())
+    };
+
+    let resolved = if rev == LATEST_TAG_REV {
+        refs.iter()
+            .filter_map(|(_, r)| r.strip_prefix("refs/tags/"))
+            .filter(|tag| !tag.ends_with("^{}"))
+            .filter_map(|tag| Some((Version::parse(tag.trim_start_matches('v')).ok()?, tag)))
+            .max()
+            .and_then(|(_, tag)| commit_of(&format!("refs/tags/{tag}")))
+            .with_context(|| format!("decomp repository {} has no release tags", url))?
+    } else {
+        commit_of(&format!("refs/tags/{rev}"))
+            .or_else(|| commit_of(&format!("refs/heads/{rev}")))
+            .with_context(|| format!(
+                "decomp revision {:?} is not a commit hash, or a branch or tag of {}; use `{}` for the newest release",
+                rev, url, LATEST_TAG_REV,
+            ))?
+    };
+    log::info!("resolved decomp revision {} to {}", rev, resolved);
+    Ok(resolved)
+}
+
+/// Lists the branches and tags of a remote repository as `(hash, ref)` pairs. If any patterns are given, only refs
+/// matching them are listed.
+fn ls_remote(url: &str, patterns: &[&str]) -> Result<Vec<(String, String)>> {
+    let output = Command::new("git")
+        .arg("ls-remote")
+        .arg("--heads")
+        .arg("--tags")
+        .arg("--")
+        .arg(url)
+        .args(patterns)
+        .output()?;
+    if !output.status.success() {
+        bail!("failed to list refs of {}: {}", url, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(hash, r)| (hash.to_owned(), r.to_owned()))
+        .collect())
+}
+
+/// Clones the git repository at `url` into a temporary directory and checks out `rev`, if given. Returns the clone,
+/// the commit hash that is checked out, and the path of the Merlon package in the repository, which may be in a
+/// subdirectory.
+fn clone_git_package(url: &str, rev: Option<&str>) -> Result<(temp_dir::TempDir, String, PathBuf)> {
+    let clone = temp_dir::TempDir::new().context("failed to create temporary directory")?;
+    let status = run_git(Command::new("git")
+        .arg("clone")
+        .arg("--quiet")
+        .arg("--")
+        .arg(url)
+        .arg(clone.path()))?;
+    if !status.success() {
+        bail!("failed to clone git repository {}", url);
+    }
+    if let Some(rev) = rev {
+        let status = run_git(Command::new("git")
+            .arg("checkout")
+            .arg("--quiet")
+            .arg(rev)
+            .arg("--")
+            .current_dir(clone.path()))?;
+        if !status.success() {
+            bail!("revision {:?} not found in git repository {}", rev, url);
+        }
+    }
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(clone.path())
+        .output()?;
+    if !output.status.success() {
+        bail!("failed to get the checked out commit of git repository {}", url);
+    }
+    let rev = String::from_utf8(output.stdout)?.trim().to_owned();
+
+    let mut packages = Vec::new();
+    find_packages(clone.path(), &mut packages)?;
+    let path = match packages.as_slice() {
+        [path] => path.clone(),
+        [] => bail!("git repository {} does not contain a Merlon package", url),
+        _ => bail!(
+            "git repository {} contains more than one Merlon package: {}",
+            url,
+            packages.iter()
+                .map(|path| path.strip_prefix(clone.path()).unwrap_or(path).display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+    };
+    Ok((clone, rev, path))
+}
+
+/// Collects the packages in `dir` and its subdirectories, not including hidden directories such as `.git` or the
+/// contents of the packages themselves.
+fn find_packages(dir: &Path, packages: &mut Vec<PathBuf>) -> Result<()> {
+    if super::is_unexported_package(dir) {
+        packages.push(dir.to_owned());
+        return Ok(());
+    }
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            find_packages(&entry.path(), packages)?;
+        }
+    }
+    Ok(())
+}
+
+/// Registers every package in the given dependencies directory.
+///
+/// Each dependency is expected to be in a directory named after its package ID, and is registered lazily under that
+/// ID without reading its manifest. Any that aren't (e.g. because they were copied in by hand) are read now and
+/// renamed to match, so that the registry and the filesystem agree. Directories named after the wrong ID are renamed
+/// when the package is first accessed; see [`Registry::load_all`].
+fn register_dependencies_dir(registry: &mut Registry, dependencies_dir: &Path) -> Result<()> {
+    // Collect first, since renaming while iterating could visit a directory twice
+    let mut paths = Vec::new();
+    for entry in dependencies_dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            paths.push(entry.path());
+        }
+    }
+
+    for path in paths {
+        let mut package = Package::try_from(path.clone())?;
+
+        // Directories named after an ID are trusted until the package is accessed, so that loading a package with
+        // many dependencies doesn't read every manifest up front. See `Registry::load_all` for the eager path.
+        let dir_id = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.parse::<Id>().ok());
+        if let Some(id) = dir_id {
+            registry.register_lazily(id, package)?;
+            continue;
+        }
+
+        let id = package.id()?;
+        if path != dependencies_dir.join(id.to_string()) {
+            package = Package::try_from(rename_to_id(&path, id)?)?;
+        }
+        registry.register(package)?;
+    }
+    Ok(())
+}
+
+/// Creates the directory for a package's custom assets in the decomp repo at `subrepo`, if it doesn't exist.
+fn create_assets_dir(subrepo: &Path, id: Id) -> Result<PathBuf> {
+    let path = subrepo.join("assets").join(id.to_string());
+    create_dir_all(&path)
+        .with_context(|| format!("failed to create assets subdirectory {}", path.display()))?;
+    Ok(path)
+}
+
+/// Returns the splat config with the assets directories of `packages` at the top of its top-level `asset_stack`
+/// list, in place of any that were there before. The other directories in the list are kept as they are written,
+/// and if there are none, that of `region` (e.g. `us`) is added after the packages. Everything else is left as it is.
+fn with_asset_stack(config: &str, packages: &[Id], region: Region) -> String {
+    let stripped = without_package_assets(config);
+    let mut lines: Vec<String> = stripped.lines().map(ToOwned::to_owned).collect();
+    let start = match lines.iter().position(|line| line.trim_end() == "asset_stack:") {
+        Some(start) => start,
+        None => {
+            lines.push("asset_stack:".to_owned());
+            lines.len() - 1
+        }
+    };
+    let existing = lines[start + 1..].iter().take_while(|line| line.trim_start().starts_with('-')).count();
+    let indent = match lines.get(start + 1) {
+        Some(line) if existing > 0 => line[..line.len() - line.trim_start().len()].to_owned(),
+        _ => "  ".to_owned(),
+    };
+    let mut entries: Vec<String> = packages.iter().map(|id| format!("{indent}- {id}")).collect();
+    if existing == 0 {
+        entries.push(format!("{indent}- {}", region.as_str()));
+    }
+    lines.splice(start + 1..start + 1, entries);
+    lines.join("\n") + "\n"
+}
+
+/// Returns the splat config without list entries that are package IDs, as added by [`with_asset_stack`].
+fn without_package_assets(config: &str) -> String {
+    let is_package_entry = |line: &str| {
+        line.trim_start()
+            .strip_prefix('-')
+            .filter(|entry| entry.starts_with(char::is_whitespace))
+            .map(|entry| entry.trim().trim_matches(|c| c == '"' || c == '\''))
+            .is_some_and(|entry| entry.parse::<Id>().is_ok())
+    };
+    let mut output = String::with_capacity(config.len());
+    for line in config.split_inclusive('\n') {
+        if !is_package_entry(line) {
+            output.push_str(line);
+        }
+    }
+    output
+}
+
+/// Returns true if the event adds, removes, or renames files, meaning the build needs to be reconfigured.
+fn changes_file_tree(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+    )
+}
+
 fn warn_if_err<T, E: std::fmt::Debug>(result: Result<T, E>) {
     if let Err(err) = result {
         log::warn!("{:?}", err);
     }
 }
+
+/// Runs the configure script of the decomp repo at `dir` for a region, which also splits assets out of the baserom.
+fn configure_decomp(dir: &Path, region: Region, clean: bool) -> Result<()> {
+    let python = python_program();
+    require_program(&python, if cfg!(target_os = "macos") {
+        "python3 is required to configure the decomp; install it with `brew install python`, or set MERLON_PYTHON to \
+        its path"
+    } else {
+        "python3 is required to configure the decomp; install it with your package manager (e.g. `sudo apt install \
+        python3`), or set MERLON_PYTHON to its path"
+    })?;
+
+    let mut command = Command::new(&python);
+    command
+        .arg("configure")
+        //.arg("--non-matching")
+        //.arg("--debug")
+        .arg("--shift")
+        .arg(region.as_str());
+    if clean {
+        command.arg("--clean");
+    }
+    let status = command.current_dir(dir).status()?;
+    if !status.success() {
+        bail!(crate::Error::Build("failed to configure".to_owned()));
+    }
+    Ok(())
+}
+
+/// Returns the ninja executable to build with. Can be overridden with the `MERLON_NINJA` environment variable.
+fn ninja_program() -> OsString {
+    std::env::var_os("MERLON_NINJA").unwrap_or_else(|| "ninja".into())
+}
+
+/// Returns the Python interpreter to run the decomp's scripts with. Can be overridden with the `MERLON_PYTHON`
+/// environment variable.
+fn python_program() -> OsString {
+    std::env::var_os("MERLON_PYTHON").unwrap_or_else(|| "python3".into())
+}
+
+/// Errors with `hint` if `program` can't be run.
+fn require_program(program: &OsStr, hint: &str) -> Result<()> {
+    let result = Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => bail!("{}", hint),
+        Err(error) => Err(error).with_context(|| format!("failed to run {}", program.to_string_lossy())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+    use crate::package::test::{git, init_upstream, clone_upstream};
+
+    #[test]
+    fn mismatched_dependency_dir_is_renamed() -> Result<()> {
+        let dir = TempDir::new()?;
+        let dependencies_dir = dir.path().join("dependencies");
+        create_dir(&dependencies_dir)?;
+        let good = Package::new("Good", dependencies_dir.join("placeholder"))?;
+        let good_path = dependencies_dir.join(good.id()?.to_string());
+        std::fs::rename(good.path(), &good_path)?;
+        let bad = Package::new("Bad", dependencies_dir.join("copied-by-hand"))?;
+        let bad_id = bad.id()?;
+
+        let mut registry = Registry::new();
+        register_dependencies_dir(&mut registry, &dependencies_dir)?;
+
+        assert_eq!(registry.package_ids().count(), 2);
+        assert!(!dependencies_dir.join("copied-by-hand").exists());
+        assert_eq!(registry.get_or_error(bad_id)?.path(), dependencies_dir.join(bad_id.to_string()));
+        assert!(good_path.is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn dependency_manifests_are_read_on_first_access() -> Result<()> {
+        use super::super::manifest::test::READ_COUNT;
+
+        let dir = TempDir::new()?;
+        let dependencies_dir = dir.path().join("dependencies");
+        create_dir(&dependencies_dir)?;
+        let mut ids = Vec::new();
+        for index in 0..5 {
+            let package = Package::new(format!("Dependency {index}"), dependencies_dir.join("placeholder"))?;
+            let id = package.id()?;
+            std::fs::rename(package.path(), dependencies_dir.join(id.to_string()))?;
+            ids.push(id);
+        }
+        // A directory claiming the wrong ID is only caught once it is accessed
+        let liar = Package::new("Liar", dependencies_dir.join("placeholder"))?;
+        let liar_dir_id = Id::new();
+        std::fs::rename(liar.path(), dependencies_dir.join(liar_dir_id.to_string()))?;
+
+        READ_COUNT.with(|count| count.set(0));
+        let mut registry = Registry::new();
+        register_dependencies_dir(&mut registry, &dependencies_dir)?;
+        assert_eq!(registry.package_ids().count(), 6);
+        assert_eq!(READ_COUNT.with(|count| count.get()), 0);
+
+        registry.get_or_error(ids[0])?;
+        registry.get_or_error(ids[0])?;
+        assert_eq!(READ_COUNT.with(|count| count.get()), 1);
+
+        assert!(registry.get(liar_dir_id).is_err());
+        assert!(registry.get_or_error(liar_dir_id).is_err());
+        registry.take(liar_dir_id)?;
+        READ_COUNT.with(|count| count.set(0));
+        registry.load_all()?;
+        assert_eq!(READ_COUNT.with(|count| count.get()), ids.len() - 1);
+        Ok(())
+    }
+
+    #[test]
+    fn dependency_dir_named_after_wrong_id_is_renamed() -> Result<()> {
+        let dir = TempDir::new()?;
+        let dependencies_dir = dir.path().join("dependencies");
+        create_dir(&dependencies_dir)?;
+        let package = Package::new("Misnamed", dependencies_dir.join("placeholder"))?;
+        let id = package.id()?;
+        let wrong_id = Id::new();
+        let wrong_path = dependencies_dir.join(wrong_id.to_string());
+        std::fs::rename(package.path(), &wrong_path)?;
+
+        // Renamed on first access, and registered correctly next time
+        let mut registry = Registry::new();
+        register_dependencies_dir(&mut registry, &dependencies_dir)?;
+        let error = registry.get(wrong_id).unwrap_err();
+        assert!(error.to_string().contains("has been moved to"), "{:#}", error);
+        assert!(!wrong_path.exists());
+        let mut registry = Registry::new();
+        register_dependencies_dir(&mut registry, &dependencies_dir)?;
+        assert_eq!(registry.get_or_error(id)?.path(), dependencies_dir.join(id.to_string()));
+
+        // Renamed and registered again under its real ID when loading everything
+        std::fs::rename(dependencies_dir.join(id.to_string()), &wrong_path)?;
+        let mut registry = Registry::new();
+        register_dependencies_dir(&mut registry, &dependencies_dir)?;
+        registry.load_all()?;
+        assert!(!registry.has(wrong_id));
+        assert_eq!(registry.get_or_error(id)?.path(), dependencies_dir.join(id.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_decomp_update_is_aborted() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        let package = Package::new("Conflicted", dir.path().join("package"))?;
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        let initialised = clone_upstream(&package, &upstream)?;
+
+        // The package and upstream both change the same line
+        git(&subrepo, &["checkout", "--quiet", "-b", "package"])?;
+        write(subrepo.join("file.c"), "int x = 1;\n")?;
+        git(&subrepo, &["commit", "--quiet", "-am", "package change"])?;
+        write(upstream.join("file.c"), "int x = 2;\n")?;
+        git(&upstream, &["commit", "--quiet", "-am", "upstream change"])?;
+        write(subrepo.join("notes.txt"), "work in progress\n")?;
+        git(&subrepo, &["add", "notes.txt"])?;
+
+        let error = initialised.update_decomp().unwrap_err();
+        assert!(error.to_string().contains("produced conflicts in file.c"), "{}", error);
+        assert_eq!(initialised.git_current_branch()?, "package");
+        assert!(!subrepo.join(".git/MERGE_HEAD").exists());
+        assert_eq!(std::fs::read_to_string(subrepo.join("file.c"))?, "int x = 1;\n");
+        assert_eq!(std::fs::read_to_string(subrepo.join("notes.txt"))?, "work in progress\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decomp_update_only_moves_the_decomp_dependency_forward() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+        let initial = git(&upstream, &["rev-parse", "HEAD"])?;
+
+        let package = Package::new("Pinned", dir.path().join("package"))?;
+        package.edit_manifest_unchecked(|manifest| manifest.upsert_decomp_dependency(initial.clone()))?;
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        let initialised = clone_upstream(&package, &upstream)?;
+        let pinned = || -> Result<String> {
+            Ok(initialised.package().manifest()?.get_direct_decomp_dependency_rev().unwrap().to_owned())
+        };
+
+        // Moves forward to the pulled commit
+        write(upstream.join("file.c"), "int x = 1;\n")?;
+        git(&upstream, &["commit", "--quiet", "-am", "upstream change"])?;
+        let pulled = git(&upstream, &["rev-parse", "HEAD"])?;
+        initialised.update_decomp()?;
+        assert_eq!(pinned()?, pulled);
+
+        // Doesn't move back from a newer commit
+        git(&subrepo, &["checkout", "--quiet", "-b", "newer"])?;
+        write(subrepo.join("file.c"), "int x = 2;\n")?;
+        git(&subrepo, &["commit", "--quiet", "-am", "newer change"])?;
+        let newer = git(&subrepo, &["rev-parse", "HEAD"])?;
+        git(&subrepo, &["checkout", "--quiet", "main"])?;
+        initialised.package().edit_manifest_unchecked(|manifest| manifest.upsert_decomp_dependency(newer.clone()))?;
+        initialised.update_decomp()?;
+        assert_eq!(pinned()?, newer);
+        Ok(())
+    }
+
+    #[test]
+    fn package_branches_match_registry_after_sync() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let dependency = Package::new("Dependency", dir.path().join("dependency"))?;
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        initialised.add_dependency(AddDependencyOptions {
+            path: Some(dependency.path().to_owned()),
+            git: None,
+            rev: None,
+        })?;
+
+        // Not synced yet
+        assert!(initialised.list_package_branches()?.iter().all(|(_, exists)| !exists));
+
+        initialised.sync_repo()?;
+        let branches = initialised.list_package_branches()?;
+        let mut ids: Vec<_> = branches.iter().map(|(id, _)| *id).collect();
+        ids.sort();
+        let mut registry_ids: Vec<_> = initialised.registry().package_ids().collect();
+        registry_ids.sort();
+        assert_eq!(ids, registry_ids);
+        assert_eq!(ids.len(), 2);
+        assert!(branches.iter().all(|(_, exists)| *exists));
+        Ok(())
+    }
+
+    #[test]
+    fn doctor_finds_broken_working_state() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let dependency = Package::new("Dependency", dir.path().join("dependency"))?;
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        let dependency_id = initialised.add_dependency(AddDependencyOptions {
+            path: Some(dependency.path().to_owned()),
+            git: None,
+            rev: None,
+        })?;
+        initialised.sync_repo()?;
+        assert_eq!(initialised.doctor()?, vec![]);
+
+        write(subrepo.join("notes.txt"), "work in progress\n")?;
+        git(&subrepo, &["checkout", "--quiet", "main"])?;
+        git(&subrepo, &["branch", "-D", &dependency_id.to_string()])?;
+        git(&subrepo, &["branch", &Id::new().to_string()])?;
+        std::fs::remove_dir(subrepo.join("assets").join(dependency_id.to_string()))?;
+        package.edit_manifest_unchecked(|manifest| manifest.upsert_decomp_dependency("0".repeat(40)))?;
+        let orphan = Package::new("Orphan", package.path().join(DEPENDENCIES_DIR_NAME).join("placeholder"))?;
+        std::fs::rename(orphan.path(), package.path().join(DEPENDENCIES_DIR_NAME).join(orphan.id()?.to_string()))?;
+
+        let initialised = InitialisedPackage::try_from(package)?;
+        let kinds: Vec<DiagnosticKind> = initialised.doctor()?.iter().map(|diagnostic| diagnostic.kind).collect();
+        assert_eq!(kinds, vec![
+            DiagnosticKind::DirtyTree,
+            DiagnosticKind::WrongBranch,
+            DiagnosticKind::MissingBranch,
+            DiagnosticKind::MissingAssetsDir,
+            DiagnosticKind::StaleBranch,
+            DiagnosticKind::DecompRevDrift,
+            DiagnosticKind::OrphanedDependency,
+        ]);
+
+        // A deleted dependency directory hides the checks that need it
+        remove_dir_all(initialised.registry().get_or_error(dependency_id)?.path())?;
+        let kinds: Vec<DiagnosticKind> = initialised.doctor()?.iter().map(|diagnostic| diagnostic.kind).collect();
+        assert_eq!(kinds, vec![
+            DiagnosticKind::DirtyTree,
+            DiagnosticKind::WrongBranch,
+            DiagnosticKind::InconsistentDependencies,
+        ]);
+        Ok(())
+    }
+
+    /// Returns a patch, as made by `git format-patch`, that adds `sprite.txt` to the assets directory of package `id`.
+    fn asset_patch(id: Id) -> String {
+        let asset = format!("assets/{}/sprite.txt", id);
+        format!(
+            "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001\n\
+            From: Test <test@example.com>\n\
+            Date: Mon, 1 Jan 2024 00:00:00 +0000\n\
+            Subject: [PATCH] Add sprite\n\
+            \n\
+            ---\n\
+            diff --git a/{asset} b/{asset}\n\
+            new file mode 100644\n\
+            index 0000000..ce01362\n\
+            --- /dev/null\n\
+            +++ b/{asset}\n\
+            @@ -0,0 +1 @@\n\
+            +hello\n\
+            -- \n\
+            2.40.0\n\
+            \n",
+        )
+    }
+
+    #[test]
+    fn asset_patches_apply_to_fresh_tree() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let dependency = Package::new("Sprites", dir.path().join("dependency"))?;
+        let dependency_id = dependency.id()?;
+        let asset = format!("assets/{}/sprite.txt", dependency_id);
+        write(dependency.path().join(PATCHES_DIR_NAME).join("0001-Add-sprite.patch"), asset_patch(dependency_id))?;
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        initialised.add_dependency(AddDependencyOptions {
+            path: Some(dependency.path().to_owned()),
+            git: None,
+            rev: None,
+        })?;
+
+        initialised.sync_repo()?;
+        assert_eq!(std::fs::read_to_string(subrepo.join(&asset))?, "hello\n");
+        // Packages without asset patches still get a directory to put assets in
+        assert!(initialised.assets_path().is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn asset_stack_layers_packages_over_dependencies() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+        let splat_config = "name: Paper Mario (North America)\nsha1: 3837f44cda784b466c9a2d99df70d77c322b97a0\n\
+            asset_stack:\n  - us\noptions:\n  basename: papermario\n";
+        create_dir_all(upstream.join("ver/us"))?;
+        write(upstream.join(splat_config_path(Region::Us)), splat_config)?;
+        git(&upstream, &["add", &splat_config_path(Region::Us)])?;
+        git(&upstream, &["commit", "--quiet", "-m", "add splat config"])?;
+
+        // Root depends on A, which depends on Base. A and Base each ship an asset.
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let base = Package::new("Base", dir.path().join("base"))?;
+        let a = Package::new("A", dir.path().join("a"))?;
+        let base_dependency = Dependency::from(base.manifest()?.metadata());
+        a.edit_manifest(|manifest| manifest.declare_direct_dependency(base_dependency))?;
+        for dependency in [&base, &a] {
+            let patch_path = dependency.path().join(PATCHES_DIR_NAME).join("0001-Add-sprite.patch");
+            write(patch_path, asset_patch(dependency.id()?))?;
+        }
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        initialised.add_dependency(AddDependencyOptions { path: Some(a.path().to_owned()), git: None, rev: None })?;
+        initialised.add_dependency(AddDependencyOptions { path: Some(base.path().to_owned()), git: None, rev: None })?;
+
+        let expected = format!(
+            "name: Paper Mario (North America)\nsha1: 3837f44cda784b466c9a2d99df70d77c322b97a0\n\
+            asset_stack:\n  - {}\n  - {}\n  - {}\n  - us\noptions:\n  basename: papermario\n",
+            package.id()?,
+            a.id()?,
+            base.id()?,
+        );
+        initialised.sync_repo()?;
+        assert_eq!(std::fs::read_to_string(subrepo.join(splat_config_path(Region::Us)))?, expected);
+        assert!(subrepo.join(format!("assets/{}/sprite.txt", a.id()?)).is_file());
+        assert!(subrepo.join(format!("assets/{}/sprite.txt", base.id()?)).is_file());
+        // Ignored by git, so syncing again (which needs a clean tree to save patches) works and changes nothing
+        assert!(!initialised.is_git_dirty()?);
+        assert_eq!(git(&subrepo, &["status", "--porcelain"])?, "");
+        assert!(!std::fs::read_to_string(subrepo.join(".git/config"))?.contains("filter"));
+        initialised.sync_repo()?;
+        assert_eq!(std::fs::read_to_string(subrepo.join(splat_config_path(Region::Us)))?, expected);
+        assert_eq!(std::fs::read_dir(package.path().join(PATCHES_DIR_NAME))?.count(), 0);
+
+        // Changes the user makes to splat.yaml are shown when saving patches, without the asset stack, to be committed
+        let splat_yaml = subrepo.join(splat_config_path(Region::Us));
+        let edited = expected.replace("  basename: papermario\n", "  basename: papermario\n  my_option: true\n");
+        write(&splat_yaml, edited)?;
+        assert!(initialised.sync_repo().is_err());
+        let diff = git(&subrepo, &["diff"])?;
+        assert!(diff.contains("+  my_option: true"), "{}", diff);
+        assert!(!diff.contains(&a.id()?.to_string()), "{}", diff);
+        git(&subrepo, &["commit", "--quiet", "-am", "Add my option"])?;
+        assert!(!initialised.is_git_dirty()?);
+        initialised.sync_repo()?;
+        assert_eq!(std::fs::read_to_string(&splat_yaml)?, format!("{expected}  my_option: true\n"));
+        let patches: Vec<_> = std::fs::read_dir(package.path().join(PATCHES_DIR_NAME))?.collect::<Result<_, _>>()?;
+        assert_eq!(patches.len(), 1);
+        let patch = std::fs::read_to_string(patches[0].path())?;
+        assert!(patch.contains("+  my_option: true"), "{}", patch);
+        assert!(!patch.contains(&a.id()?.to_string()), "{}", patch);
+        Ok(())
+    }
+
+    #[test]
+    fn asset_stack_keeps_the_rest_of_the_config() {
+        let id: Id = "4c5b1e37-8a13-4b4b-b2d1-aa0e3a7ae21f".parse().unwrap();
+        let stale: Id = "9e43fd52-35c8-4fb6-9d5c-3d41ca4c1d6b".parse().unwrap();
+        // Stale packages are dropped, but other directories are kept as they are after the packages
+        let config = format!("sha1: abc\nasset_stack:\n    - {}\n    - us\n    - \"base\"\nsegments: []", stale);
+        let updated = with_asset_stack(&config, &[id], Region::Us);
+        assert_eq!(updated, format!("sha1: abc\nasset_stack:\n    - {}\n    - us\n    - \"base\"\nsegments: []\n", id));
+        assert_eq!(
+            without_package_assets(&updated),
+            "sha1: abc\nasset_stack:\n    - us\n    - \"base\"\nsegments: []\n"
+        );
+        assert_eq!(
+            with_asset_stack("sha1: abc\n", &[id], Region::Jp),
+            format!("sha1: abc\nasset_stack:\n  - {}\n  - jp\n", id),
+        );
+    }
+
+    #[test]
+    fn git_dependency_is_cloned_and_updated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        // The package doesn't have to be at the root of the repository
+        let remote = dir.path().join("remote");
+        create_dir(&remote)?;
+        let dependency = Package::new("Hosted", remote.join("hosted"))?;
+        let dependency_id = dependency.id()?;
+        git(&remote, &["init", "--quiet", "--initial-branch", "main"])?;
+        git(&remote, &["add", "."])?;
+        git(&remote, &["commit", "--quiet", "-m", "initial"])?;
+        let first_rev = git(&remote, &["rev-parse", "HEAD"])?;
+
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        let url = remote.to_string_lossy().into_owned();
+        let id = initialised.add_dependency(AddDependencyOptions {
+            path: None,
+            git: Some(url.clone()),
+            rev: None,
+        })?;
+        assert_eq!(id, dependency_id);
+        assert!(initialised.package().manifest()?.iter_direct_dependencies().any(|dependency| {
+            *dependency == Dependency::Git { id, url: url.clone(), rev: first_rev.clone(), requested_rev: None }
+        }));
+        assert!(initialised.update_git_dependencies()?.is_empty());
+
+        write(dependency.path().join("README.md"), "# Hosted\n\nNow with more features.\n")?;
+        git(&remote, &["commit", "--quiet", "-am", "update readme"])?;
+        let second_rev = git(&remote, &["rev-parse", "HEAD"])?;
+        assert_eq!(initialised.update_git_dependencies()?, vec![id]);
+        assert!(initialised.package().manifest()?.iter_direct_dependencies().any(|dependency| {
+            *dependency == Dependency::Git { id, url: url.clone(), rev: second_rev.clone(), requested_rev: None }
+        }));
+        let readme = std::fs::read_to_string(initialised.registry().get_or_error(id)?.path().join("README.md"))?;
+        assert!(readme.contains("Now with more features."));
+
+        initialised.sync_repo()?;
+        let root_id = initialised.package_id();
+        assert_eq!(initialised.registry().calc_dependency_patch_order(root_id)?, vec![id, root_id]);
+        Ok(())
+    }
+
+    #[test]
+    fn git_dependency_stays_at_requested_rev() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        let remote = dir.path().join("remote");
+        let dependency = Package::new("Hosted", remote.clone())?;
+        git(&remote, &["init", "--quiet", "--initial-branch", "main"])?;
+        git(&remote, &["add", "."])?;
+        git(&remote, &["commit", "--quiet", "-m", "initial"])?;
+        git(&remote, &["tag", "v1"])?;
+        let tagged_rev = git(&remote, &["rev-parse", "HEAD"])?;
+
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        let url = remote.to_string_lossy().into_owned();
+        let add = |initialised: &mut InitialisedPackage, rev: &str| initialised.add_dependency(AddDependencyOptions {
+            path: None,
+            git: Some(url.clone()),
+            rev: Some(rev.to_owned()),
+        });
+        let id = add(&mut initialised, "v1")?;
+        let declared = |initialised: &InitialisedPackage| -> Result<Dependency> {
+            Ok(initialised.package().manifest()?.iter_direct_dependencies().find(|dependency| {
+                dependency.package_id() == Some(id)
+            }).unwrap().clone())
+        };
+
+        // A tag stays where it is
+        write(dependency.path().join("README.md"), "# Hosted\n\nNow with more features.\n")?;
+        git(&remote, &["commit", "--quiet", "-am", "update readme"])?;
+        let main_rev = git(&remote, &["rev-parse", "HEAD"])?;
+        assert!(initialised.update_git_dependencies()?.is_empty());
+        assert_eq!(declared(&initialised)?, Dependency::Git {
+            id,
+            url: url.clone(),
+            rev: tagged_rev.clone(),
+            requested_rev: Some("v1".to_owned()),
+        });
+
+        // A branch is followed
+        add(&mut initialised, "main")?;
+        assert_eq!(declared(&initialised)?, Dependency::Git {
+            id,
+            url: url.clone(),
+            rev: main_rev,
+            requested_rev: Some("main".to_owned()),
+        });
+        write(dependency.path().join("README.md"), "# Hosted\n\nEven more features.\n")?;
+        git(&remote, &["commit", "--quiet", "-am", "update readme again"])?;
+        let new_main_rev = git(&remote, &["rev-parse", "HEAD"])?;
+        assert_eq!(initialised.update_git_dependencies()?, vec![id]);
+        assert_eq!(declared(&initialised)?, Dependency::Git {
+            id,
+            url: url.clone(),
+            rev: new_main_rev,
+            requested_rev: Some("main".to_owned()),
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn failed_add_dependency_leaves_no_directory() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let dependency = Package::new("Dependency", dir.path().join("dependency"))?;
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        create_dir(&subrepo)?;
+        git(&subrepo, &["init", "--quiet"])?;
+        create_dir(package.path().join(MERLON_DIR_NAME))?;
+        let mut initialised = InitialisedPackage::from_initialised(package.clone())?;
+
+        // Declaring the dependency in the manifest fails after the dependency has been copied
+        write(package.path().join(MANIFEST_FILE_NAME), "not a manifest")?;
+        assert!(initialised.add_dependency(AddDependencyOptions {
+            path: Some(dependency.path().to_owned()),
+            git: None,
+            rev: None,
+        }).is_err());
+        let dependency_dir = package.path().join(DEPENDENCIES_DIR_NAME).join(dependency.id()?.to_string());
+        assert!(!dependency_dir.exists());
+        assert!(!initialised.registry().has(dependency.id()?));
+        Ok(())
+    }
+
+    #[test]
+    fn remove_dependency_undoes_add() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        init_upstream(&upstream)?;
+
+        // A depends on Base, and Root on both
+        let package = Package::new("Root", dir.path().join("package"))?;
+        let base = Package::new("Base", dir.path().join("base"))?;
+        let a = Package::new("A", dir.path().join("a"))?;
+        let base_dependency = Dependency::from(base.manifest()?.metadata());
+        a.edit_manifest(|manifest| manifest.declare_direct_dependency(base_dependency))?;
+        let mut initialised = clone_upstream(&package, &upstream)?;
+        let mut add = |path: &Path| initialised.add_dependency(AddDependencyOptions {
+            path: Some(path.to_owned()),
+            git: None,
+            rev: None,
+        });
+        let base_id = add(base.path())?;
+        let a_id = add(a.path())?;
+        initialised.sync_repo()?;
+
+        let error = initialised.remove_dependency(base_id).unwrap_err();
+        assert!(error.to_string().contains("required by: A"), "{}", error);
+        assert!(initialised.remove_dependency(Id::new()).is_err());
+
+        // If the sync fails, nothing is removed
+        remove_file(initialised.lockfile_path())?;
+        create_dir(initialised.lockfile_path())?;
+        assert!(initialised.remove_dependency(a_id).is_err());
+        assert!(package.path().join(DEPENDENCIES_DIR_NAME).join(a_id.to_string()).is_dir());
+        assert!(initialised.registry().has(a_id));
+        assert!(package.manifest()?.iter_direct_dependencies().any(|dep| dep.package_id() == Some(a_id)));
+        remove_dir_all(initialised.lockfile_path())?;
+
+        initialised.remove_dependency(a_id)?;
+        assert!(!package.path().join(DEPENDENCIES_DIR_NAME).join(a_id.to_string()).exists());
+        assert!(!initialised.registry().has(a_id));
+        assert!(initialised.registry().has(base_id));
+        assert!(!package.manifest()?.iter_direct_dependencies().any(|dep| dep.package_id() == Some(a_id)));
+        assert!(!initialised.git_branch_exists(&a_id.to_string())?);
+        let lockfile = Lockfile::read_from_path(&initialised.lockfile_path())?;
+        assert_eq!(lockfile.packages.iter().map(|package| package.id).collect::<Vec<_>>(), vec![base_id]);
+        assert_eq!(initialised.git_current_branch()?, package.id()?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn symbolic_decomp_revs_resolve_to_commits() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        create_dir(&upstream)?;
+        git(&upstream, &["init", "--quiet", "--initial-branch", "main"])?;
+        let mut commits = Vec::new();
+        for (i, tag) in ["v1.2.0", "v1.10.0", "not-a-version"].iter().enumerate() {
+            write(upstream.join("file.c"), format!("int x = {};\n", i))?;
+            git(&upstream, &["add", "file.c"])?;
+            git(&upstream, &["commit", "--quiet", "-m", tag])?;
+            git(&upstream, &["tag", "-a", "-m", tag, tag])?;
+            commits.push(git(&upstream, &["rev-parse", "HEAD"])?);
+        }
+        git(&upstream, &["branch", "dev", &commits[0]])?;
+        let url = upstream.to_string_lossy();
+
+        // Semver order, not string order, and non-version tags are ignored
+        assert_eq!(resolve_decomp_rev(&url, LATEST_TAG_REV)?, commits[1]);
+        // Annotated tags resolve to the commit, not the tag object
+        assert_eq!(resolve_decomp_rev(&url, "v1.2.0")?, commits[0]);
+        assert_eq!(resolve_decomp_rev(&url, "dev")?, commits[0]);
+        assert_eq!(resolve_decomp_rev(&url, "main")?, commits[2]);
+        assert_eq!(resolve_decomp_rev(&url, &commits[1])?, commits[1]);
+        let error = resolve_decomp_rev(&url, "no-such-branch").unwrap_err();
+        assert!(error.to_string().contains("is not a commit hash, or a branch or tag"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_initialise_options_are_rejected() -> Result<()> {
+        let dir = TempDir::new()?;
+        let upstream = dir.path().join("upstream");
+        create_dir(&upstream)?;
+        git(&upstream, &["init", "--quiet", "--initial-branch", "main"])?;
+        git(&upstream, &["commit", "--quiet", "--allow-empty", "-m", "initial"])?;
+        let url = upstream.to_string_lossy();
+        let fake_rom = dir.path().join("baserom.z64");
+        write(&fake_rom, "pretend this is a ROM")?;
+        let options = |baserom: PathBuf, rev: Option<&str>, skip_baserom_check: bool| InitialiseOptions {
+            baserom,
+            region: None,
+            rev: rev.map(str::to_owned),
+            skip_baserom_check,
+            reference: None,
+        };
+        let error_message = |options: InitialiseOptions| options.check(&url, Region::Us).unwrap_err().to_string();
+
+        assert!(error_message(options(dir.path().join("missing.z64"), None, true)).contains("is not a file"));
+        assert!(error_message(options(dir.path().to_owned(), None, true)).contains("is not a file"));
+        let wrong_extension = dir.path().join("baserom.txt");
+        write(&wrong_extension, "pretend this is a ROM")?;
+        assert!(error_message(options(wrong_extension, None, true)).contains("unsupported extension"));
+        let jp_rom = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms/jp-header.z64");
+        let error = options(jp_rom.clone(), None, true).check(&url, Region::Us).unwrap_err();
+        assert_eq!(error.to_string(), "baserom is region JP but package targets US");
+        assert_eq!(options(jp_rom, None, false).check(&url, Region::Jp)?.1, None);
+        let error = options(fake_rom.clone(), None, false).check(&url, Region::Us).unwrap_err();
+        assert!(error.to_string().contains("is not an unmodified US-release"), "{}", error);
+        assert!(matches!(error.downcast_ref::<crate::Error>(), Some(crate::Error::Baserom(_))));
+        // Regions without a known SHA1 are checked against the decomp's splat config once it is cloned
+        options(fake_rom.clone(), None, false).check(&url, Region::Pal)?;
+        assert!(error_message(options(fake_rom.clone(), Some("no-such-branch"), true)).contains("no-such-branch"));
+
+        let (baserom, rev) = options(fake_rom.clone(), Some("main"), true).check(&url, Region::Us)?;
+        assert_eq!(baserom.path(), fake_rom);
+        assert_eq!(rev.map(|rev| rev.len()), Some(40));
+
+        // Byte-swapped dumps are converted
+        let v64_rom = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms/us-header.v64");
+        let (baserom, _) = options(v64_rom, None, true).check(&url, Region::Us)?;
+        assert_eq!(baserom.byte_order()?, Some(rom::ByteOrder::BigEndian));
+        assert_eq!(baserom.path().extension().unwrap(), rom::EXTENSION);
+
+        // Invalid options are found before cloning, so there is nothing to clean up
+        let package = Package::new("Invalid", dir.path().join("package"))?;
+        assert!(InitialisedPackage::initialise(package.clone(), options(fake_rom, None, false)).is_err());
+        assert!(!package.path().join(SUBREPO_DIR_NAME).exists());
+        assert!(!package.path().join(MERLON_DIR_NAME).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn is_initialised_requires_subrepo_and_merlon_dir() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Initialised", dir.path().join("package"))?;
+        assert!(!package.is_initialised()?);
+
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        create_dir(&subrepo)?;
+        assert!(!package.is_initialised()?, "subrepo is not a git repo");
+        git(&subrepo, &["init", "--quiet"])?;
+        assert!(!package.is_initialised()?, "missing {}", MERLON_DIR_NAME);
+
+        create_dir(package.path().join(MERLON_DIR_NAME))?;
+        assert!(package.is_initialised()?);
+        assert!(InitialisedPackage::is_initialised(&package)?);
+
+        write(package.path().join(INIT_INCOMPLETE_FILE_NAME), "")?;
+        assert!(!package.is_initialised()?, "initialisation is incomplete");
+        assert!(package.has_incomplete_initialisation());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_install_keeps_clone_to_resume() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        write(&baserom, "pretend this is a ROM")?;
+        let package = Package::new("Resumable", dir.path().join("package"))?;
+
+        // Pretend an earlier attempt cloned the decomp, but install.sh failed
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        create_dir_all(subrepo.join("ver/us"))?;
+        write(subrepo.join("install.sh"), "exit 1\n")?;
+        git(&subrepo, &["init", "--quiet"])?;
+        create_dir(package.path().join(MERLON_DIR_NAME))?;
+        write(package.path().join(INIT_INCOMPLETE_FILE_NAME), "")?;
+
+        // Resuming skips cloning, and failing again keeps the clone
+        let error = InitialisedPackage::initialise(package.clone(), InitialiseOptions {
+            baserom,
+            region: None,
+            rev: None,
+            skip_baserom_check: true,
+            reference: None,
+        }).unwrap_err();
+        assert!(format!("{:#}", error).contains("failed to run decomp install.sh"), "{:#}", error);
+        assert!(format!("{:#}", error).contains("run `merlon init` again to resume"));
+        assert!(subrepo.join("ver/us/baserom.z64").is_file());
+        assert!(package.has_incomplete_initialisation());
+        assert!(!package.is_initialised()?);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_program_reports_hint() {
+        let error = require_program(OsStr::new("merlon-definitely-not-installed"), "install it").unwrap_err();
+        assert_eq!(error.to_string(), "install it");
+        assert!(require_program(OsStr::new("git"), "install git").is_ok());
+    }
+
+    #[test]
+    fn git_helpers_manage_branches_in_process() -> Result<()> {
+        let dir = TempDir::new()?;
+        let package = Package::new("Branches", dir.path().join("package"))?;
+        let subrepo = package.path().join(SUBREPO_DIR_NAME);
+        init_upstream(&subrepo)?;
+        create_dir(package.path().join(MERLON_DIR_NAME))?;
+        let initialised = InitialisedPackage::from_initialised(package)?;
+
+        let initial = initialised.git_head_commit()?;
+        assert_eq!(initialised.git_current_branch()?, "main");
+        initialised.git_create_branch("feature")?;
+        assert!(initialised.git_branch_exists("feature")?);
+        assert!(!initialised.git_branch_exists("missing")?);
+        assert_eq!(initialised.git_branches()?, vec!["feature", "main"]);
+
+        initialised.git_checkout_branch("feature")?;
+        assert_eq!(initialised.git_current_branch()?, "feature");
+        write(subrepo.join("file.c"), "int x = 1;\n")?;
+        assert!(initialised.is_git_dirty()?);
+        git(&subrepo, &["commit", "--quiet", "-am", "change"])?;
+        assert!(!initialised.is_git_dirty()?);
+        assert!(initialised.git_is_ancestor(&initial, "feature")?);
+        assert!(!initialised.git_is_ancestor("feature", &initial)?);
+        assert!(!initialised.git_is_ancestor("missing", "feature")?);
+        assert!(initialised.git_commit_exists(&initial)?);
+        assert!(!initialised.git_commit_exists("missing")?);
+
+        // Stashed changes survive switching branches
+        write(subrepo.join("file.c"), "int x = 2;\n")?;
+        initialised.git_stash()?;
+        assert!(!initialised.is_git_dirty()?);
+        initialised.git_checkout_branch("main")?;
+        assert_eq!(std::fs::read_to_string(subrepo.join("file.c"))?, "int x = 0;\n");
+        initialised.git_checkout_branch("feature")?;
+        initialised.git_stash_pop()?;
+        assert_eq!(std::fs::read_to_string(subrepo.join("file.c"))?, "int x = 2;\n");
+
+        // Uncommitted changes are never overwritten
+        assert!(initialised.git_checkout_branch("main").is_err());
+        assert_eq!(initialised.git_current_branch()?, "feature");
+        git(&subrepo, &["checkout", "--", "file.c"])?;
+
+        // Other revisions are checked out detached
+        initialised.git_checkout_branch(&initial)?;
+        assert_eq!(initialised.git_current_branch()?, "HEAD");
+        initialised.git_checkout_branch("main")?;
+        initialised.git_delete_branch("feature")?;
+        assert!(!initialised.git_branch_exists("feature")?);
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@
 use std::io::prelude::*;
 use std::{fs::File, path::Path, io::{BufReader, BufWriter}};
 use anyhow::{Result, bail};
+use thiserror::Error;
 use pyo3::exceptions::PyValueError;
 use pyo3::types::PyDict;
 use serde::{Deserialize, Serialize};
@@ -18,9 +19,20 @@ use name::Name;
 mod id;
 pub use id::Id;
 
-use super::Package;
+mod patch_context;
+pub use patch_context::PatchContext;
+
+mod author;
+pub use author::Author;
+
+mod license;
+
+mod legacy;
 
-// TODO: use taplo instead of toml to preserve comments etc
+mod document;
+
+use super::Package;
+use super::warning::{Warning, Code};
 
 /// Package manifest data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,8 +44,104 @@ pub struct Manifest {
 
     /// Direct dependencies (not transitive)
     dependencies: Vec<Dependency>,
+
+    /// Optional conventions for commit subjects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit_convention: Option<CommitConvention>,
+}
+
+/// Conventions that the subjects of a package's commits, and hence its patches, should follow.
+/// Corresponds to the optional `[commit_convention]` section in `merlon.toml`.
+///
+/// See [`Package::check_commit_subjects`](super::Package::check_commit_subjects).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[pyclass(module = "merlon.package.manifest")]
+pub struct CommitConvention {
+    /// If not empty, subjects must start with one of these prefixes, e.g. `fix: `.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[pyo3(get, set)]
+    pub prefixes: Vec<String>,
+
+    /// Maximum subject length, in characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[pyo3(get, set)]
+    pub max_length: Option<usize>,
+}
+
+impl CommitConvention {
+    /// Returns a description of each way the given subject breaks the convention.
+    pub fn check(&self, subject: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.prefixes.is_empty() && !self.prefixes.iter().any(|prefix| subject.starts_with(prefix.as_str())) {
+            problems.push(format!("does not start with any of {:?}", self.prefixes));
+        }
+        if let Some(max_length) = self.max_length {
+            let length = subject.chars().count();
+            if length > max_length {
+                problems.push(format!("is {} characters long (maximum {})", length, max_length));
+            }
+        }
+        problems
+    }
+}
+
+/// Which component of a version to increment. See [`Metadata::bump_version`].
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[pyclass(module = "merlon.package.manifest")]
+pub enum VersionBump {
+    /// Incompatible changes, e.g. `1.2.3` to `2.0.0`.
+    Major,
+    /// Backwards-compatible additions, e.g. `1.2.3` to `1.3.0`.
+    Minor,
+    /// Backwards-compatible fixes, e.g. `1.2.3` to `1.2.4`.
+    Patch,
+}
+
+/// A release of Paper Mario that the decomp can build.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[pyclass(module = "merlon.package.manifest")]
+pub enum Region {
+    /// North America.
+    #[default]
+    Us,
+    /// Japan.
+    Jp,
+    /// Europe.
+    Pal,
+    /// China (iQue Player).
+    Ique,
+}
+
+impl Region {
+    /// Returns the name the decomp uses for the region, e.g. `us` in `ver/us/`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Region::Us => "us",
+            Region::Jp => "jp",
+            Region::Pal => "pal",
+            Region::Ique => "ique",
+        }
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error returned when writing a manifest whose metadata fails [`Metadata::validate`].
+#[derive(Error, Debug)]
+#[error("refusing to write invalid manifest: {}", .errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+pub struct ValidationError {
+    /// The validation errors. These all have [`Severity::Error`](super::warning::Severity::Error).
+    pub errors: Vec<Warning>,
 }
 
+/// Maximum number of characters in [`Metadata::long_description`].
+pub const MAX_LONG_DESCRIPTION_LEN: usize = 4000;
+
 /// Metadata about a package. Corresponds to the `[package]` section in `merlon.toml`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[pyclass(module = "merlon.package.manifest")]
@@ -45,6 +153,40 @@ pub struct Metadata {
     description: String,
     license: String,
     keywords: Vec<String>,
+
+    /// A longer, possibly multi-line, description explaining the package in more detail than `description`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    long_description: Option<String>,
+
+    /// URL of the package's source repository, e.g. on GitHub.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repository: Option<String>,
+
+    /// URL of the package's website.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    homepage: Option<String>,
+
+    /// Whether the package's patches apply to base ROMs of any region.
+    /// Required for [`Distributable::repack`](super::Distributable::repack).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    region_portable: bool,
+
+    /// Codes of warnings to suppress, e.g. `W003`. See [`Warning`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allow: Vec<String>,
+
+    /// Versions of Merlon that can open the package, e.g. `>=1.2.0`. See [`Metadata::check_merlon_version`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    required_merlon_version: Option<VersionReq>,
+
+    /// Regions the package targets, for [`InitialisedPackage::build_all_regions`](super::InitialisedPackage::build_all_regions).
+    /// The first is the one the package is developed against. If empty, only [`Region::Us`] is targeted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    regions: Vec<Region>,
+
+    /// How much context the package's patches are written with. See [`PatchContext`].
+    #[serde(default, skip_serializing_if = "PatchContext::is_default")]
+    patch_context: PatchContext,
 }
 
 #[pymethods]
@@ -80,36 +222,119 @@ impl Metadata {
         &self.description
     }
 
-    /// Validate package metadata, returning a list of errors
-    pub fn validate(&self) -> Vec<String> {
-        let mut errors = Vec::new();
+    /// Set the package one-line description.
+    #[setter]
+    pub fn set_description(&mut self, description: String) {
+        self.description = description;
+    }
+
+    /// The package's long description, if it has one.
+    #[getter]
+    pub fn long_description(&self) -> Option<&str> {
+        self.long_description.as_deref()
+    }
+
+    /// Set the package's long description. Leading and trailing whitespace is removed, and an empty description is
+    /// treated as none.
+    #[setter]
+    pub fn set_long_description(&mut self, long_description: Option<String>) {
+        self.long_description = long_description
+            .map(|description| description.trim().to_owned())
+            .filter(|description| !description.is_empty());
+    }
+
+    /// URL of the package's source repository, if it has one.
+    #[getter]
+    pub fn repository(&self) -> Option<&str> {
+        self.repository.as_deref()
+    }
+
+    /// Set the URL of the package's source repository.
+    #[setter]
+    pub fn set_repository(&mut self, repository: Option<String>) {
+        self.repository = repository;
+    }
+
+    /// URL of the package's website, if it has one.
+    #[getter]
+    pub fn homepage(&self) -> Option<&str> {
+        self.homepage.as_deref()
+    }
+
+    /// Set the URL of the package's website.
+    #[setter]
+    pub fn set_homepage(&mut self, homepage: Option<String>) {
+        self.homepage = homepage;
+    }
+
+    /// Validate package metadata, returning a list of warnings.
+    /// Warnings allowed by the `allow` field are not included, unless they are errors.
+    pub fn validate(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
         // TODO: use newtypes for these, like Name
-        // TODO: validate version
+        // Malformed pre-release identifiers are already rejected when the version is parsed
+        if self.version == Version::new(0, 0, 0) {
+            warnings.push(Warning::new(Code::InvalidVersion, "version cannot be 0.0.0, start at 0.1.0 instead"));
+        } else if self.version.major == 0 && self.version.minor == 0 {
+            warnings.push(Warning::new(
+                Code::UnstableVersion,
+                format!(
+                    "version {} is unstable: dependents of a 0.0.x version can only require that exact version, \
+                    consider 0.1.0",
+                    self.version,
+                ),
+            ));
+        }
         if self.authors.is_empty() {
-            errors.push("authors cannot be empty".to_owned());
+            warnings.push(Warning::new(Code::EmptyAuthors, "authors cannot be empty"));
         }
         if self.description.is_empty() {
-            errors.push("description cannot be empty".to_owned());
+            warnings.push(Warning::new(Code::EmptyDescription, "description cannot be empty"));
         }
         if self.description.len() > 100 {
-            errors.push("description must be less than 100 characters".to_owned());
+            warnings.push(Warning::new(Code::LongDescription, "description must be less than 100 characters"));
+        }
+        if let Some(long_description) = &self.long_description {
+            if long_description.chars().count() > MAX_LONG_DESCRIPTION_LEN {
+                warnings.push(Warning::new(
+                    Code::LongLongDescription,
+                    format!("long_description must be at most {} characters", MAX_LONG_DESCRIPTION_LEN),
+                ));
+            }
+        }
+        for (field, url) in [("repository", &self.repository), ("homepage", &self.homepage)] {
+            if let Some(url) = url {
+                if let Err(message) = check_url(url) {
+                    warnings.push(Warning::new(Code::InvalidUrl, format!("{} {:?} {}", field, url, message)));
+                }
+            }
         }
         if self.license.is_empty() {
-            errors.push("license cannot be empty".to_owned());
+            warnings.push(Warning::new(Code::EmptyLicense, "license cannot be empty"));
+        } else if let Err(message) = license::check(&self.license) {
+            warnings.push(Warning::new(Code::InvalidLicense, message));
         }
-        // TODO: validate license
         for keyword in &self.keywords {
             const VALID_KEYWORDS: &[&str] = &["qol", "cheat", "bugfix", "cosmetic", "feature"];
             if !VALID_KEYWORDS.contains(&keyword.as_str()) {
-                errors.push(format!("invalid keyword: {} (valid keywords: {:?})", keyword, VALID_KEYWORDS));
+                warnings.push(Warning::new(
+                    Code::InvalidKeyword,
+                    format!("invalid keyword: {} (valid keywords: {:?})", keyword, VALID_KEYWORDS),
+                ));
             }
         }
-        errors
+        warnings.retain(|warning| warning.is_error() || !self.allows(warning.code()));
+        warnings
+    }
+
+    /// Returns whether warnings with the given code are allowed by the `allow` field.
+    pub fn allows(&self, code: Code) -> bool {
+        self.allow.iter().any(|allowed| allowed == code.as_str())
     }
 
-    /// Returns whether the package metadata is valid.
+    /// Returns whether the package metadata is valid, i.e. there are no errors. Warnings are permitted.
     pub fn is_valid(&self) -> bool {
-        self.validate().is_empty()
+        !self.validate().iter().any(Warning::is_error)
     }
 
     /// The package authors.
@@ -117,6 +342,111 @@ impl Metadata {
     fn get_authors(&self) -> Vec<String> {
         self.authors.clone()
     }
+
+    /// Replaces the package authors.
+    #[setter]
+    pub fn set_authors(&mut self, authors: Vec<String>) {
+        self.authors = authors;
+    }
+
+    /// Returns the package authors, parsed into names and emails. See [`Author::parse`].
+    pub fn parsed_authors(&self) -> Vec<Author> {
+        self.authors.iter().map(|author| Author::parse(author)).collect()
+    }
+
+    /// Whether the package's patches apply to base ROMs of any region.
+    #[getter]
+    pub fn region_portable(&self) -> bool {
+        self.region_portable
+    }
+
+    /// Declare whether the package's patches apply to base ROMs of any region.
+    #[setter]
+    pub fn set_region_portable(&mut self, region_portable: bool) {
+        self.region_portable = region_portable;
+    }
+
+    /// Regions the package targets. Defaults to just `us`.
+    #[getter]
+    pub fn regions(&self) -> Vec<Region> {
+        if self.regions.is_empty() {
+            vec![Region::default()]
+        } else {
+            self.regions.clone()
+        }
+    }
+
+    /// The region the package is developed against, i.e. the first of [`regions`](Self::regions). The decomp is
+    /// initialised with a base ROM of this region, and [`build_rom`](super::InitialisedPackage::build_rom) builds it.
+    #[getter]
+    pub fn region(&self) -> Region {
+        self.regions().remove(0)
+    }
+
+    /// Declare the regions the package targets.
+    #[setter]
+    pub fn set_regions(&mut self, regions: Vec<Region>) {
+        self.regions = regions;
+    }
+
+    /// How much context the package's patches are written with: `minimal`, `function`, or `lines=N`.
+    #[getter]
+    pub fn patch_context(&self) -> PatchContext {
+        self.patch_context
+    }
+
+    /// Change how much context the package's patches are written with. Takes effect when patches are next written.
+    #[setter]
+    pub fn set_patch_context(&mut self, patch_context: PatchContext) {
+        self.patch_context = patch_context;
+    }
+
+    /// Versions of Merlon that can open the package, if restricted.
+    #[getter]
+    fn get_required_merlon_version(&self) -> Option<String> {
+        self.required_merlon_version.as_ref().map(ToString::to_string)
+    }
+
+    /// Restrict the versions of Merlon that can open the package. Must be a valid semver requirement (e.g. `>=1.2.0`).
+    #[setter(required_merlon_version)]
+    fn py_set_required_merlon_version(&mut self, required_merlon_version: Option<String>) -> Result<()> {
+        self.required_merlon_version = required_merlon_version.map(|req| req.parse()).transpose()?;
+        Ok(())
+    }
+
+    /// Increments the major version, e.g. `1.2.3` to `2.0.0`, and returns the new version.
+    #[pyo3(name = "bump_major")]
+    fn py_bump_major(&mut self) -> String {
+        self.bump_major().to_string()
+    }
+
+    /// Increments the minor version, e.g. `1.2.3` to `1.3.0`, and returns the new version.
+    #[pyo3(name = "bump_minor")]
+    fn py_bump_minor(&mut self) -> String {
+        self.bump_minor().to_string()
+    }
+
+    /// Increments the patch version, e.g. `1.2.3` to `1.2.4`, and returns the new version.
+    #[pyo3(name = "bump_patch")]
+    fn py_bump_patch(&mut self) -> String {
+        self.bump_patch().to_string()
+    }
+
+    /// Errors if the running version of Merlon doesn't satisfy the package's `required_merlon_version`.
+    pub fn check_merlon_version(&self) -> Result<()> {
+        if let Some(required) = &self.required_merlon_version {
+            let current = Version::parse(env!("CARGO_PKG_VERSION"))?;
+            if !required.matches(&current) {
+                bail!(
+                    "package {} requires Merlon {}, but this is Merlon {} - try updating Merlon",
+                    self.name,
+                    required,
+                    current,
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Metadata {
@@ -135,6 +465,64 @@ impl Metadata {
         self.version = version;
     }
 
+    /// Increments the major version, e.g. `1.2.3` to `2.0.0`. See [`Metadata::bump_version`].
+    pub fn bump_major(&mut self) -> Version {
+        self.bump_version(VersionBump::Major).clone()
+    }
+
+    /// Increments the minor version, e.g. `1.2.3` to `1.3.0`. See [`Metadata::bump_version`].
+    pub fn bump_minor(&mut self) -> Version {
+        self.bump_version(VersionBump::Minor).clone()
+    }
+
+    /// Increments the patch version, e.g. `1.2.3` to `1.2.4`. See [`Metadata::bump_version`].
+    pub fn bump_patch(&mut self) -> Version {
+        self.bump_version(VersionBump::Patch).clone()
+    }
+
+    /// Increments the version according to semver rules, and returns the new version.
+    ///
+    /// Lower components are reset to zero, and any pre-release and build metadata is removed. A pre-release of the
+    /// version that would be bumped to is released instead of being bumped past, e.g. bumping the minor version of
+    /// `1.3.0-rc1` gives `1.3.0`.
+    pub fn bump_version(&mut self, bump: VersionBump) -> &Version {
+        let version = &mut self.version;
+        let is_pre = !version.pre.is_empty();
+        match bump {
+            VersionBump::Major if is_pre && version.minor == 0 && version.patch == 0 => {}
+            VersionBump::Major => {
+                version.major += 1;
+                version.minor = 0;
+                version.patch = 0;
+            }
+            VersionBump::Minor if is_pre && version.patch == 0 => {}
+            VersionBump::Minor => {
+                version.minor += 1;
+                version.patch = 0;
+            }
+            VersionBump::Patch if is_pre => {}
+            VersionBump::Patch => version.patch += 1,
+        }
+        version.pre = semver::Prerelease::EMPTY;
+        version.build = semver::BuildMetadata::EMPTY;
+        &self.version
+    }
+
+    /// Returns the versions of Merlon that can open the package, if restricted.
+    pub fn required_merlon_version(&self) -> Option<&VersionReq> {
+        self.required_merlon_version.as_ref()
+    }
+
+    /// Restricts the versions of Merlon that can open the package.
+    pub fn set_required_merlon_version(&mut self, required_merlon_version: Option<VersionReq>) {
+        self.required_merlon_version = required_merlon_version;
+    }
+
+    /// Changes the package ID. Only used to recreate packages, e.g. from a registry snapshot.
+    pub(crate) fn set_id(&mut self, id: Id) {
+        self.id = id;
+    }
+
     /// Returns the package authors.
     pub fn authors(&self) -> &Vec<String> {
         &self.authors
@@ -143,8 +531,8 @@ impl Metadata {
     /// Prints validation warnings to stderr.
     #[deprecated(since = "1.1.0", note = "iterate over validate() instead")]
     pub fn print_validation_warnings(&self) {
-        for error in self.validate() {
-            eprintln!("warning: {}", error);
+        for warning in self.validate() {
+            eprintln!("{}", warning);
         }
     }
 }
@@ -168,6 +556,36 @@ pub enum Dependency {
         /// Git commit hash
         rev: String,
     },
+    /// Dependency on a Merlon package hosted in a git repository, like cargo's git dependencies.
+    /// The package is cloned into the dependencies directory, so it is tracked by revision rather than by version.
+    Git {
+        /// The ID of the package in the repository, so that the dependency can be resolved without fetching it.
+        id: Id,
+
+        /// URL of the git repository.
+        url: String,
+
+        /// Git commit hash that the repository is pinned to.
+        rev: String,
+
+        /// The revision that was asked for when the dependency was added, e.g. a branch or tag, which is resolved
+        /// again to update the dependency. If not set, the repository's default branch is followed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        requested_rev: Option<String>,
+    },
+}
+
+/// Version requirement of [`Dependency::Git`] dependencies, which are pinned by revision instead.
+static ANY_VERSION: VersionReq = VersionReq::STAR;
+
+impl Dependency {
+    /// Returns the ID of the package depended on, or `None` for a dependency on the decomp.
+    pub fn package_id(&self) -> Option<Id> {
+        match self {
+            Self::Package { id, .. } | Self::Git { id, .. } => Some(*id),
+            Self::Decomp { .. } => None,
+        }
+    }
 }
 
 impl From<&Metadata> for Dependency {
@@ -216,6 +634,15 @@ impl ToPyObject for Dependency {
                 dict.set_item("rev", rev).unwrap();
                 dict.into()
             }
+            Self::Git { id, url, rev, requested_rev } => {
+                let dict = PyDict::new(py);
+                dict.set_item("type", "git").unwrap();
+                dict.set_item("id", id.to_string()).unwrap();
+                dict.set_item("url", url).unwrap();
+                dict.set_item("rev", rev).unwrap();
+                dict.set_item("requested_rev", requested_rev).unwrap();
+                dict.into()
+            }
         }
     }
 }
@@ -244,6 +671,22 @@ impl FromPyObject<'_> for Dependency {
                     .extract()?;
                 Ok(Self::Decomp { rev })
             }
+            "git" => {
+                let id: Id = dict.get_item("id")
+                    .ok_or(PyValueError::new_err("missing dependency id"))?
+                    .extract()?;
+                let url: String = dict.get_item("url")
+                    .ok_or(PyValueError::new_err("missing dependency url"))?
+                    .extract()?;
+                let rev: String = dict.get_item("rev")
+                    .ok_or(PyValueError::new_err("missing dependency rev"))?
+                    .extract()?;
+                let requested_rev: Option<String> = match dict.get_item("requested_rev") {
+                    Some(requested_rev) => requested_rev.extract()?,
+                    None => None,
+                };
+                Ok(Self::Git { id, url, rev, requested_rev })
+            }
             _ => Err(PyValueError::new_err(format!("invalid dependency type: {}", type_))),
         }
     }
@@ -269,8 +712,17 @@ impl Manifest {
                 description: "An amazing mod".to_owned(),
                 license: "CC-BY-SA-4.0".to_owned(),
                 keywords: vec![],
+                long_description: None,
+                repository: None,
+                homepage: None,
+                region_portable: false,
+                allow: vec![],
+                required_merlon_version: None,
+                regions: vec![],
+                patch_context: PatchContext::default(),
             },
             dependencies: vec![], // note: no Dependency::Decomp (init will add this)
+            commit_convention: None,
         })
     }
 
@@ -298,70 +750,185 @@ impl Manifest {
         &mut self.metadata
     }
 
+    /// Replaces all direct dependencies.
+    pub(crate) fn set_dependencies(&mut self, dependencies: Vec<Dependency>) {
+        self.dependencies = dependencies;
+    }
+
+    /// Returns the commit subject convention, if the package has one.
+    pub fn commit_convention(&self) -> Option<&CommitConvention> {
+        self.commit_convention.as_ref()
+    }
+
+    /// Sets or removes the commit subject convention.
+    pub fn set_commit_convention(&mut self, commit_convention: Option<CommitConvention>) {
+        self.commit_convention = commit_convention;
+    }
+
     /// Reads a manifest from a file. Typically, manifest files are named `merlon.toml`.
+    ///
+    /// Legacy manifests are converted to the current format, see [`Manifest::migrate_from_legacy`]. The file isn't
+    /// changed until the manifest is next written.
     pub fn read_from_path(path: &Path) -> Result<Self> {
+        #[cfg(test)]
+        test::READ_COUNT.with(|count| count.set(count.get() + 1));
         let file = File::open(path)?;
         let mut reader = BufReader::new(file);
         let mut toml_string = String::new();
         reader.read_to_string(&mut toml_string)?;
+        if legacy::is_legacy_str(&toml_string) {
+            log::info!("{} is in the legacy manifest format, reading it as the current format", path.display());
+            return Manifest::from_legacy_str(&toml_string);
+        }
         let config = toml::from_str(&toml_string)?;
         Ok(config)
     }
 
-    /// Writes a manifest to a file.
+    /// Writes a manifest to a file. If the file already exists, only the keys that changed are rewritten, keeping its
+    /// comments and formatting.
     pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        let mut toml_string = toml::to_string_pretty(self)?;
+        if let Ok(existing) = std::fs::read_to_string(path) {
+            // If the existing file isn't valid TOML there is nothing worth keeping
+            if let Ok(merged) = document::merge(&existing, &toml_string) {
+                toml_string = merged;
+            }
+        }
         let file = File::create(path)?;
         let mut writer = BufWriter::new(file);
-        let toml_string = toml::to_string_pretty(self)?;
         writer.write_all(toml_string.as_bytes())?;
         Ok(())
     }
 
     /// Adds a dependency to the manifest.
-    /// If the dependency is declared already but with a different version/revision, errors.
+    ///
+    /// If the package is declared already with an older version requirement, e.g. `~0.1.0` when adding `~0.2.0`, the
+    /// requirement is upgraded. If it is declared with a newer requirement that also satisfies the given one, it is
+    /// kept. Otherwise the requirements conflict, and this errors. Git dependencies have their URL and revision
+    /// updated, since that is how they are updated to a newer revision. A decomp dependency with a different revision
+    /// errors, since its history isn't known here; see [`Manifest::upgrade_decomp_dependency`].
     pub fn declare_direct_dependency(&mut self, dependency: Dependency) -> Result<()> {
         match &dependency {
             Dependency::Package { id, version } => {
-                if let Some(Dependency::Package { version: existing_version, .. }) = self.dependencies
-                    .iter_mut()
-                    .find(|dep| matches!(dep, Dependency::Package { id: dep_id, .. } if *id == *dep_id))
-                {
-                    // TODO: if existing version <= version, update it
-                    if *existing_version != *version {
-                        bail!("dependency on package ID {} already declared with incompatible version", id);
+                if let Some(existing) = self.dependencies.iter_mut().find(|dep| dep.package_id() == Some(*id)) {
+                    let existing_version = match existing {
+                        Dependency::Package { version: existing_version, .. } => existing_version,
+                        Dependency::Git { .. } => {
+                            bail!("dependency on package ID {} already declared as a git dependency", id)
+                        }
+                        Dependency::Decomp { .. } => unreachable!("decomp dependencies have no package ID"),
+                    };
+                    if *existing_version == *version {
+                        return Ok(());
+                    }
+                    match (minimum_version(existing_version), minimum_version(version)) {
+                        (Some(existing_minimum), Some(minimum)) if minimum > existing_minimum => {
+                            log::info!(
+                                "upgrading dependency on package ID {} from {} to {}",
+                                id,
+                                existing_version,
+                                version,
+                            );
+                            *existing_version = version.clone();
+                        }
+                        (Some(existing_minimum), Some(_)) if version.matches(&existing_minimum) => {}
+                        _ => bail!(
+                            "dependency on package ID {} already declared with incompatible version {} (adding {})",
+                            id,
+                            existing_version,
+                            version,
+                        ),
                     }
                     return Ok(());
                 }
             }
-            Dependency::Decomp { rev } => {
-                if let Some(Dependency::Decomp { rev: existing_rev, .. }) = self.dependencies
-                    .iter_mut()
-                    .find(|dep| matches!(dep, Dependency::Decomp { .. }))
-                {
-                    // TODO: if existing rev <= rev, update it
-                    if *existing_rev != *rev {
-                        bail!("dependency on decomp already declared with incompatible revision");
+            Dependency::Git { id, .. } => {
+                if let Some(existing) = self.dependencies.iter_mut().find(|dep| dep.package_id() == Some(*id)) {
+                    if let Dependency::Package { .. } = existing {
+                        bail!("dependency on package ID {} already declared as a package dependency", id);
                     }
+                    *existing = dependency;
                     return Ok(());
                 }
             }
+            Dependency::Decomp { rev } => {
+                return self.upgrade_decomp_dependency(rev.clone(), |_, _| Ok(false));
+            }
         }
         self.dependencies.push(dependency);
         Ok(())
     }
 
+    /// Adds a decomp dependency, or moves the existing one forward to `rev` if it is an ancestor of `rev`.
+    /// If `rev` is an ancestor of the existing revision, the existing, newer, revision is kept. If neither is an
+    /// ancestor of the other, errors.
+    ///
+    /// `is_ancestor(ancestor, descendant)` should look the commits up in the decomp's history, returning false if
+    /// either is unknown.
+    pub fn upgrade_decomp_dependency<F>(&mut self, rev: String, is_ancestor: F) -> Result<()>
+    where
+        F: Fn(&str, &str) -> Result<bool>,
+    {
+        let existing_rev = self.dependencies.iter_mut().find_map(|dep| match dep {
+            Dependency::Decomp { rev } => Some(rev),
+            Dependency::Package { .. } | Dependency::Git { .. } => None,
+        });
+        match existing_rev {
+            None => self.dependencies.push(Dependency::Decomp { rev }),
+            Some(existing_rev) if *existing_rev == rev => {}
+            Some(existing_rev) if is_ancestor(existing_rev, &rev)? => {
+                log::info!("upgrading dependency on decomp from {} to {}", existing_rev, rev);
+                *existing_rev = rev;
+            }
+            Some(existing_rev) if is_ancestor(&rev, existing_rev)? => {}
+            Some(existing_rev) => bail!(
+                "dependency on decomp already declared with incompatible revision {} (adding {})",
+                existing_rev,
+                rev,
+            ),
+        }
+        Ok(())
+    }
+
+    /// Removes the declared dependency on a package, whether by version or git, and returns it.
+    pub fn undeclare_direct_dependency(&mut self, id: Id) -> Result<Dependency> {
+        let Some(index) = self.dependencies.iter().position(|dep| dep.package_id() == Some(id)) else {
+            bail!("no dependency on package ID {} is declared", id);
+        };
+        Ok(self.dependencies.remove(index))
+    }
+
     /// Iterates over the dependencies that are declared in the manifest.
     pub fn iter_direct_dependencies(&self) -> impl Iterator<Item = &Dependency> {
         self.dependencies.iter()
     }
 
+    /// Iterates over the declared dependencies on other packages, as their ID and version requirement.
+    /// Git dependencies are pinned by revision, so any version satisfies them.
+    pub fn iter_package_dependencies(&self) -> impl Iterator<Item = (Id, &VersionReq)> {
+        self.dependencies.iter().filter_map(|dependency| match dependency {
+            Dependency::Package { id, version } => Some((*id, version)),
+            Dependency::Git { id, .. } => Some((*id, &ANY_VERSION)),
+            Dependency::Decomp { .. } => None,
+        })
+    }
+
+    /// Iterates over the revisions of the declared decomp dependencies. There is at most one.
+    pub fn iter_decomp_dependencies(&self) -> impl Iterator<Item = &str> {
+        self.dependencies.iter().filter_map(|dependency| match dependency {
+            Dependency::Decomp { rev } => Some(rev.as_str()),
+            Dependency::Package { .. } | Dependency::Git { .. } => None,
+        })
+    }
+
     /// Returns true if the manifest has a decomp-type dependency.
     pub fn has_direct_decomp_dependency(&self) -> bool {
-        self.dependencies.iter().any(|dep| matches!(dep, Dependency::Decomp { .. }))
+        self.iter_decomp_dependencies().next().is_some()
     }
 
     /// Adds a Dependency::Decomp dependency if one does not already exist.
-    /// If it does exist, updates it.
+    /// If it does exist, updates it, even to an older revision. To only ever move forward, use
+    /// [`Manifest::upgrade_decomp_dependency`].
     pub fn upsert_decomp_dependency(&mut self, rev: String) -> Result<()> {
         if let Some(dep) = self.dependencies.iter_mut().find(|dep| matches!(dep, Dependency::Decomp { .. })) {
             if let Dependency::Decomp { rev: existing_rev } = dep {
@@ -374,15 +941,34 @@ impl Manifest {
 
     /// Returns the Git revision (commit hash) of the decomp dependency, if one exists.
     pub fn get_direct_decomp_dependency_rev(&self) -> Option<&str> {
-        if let Some(dep) = self.dependencies.iter().find(|dep| matches!(dep, Dependency::Decomp { .. })) {
-            if let Dependency::Decomp { rev } = dep {
-                return Some(rev);
-            }
-        }
-        None
+        self.iter_decomp_dependencies().next()
     }
 }
 
+/// Returns the lowest version that satisfies `requirement`, or `None` if it has no lower bound, e.g. `<2.0.0`.
+fn minimum_version(requirement: &VersionReq) -> Option<Version> {
+    let minimum = requirement.comparators.iter()
+        .filter_map(|comparator| {
+            let minor = comparator.minor.unwrap_or(0);
+            let mut version = Version::new(comparator.major, minor, comparator.patch.unwrap_or(0));
+            match comparator.op {
+                semver::Op::Exact | semver::Op::GreaterEq | semver::Op::Tilde | semver::Op::Caret => {
+                    version.pre = comparator.pre.clone();
+                }
+                semver::Op::Greater => match (comparator.minor, comparator.patch) {
+                    (Some(_), Some(_)) => version.patch += 1,
+                    (Some(_), None) => version = Version::new(comparator.major, minor + 1, 0),
+                    _ => version = Version::new(comparator.major + 1, 0, 0),
+                },
+                semver::Op::Wildcard => {}
+                _ => return None,
+            }
+            Some(version)
+        })
+        .max()?;
+    requirement.matches(&minimum).then_some(minimum)
+}
+
 /// Get author from git config as `name <email>`
 fn get_author() -> Result<String> {
     let git_user_name = std::process::Command::new("git")
@@ -402,3 +988,20 @@ fn get_author() -> Result<String> {
 
     Ok(format!("{} <{}>", git_user_name, git_user_email))
 }
+
+/// Checks that `url` is an absolute http or https URL. Returns a message describing the problem if it isn't.
+fn check_url(url: &str) -> Result<(), String> {
+    match url::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => Ok(()),
+        Ok(_) => Err("must be an http or https URL".to_owned()),
+        Err(error) => Err(format!("is not a valid URL: {}", error)),
+    }
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    thread_local! {
+        /// Number of manifests read from disk on this thread, so tests can check that they aren't read needlessly.
+        pub(in crate::package) static READ_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use pyo3::prelude::*;
+
+/// A package author, parsed from an entry of the manifest's `authors` list.
+///
+/// Authors are stored as strings in `Name <email>` form, which is what `merlon new` writes. Entries that aren't in
+/// that form are kept whole as the name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[pyclass(module = "merlon.package.manifest")]
+pub struct Author {
+    /// The author's name.
+    #[pyo3(get)]
+    pub name: String,
+
+    /// The author's email address, if given.
+    #[pyo3(get)]
+    pub email: Option<String>,
+}
+
+impl Author {
+    /// Parses an author from a `Name <email>` string. If the string isn't in that form, e.g. it has no email or an
+    /// empty name, the whole string is taken as the name.
+    pub fn parse(author: &str) -> Self {
+        let author = author.trim();
+        let parsed = author
+            .strip_suffix('>')
+            .and_then(|rest| rest.rsplit_once('<'))
+            .map(|(name, email)| (name.trim(), email.trim()))
+            .filter(|(name, email)| !name.is_empty() && !email.is_empty())
+            .filter(|(name, email)| !name.contains(['<', '>']) && !email.contains(['<', '>']));
+        match parsed {
+            Some((name, email)) => Self {
+                name: name.to_owned(),
+                email: Some(email.to_owned()),
+            },
+            None => Self {
+                name: author.to_owned(),
+                email: None,
+            },
+        }
+    }
+}
+
+#[pymethods]
+impl Author {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Author {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.email {
+            Some(email) => write!(f, "{} <{}>", self.name, email),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl From<&str> for Author {
+    fn from(author: &str) -> Self {
+        Self::parse(author)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_name_and_email() {
+        let author = Author::parse("Alex Bates <alex@example.com>");
+        assert_eq!(author.name, "Alex Bates");
+        assert_eq!(author.email.as_deref(), Some("alex@example.com"));
+        assert_eq!(author.to_string(), "Alex Bates <alex@example.com>");
+
+        let author = Author::parse("  Alex   <alex@example.com>  ");
+        assert_eq!(author.name, "Alex");
+        assert_eq!(author.email.as_deref(), Some("alex@example.com"));
+    }
+
+    #[test]
+    fn name_without_email() {
+        let author = Author::parse("Alex Bates");
+        assert_eq!(author.name, "Alex Bates");
+        assert_eq!(author.email, None);
+        assert_eq!(author.to_string(), "Alex Bates");
+    }
+
+    #[test]
+    fn malformed_authors_are_kept_whole() {
+        // What `merlon new` writes when git has no user configured
+        for malformed in ["<>", "Alex <>", "<alex@example.com>", "Alex <alex@example.com", "Alex <a<b>"] {
+            let author = Author::parse(malformed);
+            assert_eq!(author.name, malformed);
+            assert_eq!(author.email, None);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Writing manifests without losing the comments and formatting of the existing file.
+//!
+//! The manifest is serialized as usual, then merged into the existing document: keys that are unchanged are left
+//! exactly as they were written, changed values are replaced in place, and removed keys are dropped. Entries of
+//! arrays of tables, such as `[[dependencies]]`, are matched by their `id` (or `type`, for the decomp) rather than
+//! their position, so that removing one doesn't move the comments of the others.
+
+use anyhow::Result;
+use toml_edit::{ArrayOfTables, Document, Item, Table, Value};
+
+/// Returns `updated` written in the style of `existing`. Both must be valid TOML.
+pub fn merge(existing: &str, updated: &str) -> Result<String> {
+    let mut document: Document = existing.parse()?;
+    let updated: Document = updated.parse()?;
+    let mut next_position = max_position(document.as_table()) + 1;
+    merge_table(document.as_table_mut(), updated.as_table(), &mut next_position);
+    Ok(document.to_string())
+}
+
+fn merge_table(table: &mut Table, updated: &Table, next_position: &mut usize) {
+    table.retain(|key, _| updated.contains_key(key));
+    for (key, updated_item) in updated.iter() {
+        match (table.get_mut(key), updated_item) {
+            (Some(Item::Table(table)), Item::Table(updated)) => merge_table(table, updated, next_position),
+            (Some(Item::ArrayOfTables(tables)), Item::ArrayOfTables(updated)) => {
+                merge_array_of_tables(tables, updated, next_position)
+            }
+            (Some(Item::Value(value)), Item::Value(updated)) => {
+                if !values_equal(value, updated) {
+                    let decor = value.decor().clone();
+                    *value = updated.clone();
+                    *value.decor_mut() = decor;
+                }
+            }
+            // New, or a different kind of item, e.g. `dependencies = []` becoming `[[dependencies]]`
+            _ => {
+                let mut item = updated_item.clone();
+                set_position(&mut item, *next_position);
+                *next_position += 1;
+                table.remove(key);
+                table.insert(key, item);
+            }
+        }
+    }
+}
+
+fn merge_array_of_tables(tables: &mut ArrayOfTables, updated: &ArrayOfTables, next_position: &mut usize) {
+    let mut unmatched: Vec<Option<Table>> = tables.iter().cloned().map(Some).collect();
+    let mut merged = ArrayOfTables::new();
+    let mut position = tables.iter().filter_map(Table::position).min().unwrap_or(*next_position);
+    for updated_table in updated.iter() {
+        let existing = match identity(updated_table) {
+            Some(id) => unmatched.iter_mut()
+                .find(|table| table.as_ref().and_then(identity) == Some(id))
+                .and_then(Option::take),
+            None => None,
+        };
+        let mut table = match existing {
+            Some(mut table) => {
+                merge_table(&mut table, updated_table, next_position);
+                // Tables are written in position order, so never move one before the previous entry
+                position = position.max(table.position().unwrap_or(position));
+                table
+            }
+            None => updated_table.clone(),
+        };
+        table.set_position(position);
+        merged.push(table);
+    }
+    *tables = merged;
+}
+
+/// What an entry of an array of tables is matched by.
+fn identity(table: &Table) -> Option<&str> {
+    table.get("id").or_else(|| table.get("type")).and_then(Item::as_str)
+}
+
+/// Compares values, ignoring formatting.
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| values_equal(a, b))
+        }
+        (Value::InlineTable(a), Value::InlineTable(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| b.get(key).is_some_and(|b| values_equal(a, b)))
+        }
+        _ => false,
+    }
+}
+
+/// Moves every table in `item` to `position`, so that new tables are written after the existing ones.
+fn set_position(item: &mut Item, position: usize) {
+    match item {
+        Item::Table(table) => {
+            table.set_position(position);
+            for (_, item) in table.iter_mut() {
+                set_position(item, position);
+            }
+        }
+        Item::ArrayOfTables(tables) => {
+            for table in tables.iter_mut() {
+                table.set_position(position);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn max_position(table: &Table) -> usize {
+    let mut max = table.position().unwrap_or(0);
+    for (_, item) in table.iter() {
+        let position = match item {
+            Item::Table(table) => max_position(table),
+            Item::ArrayOfTables(tables) => tables.iter().map(max_position).max().unwrap_or(0),
+            _ => 0,
+        };
+        max = max.max(position);
+    }
+    max
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unchanged_keys_keep_their_formatting() -> Result<()> {
+        let existing = r#"# My mod
+[package]
+name = "Mod"   # not final
+authors = [
+    "Alice",
+]
+
+# Needs the decomp
+[[dependencies]]
+type = "decomp"
+rev = "abc"
+
+# The base mod
+[[dependencies]]
+type = "package"
+id = "a"
+version = "^1.0.0"
+"#;
+        let updated = r#"[package]
+name = "Renamed"
+authors = ["Alice"]
+
+[[dependencies]]
+type = "decomp"
+rev = "abc"
+
+[[dependencies]]
+type = "package"
+id = "b"
+version = "^0.1.0"
+
+[[dependencies]]
+type = "package"
+id = "a"
+version = "^1.1.0"
+"#;
+        let merged = merge(existing, updated)?;
+        assert_eq!(merged, r#"# My mod
+[package]
+name = "Renamed"   # not final
+authors = [
+    "Alice",
+]
+
+# Needs the decomp
+[[dependencies]]
+type = "decomp"
+rev = "abc"
+
+[[dependencies]]
+type = "package"
+id = "b"
+version = "^0.1.0"
+
+# The base mod
+[[dependencies]]
+type = "package"
+id = "a"
+version = "^1.1.0"
+"#);
+        Ok(())
+    }
+}
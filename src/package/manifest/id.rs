@@ -8,11 +8,20 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Id(Uuid);
 
+/// Namespace of the IDs generated for legacy packages, which were identified by name. See [`Id::from_legacy_name`].
+const LEGACY_NAMESPACE: Uuid = uuid::uuid!("d2340a7b-382e-4948-92f5-aacef344b61b");
+
 impl Id {
     /// Generates a new unique package ID.
     pub fn new() -> Self {
         Self(Uuid::new_v4())
     }
+
+    /// Returns the ID of a legacy package, i.e. one from before packages had IDs, with the given name.
+    /// The same name always gives the same ID, so a legacy package and its dependents agree on it.
+    pub fn from_legacy_name(name: &str) -> Self {
+        Self(Uuid::new_v5(&LEGACY_NAMESPACE, name.as_bytes()))
+    }
 }
 
 impl From<Uuid> for Id {
@@ -0,0 +1,116 @@
+//! Manifests written by Merlon before packages had IDs or dependencies, which look like this:
+//!
+//! ```toml
+//! [package]
+//! name = "My Mod"
+//! version = "0.1.0"
+//! authors = ["Alice"]
+//! description = "An amazing mod"
+//! license = "CC-BY-SA-4.0"
+//! keywords = []
+//! base_commit = "7a9df943ad079e7b19df0f8690bdc92e2beed964"
+//!
+//! [dependencies]
+//! "Base Mod" = "0.2.0"
+//! ```
+//!
+//! Packages are identified by name, so they are given IDs generated from their names with [`Id::from_legacy_name`],
+//! and dependencies refer to packages by those IDs. Reading the same manifest again gives the same IDs.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, Context};
+use serde::Deserialize;
+
+use super::{Manifest, Metadata, Dependency, Id, Name, Version, VersionReq, PatchContext};
+
+#[derive(Debug, Deserialize)]
+struct LegacyManifest {
+    package: LegacyMetadata,
+
+    /// Dependencies, by package name.
+    #[serde(default)]
+    dependencies: BTreeMap<String, VersionReq>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyMetadata {
+    name: Name,
+    version: Version,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    license: String,
+    #[serde(default)]
+    keywords: Vec<String>,
+
+    /// The decomp commit the package was made against.
+    base_commit: Option<String>,
+}
+
+impl Manifest {
+    /// Reads a legacy manifest, i.e. one without package IDs, and converts it to the current format.
+    /// See the [module documentation](self).
+    pub fn migrate_from_legacy(path: &Path) -> Result<Manifest> {
+        let string = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Manifest::from_legacy_str(&string)
+            .with_context(|| format!("invalid legacy manifest {}", path.display()))
+    }
+
+    pub(super) fn from_legacy_str(string: &str) -> Result<Manifest> {
+        let legacy: LegacyManifest = toml::from_str(string)?;
+        let LegacyMetadata { name, version, authors, description, license, keywords, base_commit } = legacy.package;
+
+        let mut dependencies: Vec<Dependency> = base_commit
+            .into_iter()
+            .map(|rev| Dependency::Decomp { rev })
+            .collect();
+        for (key, version) in legacy.dependencies {
+            let name = Name::new(key.clone())
+                .with_context(|| format!("cannot map dependency {:?} to a package ID", key))?;
+            dependencies.push(Dependency::Package { id: Id::from_legacy_name(&name.to_string()), version });
+        }
+
+        Ok(Manifest {
+            metadata: Metadata {
+                id: Id::from_legacy_name(&name.to_string()),
+                name,
+                version,
+                authors,
+                description,
+                license,
+                keywords,
+                long_description: None,
+                repository: None,
+                homepage: None,
+                region_portable: false,
+                allow: vec![],
+                required_merlon_version: None,
+                regions: vec![],
+                patch_context: PatchContext::default(),
+            },
+            dependencies,
+            commit_convention: None,
+        })
+    }
+
+    /// Returns true if the manifest at `path` is a legacy manifest, i.e. its `[package]` has no `id`.
+    /// Returns false if it can't be read or isn't TOML, so that reading it normally reports the problem.
+    pub fn is_legacy(path: &Path) -> bool {
+        fs::read_to_string(path).is_ok_and(|string| is_legacy_str(&string))
+    }
+}
+
+pub(super) fn is_legacy_str(string: &str) -> bool {
+    let Ok(table) = string.parse::<toml::Table>() else {
+        return false;
+    };
+    match table.get("package") {
+        Some(toml::Value::Table(package)) => !package.contains_key("id"),
+        _ => false,
+    }
+}
@@ -0,0 +1,86 @@
+//! Validation of the manifest's `license` field, which must be an SPDX license expression.
+//! See https://spdx.org/licenses/
+
+use spdx::Expression;
+use spdx::error::Reason;
+
+/// Checks that `license` is a valid SPDX license expression, such as `CC-BY-SA-4.0` or `MIT OR Apache-2.0`.
+/// Returns a message describing the problem if it isn't, suggesting the closest known license for unknown ones.
+pub fn check(license: &str) -> Result<(), String> {
+    let error = match Expression::parse(license) {
+        Ok(_) => return Ok(()),
+        Err(error) => error,
+    };
+    match error.reason {
+        Reason::UnknownLicense | Reason::UnknownTerm => {
+            let term = &license[error.span.clone()];
+            let mut message = format!("license has unknown SPDX identifier {:?}", term);
+            if let Some(suggestion) = closest_license(term) {
+                message.push_str(&format!(", did you mean {:?}?", suggestion));
+            }
+            message.push_str(" (see https://spdx.org/licenses/)");
+            Err(message)
+        }
+        reason => Err(format!("license {:?} is not a valid SPDX expression: {}", license, reason)),
+    }
+}
+
+/// Returns the known license identifier most like `term`, if any is close enough to be a likely typo.
+fn closest_license(term: &str) -> Option<&'static str> {
+    let lowercase = term.to_lowercase();
+    let exact = spdx::identifiers::LICENSES.iter().find(|(name, _, _)| name.to_lowercase() == lowercase);
+    if let Some((name, _, _)) = exact {
+        return Some(name);
+    }
+    if let Some((license, _)) = spdx::imprecise_license_id(term) {
+        return Some(license.name);
+    }
+    spdx::identifiers::LICENSES.iter()
+        .map(|(name, _, _)| (edit_distance(&lowercase, &name.to_lowercase()), *name))
+        .filter(|(distance, _)| *distance <= term.len() / 4)
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Levenshtein distance between two strings, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spdx_expressions_are_checked() {
+        assert_eq!(check("CC-BY-SA-4.0"), Ok(()));
+        assert_eq!(check("MIT OR Apache-2.0"), Ok(()));
+        assert_eq!(check("GPL-3.0-or-later WITH Classpath-exception-2.0"), Ok(()));
+        assert_eq!(check("LicenseRef-Proprietary"), Ok(()));
+
+        let error = check("CC-BY-SA-4").unwrap_err();
+        assert!(error.contains("unknown SPDX identifier \"CC-BY-SA-4\", did you mean \"CC-BY-SA-4.0\"?"), "{}", error);
+        let error = check("MIT OR apache-2.0").unwrap_err();
+        assert!(error.contains("did you mean \"Apache-2.0\"?"), "{}", error);
+        let error = check("All rights reserved").unwrap_err();
+        assert!(!error.contains("did you mean"), "{}", error);
+        assert!(check("(MIT").is_err());
+    }
+
+    #[test]
+    fn edit_distance_counts_changes() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("MIT", "MIT"), 0);
+    }
+}
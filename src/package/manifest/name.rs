@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use pyo3::prelude::*;
 
 /// A validated package name.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Name(String);
 
@@ -24,6 +24,15 @@ pub enum Error {
     /// Package name is multiple lines (contains a newline character).
     #[error("package name must be single line")]
     ContainsNewline,
+
+    /// Package name is too long once converted to kebab-case, which is used for directory and file names.
+    #[error("package name is too long ({len} characters as {kebab_case:?}, the maximum is {max})", max = Name::MAX_LEN)]
+    TooLong {
+        /// The name in kebab-case.
+        kebab_case: String,
+        /// The length of the kebab-case name.
+        len: usize,
+    },
 }
 
 mod python_exception {
@@ -53,6 +62,9 @@ impl TryIntoName for Name {
 }
 
 impl Name {
+    /// The maximum length of a name in kebab-case. See [`Name::as_kebab_case`].
+    pub const MAX_LEN: usize = 64;
+
     /// Creates a new name from a string.
     pub fn new(name: String) -> Result<Self> {
         if name.is_empty() { 
@@ -64,7 +76,13 @@ impl Name {
         if name.contains('\n') {
             return Err(Error::ContainsNewline);
         }
-        Ok(Self(name))
+        let name = Self(name);
+        let kebab_case = name.as_kebab_case();
+        let len = kebab_case.chars().count();
+        if len > Self::MAX_LEN {
+            return Err(Error::TooLong { kebab_case, len });
+        }
+        Ok(name)
     }
 
     /// Returns the name as kebab-case.
@@ -118,3 +136,20 @@ impl IntoPy<PyObject> for Name {
         self.0.into_py(py)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kebab_case_length_is_capped() {
+        // "a a a ..." becomes "a-a-a-...", so n words are 2n - 1 characters in kebab-case
+        let words = |n: usize| vec!["a"; n].join(" ");
+        let name = Name::new(words(32)).unwrap();
+        assert_eq!(name.as_kebab_case().len(), 63);
+        assert!(Name::new("a".repeat(Name::MAX_LEN)).is_ok());
+
+        assert!(matches!(Name::new("a".repeat(Name::MAX_LEN + 1)), Err(Error::TooLong { len: 65, .. })));
+        assert!(matches!(Name::new(words(33)), Err(Error::TooLong { len: 65, .. })));
+    }
+}
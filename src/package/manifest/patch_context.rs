@@ -0,0 +1,112 @@
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{Error, Result, bail};
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use serde::{Deserialize, Serialize};
+
+/// How much unchanged code surrounds each change in a package's patches.
+///
+/// More context makes patches bigger, but helps `git am --3way` apply them when the decomp has changed nearby.
+/// Written as `minimal`, `function`, or `lines=N` in `merlon.toml` and on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PatchContext {
+    /// Git's default of three lines of context.
+    Minimal,
+    /// The whole function that each change is in.
+    #[default]
+    Function,
+    /// The given number of lines of context.
+    Lines(u32),
+}
+
+impl PatchContext {
+    /// Returns the arguments to pass to `git format-patch` to produce this much context.
+    pub fn format_patch_args(&self) -> Vec<String> {
+        match self {
+            PatchContext::Minimal => vec![],
+            PatchContext::Function => vec!["--function-context".to_owned()],
+            PatchContext::Lines(lines) => vec![format!("--unified={}", lines)],
+        }
+    }
+
+    /// Returns true if this is the default, [`PatchContext::Function`].
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for PatchContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchContext::Minimal => f.write_str("minimal"),
+            PatchContext::Function => f.write_str("function"),
+            PatchContext::Lines(lines) => write!(f, "lines={}", lines),
+        }
+    }
+}
+
+impl FromStr for PatchContext {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "minimal" => Ok(PatchContext::Minimal),
+            "function" => Ok(PatchContext::Function),
+            _ => match s.strip_prefix("lines=").map(str::parse) {
+                Some(Ok(lines)) => Ok(PatchContext::Lines(lines)),
+                _ => bail!("invalid patch context {:?} (expected minimal, function, or lines=N)", s),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for PatchContext {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<PatchContext> for String {
+    fn from(context: PatchContext) -> Self {
+        context.to_string()
+    }
+}
+
+impl FromPyObject<'_> for PatchContext {
+    fn extract(ob: &PyAny) -> PyResult<Self> {
+        let string: String = ob.extract()?;
+        string.parse().map_err(|e: Error| PyValueError::new_err(e.to_string()))
+    }
+}
+
+impl ToPyObject for PatchContext {
+    fn to_object(&self, py: Python) -> PyObject {
+        self.to_string().to_object(py)
+    }
+}
+
+impl IntoPy<PyObject> for PatchContext {
+    fn into_py(self, py: Python) -> PyObject {
+        self.to_object(py)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats() -> Result<()> {
+        for context in [PatchContext::Minimal, PatchContext::Function, PatchContext::Lines(10)] {
+            assert_eq!(context.to_string().parse::<PatchContext>()?, context);
+        }
+        assert_eq!("lines=0".parse::<PatchContext>()?.format_patch_args(), vec!["--unified=0"]);
+        assert!("lines=many".parse::<PatchContext>().is_err());
+        assert!("full".parse::<PatchContext>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! Parsing of patch files in a package's `patches/` directory.
+//!
+//! Patches are in the mbox format produced by `git format-patch`. Only the parts needed to describe a patch
+//! without applying it are parsed: the subject, author, and which files it changes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, bail};
+use pyo3::prelude::*;
+
+/// Information about a single patch file. See [`Package::patches`](super::Package::patches).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[pyclass(module = "merlon.package.patch")]
+pub struct PatchInfo {
+    /// Path to the patch file.
+    #[pyo3(get)]
+    pub path: PathBuf,
+
+    /// Commit subject, without the `[PATCH]` prefix.
+    #[pyo3(get)]
+    pub subject: String,
+
+    /// Commit author, e.g. `Alex Bates <alex@nanaian.town>`.
+    #[pyo3(get)]
+    pub author: String,
+
+    /// Paths of the files changed by the patch, relative to the decomp repo.
+    #[pyo3(get)]
+    pub files: Vec<String>,
+
+    /// Number of lines added.
+    #[pyo3(get)]
+    pub insertions: usize,
+
+    /// Number of lines removed.
+    #[pyo3(get)]
+    pub deletions: usize,
+}
+
+#[pymethods]
+impl PatchInfo {
+    /// Reads and parses a patch file.
+    #[staticmethod]
+    pub fn read_from_path(path: PathBuf) -> Result<Self> {
+        let string = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read patch {}", path.display()))?;
+        Self::parse(&string, &path)
+            .with_context(|| format!("failed to parse patch {}", path.display()))
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl PatchInfo {
+    /// Parses a patch from a string. `path` is only recorded, not read.
+    pub fn parse(patch: &str, path: &Path) -> Result<Self> {
+        let mut lines = patch.lines().map(|line| line.trim_end_matches('\r')).peekable();
+
+        // Headers, which end at the first blank line. Long headers may be folded onto indented lines.
+        let mut subject = None;
+        let mut author = None;
+        while let Some(line) = lines.next() {
+            if line.is_empty() {
+                break;
+            }
+            let mut value = match line.split_once(": ") {
+                Some((_, value)) => value.to_owned(),
+                None => continue,
+            };
+            while let Some(continuation) = lines.next_if(|line| line.starts_with([' ', '\t'])) {
+                value.push(' ');
+                value.push_str(continuation.trim_start());
+            }
+            if line.starts_with("Subject: ") {
+                subject = Some(strip_patch_prefix(&value).to_owned());
+            } else if line.starts_with("From: ") {
+                author = Some(value);
+            }
+        }
+        let Some(subject) = subject else {
+            bail!("missing Subject header");
+        };
+
+        // Body
+        let mut files = Vec::new();
+        let mut insertions = 0;
+        let mut deletions = 0;
+        let mut in_diff = false;
+        for line in lines {
+            if let Some(paths) = line.strip_prefix("diff --git ") {
+                in_diff = true;
+                if let Some((_, b)) = paths.split_once(" b/") {
+                    files.push(b.to_owned());
+                }
+            } else if line == "-- " {
+                // Signature, e.g. the git version
+                break;
+            } else if in_diff {
+                if line.starts_with('+') && !line.starts_with("+++ ") {
+                    insertions += 1;
+                } else if line.starts_with('-') && !line.starts_with("--- ") {
+                    deletions += 1;
+                }
+            }
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            subject,
+            author: author.unwrap_or_default(),
+            files,
+            insertions,
+            deletions,
+        })
+    }
+}
+
+impl fmt::Display for PatchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} files, +{} -{})", self.subject, self.files.len(), self.insertions, self.deletions)
+    }
+}
+
+/// Rewrites the paths of the files changed by a patch according to a map of renames, so that the patch applies to a
+/// repo where those files have been moved. Returns `None` if no paths were renamed.
+///
+/// The keys of `renames` are old paths and the values are new paths, relative to the decomp repo. A key may be a
+/// directory, in which case everything in it is renamed. Where keys overlap, the longest one is used.
+pub fn rename_paths(patch: &str, renames: &HashMap<String, String>) -> Option<String> {
+    let mut output = String::with_capacity(patch.len());
+    let mut changed = false;
+    let mut in_diff_header = false;
+    for line in patch.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+
+        let rewritten = if let Some(paths) = content.strip_prefix("diff --git a/") {
+            in_diff_header = true;
+            paths.split_once(" b/").and_then(|(a, b)| {
+                match (renamed_path(a, renames), renamed_path(b, renames)) {
+                    (None, None) => None,
+                    (new_a, new_b) => Some(format!(
+                        "diff --git a/{} b/{}",
+                        new_a.as_deref().unwrap_or(a),
+                        new_b.as_deref().unwrap_or(b),
+                    )),
+                }
+            })
+        } else if content.starts_with("@@") || content == "-- " {
+            // Hunk lines are file content, which may look like headers
+            in_diff_header = false;
+            None
+        } else if in_diff_header {
+            ["--- a/", "+++ b/", "rename from ", "rename to ", "copy from ", "copy to "]
+                .into_iter()
+                .find_map(|prefix| {
+                    let path = content.strip_prefix(prefix)?;
+                    renamed_path(path, renames).map(|path| format!("{prefix}{path}"))
+                })
+        } else {
+            None
+        };
+
+        match rewritten {
+            Some(rewritten) => {
+                output.push_str(&rewritten);
+                output.push_str(ending);
+                changed = true;
+            }
+            None => output.push_str(line),
+        }
+    }
+    changed.then_some(output)
+}
+
+/// Returns the new path of a file according to a map of renames, or `None` if it isn't renamed.
+fn renamed_path(path: &str, renames: &HashMap<String, String>) -> Option<String> {
+    renames.iter()
+        .filter_map(|(from, to)| {
+            let from = from.trim_end_matches('/');
+            let rest = path.strip_prefix(from)?;
+            if rest.is_empty() || rest.starts_with('/') {
+                Some((from.len(), format!("{}{}", to.trim_end_matches('/'), rest)))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, path)| path)
+}
+
+/// Strips the `[PATCH]` or `[PATCH 1/2]` prefix that `git format-patch` adds to subjects.
+fn strip_patch_prefix(subject: &str) -> &str {
+    if subject.starts_with("[PATCH") {
+        if let Some((_, rest)) = subject.split_once("] ") {
+            return rest;
+        }
+    }
+    subject
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_format_patch_output() -> Result<()> {
+        let patch = include_str!("../../templates/packages/skip-intro/0001-set-bSkipIntro-to-true.patch");
+        let info = PatchInfo::parse(patch, Path::new("0001-set-bSkipIntro-to-true.patch"))?;
+        assert_eq!(info.subject, "set bSkipIntro to true");
+        assert_eq!(info.author, "Alex Bates <alex@nanaian.town>");
+        assert_eq!(info.files, vec!["src/state_logos.c".to_owned()]);
+        assert_eq!((info.insertions, info.deletions), (1, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn rename_paths_in_diff_headers() {
+        let patch = include_str!("../../templates/packages/skip-intro/0001-set-bSkipIntro-to-true.patch");
+        let renames = HashMap::from([
+            ("src".to_owned(), "source/game".to_owned()),
+            ("src/state_logos.c".to_owned(), "source/logos.c".to_owned()),
+        ]);
+        let renamed = rename_paths(patch, &renames).unwrap();
+        assert!(renamed.contains("diff --git a/source/logos.c b/source/logos.c\n"));
+        assert!(renamed.contains("\n--- a/source/logos.c\n+++ b/source/logos.c\n"));
+        assert!(!renamed.contains("a/src/"));
+        assert_eq!(PatchInfo::parse(&renamed, Path::new("renamed.patch")).unwrap().files, vec!["source/logos.c"]);
+
+        // Only whole path components are matched
+        let renames = HashMap::from([("src/state".to_owned(), "src/other".to_owned())]);
+        assert_eq!(rename_paths(patch, &renames), None);
+    }
+
+    #[test]
+    fn parse_folded_prefixed_subject() -> Result<()> {
+        let patch = "From: Someone <someone@example.com>\r\nSubject: [PATCH 2/3] a very long subject that\r\n was folded\r\n\r\n";
+        let info = PatchInfo::parse(patch, Path::new("0002.patch"))?;
+        assert_eq!(info.subject, "a very long subject that was folded");
+        assert!(info.files.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! Project-local defaults for command options.
+//!
+//! A package may have a `.merlonrc` file in its root, in TOML, that is committed along with it so that everyone
+//! working on the package shares the same defaults. Unlike the user's [config](crate::config), which is specific to
+//! their machine, it only holds preferences about how to build and run the package:
+//!
+//! ```toml
+//! verbose = true
+//!
+//! [build]
+//! symbols = true
+//!
+//! [run]
+//! fullscreen = true
+//! no_audio = true
+//! ```
+//!
+//! Options given on the command line or through environment variables always take precedence. Each of these flags
+//! has a `--no-` counterpart (`--audio` for `--no-audio`) to turn it off for one command when `.merlonrc` turns it
+//! on. Anything not set in either falls back to Merlon's built-in default, which is off.
+//!
+//! `.merlonrc` is not included in exported distributables.
+
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+
+use super::init::BuildRomOptions;
+
+/// File name of the defaults file in a package's root directory.
+pub const FILE_NAME: &str = ".merlonrc";
+
+/// Defaults read from a package's `.merlonrc`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectDefaults {
+    /// Show debug logs, as if `--verbose` were given.
+    pub verbose: bool,
+
+    /// Defaults for commands that build the package.
+    pub build: BuildDefaults,
+
+    /// Defaults for `merlon run`.
+    pub run: RunDefaults,
+}
+
+/// Defaults for [`BuildRomOptions`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildDefaults {
+    /// Copy the ELF and symbol map next to the output ROM, as if `--symbols` were given.
+    pub symbols: bool,
+}
+
+/// Defaults for running the package in an emulator.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunDefaults {
+    /// Start the emulator in fullscreen, as if `--fullscreen` were given.
+    pub fullscreen: bool,
+
+    /// Disable the emulator's audio, as if `--no-audio` were given.
+    pub no_audio: bool,
+}
+
+impl ProjectDefaults {
+    /// Loads the `.merlonrc` of the package in `dir`, or the built-in defaults if it doesn't have one.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(FILE_NAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let string = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&string).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    /// Fills in build options that weren't given with these defaults.
+    pub fn apply_to_build_rom_options(&self, options: &mut BuildRomOptions) {
+        options.with_symbols = flag(options.with_symbols, options.no_symbols, self.build.symbols);
+    }
+}
+
+/// Resolves a flag that can be turned on with `on` or off with `off`, or else is `default`.
+pub fn flag(on: bool, off: bool, default: bool) -> bool {
+    on || (default && !off)
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn defaults_are_merged_below_given_options() -> Result<()> {
+        let dir = TempDir::new()?;
+        assert_eq!(ProjectDefaults::load(dir.path())?, ProjectDefaults::default());
+
+        // Built-in default
+        let mut options = BuildRomOptions::default();
+        ProjectDefaults::load(dir.path())?.apply_to_build_rom_options(&mut options);
+        assert!(!options.with_symbols);
+
+        // .merlonrc over the built-in default
+        fs::write(dir.path().join(FILE_NAME), "verbose = true\n\n[build]\nsymbols = true\n")?;
+        let defaults = ProjectDefaults::load(dir.path())?;
+        assert!(defaults.verbose);
+        assert_eq!(defaults.run, RunDefaults::default());
+        let mut options = BuildRomOptions::default();
+        defaults.apply_to_build_rom_options(&mut options);
+        assert!(options.with_symbols);
+
+        // Given options over .merlonrc
+        let mut options = BuildRomOptions { no_symbols: true, ..Default::default() };
+        defaults.apply_to_build_rom_options(&mut options);
+        assert!(!options.with_symbols);
+        fs::write(dir.path().join(FILE_NAME), "[build]\nsymbols = false\n")?;
+        let mut options = BuildRomOptions { with_symbols: true, ..Default::default() };
+        ProjectDefaults::load(dir.path())?.apply_to_build_rom_options(&mut options);
+        assert!(options.with_symbols);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(dir.path().join(FILE_NAME), "[build]\nsymbol = true\n")?;
+        let error = ProjectDefaults::load(dir.path()).unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `symbol`"), "{:#}", error);
+        Ok(())
+    }
+}
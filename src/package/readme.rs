@@ -0,0 +1,62 @@
+//! Rendering of package READMEs to HTML.
+//!
+//! READMEs come from other people's packages, so the output must be safe to embed, e.g. in a web page. The markdown
+//! is rendered with [`pulldown_cmark`], and the HTML is then sanitised with [`ammonia`], which removes scripts, event
+//! handler and style attributes, and any other tags that aren't known to be safe. Link and image URLs are dropped
+//! unless they are relative or use a scheme in [`ALLOWED_URL_SCHEMES`].
+
+use std::collections::HashSet;
+use pulldown_cmark::{Parser, Options, html};
+
+/// URL schemes that links and images may use.
+const ALLOWED_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Renders markdown to sanitised HTML.
+pub fn render_html(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+    ammonia::Builder::default()
+        .url_schemes(ALLOWED_URL_SCHEMES.iter().copied().collect::<HashSet<_>>())
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_common_markdown() {
+        let html = render_html(
+            "# My Mod\n\nAdds **new** items\nand *more*.\n\n- one\n- `two`\n\n1. first\n\n```c\nint x = 1 < 2;\n```\n\n---\n\n\
+            > quoted\n\nSee [the wiki](https://example.com/wiki).\n",
+        );
+        assert_eq!(html, concat!(
+            "<h1>My Mod</h1>\n",
+            "<p>Adds <strong>new</strong> items\nand <em>more</em>.</p>\n",
+            "<ul>\n<li>one</li>\n<li><code>two</code></li>\n</ul>\n",
+            "<ol>\n<li>first</li>\n</ol>\n",
+            "<pre><code>int x = 1 &lt; 2;\n</code></pre>\n",
+            "<hr>\n",
+            "<blockquote>\n<p>quoted</p>\n</blockquote>\n",
+            "<p>See <a href=\"https://example.com/wiki\" rel=\"noopener noreferrer\">the wiki</a>.</p>\n",
+        ));
+    }
+
+    #[test]
+    fn strips_scripts_and_unsafe_urls() {
+        let html = render_html(concat!(
+            "<script>alert(1)</script>\n\n",
+            "<img src=x onerror=\"alert(1)\">\n\n",
+            "[click](javascript:alert(1)) [also](JavaScript:alert(1)) [data](data:text/html,hi)\n\n",
+            "![pic](java\tscript:alert(1)) ![ok](images/screenshot.png)\n",
+        ));
+        assert!(!html.contains("<script"), "{}", html);
+        assert!(!html.contains("onerror"), "{}", html);
+        assert!(!html.to_lowercase().contains("javascript:"), "{}", html);
+        assert!(!html.contains("data:"), "{}", html);
+        assert!(!html.contains("href"), "{}", html);
+        assert!(html.contains("<img src=\"images/screenshot.png\" alt=\"ok\">"), "{}", html);
+    }
+}
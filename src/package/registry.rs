@@ -1,11 +1,28 @@
 //! Package registry
 
+use std::cell::RefCell;
 use std::collections::{HashSet, HashMap, BinaryHeap};
+use std::path::{Path, PathBuf};
 
-use anyhow::{Result, bail};
+use anyhow::{Result, Context, bail};
 use pyo3::prelude::*;
+use sha1::{Sha1, Digest};
+use thiserror::Error;
 
-use super::{Package, Id, manifest::{Dependency, Version}};
+use super::{Package, Id, manifest::{Dependency, Version, VersionReq}};
+use super::distribute::{Distributable, OpenOptions};
+
+pub mod snapshot;
+pub use snapshot::RegistrySnapshot;
+
+pub mod diff;
+pub use diff::RegistryDiff;
+
+pub mod tree;
+pub use tree::{DependencyTree, DependencyNode};
+
+pub mod lockfile;
+pub use lockfile::Lockfile;
 
 /// A package registry. This is an arena of packages.
 /// Allows for querying packages by name, uuid, etc., and dependency queries.
@@ -13,6 +30,8 @@ use super::{Package, Id, manifest::{Dependency, Version}};
 #[pyclass(module = "merlon.package.registry")]
 pub struct Registry {
     packages: HashMap<Id, Package>,
+    /// Packages added with [`Registry::register_lazily`] whose manifests haven't been read yet.
+    unverified: RefCell<HashSet<Id>>,
 }
 
 #[pymethods]
@@ -22,6 +41,7 @@ impl Registry {
     pub fn new() -> Self {
         Self {
             packages: Default::default(),
+            unverified: Default::default(),
         }
     }
 
@@ -37,9 +57,47 @@ impl Registry {
         Ok(id)
     }
 
+    /// Add a package to the registry under an ID that is already known, e.g. from the name of its directory,
+    /// without reading its manifest. The manifest is read and checked against the ID when the package is first
+    /// accessed, or by `load_all`.
+    /// Returns an error if a package with the ID is already in the registry.
+    pub fn register_lazily(&mut self, id: Id, package: Package) -> Result<()> {
+        if self.packages.contains_key(&id) {
+            bail!("package {} already in registry", id);
+        }
+        self.packages.insert(id, package);
+        self.unverified.get_mut().insert(id);
+        Ok(())
+    }
+
+    /// Reads the manifests of all lazily registered packages, checking that they have the IDs they were registered
+    /// under. Packages in directories named after an ID they don't have are renamed to match, and registered again
+    /// under their real ID.
+    pub fn load_all(&mut self) -> Result<()> {
+        let mut unverified: Vec<Id> = self.unverified.get_mut().iter().copied().collect();
+        unverified.sort();
+        for id in unverified {
+            let package = &self.packages[&id];
+            let manifest_id = package.id()
+                .with_context(|| format!("failed to read manifest of package {}", id))?;
+            if manifest_id == id {
+                self.unverified.get_mut().remove(&id);
+                continue;
+            }
+            if !is_named_after(package.path(), id) {
+                bail!("package at {} has ID {} but was registered as {}", package.path().display(), manifest_id, id);
+            }
+            let package = self.take(id)?;
+            let path = rename_to_id(package.path(), manifest_id)?;
+            self.register(Package::try_from(path)?)?;
+        }
+        Ok(())
+    }
+
     /// Remove a package from the registry.
     /// Returns an error if the package is not in the registry.
     pub fn take(&mut self, id: Id) -> Result<Package> {
+        self.unverified.get_mut().remove(&id);
         match self.packages.remove(&id) {
             Some(package) => Ok(package),
             None => bail!("package {} not in registry", id),
@@ -53,19 +111,99 @@ impl Registry {
 }
 
 impl Registry {
-    /// Get a package by ID.
-    pub fn get(&self, id: Id) -> Option<&Package> {
-        self.packages.get(&id)
+    /// Opens several independent distributables into subdirectories of `dir` and registers them all, so that they
+    /// can be applied together. Returns the registry and the IDs of the given distributables' packages.
+    ///
+    /// Fails if the distributables contain the same package twice, or if any of them have dependencies that are
+    /// missing from the set or incompatible with each other.
+    pub fn from_distributable_closure(
+        distributables: &[Distributable],
+        baserom: &Path,
+        dir: &Path,
+    ) -> Result<(Self, Vec<Id>)> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+        let mut registry = Self::new();
+        let mut ids = Vec::with_capacity(distributables.len());
+        for (index, distributable) in distributables.iter().enumerate() {
+            let package = distributable
+                .open_to_dir(OpenOptions {
+                    output: Some(dir.join(index.to_string())),
+                    baserom: baserom.to_owned(),
+                })
+                .with_context(|| format!("failed to open {}", distributable))?;
+            ids.push(registry.register(package)?);
+        }
+
+        let mut errors = Vec::new();
+        for id in &ids {
+            if let Err(root_errors) = registry.resolve(*id) {
+                for error in root_errors {
+                    // The other distributables are expected to be outside this one's dependency tree
+                    if matches!(error, ResolutionError::Orphan { .. }) {
+                        continue;
+                    }
+                    if !errors.contains(&error) {
+                        errors.push(error);
+                    }
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(resolution_errors_to_anyhow(errors));
+        }
+
+        Ok((registry, ids))
+    }
+
+    /// Get a package by ID, or `None` if it is not in the registry.
+    /// Returns an error if a lazily registered package turns out not to have the ID it was registered under.
+    pub fn get(&self, id: Id) -> Result<Option<&Package>> {
+        self.verify(id)?;
+        Ok(self.packages.get(&id))
     }
 
     /// Get a package by ID, or return an error if it is not in the registry.
     pub fn get_or_error(&self, id: Id) -> Result<&Package> {
-        match self.get(id) {
+        self.verify(id)?;
+        match self.packages.get(&id) {
             Some(package) => Ok(package),
             None => bail!("package {id} not found in registry"),
         }
     }
 
+    /// Reads the manifest of a lazily registered package, if it hasn't been read already, and checks that the
+    /// package has the ID it was registered under.
+    ///
+    /// If it doesn't, and its directory is named after the ID it was registered under (as in a dependencies
+    /// directory), the directory is renamed to match the package's real ID so that it is registered correctly the
+    /// next time it is loaded. Either way an error is returned, since the package isn't the one asked for.
+    fn verify(&self, id: Id) -> Result<()> {
+        if !self.unverified.borrow().contains(&id) {
+            return Ok(());
+        }
+        let Some(package) = self.packages.get(&id) else {
+            return Ok(());
+        };
+        let manifest_id = package.id()
+            .with_context(|| format!("failed to read manifest of package {}", id))?;
+        if manifest_id != id {
+            if is_named_after(package.path(), id) {
+                let path = rename_to_id(package.path(), manifest_id)?;
+                bail!(
+                    "package at {} has ID {} but was registered as {}; it has been moved to {}, try again",
+                    package.path().display(),
+                    manifest_id,
+                    id,
+                    path.display(),
+                );
+            }
+            bail!("package at {} has ID {} but was registered as {}", package.path().display(), manifest_id, id);
+        }
+        self.unverified.borrow_mut().remove(&id);
+        Ok(())
+    }
+
     /// Edits a package given its ID. The callback is given a mutable reference to the package.
     pub fn edit<F, T>(&mut self, id: Id, f: F) -> Result<T>
     where
@@ -81,6 +219,177 @@ impl Registry {
     pub fn package_ids(&self) -> impl Iterator<Item = Id> + '_ {
         self.packages.keys().copied()
     }
+
+    /// Returns the packages that depend on the given package with a version requirement that `version` doesn't
+    /// satisfy, i.e. the packages that would break if the given package's version was changed to `version`.
+    pub fn dependents_incompatible_with(&self, id: Id, version: &Version) -> Result<Vec<Id>> {
+        let mut dependents = Vec::new();
+        for dependent in self.package_ids() {
+            let manifest = self.get_or_error(dependent)?.manifest()?;
+            for (dependency_id, requirement) in manifest.iter_package_dependencies() {
+                if dependency_id == id && !requirement.matches(version) {
+                    dependents.push(dependent);
+                }
+            }
+        }
+        Ok(dependents)
+    }
+}
+
+/// A complete resolution of a package's dependency tree. See [`Registry::resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// Packages in patch order, such that dependencies appear before dependents. The root package is last.
+    pub order: Vec<Id>,
+
+    /// The exact version of every package in the resolution.
+    pub versions: HashMap<Id, Version>,
+
+    /// The decomp revision pinned by the root package, if any.
+    pub decomp_rev: Option<String>,
+}
+
+/// A problem found while resolving a dependency tree. See [`Registry::resolve`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ResolutionError {
+    /// The root package is not in the registry.
+    #[error("package {id} is not in the registry")]
+    Unregistered {
+        /// The package ID.
+        id: Id,
+    },
+
+    /// A package's manifest could not be read.
+    #[error("failed to read manifest of package {id}: {message}")]
+    Manifest {
+        /// The package ID.
+        id: Id,
+        /// Description of the underlying error.
+        message: String,
+    },
+
+    /// A dependency is declared but the package is not in the registry.
+    #[error("package {dependent} depends on {id} {requirement}, but it is not in the registry")]
+    Missing {
+        /// The missing package ID.
+        id: Id,
+        /// The version requirement declared by the dependent.
+        requirement: VersionReq,
+        /// The package that declared the dependency.
+        dependent: Id,
+    },
+
+    /// A dependency is registered, but with a version that does not satisfy the requirement.
+    #[error("package {dependent} depends on {id} {requirement}, which is incompatible with its actual version {actual}")]
+    Conflict {
+        /// The dependency package ID.
+        id: Id,
+        /// The version requirement declared by the dependent.
+        requirement: VersionReq,
+        /// The version of the registered package.
+        actual: Version,
+        /// The package that declared the dependency.
+        dependent: Id,
+    },
+
+    /// A package transitively depends on itself.
+    #[error("found circular dependency involving package {id}")]
+    Cycle {
+        /// A package that is part of the cycle.
+        id: Id,
+    },
+
+    /// A package is registered under a different ID to the one in its manifest.
+    #[error("package {id} is registered, but its manifest has ID {manifest_id}")]
+    IdMismatch {
+        /// The ID the package is registered under.
+        id: Id,
+        /// The ID in the package's manifest.
+        manifest_id: Id,
+    },
+
+    /// A package is registered but does not appear in the dependency tree of the root.
+    #[error("package {id} is orphaned (nothing depends on it)")]
+    Orphan {
+        /// The orphaned package ID.
+        id: Id,
+    },
+}
+
+/// Combines a list of resolution errors into a single error.
+pub(crate) fn resolution_errors_to_anyhow(errors: Vec<ResolutionError>) -> anyhow::Error {
+    let mut message = "failed to resolve dependencies:".to_string();
+    for error in errors {
+        message.push_str(&format!("\n  - {}", error));
+    }
+    crate::Error::Dependency(message).into()
+}
+
+/// A way that a package's dependency directories on disk disagree with its declared dependencies or the registry,
+/// e.g. after `merlon.toml` was edited or a directory deleted by hand. See [`Registry::is_consistent_with_disk`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// A dependency is declared, but its directory does not exist.
+    #[error("package {dependent} depends on {id}, but {} does not exist", .path.display())]
+    MissingDirectory {
+        /// The dependency package ID.
+        id: Id,
+        /// The package that declared the dependency.
+        dependent: Id,
+        /// Where the dependency's directory should be.
+        path: PathBuf,
+    },
+
+    /// A dependency directory exists, but nothing in the dependency tree declares it.
+    #[error("{} is not declared as a dependency by any package", .path.display())]
+    UndeclaredDirectory {
+        /// The dependency directory.
+        path: PathBuf,
+    },
+
+    /// A dependency directory is named after a different ID to the one in its manifest.
+    #[error("{} should hold package {id}, but its manifest has ID {manifest_id}", .path.display())]
+    IdMismatch {
+        /// The ID that the directory is named after.
+        id: Id,
+        /// The ID in the directory's manifest.
+        manifest_id: Id,
+        /// The dependency directory.
+        path: PathBuf,
+    },
+
+    /// A dependency directory holds a version that does not satisfy the declared requirement.
+    #[error("package {dependent} depends on {id} {requirement}, but {} has version {actual}", .path.display())]
+    VersionMismatch {
+        /// The dependency package ID.
+        id: Id,
+        /// The version requirement declared by the dependent.
+        requirement: VersionReq,
+        /// The version in the directory's manifest.
+        actual: Version,
+        /// The package that declared the dependency.
+        dependent: Id,
+        /// The dependency directory.
+        path: PathBuf,
+    },
+
+    /// A package is registered, but its directory has since been deleted.
+    #[error("package {id} is registered, but {} has been deleted", .path.display())]
+    DeletedDirectory {
+        /// The registered package ID.
+        id: Id,
+        /// The package's directory.
+        path: PathBuf,
+    },
+
+    /// A dependency directory's manifest could not be read.
+    #[error("failed to read manifest in {}: {message}", .path.display())]
+    Manifest {
+        /// The dependency directory.
+        path: PathBuf,
+        /// Description of the underlying error.
+        message: String,
+    },
 }
 
 // Queries. Note they talk in IDs, not a &Package, to satisfy the borrow checker.
@@ -106,11 +415,11 @@ impl Registry {
             if dependencies.contains(&popped_dep) {
                 continue;
             }
-            if let Dependency::Package { id: dep_id, .. } = &popped_dep {
-                if *dep_id == id {
+            if let Some(dep_id) = popped_dep.package_id() {
+                if dep_id == id {
                     bail!("found circular dependency");
                 }
-                for dependency in self.get_direct_dependencies(*dep_id)? {
+                for dependency in self.get_direct_dependencies(dep_id)? {
                     stack.push(dependency);
                 }
             }
@@ -133,13 +442,7 @@ impl Registry {
     /// Returns true if a package has a dependency - transitive or direct - on another package.
     pub fn has_dependency(&self, id: Id, dependency_id: Id) -> Result<bool> {
         let dependencies = self.get_dependencies(id)?;
-        Ok(dependencies.iter().any(|dep| {
-            if let Dependency::Package { id: dep_id, .. } = dep {
-                *dep_id == dependency_id
-            } else {
-                false
-            }
-        }))
+        Ok(dependencies.iter().any(|dep| dep.package_id() == Some(dependency_id)))
     }
 
     /// Adds a direct dependency to a package.
@@ -150,7 +453,7 @@ impl Registry {
         let dependency_manifest = dependency_package.manifest()?;
         let dependency_metadata = dependency_manifest.metadata();
         let dependency = dependency_metadata.into();
-        package.edit_manifest(|manifest| {
+        package.edit_manifest_unchecked(|manifest| {
             manifest.declare_direct_dependency(dependency)
         })
     }
@@ -160,21 +463,27 @@ impl Registry {
     pub fn check_version_compatibility(&self) -> Result<()> {
         let map = self.package_version_map()?;
         for dependency in self.all_dependencies()? {
-            if let Dependency::Package { id, version } = dependency {
-                match map.get(&id) {
+            match dependency {
+                Dependency::Package { id, version } => match map.get(&id) {
                     None => bail!("dependency exists for {id} {version}, but it is not in registry"),
                     Some(actual_version) => {
                         if !version.matches(actual_version) {
                             bail!(
                                 "a package depends on {} {} which is incompatible with its actual version {}",
-                                self.get(id).unwrap(), // unwrap: if its in map, its in registry
+                                self.get_or_error(id)?,
                                 version,
                                 actual_version,
                             );
                         }
                     }
+                },
+                // Pinned by revision, so any version is compatible
+                Dependency::Git { id, url, .. } => {
+                    if !map.contains_key(&id) {
+                        bail!("git dependency exists for {id} from {url}, but it is not in registry");
+                    }
                 }
-                
+                Dependency::Decomp { .. } => {}
             }
         }
         Ok(())
@@ -182,22 +491,9 @@ impl Registry {
 
     /// Calculates the patch order in order to build a given root package.
     pub fn calc_dependency_patch_order(&self, root: Id) -> Result<Vec<Id>> {
-        // https://en.wikipedia.org/wiki/Longest_path_problem#Acyclic_graphs
-
-        // Topological ordering will be wrong if there are orphaned packages
-        if !self.get_orphans(root)?.is_empty() {
-            bail!("there are orphaned packages");
-        }
-
-        // Because all graph weights are the same, we can just use the topological ordering
-        let ordering = self.topological_ordering()?;
-
-        // The root package should be the final package in the ordering
-        if ordering.last() != Some(&root) {
-            bail!("root package is not the final package in the topological ordering");
-        }
-
-        Ok(ordering)
+        self.resolve(root)
+            .map(|resolution| resolution.order)
+            .map_err(resolution_errors_to_anyhow)
     }
 
     /// Returns a topological ordering of the packages in the registry.
@@ -214,17 +510,26 @@ impl Registry {
         Ok(topological_ordering)
     }
 
+    /// Returns a SHA1 hash of the whole dependency tree of the given root package, for detecting changes to it.
+    ///
+    /// The hash covers the [content hash](Package::content_hash) of every package in the tree, in patch order, and
+    /// therefore also their IDs, versions, and decomp revisions. It is stable across machines given the same content.
+    /// Packages outside the tree don't affect it.
+    pub fn closure_hash(&self, root: Id) -> Result<String> {
+        let mut order = Vec::new();
+        self.topological_ordering_visit(root, &mut order, &mut HashSet::new(), &mut HashSet::new())?;
+        let mut hasher = Sha1::new();
+        for id in order {
+            hasher.update(self.get_or_error(id)?.content_hash()?.as_bytes());
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
     /// Returns packages that don't appear in the dependency tree for the given root package.
     pub fn get_orphans(&self, root: Id) -> Result<HashSet<Id>> {
         let dependency_ids: HashSet<Id> = self.get_dependencies(root)?
             .into_iter()
-            .filter_map(|dep| {
-                if let Dependency::Package { id, .. } = dep {
-                    Some(id)
-                } else {
-                    None
-                }
-            })
+            .filter_map(|dep| dep.package_id())
             .collect();
         Ok(self.packages.keys()
             .map(|&id| id)
@@ -232,6 +537,28 @@ impl Registry {
             .collect())
     }
 
+    /// Returns the packages in the dependency tree of the given root whose pinned decomp revision differs from the
+    /// root's, along with the revision they pin. Packages that don't pin a revision are not included.
+    pub fn get_decomp_rev_skew(&self, root: Id) -> Result<Vec<(Id, String)>> {
+        let root_rev = self.get_or_error(root)?
+            .manifest()?
+            .get_direct_decomp_dependency_rev()
+            .map(str::to_owned);
+        let mut skewed = Vec::new();
+        for dependency in self.get_dependencies(root)? {
+            if let Some(id) = dependency.package_id() {
+                let manifest = self.get_or_error(id)?.manifest()?;
+                if let Some(rev) = manifest.get_direct_decomp_dependency_rev() {
+                    if root_rev.as_deref() != Some(rev) {
+                        skewed.push((id, rev.to_owned()));
+                    }
+                }
+            }
+        }
+        skewed.sort();
+        Ok(skewed)
+    }
+
     /// Unregisters and deletes the directories for all orphaned packages.
     pub fn delete_orphans(&mut self, root: Id) -> Result<()> {
         for id in self.get_orphans(root)? {
@@ -243,6 +570,235 @@ impl Registry {
 }
 
 impl Registry {
+    /// Resolves the entire dependency tree of the given root package.
+    ///
+    /// Unlike the individual checks ([`Registry::check_version_compatibility`], [`Registry::get_orphans`],
+    /// [`Registry::topological_ordering`]), this does not stop at the first problem: every missing dependency,
+    /// version conflict, cycle, and orphan is reported.
+    pub fn resolve(&self, root: Id) -> Result<Resolution, Vec<ResolutionError>> {
+        if !self.has(root) {
+            return Err(vec![ResolutionError::Unregistered { id: root }]);
+        }
+
+        // https://en.wikipedia.org/wiki/Topological_sorting#Depth-first_search
+        // Because all graph weights are the same, the topological ordering is also the patch order.
+        let mut order = Vec::new();
+        let mut versions = HashMap::new();
+        let mut visit_in_progress = HashSet::new();
+        let mut errors = Vec::new();
+        self.resolve_visit(root, &mut order, &mut versions, &mut visit_in_progress, &mut errors);
+
+        // Anything not visited is not in the dependency tree of root
+        let mut orphans: Vec<Id> = self.package_ids()
+            .filter(|id| !versions.contains_key(id))
+            .collect();
+        orphans.sort_unstable();
+        errors.extend(orphans.into_iter().map(|id| ResolutionError::Orphan { id }));
+
+        let decomp_rev = match self.get_or_error(root).and_then(|package| package.manifest()) {
+            Ok(manifest) => manifest.get_direct_decomp_dependency_rev().map(str::to_owned),
+            Err(_) => None, // Already reported by resolve_visit
+        };
+
+        if errors.is_empty() {
+            Ok(Resolution {
+                order,
+                versions,
+                decomp_rev,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks the whole registry for problems, with the given package as the root of the dependency tree.
+    ///
+    /// This runs the same checks as [`Registry::resolve`], but also checks the packages outside the root's dependency
+    /// tree, and that every package is registered under the ID in its manifest. Every problem found is returned, so
+    /// they can all be reported at once. An empty list means the registry is valid.
+    pub fn validate_all(&self, root: Id) -> Vec<ResolutionError> {
+        let mut order = Vec::new();
+        let mut versions = HashMap::new();
+        let mut visit_in_progress = HashSet::new();
+        let mut errors = Vec::new();
+
+        let mut orphans = Vec::new();
+        if self.has(root) {
+            self.resolve_visit(root, &mut order, &mut versions, &mut visit_in_progress, &mut errors);
+            orphans = self.package_ids()
+                .filter(|id| !versions.contains_key(id))
+                .collect();
+            orphans.sort_unstable();
+        } else {
+            errors.push(ResolutionError::Unregistered { id: root });
+        }
+
+        // Visit every other package too, so problems outside the root's tree are also found
+        let mut ids: Vec<Id> = self.package_ids().collect();
+        ids.sort_unstable();
+        for &id in &ids {
+            self.resolve_visit(id, &mut order, &mut versions, &mut visit_in_progress, &mut errors);
+        }
+        errors.extend(orphans.into_iter().map(|id| ResolutionError::Orphan { id }));
+
+        for id in ids {
+            // Unreadable manifests were already reported by resolve_visit
+            if let Ok(manifest) = self.get_or_error(id).and_then(|package| package.manifest()) {
+                let manifest_id = manifest.metadata().id();
+                if manifest_id != id {
+                    errors.push(ResolutionError::IdMismatch { id, manifest_id });
+                }
+            }
+        }
+
+        // Packages that fail to visit are visited again from each of their dependents
+        let mut unique_errors = Vec::with_capacity(errors.len());
+        for error in errors {
+            if !unique_errors.contains(&error) {
+                unique_errors.push(error);
+            }
+        }
+        unique_errors
+    }
+
+    /// Cross-checks the dependencies declared by the package at `package_root`, and transitively by its
+    /// dependencies, against the directories in its `.merlon/dependencies` and against this registry. Every
+    /// inconsistency found is returned; an empty list means the disk is consistent.
+    ///
+    /// Unlike [`Registry::validate_all`], manifests are read from disk rather than through the registry, so this
+    /// finds changes made since the registry was loaded.
+    pub fn is_consistent_with_disk(&self, package_root: &Path) -> Result<Vec<Inconsistency>> {
+        let root = Package::try_from(package_root.to_owned())?;
+        let root_id = root.id()?;
+        let dependencies_dir = package_root.join(super::init::DEPENDENCIES_DIR_NAME);
+        let mut inconsistencies = Vec::new();
+
+        // Walk the declared dependency tree through the directories on disk
+        let mut declared = HashSet::new();
+        let mut stack = vec![(root_id, root.manifest()?)];
+        while let Some((dependent, manifest)) = stack.pop() {
+            for (id, requirement) in manifest.iter_package_dependencies() {
+                let path = dependencies_dir.join(id.to_string());
+                if !path.is_dir() {
+                    inconsistencies.push(Inconsistency::MissingDirectory { id, dependent, path });
+                    declared.insert(id);
+                    continue;
+                }
+                let dependency_manifest = match Package::try_from(path.clone()).and_then(|package| package.manifest()) {
+                    Ok(dependency_manifest) => dependency_manifest,
+                    Err(error) => {
+                        inconsistencies.push(Inconsistency::Manifest { path, message: format!("{:#}", error) });
+                        declared.insert(id);
+                        continue;
+                    }
+                };
+                let manifest_id = dependency_manifest.metadata().id();
+                if manifest_id != id {
+                    inconsistencies.push(Inconsistency::IdMismatch { id, manifest_id, path: path.clone() });
+                }
+                let actual = dependency_manifest.metadata().version();
+                if !requirement.matches(actual) {
+                    inconsistencies.push(Inconsistency::VersionMismatch {
+                        id,
+                        requirement: requirement.clone(),
+                        actual: actual.clone(),
+                        dependent,
+                        path,
+                    });
+                }
+                if declared.insert(id) {
+                    stack.push((id, dependency_manifest));
+                }
+            }
+        }
+
+        // Directories that nothing declares
+        if dependencies_dir.is_dir() {
+            let mut undeclared = Vec::new();
+            for entry in dependencies_dir.read_dir()? {
+                let path = entry?.path();
+                let id = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.parse::<Id>().ok());
+                if path.is_dir() && !id.is_some_and(|id| declared.contains(&id)) {
+                    undeclared.push(path);
+                }
+            }
+            undeclared.sort();
+            inconsistencies.extend(undeclared.into_iter().map(|path| Inconsistency::UndeclaredDirectory { path }));
+        }
+
+        // Registered packages whose directories are gone, unless already reported as missing
+        let missing: HashSet<Id> = inconsistencies.iter()
+            .filter_map(|inconsistency| match inconsistency {
+                Inconsistency::MissingDirectory { id, .. } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        let mut ids: Vec<Id> = self.package_ids().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let path = self.packages[&id].path().to_owned();
+            if !path.is_dir() && !missing.contains(&id) {
+                inconsistencies.push(Inconsistency::DeletedDirectory { id, path });
+            }
+        }
+
+        Ok(inconsistencies)
+    }
+
+    fn resolve_visit(
+        &self,
+        id: Id,
+        order: &mut Vec<Id>,
+        versions: &mut HashMap<Id, Version>,
+        visit_in_progress: &mut HashSet<Id>,
+        errors: &mut Vec<ResolutionError>,
+    ) {
+        if versions.contains_key(&id) {
+            return;
+        }
+        if visit_in_progress.contains(&id) {
+            errors.push(ResolutionError::Cycle { id });
+            return;
+        }
+        let manifest = match self.get_or_error(id).and_then(|package| package.manifest()) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                errors.push(ResolutionError::Manifest { id, message: format!("{:#}", error) });
+                return;
+            }
+        };
+        visit_in_progress.insert(id);
+        for (dependency_id, requirement) in manifest.iter_package_dependencies() {
+            let dependency_manifest = match self.get(dependency_id) {
+                Ok(Some(package)) => package.manifest(),
+                Err(error) => Err(error),
+                Ok(None) => {
+                    errors.push(ResolutionError::Missing {
+                        id: dependency_id,
+                        requirement: requirement.clone(),
+                        dependent: id,
+                    });
+                    continue;
+                }
+            };
+            if let Ok(dependency_manifest) = dependency_manifest {
+                let actual = dependency_manifest.metadata().version();
+                if !requirement.matches(actual) {
+                    errors.push(ResolutionError::Conflict {
+                        id: dependency_id,
+                        requirement: requirement.clone(),
+                        actual: actual.clone(),
+                        dependent: id,
+                    });
+                }
+            }
+            self.resolve_visit(dependency_id, order, versions, visit_in_progress, errors);
+        }
+        visit_in_progress.remove(&id);
+        versions.insert(id, manifest.metadata().version().clone());
+        order.push(id);
+    }
+
     /// Generates a map of package IDs to their versions.
     pub fn package_version_map(&self) -> Result<HashMap<Id, Version>> {
         let mut map = HashMap::new();
@@ -277,10 +833,8 @@ impl Registry {
             visit_in_progress.insert(id);
             let package = self.get_or_error(id)?;
             let manifest = package.manifest()?;
-            for dependency in manifest.iter_direct_dependencies() {
-                if let Dependency::Package { id, .. } = dependency {
-                    self.topological_ordering_visit(*id, topological_ordering, visit_in_progress, visited)?;
-                }
+            for (id, _) in manifest.iter_package_dependencies() {
+                self.topological_ordering_visit(id, topological_ordering, visit_in_progress, visited)?;
             }
             visit_in_progress.remove(&id);
             visited.insert(id);
@@ -290,13 +844,38 @@ impl Registry {
     }
 }
 
+/// Returns true if the directory at `path` is named after `id`.
+fn is_named_after(path: &Path, id: Id) -> bool {
+    path.file_name().map(|name| name == id.to_string().as_str()).unwrap_or(false)
+}
+
+/// Renames the package directory at `path` to be named after `id`, its package ID, so that it can be registered
+/// lazily under that ID. Returns the new path.
+/// Returns an error if a directory with that name already exists.
+pub(crate) fn rename_to_id(path: &Path, id: Id) -> Result<PathBuf> {
+    let expected_path = path.with_file_name(id.to_string());
+    log::warn!("package directory {} does not match its package ID {}, renaming it", path.display(), id);
+    if expected_path.exists() {
+        bail!(
+            "cannot rename {} to {}: it already exists, remove one of them and try again",
+            path.display(),
+            expected_path.display(),
+        );
+    }
+    std::fs::rename(path, &expected_path)
+        .with_context(|| format!("failed to rename {}", path.display()))?;
+    Ok(expected_path)
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
+    use std::fs;
     use temp_dir::TempDir;
     use anyhow::Result;
 
-    use super::{Registry, Package, Dependency, Version};
+    use super::{Registry, Package, Id, Dependency, Version, ResolutionError, Inconsistency};
+    use crate::package::distribute::{Distributable, ExportOptions};
 
     #[test]
     fn dependency_graph() -> Result<()> {
@@ -381,4 +960,333 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn resolve_reports_all_errors() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        registry.add_direct_dependency(root, base)?;
+
+        let resolution = registry.resolve(root).unwrap();
+        assert_eq!(resolution.order, vec![base, root]);
+        assert_eq!(resolution.versions[&base], Version::new(0, 1, 0));
+        assert_eq!(resolution.decomp_rev, None);
+
+        // Break the tree in two different ways at once
+        registry.edit(base, |package| {
+            package.edit_manifest(|manifest| {
+                manifest.metadata_mut().set_version(Version::new(2, 0, 0));
+                Ok(())
+            })
+        })?;
+        let orphan = registry.register(Package::new("Orphan", dir.path().join("orphan"))?)?;
+
+        let errors = registry.resolve(root).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|error| matches!(error, ResolutionError::Conflict { id, .. } if *id == base)));
+        assert!(errors.contains(&ResolutionError::Orphan { id: orphan }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_all_reports_problems_outside_root_tree() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        registry.add_direct_dependency(root, base)?;
+        assert!(registry.validate_all(root).is_empty());
+
+        // An orphan with an incompatible dependency on base, and two orphans depending on each other
+        let orphan = registry.register(Package::new("Orphan", dir.path().join("orphan"))?)?;
+        registry.add_direct_dependency(orphan, base)?;
+        let x = registry.register(Package::new("X", dir.path().join("x"))?)?;
+        let y = registry.register(Package::new("Y", dir.path().join("y"))?)?;
+        registry.add_direct_dependency(x, y)?;
+        registry.add_direct_dependency(y, x)?;
+        registry.edit(base, |package| {
+            package.edit_manifest(|manifest| {
+                manifest.metadata_mut().set_version(Version::new(2, 0, 0));
+                Ok(())
+            })
+        })?;
+
+        // resolve only sees the conflict in the root's tree
+        let errors = registry.resolve(root).unwrap_err();
+        assert!(!errors.iter().any(|error| matches!(error, ResolutionError::Cycle { .. })));
+
+        let errors = registry.validate_all(root);
+        let conflicts: Vec<Id> = errors.iter()
+            .filter_map(|error| match error {
+                ResolutionError::Conflict { dependent, .. } => Some(*dependent),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts.contains(&root) && conflicts.contains(&orphan));
+        assert!(errors.iter().any(|error| matches!(error, ResolutionError::Cycle { id } if *id == x || *id == y)));
+        for id in [orphan, x, y] {
+            assert!(errors.contains(&ResolutionError::Orphan { id }));
+        }
+
+        // Unknown root
+        let unknown = Id::new();
+        let errors = registry.validate_all(unknown);
+        assert_eq!(errors[0], ResolutionError::Unregistered { id: unknown });
+        assert!(!errors.iter().any(|error| matches!(error, ResolutionError::Orphan { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn disk_is_checked_against_declared_dependencies() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        let dependencies_dir = dir.path().join("root/.merlon/dependencies");
+        fs::create_dir_all(&dependencies_dir)?;
+        let create_dependency = |name: &str| -> Result<Package> {
+            let package = Package::new(name, dependencies_dir.join(name))?;
+            let path = dependencies_dir.join(package.id()?.to_string());
+            fs::rename(package.path(), &path)?;
+            Package::try_from(path)
+        };
+        let mut declare = |name: &str| -> Result<(Id, Package)> {
+            let package = create_dependency(name)?;
+            let id = registry.register(package.clone())?;
+            registry.add_direct_dependency(root, id)?;
+            Ok((id, package))
+        };
+        let (base, base_package) = declare("Base")?;
+        let (gone, gone_package) = declare("Gone")?;
+        let (mismatched, mismatched_package) = declare("Mismatched")?;
+        let outside = registry.register(Package::new("Outside", dir.path().join("outside"))?)?;
+        let root_path = dir.path().join("root");
+        assert_eq!(registry.is_consistent_with_disk(&root_path)?, vec![]);
+
+        // Tamper with the dependencies by hand
+        base_package.edit_manifest(|manifest| {
+            manifest.metadata_mut().set_version(Version::new(2, 0, 0));
+            Ok(())
+        })?;
+        fs::remove_dir_all(gone_package.path())?;
+        let undeclared = create_dependency("Undeclared")?;
+        fs::copy(undeclared.path().join("merlon.toml"), mismatched_package.path().join("merlon.toml"))?;
+        fs::remove_dir_all(dir.path().join("outside"))?;
+
+        let inconsistencies = registry.is_consistent_with_disk(&root_path)?;
+        assert_eq!(inconsistencies.len(), 5, "{:#?}", inconsistencies);
+        assert!(inconsistencies.contains(&Inconsistency::MissingDirectory {
+            id: gone,
+            dependent: root,
+            path: gone_package.path().to_owned(),
+        }));
+        assert!(inconsistencies.contains(&Inconsistency::UndeclaredDirectory { path: undeclared.path().to_owned() }));
+        assert!(inconsistencies.contains(&Inconsistency::IdMismatch {
+            id: mismatched,
+            manifest_id: undeclared.id()?,
+            path: mismatched_package.path().to_owned(),
+        }));
+        assert!(inconsistencies.iter().any(|inconsistency| matches!(
+            inconsistency,
+            Inconsistency::VersionMismatch { id, dependent, .. } if *id == base && *dependent == root
+        )));
+        assert!(inconsistencies.iter().any(|inconsistency| matches!(
+            inconsistency,
+            Inconsistency::DeletedDirectory { id, .. } if *id == outside
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn dependents_incompatible_with_version() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        registry.add_direct_dependency(root, base)?;
+
+        assert!(registry.dependents_incompatible_with(base, &Version::new(0, 1, 1))?.is_empty());
+        assert_eq!(registry.dependents_incompatible_with(base, &Version::new(0, 2, 0))?, vec![root]);
+        assert!(registry.dependents_incompatible_with(root, &Version::new(1, 0, 0))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn closure_hash_tracks_dependency_patches() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        registry.add_direct_dependency(root, base)?;
+
+        let hash = registry.closure_hash(root)?;
+        assert_eq!(registry.closure_hash(root)?, hash);
+
+        // Packages outside the tree don't matter
+        registry.register(Package::new("Unrelated", dir.path().join("unrelated"))?)?;
+        assert_eq!(registry.closure_hash(root)?, hash);
+
+        let patch = dir.path().join("base/patches/0001-change.patch");
+        fs::write(&patch, "Subject: [PATCH] change\n\n")?;
+        let changed = registry.closure_hash(root)?;
+        assert_ne!(changed, hash);
+        fs::write(&patch, "Subject: [PATCH] different change\n\n")?;
+        assert_ne!(registry.closure_hash(root)?, changed);
+        fs::remove_file(&patch)?;
+        assert_eq!(registry.closure_hash(root)?, hash);
+        Ok(())
+    }
+
+    #[test]
+    fn distributable_closure_requires_dependencies() -> Result<()> {
+        let dir = TempDir::new()?;
+        let baserom = dir.path().join("baserom.z64");
+        fs::write(&baserom, "pretend this is a ROM")?;
+
+        let mut registry = Registry::new();
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        let other = registry.register(Package::new("Other", dir.path().join("other"))?)?;
+        registry.add_direct_dependency(root, base)?;
+        for (id, name) in [(base, "base"), (root, "root"), (other, "other")] {
+            registry.get_or_error(id)?.export_distributable(ExportOptions {
+                output: Some(dir.path().join(name)),
+                baserom: Some(baserom.clone()),
+                if_exists: Default::default(),
+                dry_run: false,
+                exclude_patches: vec![],
+                verify_reproducible: false,
+                patch_context: None,
+                split_size: None,
+            })?;
+        }
+        let distributable = |name: &str| Distributable::try_from(dir.path().join(name).with_extension("merlon"));
+
+        // Root is missing its dependency
+        let result = Registry::from_distributable_closure(
+            &[distributable("root")?, distributable("other")?],
+            &baserom,
+            &dir.path().join("open1"),
+        );
+        assert!(result.is_err());
+
+        let (closure, ids) = Registry::from_distributable_closure(
+            &[distributable("root")?, distributable("base")?, distributable("other")?],
+            &baserom,
+            &dir.path().join("open2"),
+        )?;
+        assert_eq!(ids, vec![root, base, other]);
+        assert_eq!(closure.package_ids().count(), 3);
+
+        // The same package twice is a conflict
+        let result = Registry::from_distributable_closure(
+            &[distributable("other")?, distributable("other")?],
+            &baserom,
+            &dir.path().join("open3"),
+        );
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_round_trip() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        registry.add_direct_dependency(root, base)?;
+        registry.get_or_error(root)?.edit_manifest(|manifest| {
+            manifest.upsert_decomp_dependency("abc123".to_owned())
+        })?;
+
+        let json = registry.to_snapshot()?.to_json()?;
+        let snapshot = super::RegistrySnapshot::from_json(&json)?;
+        let restored = Registry::from_snapshot(&snapshot, dir.path().join("restored"))?;
+
+        assert_eq!(restored.to_snapshot()?, snapshot);
+        assert_eq!(restored.resolve(root).unwrap(), registry.resolve(root).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn diff_lists_changes() -> Result<()> {
+        let dir = TempDir::new()?;
+        let old_rev = "7a9df943ad079e7b19df0f8690bdc92e2beed964";
+        let new_rev = "a1b2c3d4e5f60718293a4b5c6d7e8f9012345678";
+
+        let mut old = Registry::new();
+        let root = old.register(Package::new("Root", dir.path().join("root"))?)?;
+        let kept = old.register(Package::new("Kept", dir.path().join("kept"))?)?;
+        let dropped = old.register(Package::new("Dropped", dir.path().join("dropped"))?)?;
+        old.add_direct_dependency(root, kept)?;
+        old.add_direct_dependency(root, dropped)?;
+        old.get_or_error(root)?.edit_manifest(|manifest| {
+            manifest.upsert_decomp_dependency(old_rev.to_owned())
+        })?;
+        let mut new = Registry::from_snapshot(&old.to_snapshot()?, dir.path().join("new"))?;
+        assert!(old.diff(&new)?.is_empty());
+
+        new.take(dropped)?;
+        new.register(Package::new("Added", dir.path().join("added"))?)?;
+        new.get_or_error(kept)?.edit_manifest_unchecked(|manifest| {
+            manifest.metadata_mut().set_version("1.1.0".parse()?);
+            Ok(())
+        })?;
+        new.get_or_error(root)?.edit_manifest_unchecked(|manifest| {
+            manifest.upsert_decomp_dependency(new_rev.to_owned())
+        })?;
+
+        let diff = old.diff(&new)?;
+        assert_eq!(diff.changes().len(), 4);
+        let summary = diff.to_string();
+        assert!(summary.starts_with("updated decomp 7a9df94→a1b2c3d"), "{}", summary);
+        assert!(summary.contains("bumped Kept 0.1.0→1.1.0"), "{}", summary);
+        assert!(summary.contains("removed Dropped 0.1.0"), "{}", summary);
+        assert!(summary.contains("added Added 0.1.0"), "{}", summary);
+        Ok(())
+    }
+
+    #[test]
+    fn dependency_tree_lists_transitive_dependencies() -> Result<()> {
+        let dir = TempDir::new()?;
+        let rev = "7a9df943ad079e7b19df0f8690bdc92e2beed964";
+        let mut registry = Registry::new();
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        let a = registry.register(Package::new("A", dir.path().join("a"))?)?;
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        registry.add_direct_dependency(root, base)?;
+        registry.add_direct_dependency(root, a)?;
+        registry.add_direct_dependency(a, base)?;
+        registry.get_or_error(root)?.edit_manifest(|manifest| {
+            manifest.upsert_decomp_dependency(rev.to_owned())
+        })?;
+
+        // Decomp first, then in patch order, so Base before A
+        let tree = registry.dependency_tree(root)?.to_string();
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", tree);
+        assert!(lines[0].starts_with("Root 0.1.0 ("), "{}", tree);
+        assert_eq!(lines[1], "  decomp at 7a9df94");
+        assert!(lines[2].starts_with(&format!("  Base ~0.1.0 ({})", base)), "{}", tree);
+        assert!(lines[3].starts_with("  A ~0.1.0 ("), "{}", tree);
+        assert!(lines[4].starts_with("    Base ~0.1.0 ("), "{}", tree);
+        let json = registry.dependency_tree(root)?.to_json()?;
+        assert!(json.contains(rev), "{}", json);
+        assert!(json.contains("\"type\": \"decomp\""), "{}", json);
+
+        // Dependencies missing from the registry are still listed
+        registry.take(a)?;
+        let tree = registry.dependency_tree(root)?;
+        let missing = &tree.root().dependencies()[2];
+        assert!(matches!(missing, super::DependencyNode::Package { id, name: None, .. } if *id == a));
+        assert!(tree.to_string().contains("not initialised"), "{}", tree);
+        Ok(())
+    }
 }
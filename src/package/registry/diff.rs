@@ -0,0 +1,175 @@
+//! Differences between two states of a registry, e.g. before and after `merlon update`.
+
+use std::fmt;
+use pyo3::prelude::*;
+use anyhow::Result;
+
+use super::{Registry, RegistrySnapshot};
+use super::snapshot::PackageSnapshot;
+use crate::package::{Name, manifest::{Dependency, Version}};
+
+/// A single difference between two registries. See [`RegistryDiff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A package was added.
+    Added {
+        /// The name of the package.
+        name: Name,
+        /// The version of the package.
+        version: Version,
+    },
+    /// A package was removed.
+    Removed {
+        /// The name of the package.
+        name: Name,
+        /// The version the package had.
+        version: Version,
+    },
+    /// A package's version changed.
+    Bumped {
+        /// The name of the package.
+        name: Name,
+        /// The old version.
+        from: Version,
+        /// The new version.
+        to: Version,
+    },
+    /// The decomp revision that packages depend on changed.
+    DecompRev {
+        /// The old revision, if any.
+        from: Option<String>,
+        /// The new revision, if any.
+        to: Option<String>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { name, version } => write!(f, "added {} {}", name, version),
+            Change::Removed { name, version } => write!(f, "removed {} {}", name, version),
+            Change::Bumped { name, from, to } => write!(f, "bumped {} {}→{}", name, from, to),
+            Change::DecompRev { from, to } => write!(
+                f,
+                "updated decomp {}→{}",
+                from.as_deref().map(short_rev).unwrap_or("none"),
+                to.as_deref().map(short_rev).unwrap_or("none"),
+            ),
+        }
+    }
+}
+
+/// The differences between two registries. See [`Registry::diff`].
+///
+/// Formats as a comma-separated summary, e.g. `updated decomp abc1234→def5678, bumped Foo 1.0.0→1.1.0`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[pyclass(module = "merlon.package.registry")]
+pub struct RegistryDiff {
+    changes: Vec<Change>,
+}
+
+impl RegistryDiff {
+    /// The changes, with decomp revision changes first, then packages in order of ID.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+}
+
+#[pymethods]
+impl RegistryDiff {
+    /// Returns true if there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    fn __len__(&self) -> usize {
+        self.changes.len()
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for RegistryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl RegistrySnapshot {
+    /// Compares this snapshot (the old state) with another (the new state).
+    pub fn diff(&self, other: &RegistrySnapshot) -> RegistryDiff {
+        let mut changes = Vec::new();
+
+        // Report each distinct decomp revision change once, even if several packages moved together
+        for old in &self.packages {
+            let Some(new) = other.packages.iter().find(|new| new.id == old.id) else {
+                continue;
+            };
+            let (from, to) = (decomp_rev(old), decomp_rev(new));
+            if from != to {
+                let change = Change::DecompRev { from, to };
+                if !changes.contains(&change) {
+                    changes.push(change);
+                }
+            }
+        }
+
+        for old in &self.packages {
+            match other.packages.iter().find(|new| new.id == old.id) {
+                Some(new) if new.version != old.version => changes.push(Change::Bumped {
+                    name: new.name.clone(),
+                    from: old.version.clone(),
+                    to: new.version.clone(),
+                }),
+                Some(_) => {}
+                None => changes.push(Change::Removed {
+                    name: old.name.clone(),
+                    version: old.version.clone(),
+                }),
+            }
+        }
+        for new in &other.packages {
+            if !self.packages.iter().any(|old| old.id == new.id) {
+                changes.push(Change::Added {
+                    name: new.name.clone(),
+                    version: new.version.clone(),
+                });
+            }
+        }
+
+        RegistryDiff { changes }
+    }
+}
+
+#[pymethods]
+impl Registry {
+    /// Compares this registry (the old state) with another (the new state).
+    pub fn diff(&self, other: &Registry) -> Result<RegistryDiff> {
+        Ok(self.to_snapshot()?.diff(&other.to_snapshot()?))
+    }
+}
+
+fn decomp_rev(package: &PackageSnapshot) -> Option<String> {
+    package.dependencies.iter().find_map(|dependency| match dependency {
+        Dependency::Decomp { rev } => Some(rev.clone()),
+        Dependency::Package { .. } | Dependency::Git { .. } => None,
+    })
+}
+
+/// Abbreviates a git commit hash like `git log --oneline` does. Other revisions, e.g. tags, are left as-is.
+pub(super) fn short_rev(rev: &str) -> &str {
+    if rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        &rev[..7]
+    } else {
+        rev
+    }
+}
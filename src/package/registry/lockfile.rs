@@ -0,0 +1,199 @@
+//! Lockfiles, which pin the exact dependency versions a package was last resolved with.
+//!
+//! A package's `merlon.lock` is written whenever its decomp repository is synced, e.g. by `merlon add` or
+//! `merlon update`, and should be committed along with it, so that everyone working on the package builds against the
+//! same dependencies:
+//!
+//! ```toml
+//! decomp_rev = "7a9df943ad079e7b19df0f8690bdc92e2beed964"
+//!
+//! [[package]]
+//! id = "4c5b1e37-8a13-4b4b-b2d1-aa0e3a7ae21f"
+//! name = "Base"
+//! version = "1.2.0"
+//!
+//! [[package]]
+//! id = "9e43fd52-35c8-4fb6-9d5c-3d41ca4c1d6b"
+//! name = "Git Dependency"
+//! version = "0.1.0"
+//! rev = "a1b2c3d4e5f60718293a4b5c6d7e8f9012345678"
+//! ```
+//!
+//! Building checks the resolved dependencies against the lockfile, if there is one, and fails if they have drifted.
+
+use std::fs;
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use serde::{Deserialize, Serialize};
+
+use super::Registry;
+use crate::package::{Id, Name, manifest::{Dependency, Version}};
+
+/// File name of the lockfile in a package's root directory.
+pub const FILE_NAME: &str = "merlon.lock";
+
+/// The resolved dependencies of a package. See the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// The decomp revision the package pins, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decomp_rev: Option<String>,
+
+    /// Every package in the dependency tree, not including the package itself, sorted by ID.
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+/// A package pinned by a [`Lockfile`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedPackage {
+    /// The ID of the package.
+    pub id: Id,
+
+    /// The name of the package, for readability. Not checked.
+    pub name: Name,
+
+    /// The exact version of the package that was resolved.
+    pub version: Version,
+
+    /// The commit a git dependency is pinned to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+}
+
+impl Lockfile {
+    /// Reads a lockfile.
+    pub fn read_from_path(path: &Path) -> Result<Self> {
+        let string = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&string).with_context(|| format!("invalid lockfile {}", path.display()))
+    }
+
+    /// Writes the lockfile, replacing any existing one.
+    pub fn write_to_path(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+impl Registry {
+    /// Captures the resolved dependency tree of the given root package as a lockfile.
+    pub fn lockfile(&self, root: Id) -> Result<Lockfile> {
+        let decomp_rev = self.get_or_error(root)?
+            .manifest()?
+            .get_direct_decomp_dependency_rev()
+            .map(str::to_owned);
+        let mut packages: Vec<LockedPackage> = Vec::new();
+        for dependency in self.get_dependencies(root)? {
+            let (id, rev) = match dependency {
+                Dependency::Package { id, .. } => (id, None),
+                Dependency::Git { id, rev, .. } => (id, Some(rev)),
+                Dependency::Decomp { .. } => continue,
+            };
+            // The same package can be depended on by several dependents, e.g. by version and by git
+            if let Some(existing) = packages.iter_mut().find(|package| package.id == id) {
+                existing.rev = existing.rev.take().or(rev);
+                continue;
+            }
+            let manifest = self.get_or_error(id)?.manifest()?;
+            packages.push(LockedPackage {
+                id,
+                name: manifest.metadata().name().clone(),
+                version: manifest.metadata().version().clone(),
+                rev,
+            });
+        }
+        packages.sort_by_key(|package| package.id);
+        Ok(Lockfile { decomp_rev, packages })
+    }
+
+    /// Writes the lockfile of the given root package to `path`. See [`Registry::lockfile`].
+    pub fn write_lockfile(&self, root: Id, path: &Path) -> Result<()> {
+        self.lockfile(root)?.write_to_path(path)
+    }
+
+    /// Checks that the resolved dependency tree of the given root package matches a lockfile, i.e. has the same
+    /// packages at the same versions and revisions, and the same decomp revision. Errors listing every difference.
+    pub fn verify_against_lockfile(&self, root: Id, lockfile: &Lockfile) -> Result<()> {
+        let resolved = self.lockfile(root)?;
+        let mut drift = Vec::new();
+        if resolved.decomp_rev != lockfile.decomp_rev {
+            drift.push(format!(
+                "decomp is at {}, but {} is locked",
+                resolved.decomp_rev.as_deref().unwrap_or("no revision"),
+                lockfile.decomp_rev.as_deref().unwrap_or("no revision"),
+            ));
+        }
+        for locked in &lockfile.packages {
+            match resolved.packages.iter().find(|package| package.id == locked.id) {
+                None => drift.push(format!(
+                    "{} {} is locked, but is no longer a dependency",
+                    locked.name,
+                    locked.version,
+                )),
+                Some(package) if package.version != locked.version => drift.push(format!(
+                    "{} is at {}, but {} is locked",
+                    package.name,
+                    package.version,
+                    locked.version,
+                )),
+                Some(package) if package.rev != locked.rev => drift.push(format!(
+                    "{} is at revision {}, but {} is locked",
+                    package.name,
+                    package.rev.as_deref().unwrap_or("none"),
+                    locked.rev.as_deref().unwrap_or("none"),
+                )),
+                Some(_) => {}
+            }
+        }
+        for package in &resolved.packages {
+            if !lockfile.packages.iter().any(|locked| locked.id == package.id) {
+                drift.push(format!("{} {} is a dependency, but is not locked", package.name, package.version));
+            }
+        }
+        if !drift.is_empty() {
+            bail!(crate::Error::Dependency(format!(
+                "dependencies differ from {}: {}",
+                FILE_NAME,
+                drift.join("; "),
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+    use crate::package::Package;
+
+    #[test]
+    fn lockfile_round_trips_and_detects_drift() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut registry = Registry::new();
+        let root = registry.register(Package::new("Root", dir.path().join("root"))?)?;
+        let base = registry.register(Package::new("Base", dir.path().join("base"))?)?;
+        registry.add_direct_dependency(root, base)?;
+        registry.get_or_error(root)?.edit_manifest(|manifest| {
+            manifest.upsert_decomp_dependency("7a9df943ad079e7b19df0f8690bdc92e2beed964".to_owned())
+        })?;
+
+        let path = dir.path().join(FILE_NAME);
+        registry.write_lockfile(root, &path)?;
+        let lockfile = Lockfile::read_from_path(&path)?;
+        assert_eq!(lockfile, registry.lockfile(root)?);
+        assert_eq!(lockfile.packages.len(), 1);
+        assert_eq!(lockfile.packages[0].version.to_string(), "0.1.0");
+        registry.verify_against_lockfile(root, &lockfile)?;
+
+        registry.get_or_error(base)?.edit_manifest_unchecked(|manifest| {
+            manifest.metadata_mut().set_version("0.2.0".parse()?);
+            Ok(())
+        })?;
+        let error = registry.verify_against_lockfile(root, &lockfile).unwrap_err();
+        assert!(error.to_string().contains("Base is at 0.2.0, but 0.1.0 is locked"), "{}", error);
+        Ok(())
+    }
+}
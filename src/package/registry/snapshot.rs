@@ -0,0 +1,91 @@
+//! Portable snapshots of a registry's dependency graph.
+//!
+//! A snapshot records each package's ID, name, version, and dependencies (including decomp revisions), but none
+//! of its files, so it can be shared in a bug report and loaded elsewhere to reproduce resolution problems.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context};
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::Registry;
+use crate::package::{Package, Manifest, Id, Name, manifest::{Dependency, Version}};
+
+/// A snapshot of a [`Registry`]. See [`Registry::to_snapshot`] and [`Registry::from_snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[pyclass(module = "merlon.package.registry")]
+pub struct RegistrySnapshot {
+    pub(super) packages: Vec<PackageSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct PackageSnapshot {
+    pub(super) id: Id,
+    pub(super) name: Name,
+    pub(super) version: Version,
+    pub(super) dependencies: Vec<Dependency>,
+}
+
+#[pymethods]
+impl RegistrySnapshot {
+    /// Serializes the snapshot to pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(Into::into)
+    }
+
+    /// Parses a snapshot from JSON.
+    #[staticmethod]
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("invalid registry snapshot")
+    }
+
+    /// Number of packages in the snapshot.
+    fn __len__(&self) -> usize {
+        self.packages.len()
+    }
+}
+
+#[pymethods]
+impl Registry {
+    /// Captures the dependency graph of every package in the registry.
+    pub fn to_snapshot(&self) -> Result<RegistrySnapshot> {
+        let mut packages = Vec::new();
+        for id in self.package_ids() {
+            let manifest = self.get_or_error(id)?.manifest()?;
+            let metadata = manifest.metadata();
+            packages.push(PackageSnapshot {
+                id,
+                name: metadata.name().clone(),
+                version: metadata.version().clone(),
+                dependencies: manifest.iter_direct_dependencies().cloned().collect(),
+            });
+        }
+        packages.sort_by_key(|package| package.id);
+        Ok(RegistrySnapshot { packages })
+    }
+
+    /// Reconstructs a registry from a snapshot. Since snapshots don't include package files, a stub package
+    /// containing only a manifest is created for each package, in a subdirectory of `dir` named by its ID.
+    #[staticmethod]
+    pub fn from_snapshot(snapshot: &RegistrySnapshot, dir: PathBuf) -> Result<Self> {
+        let mut registry = Self::new();
+        for package in &snapshot.packages {
+            let path = dir.join(package.id.to_string());
+            write_stub_package(&path, package)
+                .with_context(|| format!("failed to create stub package {}", path.display()))?;
+            registry.register(Package::try_from(path)?)?;
+        }
+        Ok(registry)
+    }
+}
+
+fn write_stub_package(path: &Path, package: &PackageSnapshot) -> Result<()> {
+    fs::create_dir_all(path.join(super::super::PATCHES_DIR_NAME))?;
+    let mut manifest = Manifest::new(package.name.clone())?;
+    manifest.metadata_mut().set_id(package.id);
+    manifest.metadata_mut().set_authors(vec![]);
+    manifest.metadata_mut().set_version(package.version.clone());
+    manifest.set_dependencies(package.dependencies.clone());
+    manifest.write_to_file(&path.join(super::super::MANIFEST_FILE_NAME))
+}
@@ -0,0 +1,209 @@
+//! The dependency tree of a package, as printed by `merlon list`.
+
+use std::fmt;
+use anyhow::{Result, bail};
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use super::Registry;
+use super::diff::short_rev;
+use crate::package::{Id, Name, manifest::{Dependency, Version, VersionReq}};
+
+/// The dependencies of a package, and their dependencies, and so on. See [`Registry::dependency_tree`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[pyclass(module = "merlon.package.registry")]
+pub struct DependencyTree {
+    root: DependencyNode,
+}
+
+/// A package or decomp in a [`DependencyTree`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DependencyNode {
+    /// A Merlon package.
+    Package {
+        /// The ID of the package.
+        id: Id,
+
+        /// The name of the package, or `None` if it isn't in the registry, e.g. because the dependent isn't
+        /// initialised.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<Name>,
+
+        /// The version of the package, if it is in the registry.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<Version>,
+
+        /// The version requirement the dependent declared, or `None` for the root and git dependencies.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        requirement: Option<VersionReq>,
+
+        /// The repository of a git dependency.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+
+        /// The revision a git dependency is pinned to.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+
+        /// The dependencies of the package, decomp first and then in patch order.
+        dependencies: Vec<DependencyNode>,
+    },
+    /// The Paper Mario decompilation.
+    Decomp {
+        /// The revision the dependent pinned.
+        rev: String,
+    },
+}
+
+#[pymethods]
+impl DependencyTree {
+    /// Serializes the tree to pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(Into::into)
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl DependencyTree {
+    /// The root package of the tree.
+    pub fn root(&self) -> &DependencyNode {
+        &self.root
+    }
+}
+
+impl fmt::Display for DependencyTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.fmt_indented(f, 0)
+    }
+}
+
+impl DependencyNode {
+    /// The dependencies of this node. Always empty for the decomp.
+    pub fn dependencies(&self) -> &[DependencyNode] {
+        match self {
+            Self::Package { dependencies, .. } => dependencies,
+            Self::Decomp { .. } => &[],
+        }
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "{}", "  ".repeat(depth))?;
+        match self {
+            Self::Package { id, name, version, requirement, url, rev, dependencies } => {
+                match name {
+                    Some(name) => write!(f, "{}", name)?,
+                    None => write!(f, "unknown package")?,
+                }
+                if let Some(requirement) = requirement {
+                    write!(f, " {}", requirement)?;
+                } else if let Some(version) = version {
+                    write!(f, " {}", version)?;
+                }
+                write!(f, " ({})", id)?;
+                if let (Some(url), Some(rev)) = (url, rev) {
+                    write!(f, " from {} at {}", url, short_rev(rev))?;
+                }
+                if name.is_none() {
+                    write!(f, " - not initialised")?;
+                }
+                writeln!(f)?;
+                for dependency in dependencies {
+                    dependency.fmt_indented(f, depth + 1)?;
+                }
+                Ok(())
+            }
+            Self::Decomp { rev } => writeln!(f, "decomp at {}", short_rev(rev)),
+        }
+    }
+}
+
+#[pymethods]
+impl Registry {
+    /// Builds the tree of a package's dependencies. Dependencies that aren't in the registry, such as those of a
+    /// package that isn't initialised, are included without a name, version, or dependencies of their own.
+    pub fn dependency_tree(&self, root: Id) -> Result<DependencyTree> {
+        let (name, version) = self.name_and_version(root)?;
+
+        // Siblings are listed in the order they are patched in, if the tree is complete enough to resolve
+        let patch_order = self.calc_dependency_patch_order(root).ok();
+
+        Ok(DependencyTree {
+            root: DependencyNode::Package {
+                id: root,
+                name: Some(name),
+                version: Some(version),
+                requirement: None,
+                url: None,
+                rev: None,
+                dependencies: self.dependency_tree_children(root, patch_order.as_deref(), &mut vec![root])?,
+            },
+        })
+    }
+}
+
+impl Registry {
+    fn dependency_tree_children(
+        &self,
+        id: Id,
+        patch_order: Option<&[Id]>,
+        ancestors: &mut Vec<Id>,
+    ) -> Result<Vec<DependencyNode>> {
+        let mut dependencies: Vec<Dependency> = self.get_or_error(id)?
+            .manifest()?
+            .iter_direct_dependencies()
+            .cloned()
+            .collect();
+        // Stable, so packages missing from the patch order keep their manifest order, after the rest
+        dependencies.sort_by_key(|dependency| match dependency.package_id() {
+            Some(id) => match patch_order.and_then(|order| order.iter().position(|&other| other == id)) {
+                Some(position) => (1, position),
+                None => (2, 0),
+            },
+            None => (0, 0),
+        });
+
+        let mut children = Vec::new();
+        for dependency in dependencies {
+            let (dependency_id, requirement, url, rev) = match dependency {
+                Dependency::Decomp { rev } => {
+                    children.push(DependencyNode::Decomp { rev });
+                    continue;
+                }
+                Dependency::Package { id, version } => (id, Some(version), None, None),
+                Dependency::Git { id, url, rev, .. } => (id, None, Some(url), Some(rev)),
+            };
+            if ancestors.contains(&dependency_id) {
+                bail!("found circular dependency");
+            }
+            let (name, version, grandchildren) = if self.has(dependency_id) {
+                let (name, version) = self.name_and_version(dependency_id)?;
+                ancestors.push(dependency_id);
+                let grandchildren = self.dependency_tree_children(dependency_id, patch_order, ancestors)?;
+                ancestors.pop();
+                (Some(name), Some(version), grandchildren)
+            } else {
+                (None, None, Vec::new())
+            };
+            children.push(DependencyNode::Package {
+                id: dependency_id,
+                name,
+                version,
+                requirement,
+                url,
+                rev,
+                dependencies: grandchildren,
+            });
+        }
+        Ok(children)
+    }
+
+    fn name_and_version(&self, id: Id) -> Result<(Name, Version)> {
+        let manifest = self.get_or_error(id)?.manifest()?;
+        let metadata = manifest.metadata();
+        Ok((metadata.name().clone(), metadata.version().clone()))
+    }
+}
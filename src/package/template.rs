@@ -0,0 +1,47 @@
+//! Starter templates for new packages.
+
+/// A starter template that [`Package::new_from_template`](super::Package::new_from_template) copies into a new
+/// package.
+#[derive(Debug)]
+pub struct Template {
+    /// Name used to select the template, e.g. with `merlon new --template`.
+    pub name: &'static str,
+
+    /// One-line description of what the template does.
+    pub description: &'static str,
+
+    /// Patch files to copy into the new package, as `(file name, contents)` pairs.
+    pub(super) patches: &'static [(&'static str, &'static str)],
+}
+
+/// Name of the template used when none is specified.
+pub const DEFAULT_TEMPLATE_NAME: &str = "default";
+
+/// Templates bundled with Merlon.
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: DEFAULT_TEMPLATE_NAME,
+        description: "An empty package with no patches",
+        patches: &[],
+    },
+    Template {
+        name: "skip-intro",
+        description: "Skips the intro logos when the game boots",
+        patches: &[(
+            "0001-set-bSkipIntro-to-true.patch",
+            include_str!("../../templates/packages/skip-intro/0001-set-bSkipIntro-to-true.patch"),
+        )],
+    },
+];
+
+impl Template {
+    /// Finds a bundled template by name.
+    pub fn find(name: &str) -> Option<&'static Template> {
+        TEMPLATES.iter().find(|template| template.name == name)
+    }
+
+    /// The template used when none is specified.
+    pub fn default_template() -> &'static Template {
+        Self::find(DEFAULT_TEMPLATE_NAME).expect("default template missing")
+    }
+}
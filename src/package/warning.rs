@@ -0,0 +1,157 @@
+//! Structured validation warnings.
+//!
+//! Every warning has a stable [`Code`], so tools can filter warnings and packages can allow specific ones by
+//! listing their codes in the `allow` field of `merlon.toml`:
+//!
+//! ```toml
+//! [package]
+//! allow = ["W002"]
+//! ```
+//!
+//! Only warnings with [`Severity::Warning`] can be allowed.
+
+use std::fmt;
+use pyo3::prelude::*;
+
+/// How serious a [`Warning`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[pyclass(module = "merlon.package.warning")]
+pub enum Severity {
+    /// Something that is probably a mistake, but doesn't stop the package from working.
+    Warning,
+    /// Something that makes the package invalid.
+    Error,
+}
+
+/// Stable identifier for a kind of [`Warning`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[pyclass(module = "merlon.package.warning")]
+pub enum Code {
+    /// The package has no authors.
+    EmptyAuthors,
+    /// The package has no description.
+    EmptyDescription,
+    /// The package description is too long.
+    LongDescription,
+    /// The package has no license.
+    EmptyLicense,
+    /// The package has a keyword that isn't recognised.
+    InvalidKeyword,
+    /// A patch subject doesn't follow the package's commit convention.
+    CommitSubject,
+    /// A custom asset isn't in the format the decomp expects. See [`crate::package::asset`].
+    InvalidAsset,
+    /// The package long description is too long.
+    LongLongDescription,
+    /// The package license isn't a valid SPDX license expression.
+    InvalidLicense,
+    /// The package version is 0.0.0.
+    InvalidVersion,
+    /// The package version is 0.0.x, which semver treats as too unstable for dependents to allow other versions.
+    UnstableVersion,
+    /// The package repository or homepage isn't a valid web URL.
+    InvalidUrl,
+}
+
+impl Code {
+    /// Returns the code as it is written in `merlon.toml` and printed by the CLI, e.g. `W001`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::EmptyAuthors => "W001",
+            Code::EmptyDescription => "W002",
+            Code::LongDescription => "W003",
+            Code::EmptyLicense => "W004",
+            Code::InvalidKeyword => "W005",
+            Code::CommitSubject => "W006",
+            Code::InvalidAsset => "W007",
+            Code::LongLongDescription => "W008",
+            Code::InvalidLicense => "W009",
+            Code::InvalidVersion => "W010",
+            Code::UnstableVersion => "W011",
+            Code::InvalidUrl => "W012",
+        }
+    }
+
+    /// The severity of warnings with this code.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Code::EmptyAuthors
+            | Code::EmptyDescription
+            | Code::EmptyLicense
+            | Code::InvalidLicense
+            | Code::InvalidVersion
+            | Code::InvalidUrl
+            | Code::InvalidAsset => Severity::Error,
+            Code::LongDescription
+            | Code::InvalidKeyword
+            | Code::CommitSubject
+            | Code::LongLongDescription
+            | Code::UnstableVersion => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A problem found when validating a package.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[pyclass(module = "merlon.package.warning")]
+pub struct Warning {
+    code: Code,
+    message: String,
+}
+
+#[pymethods]
+impl Warning {
+    /// The warning code.
+    #[getter]
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// Human-readable description of the problem.
+    #[getter]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// How serious the warning is.
+    #[getter]
+    pub fn severity(&self) -> Severity {
+        self.code.severity()
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Warning {
+    /// Creates a warning with the given code and message.
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Returns true if this warning is an error, i.e. it has [`Severity::Error`].
+    pub fn is_error(&self) -> bool {
+        self.severity() == Severity::Error
+    }
+}
+
+/// Formats as `warning[W003]: message`, like rustc diagnostics.
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity() {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}[{}]: {}", severity, self.code, self.message)
+    }
+}
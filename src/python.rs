@@ -13,12 +13,19 @@ fn merlon(py: Python, merlon: &PyModule) -> PyResult<()> {
             let manifest = PyModule::new(py, "manifest")?;
             manifest.add_class::<package::manifest::Manifest>()?;
             manifest.add_class::<package::manifest::Metadata>()?;
+            manifest.add_class::<package::manifest::CommitConvention>()?;
+            manifest.add_class::<package::manifest::VersionBump>()?;
+            manifest.add_class::<package::manifest::Region>()?;
+            manifest.add_class::<package::manifest::Author>()?;
             manifest
         })?;
         package.add_submodule({
             let distribute = PyModule::new(py, "distribute")?;
             distribute.add_class::<package::distribute::Distributable>()?;
+            distribute.add_class::<package::distribute::FormatVersion>()?;
             distribute.add_class::<package::distribute::ExportOptions>()?;
+            distribute.add_class::<package::distribute::ExportPreview>()?;
+            distribute.add_class::<package::distribute::BuildManifest>()?;
             distribute.add_class::<package::distribute::ApplyOptions>()?;
             distribute.add_class::<package::distribute::OpenOptions>()?;
             distribute
@@ -29,23 +36,58 @@ fn merlon(py: Python, merlon: &PyModule) -> PyResult<()> {
             init.add_class::<package::init::InitialiseOptions>()?;
             init.add_class::<package::init::BuildRomOptions>()?;
             init.add_class::<package::init::AddDependencyOptions>()?;
+            init.add_class::<package::init::Diagnostic>()?;
+            init.add_class::<package::init::DiagnosticKind>()?;
             init
         })?;
+        package.add_submodule({
+            let patch = PyModule::new(py, "patch")?;
+            patch.add_class::<package::patch::PatchInfo>()?;
+            patch
+        })?;
+        package.add_submodule({
+            let warning = PyModule::new(py, "warning")?;
+            warning.add_class::<package::warning::Warning>()?;
+            warning.add_class::<package::warning::Code>()?;
+            warning.add_class::<package::warning::Severity>()?;
+            warning
+        })?;
+        package.add_submodule({
+            let asset = PyModule::new(py, "asset")?;
+            asset.add_class::<package::asset::AssetCategory>()?;
+            asset
+        })?;
         package.add_submodule({
             let registry = PyModule::new(py, "registry")?;
             registry.add_class::<package::Registry>()?;
+            registry.add_class::<package::registry::RegistrySnapshot>()?;
+            registry.add_class::<package::registry::RegistryDiff>()?;
+            registry.add_class::<package::registry::DependencyTree>()?;
             registry
         })?;
         package
     })?;
+    merlon.add_submodule({
+        let baserom = PyModule::new(py, "baserom")?;
+        baserom.add_function(wrap_pyfunction!(baserom::is_papermario_us, baserom)?)?;
+        baserom.add_function(wrap_pyfunction!(baserom::find_in_retroarch, baserom)?)?;
+        baserom
+    })?;
     merlon.add_submodule({
         let emulator = PyModule::new(py, "emulator")?;
         emulator.add_function(wrap_pyfunction!(emulator::run_rom, emulator)?)?;
+        emulator.add_function(wrap_pyfunction!(emulator::list_available, emulator)?)?;
+        emulator.add_function(wrap_pyfunction!(emulator::find_emulator, emulator)?)?;
+        emulator.add_class::<emulator::EmulatorKind>()?;
+        emulator.add_class::<emulator::LaunchIntent>()?;
         emulator
     })?;
     merlon.add_submodule({
         let rom = PyModule::new(py, "rom")?;
         rom.add_class::<rom::Rom>()?;
+        rom.add_class::<rom::OverwritePolicy>()?;
+        rom.add_class::<rom::ByteOrder>()?;
+        rom.add_class::<rom::RomHeader>()?;
         rom
     })?;
     Ok(())
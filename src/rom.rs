@@ -1,18 +1,259 @@
 //! ROM file handling
 
 use std::io::prelude::*;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::fs::File;
+use std::fs::{self, File};
 use std::fmt;
+use std::cell::RefCell;
+use std::time::SystemTime;
 use sha1::{Sha1, Digest};
-use anyhow::Result;
+use anyhow::{Result, Context, bail};
+use temp_dir::TempDir;
+use clap::ValueEnum;
 use pyo3::prelude::*;
+use thiserror::Error;
+
+use crate::package::manifest::Region;
+
+/// File extension of ROMs written by Merlon.
+pub const EXTENSION: &str = "z64";
+
+/// Length of the header at the start of an N64 ROM.
+const HEADER_LEN: usize = 0x40;
+
+/// Location of the internal name in an N64 ROM header.
+const HEADER_NAME: Range<usize> = 0x20..0x34;
+
+/// Location of the game code in an N64 ROM header. Its last character is the country code.
+const HEADER_GAME_CODE: Range<usize> = 0x3B..0x3F;
+
+/// Offset of the country code in an N64 ROM header.
+const HEADER_COUNTRY_CODE_OFFSET: usize = 0x3E;
+
+/// Length of the header that some ROM copiers put before the ROM itself.
+const COPIER_HEADER_LEN: usize = 0x200;
+
+/// Size of the chunks that [`Rom::sha1_string`] reads the ROM in.
+const SHA1_CHUNK_LEN: usize = 64 * 1024;
+
+/// Maximum number of differing byte ranges listed by [`Rom::diff_summary`].
+pub const DIFF_SUMMARY_MAX_RANGES: usize = 16;
+
+/// What to do when an output file would be written to a path that already exists.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[pyclass(module = "merlon.rom")]
+pub enum OverwritePolicy {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Fail with an error.
+    Error,
+    /// Write to a new path instead, by appending `-1`, `-2`, etc. to the file name.
+    Rename,
+}
+
+/// The byte order of an N64 ROM file, which depends on the tool that dumped it. Merlon and the decomp work with
+/// [`ByteOrder::BigEndian`] ROMs; see [`Rom::normalized_to_z64`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[pyclass(module = "merlon.rom")]
+pub enum ByteOrder {
+    /// The order the N64 reads the ROM in, usually with the extension `.z64`.
+    BigEndian,
+    /// Big-endian with each pair of bytes swapped, usually with the extension `.v64`.
+    ByteSwapped,
+    /// Each 4-byte word reversed, usually with the extension `.n64`.
+    LittleEndian,
+}
+
+impl ByteOrder {
+    /// Detects the byte order from the first word of a ROM, which is always 0x80371240 in big-endian order.
+    fn detect(first_word: &[u8]) -> Option<Self> {
+        match first_word {
+            [0x80, 0x37, 0x12, 0x40] => Some(Self::BigEndian),
+            [0x37, 0x80, 0x40, 0x12] => Some(Self::ByteSwapped),
+            [0x40, 0x12, 0x37, 0x80] => Some(Self::LittleEndian),
+            _ => None,
+        }
+    }
+
+    /// Converts bytes in this order to big-endian order, in place.
+    fn swap_to_big_endian(self, bytes: &mut [u8]) {
+        match self {
+            Self::BigEndian => {}
+            Self::ByteSwapped => bytes.chunks_exact_mut(2).for_each(<[u8]>::reverse),
+            Self::LittleEndian => bytes.chunks_exact_mut(4).for_each(<[u8]>::reverse),
+        }
+    }
+}
+
+/// The identifying fields of an N64 ROM header. See [`Rom::header`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[pyclass(module = "merlon.rom")]
+pub struct RomHeader {
+    /// The game's internal name, e.g. `PAPER MARIO`.
+    #[pyo3(get)]
+    pub name: String,
+
+    /// The game code, e.g. `NMQE`.
+    #[pyo3(get)]
+    pub game_code: String,
+
+    /// The country code, e.g. `b'E'` for North America.
+    #[pyo3(get)]
+    pub country_code: u8,
+}
+
+#[pymethods]
+impl RomHeader {
+    /// Returns the region that the country code is for, or `None` if Paper Mario wasn't released with it.
+    pub fn region(&self) -> Option<Region> {
+        match self.country_code {
+            b'E' => Some(Region::Us),
+            b'J' => Some(Region::Jp),
+            b'P' | b'D' | b'F' | b'I' | b'S' | b'U' | b'X' | b'Y' => Some(Region::Pal),
+            b'C' => Some(Region::Ique),
+            _ => None,
+        }
+    }
+
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl RomHeader {
+    /// Parses the fields from a header in big-endian byte order.
+    fn parse(header: &[u8; HEADER_LEN]) -> Self {
+        // Header strings are padded with spaces or NULs, and may use Latin-1
+        let latin1 = |bytes: &[u8]| -> String {
+            bytes.iter()
+                .map(|&byte| char::from(byte))
+                .collect::<String>()
+                .trim_end_matches([' ', '\0'])
+                .to_owned()
+        };
+        Self {
+            name: latin1(&header[HEADER_NAME]),
+            game_code: latin1(&header[HEADER_GAME_CODE]),
+            country_code: header[HEADER_COUNTRY_CODE_OFFSET],
+        }
+    }
+}
+
+/// Formats like ROM set names, e.g. `PAPER MARIO (U)`.
+impl fmt::Display for RomHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.region() {
+            Some(Region::Us) => write!(f, "{} (U)", self.name),
+            Some(Region::Jp) => write!(f, "{} (J)", self.name),
+            Some(Region::Pal) => write!(f, "{} (E)", self.name),
+            Some(Region::Ique) => write!(f, "{} (iQue)", self.name),
+            None => write!(f, "{} ({})", self.name, char::from(self.country_code)),
+        }
+    }
+}
+
+/// Why a ROM isn't an unmodified Paper Mario ROM of the expected region. See [`verify_papermario`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RomVerifyError {
+    /// The ROM is for another region. The decomp builds each region from its own ROM, so patches won't apply.
+    #[error(
+        "baserom is region {} but package targets {}",
+        .region.as_str().to_uppercase(),
+        .expected.as_str().to_uppercase(),
+    )]
+    WrongRegion {
+        /// The region in the ROM's header.
+        region: Region,
+        /// The region the ROM should be for.
+        expected: Region,
+    },
+
+    /// The ROM has a header from a ROM copier before it, so nothing is where the decomp expects.
+    #[error("baserom has a {}-byte copier header before the ROM, remove it and try again", COPIER_HEADER_LEN)]
+    Headered,
+
+    /// The ROM is for the right region, but has been modified, is a bad dump, or isn't Paper Mario at all.
+    #[error(
+        "baserom is not an unmodified {}-release Paper Mario ROM (its SHA1 is {got}, expected {expected})",
+        .region.as_str().to_uppercase(),
+    )]
+    WrongHash {
+        /// The region the ROM is for.
+        region: Region,
+        /// SHA1 of an unmodified ROM.
+        expected: String,
+        /// SHA1 of the given ROM.
+        got: String,
+    },
+
+    /// The ROM couldn't be read.
+    #[error("failed to read baserom: {0}")]
+    Unreadable(String),
+}
+
+/// Checks that a ROM is an unmodified US-release Paper Mario ROM in `.z64` byte order, which is what the decomp
+/// builds from by default. See [`verify_papermario`].
+pub fn verify_papermario_us(rom: &Rom) -> Result<(), RomVerifyError> {
+    verify_papermario(rom, Region::Us)
+}
+
+/// Checks that a ROM is an unmodified Paper Mario ROM of `region` in `.z64` byte order, which is what the decomp
+/// builds that region from. Each way that it can be wrong is a different [`RomVerifyError`], so the user can be told
+/// how to fix it.
+///
+/// The hash is only checked for regions with a [known SHA1](crate::baserom::expected_sha1). Other regions are
+/// checked against the SHA1 in the decomp's splat config once it has been cloned.
+pub fn verify_papermario(rom: &Rom, region: Region) -> Result<(), RomVerifyError> {
+    let unreadable = |error: anyhow::Error| RomVerifyError::Unreadable(format!("{:#}", error));
+
+    let mut start = Vec::with_capacity(COPIER_HEADER_LEN + 4);
+    rom.file()
+        .map_err(|error| unreadable(error.into()))?
+        .take(COPIER_HEADER_LEN as u64 + 4)
+        .read_to_end(&mut start)
+        .map_err(|error| unreadable(error.into()))?;
+    if start.len() == COPIER_HEADER_LEN + 4
+        && ByteOrder::detect(&start[..4]).is_none()
+        && ByteOrder::detect(&start[COPIER_HEADER_LEN..]).is_some()
+    {
+        return Err(RomVerifyError::Headered);
+    }
+
+    crate::baserom::check_region(rom, region)?;
+
+    let Some(expected) = crate::baserom::expected_sha1(region) else {
+        return Ok(());
+    };
+    let sha1 = rom.sha1_string().map_err(unreadable)?;
+    if sha1 != expected {
+        return Err(RomVerifyError::WrongHash {
+            region,
+            expected: expected.to_owned(),
+            got: sha1,
+        });
+    }
+    Ok(())
+}
 
 /// An N64 ROM file on disk.
+///
+/// A ROM may own the temporary directory its file is in, e.g. one from [`Rom::from_distributable_apply`], in which
+/// case the file is deleted when the ROM is dropped.
+///
+/// ROMs don't implement [`PartialEq`], since comparing them requires reading files, which can fail. Use
+/// [`Rom::content_equals`] to compare two ROMs by content.
 #[derive(Debug)]
 #[pyclass(module = "merlon.rom")]
 pub struct Rom {
     path: PathBuf,
+
+    /// SHA1 of the file, along with the modification time and length it was calculated at.
+    sha1_cache: RefCell<Option<(SystemTime, u64, String)>>,
+
+    /// The temporary directory containing the file, if the ROM owns it.
+    temp_dir: Option<TempDir>,
 }
 
 #[pymethods]
@@ -31,32 +272,206 @@ impl Rom {
         Ok(buffer)
     }
     
+    /// Replaces the contents of the ROM file with the given bytes.
+    ///
+    /// The bytes are written to a temporary file next to the ROM which is then renamed over it, so the ROM is never
+    /// left partially written. If `backup` is true, the previous contents are kept in a `.bak` file alongside.
+    #[pyo3(signature = (bytes, backup = false))]
+    pub fn write_bytes(&self, bytes: &[u8], backup: bool) -> Result<()> {
+        self.sha1_cache.take();
+        write_atomically(&self.path, |temp_path| {
+            fs::write(temp_path, bytes)
+                .with_context(|| format!("failed to write {}", temp_path.display()))?;
+            if backup && self.path.is_file() {
+                let backup_path = self.backup_path();
+                fs::copy(&self.path, &backup_path)
+                    .with_context(|| format!("failed to back up ROM to {}", backup_path.display()))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the path that [`Rom::write_bytes`] backs up the previous contents to.
+    pub fn backup_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".bak");
+        PathBuf::from(name)
+    }
+
     /// Calculates the SHA1 hash of the ROM.
+    ///
+    /// The hash is cached until the file's modification time or length changes, so calling this repeatedly is cheap.
     pub fn sha1_string(&self) -> Result<String> {
-        let mut bytes = self.read_bytes()?;
-        let generic_arr = Sha1::digest(&mut bytes);
+        let metadata = fs::metadata(&self.path)?;
+        let modified = metadata.modified()?;
+        if let Some((cached_modified, cached_len, sha1)) = self.sha1_cache.borrow().as_ref() {
+            if *cached_modified == modified && *cached_len == metadata.len() {
+                return Ok(sha1.clone());
+            }
+        }
+
+        // Hash in chunks rather than reading the whole ROM into memory
+        let mut file = self.file()?;
+        let mut hasher = Sha1::new();
+        let mut buffer = vec![0; SHA1_CHUNK_LEN];
+        loop {
+            let len = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error).with_context(|| format!("failed to read {}", self.path.display())),
+            };
+            hasher.update(&buffer[..len]);
+        }
+        let generic_arr = hasher.finalize();
         let mut hex = String::new();
         for byte in generic_arr.iter() {
             hex.push_str(&format!("{:02x}", byte));
         }
+        *self.sha1_cache.borrow_mut() = Some((modified, metadata.len(), hex.clone()));
         Ok(hex)
     }
 
+    /// Forgets the cached SHA1, so that [`Rom::sha1_string`] hashes the file again. Call this after overwriting the
+    /// file other than with [`Rom::write_bytes`], in case it kept the same modification time and length.
+    pub fn invalidate_cache(&mut self) {
+        *self.sha1_cache.get_mut() = None;
+    }
+
+    /// Returns true if both ROMs have the same contents, regardless of their paths.
+    pub fn content_equals(&self, other: &Rom) -> Result<bool> {
+        Ok(self.sha1_string()? == other.sha1_string()?)
+    }
+
+    /// Describes how the ROM differs from `other`: their sizes if they differ, and the byte ranges that differ
+    /// between them, up to [`DIFF_SUMMARY_MAX_RANGES`].
+    pub fn diff_summary(&self, other: &Rom) -> Result<String> {
+        let bytes = self.read_bytes()
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        let other_bytes = other.read_bytes()
+            .with_context(|| format!("failed to read {}", other.path.display()))?;
+
+        let mut lines = Vec::new();
+        if bytes.len() != other_bytes.len() {
+            lines.push(format!("size differs: {} bytes rather than {} bytes", bytes.len(), other_bytes.len()));
+        }
+
+        // Ranges of differing bytes, as (start, end) with end exclusive
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (offset, (a, b)) in bytes.iter().zip(other_bytes.iter()).enumerate() {
+            if a == b {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end == offset => *end = offset + 1,
+                _ => ranges.push((offset, offset + 1)),
+            }
+        }
+        if !ranges.is_empty() {
+            let differing: usize = ranges.iter().map(|(start, end)| end - start).sum();
+            lines.push(format!("{} bytes differ in {} ranges:", differing, ranges.len()));
+            for (start, end) in ranges.iter().take(DIFF_SUMMARY_MAX_RANGES) {
+                lines.push(format!("  0x{:08X}..0x{:08X}", start, end));
+            }
+            if ranges.len() > DIFF_SUMMARY_MAX_RANGES {
+                lines.push(format!("  and {} more", ranges.len() - DIFF_SUMMARY_MAX_RANGES));
+            }
+        }
+
+        if lines.is_empty() {
+            Ok("ROMs are identical".to_owned())
+        } else {
+            Ok(lines.join("\n"))
+        }
+    }
+
+    /// Reads the region of the ROM from the country code in its header. The ROM may be in any byte order.
+    ///
+    /// Returns `None` if the file doesn't have an N64 ROM header, or its country code isn't one that Paper Mario was
+    /// released in.
+    pub fn header_region(&self) -> Result<Option<Region>> {
+        Ok(self.header()?.and_then(|header| header.region()))
+    }
+
+    /// Reads the ROM's header, e.g. to show which game it is. The ROM may be in any byte order.
+    ///
+    /// Returns `None` if the file doesn't have an N64 ROM header.
+    pub fn header(&self) -> Result<Option<RomHeader>> {
+        let mut header = [0; HEADER_LEN];
+        match self.file()?.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error).with_context(|| format!("failed to read {}", self.path.display())),
+        }
+
+        let Some(byte_order) = ByteOrder::detect(&header[..4]) else {
+            return Ok(None);
+        };
+        byte_order.swap_to_big_endian(&mut header);
+        Ok(Some(RomHeader::parse(&header)))
+    }
+
+    /// Detects the byte order of the ROM from its first word. Returns `None` if the file isn't an N64 ROM.
+    pub fn byte_order(&self) -> Result<Option<ByteOrder>> {
+        let mut first_word = [0; 4];
+        match self.file()?.read_exact(&mut first_word) {
+            Ok(()) => Ok(ByteOrder::detect(&first_word)),
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(error) => Err(error).with_context(|| format!("failed to read {}", self.path.display())),
+        }
+    }
+
     fn __str__(&self) -> String {
         format!("{}", self)
     }
 }
 
 impl Rom {
+    /// Writes a copy of the ROM in big-endian (`.z64`) byte order to `output`, swapping bytes if it is a `.v64` or
+    /// `.n64` dump, and returns the copy. The byte order is detected from the ROM's contents, not its extension.
+    pub fn normalized_to_z64(&self, output: &Path) -> Result<Rom> {
+        let Some(byte_order) = self.byte_order()? else {
+            bail!("{} is not an N64 ROM", self.path.display());
+        };
+        let mut bytes = self.read_bytes()
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        byte_order.swap_to_big_endian(&mut bytes);
+        write_atomically(output, |temp_path| {
+            fs::write(temp_path, &bytes).with_context(|| format!("failed to write {}", output.display()))
+        })?;
+        Ok(Rom::from(output.to_owned()))
+    }
+
+    /// Prepares a path to write a ROM to. See [`prepare_output_path`].
+    pub fn extension_normalized(path: &Path, policy: OverwritePolicy) -> Result<PathBuf> {
+        prepare_output_path(path, EXTENSION, policy)
+    }
+
     /// Returns the ROM as a [`File`].
     pub fn file(&self) -> std::io::Result<File> {
         File::open(self.path())
     }
+
+    /// Takes ownership of the temporary directory that the ROM is in, so that it is deleted when the ROM is dropped.
+    pub(crate) fn owning_temp_dir(mut self, temp_dir: TempDir) -> Self {
+        debug_assert!(self.path.starts_with(temp_dir.path()));
+        self.temp_dir = Some(temp_dir);
+        self
+    }
+
+    /// Returns true if the ROM's file is temporary and will be deleted when the ROM is dropped.
+    pub fn is_temporary(&self) -> bool {
+        self.temp_dir.is_some()
+    }
 }
 
 impl From<PathBuf> for Rom {
     fn from(path: PathBuf) -> Self {
-        Self { path }
+        Self {
+            path,
+            sha1_cache: RefCell::new(None),
+            temp_dir: None,
+        }
     }
 }
 
@@ -69,3 +484,340 @@ impl fmt::Display for Rom {
         Ok(())
     }
 }
+
+/// Prepares a path to write an output file to.
+///
+/// The given extension is appended if the path has no extension, and any missing parent directories are created.
+/// If a file already exists at the path, `policy` decides whether to overwrite it, pick a new path alongside it, or
+/// fail.
+pub fn prepare_output_path(path: &Path, extension: &str, policy: OverwritePolicy) -> Result<PathBuf> {
+    let path = if path.extension().is_some() {
+        path.to_owned()
+    } else {
+        let mut file_name = path.file_name()
+            .with_context(|| format!("output path {} has no file name", path.display()))?
+            .to_owned();
+        file_name.push(".");
+        file_name.push(extension);
+        path.with_file_name(file_name)
+    };
+
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory {}", parent.display()))?;
+        }
+    }
+
+    if !path.exists() {
+        return Ok(path);
+    }
+    match policy {
+        OverwritePolicy::Error => bail!("{} already exists", path.display()),
+        OverwritePolicy::Overwrite => {
+            if path.is_dir() {
+                bail!("{} is a directory", path.display());
+            }
+            Ok(path)
+        }
+        OverwritePolicy::Rename => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let extension = path.extension().unwrap_or_default().to_string_lossy().into_owned();
+            (1..)
+                .map(|n| path.with_file_name(format!("{}-{}.{}", stem, n, extension)))
+                .find(|candidate| !candidate.exists())
+                .context("failed to find an unused output path")
+        }
+    }
+}
+
+/// Writes a file without ever leaving it partially written.
+///
+/// `write` is called with a temporary path next to `path`, and the file written there is renamed to `path` only if
+/// `write` succeeds. If anything fails, the temporary file is removed and `path` is left untouched.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let mut temp_name = path.file_name().context("output path has no file name")?.to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = write(&temp_path).and_then(|()| {
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("failed to replace {}", path.display()))
+    });
+    if result.is_err() && temp_path.exists() {
+        if let Err(error) = fs::remove_file(&temp_path) {
+            log::warn!("failed to remove {}: {}", temp_path.display(), error);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn prepare_output_path_appends_extension_and_creates_dirs() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = prepare_output_path(&dir.path().join("out/My Mod"), EXTENSION, OverwritePolicy::Error)?;
+        assert_eq!(path, dir.path().join("out/My Mod.z64"));
+        assert!(dir.path().join("out").is_dir());
+
+        let path = prepare_output_path(&dir.path().join("rom.Z64"), EXTENSION, OverwritePolicy::Error)?;
+        assert_eq!(path, dir.path().join("rom.Z64"));
+
+        // Explicit extensions are kept
+        let path = prepare_output_path(&dir.path().join("rom.bin"), EXTENSION, OverwritePolicy::Error)?;
+        assert_eq!(path, dir.path().join("rom.bin"));
+        Ok(())
+    }
+
+    #[test]
+    fn write_bytes_replaces_contents_and_backs_up() -> Result<()> {
+        let dir = TempDir::new()?;
+        let rom = Rom::from(dir.path().join("rom.z64"));
+        fs::write(rom.path(), b"original")?;
+
+        rom.write_bytes(b"modified", false)?;
+        assert_eq!(rom.read_bytes()?, b"modified");
+        assert!(!rom.backup_path().exists());
+
+        rom.write_bytes(b"modified again", true)?;
+        assert_eq!(rom.read_bytes()?, b"modified again");
+        assert_eq!(fs::read(rom.backup_path())?, b"modified");
+        assert_eq!(rom.backup_path(), dir.path().join("rom.z64.bak"));
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_sha1_matches_one_shot_hash() -> Result<()> {
+        let one_shot = |bytes: &[u8]| -> String {
+            Sha1::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+        };
+
+        let fixture = Rom::from(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms/jp-header.z64"));
+        assert_eq!(fixture.sha1_string()?, one_shot(&fixture.read_bytes()?));
+
+        // Spanning several chunks, ending partway through one
+        let dir = TempDir::new()?;
+        let rom = Rom::from(dir.path().join("rom.z64"));
+        let bytes: Vec<u8> = (0..SHA1_CHUNK_LEN * 3 + 123).map(|i| (i % 251) as u8).collect();
+        fs::write(rom.path(), &bytes)?;
+        assert_eq!(rom.sha1_string()?, one_shot(&bytes));
+        Ok(())
+    }
+
+    #[test]
+    fn sha1_cache_is_invalidated() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut rom = Rom::from(dir.path().join("rom.z64"));
+        fs::write(rom.path(), b"first")?;
+        let first = rom.sha1_string()?;
+        assert_eq!(rom.sha1_string()?, first);
+
+        // Overwrite behind the ROM's back, keeping the same length and modification time
+        let modified = fs::metadata(rom.path())?.modified()?;
+        fs::write(rom.path(), b"other")?;
+        File::options().write(true).open(rom.path())?.set_modified(modified)?;
+        assert_eq!(rom.sha1_string()?, first);
+
+        rom.invalidate_cache();
+        assert_ne!(rom.sha1_string()?, first);
+        Ok(())
+    }
+
+    #[test]
+    fn content_equals_ignores_path() -> Result<()> {
+        let dir = TempDir::new()?;
+        let a = Rom::from(dir.path().join("a.z64"));
+        let b = Rom::from(dir.path().join("b.z64"));
+        fs::write(a.path(), b"same")?;
+        fs::write(b.path(), b"same")?;
+        assert!(a.content_equals(&b)?);
+
+        b.write_bytes(b"different", false)?;
+        assert!(!a.content_equals(&b)?);
+        Ok(())
+    }
+
+    #[test]
+    fn diff_summary_lists_differing_ranges() -> Result<()> {
+        let dir = TempDir::new()?;
+        let a = Rom::from(dir.path().join("a.z64"));
+        let b = Rom::from(dir.path().join("b.z64"));
+        fs::write(a.path(), [0, 1, 2, 3, 4, 5, 6, 7])?;
+        fs::write(b.path(), [0, 1, 2, 3, 4, 5, 6, 7])?;
+        assert_eq!(a.diff_summary(&b)?, "ROMs are identical");
+
+        fs::write(b.path(), [0, 9, 9, 3, 4, 5, 9, 7, 8])?;
+        assert_eq!(a.diff_summary(&b)?, concat!(
+            "size differs: 8 bytes rather than 9 bytes\n",
+            "3 bytes differ in 2 ranges:\n",
+            "  0x00000001..0x00000003\n",
+            "  0x00000006..0x00000007",
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn header_region_is_read_in_any_byte_order() -> Result<()> {
+        let baseroms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        assert_eq!(Rom::from(baseroms.join("jp-header.z64")).header_region()?, Some(Region::Jp));
+        assert_eq!(Rom::from(baseroms.join("us-header.v64")).header_region()?, Some(Region::Us));
+
+        let dir = TempDir::new()?;
+        let n64 = Rom::from(dir.path().join("pal.n64"));
+        let mut header = fs::read(baseroms.join("jp-header.z64"))?;
+        header[0x3E] = b'P';
+        header.chunks_exact_mut(4).for_each(<[u8]>::reverse);
+        fs::write(n64.path(), header)?;
+        assert_eq!(n64.header_region()?, Some(Region::Pal));
+
+        let not_a_rom = Rom::from(dir.path().join("short.z64"));
+        fs::write(not_a_rom.path(), b"pretend this is a ROM")?;
+        assert_eq!(not_a_rom.header_region()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn header_fields_are_parsed() -> Result<()> {
+        let baseroms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        let header = Rom::from(baseroms.join("jp-header.z64")).header()?.unwrap();
+        assert_eq!(header, RomHeader {
+            name: "PAPER MARIO".to_owned(),
+            game_code: "NMQJ".to_owned(),
+            country_code: b'J',
+        });
+        assert_eq!(header.to_string(), "PAPER MARIO (J)");
+
+        let header = Rom::from(baseroms.join("us-header.v64")).header()?.unwrap();
+        assert_eq!(header.game_code, "NMQE");
+        assert_eq!(header.to_string(), "PAPER MARIO (U)");
+
+        // Names are padded with NULs as well as spaces, and can use Latin-1
+        let dir = TempDir::new()?;
+        let mut bytes = fs::read(baseroms.join("jp-header.z64"))?;
+        bytes[HEADER_NAME].fill(0);
+        bytes[HEADER_NAME][..6].copy_from_slice(b"MARIO\xc9");
+        bytes[HEADER_COUNTRY_CODE_OFFSET] = b'Z';
+        let rom = Rom::from(dir.path().join("rom.z64"));
+        fs::write(rom.path(), bytes)?;
+        let header = rom.header()?.unwrap();
+        assert_eq!(header.name, "MARIO\u{c9}");
+        assert_eq!(header.region(), None);
+        assert_eq!(header.to_string(), "MARIO\u{c9} (Z)");
+        Ok(())
+    }
+
+    #[test]
+    fn papermario_us_is_verified() -> Result<()> {
+        let baseroms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        let jp = Rom::from(baseroms.join("jp-header.z64"));
+        assert_eq!(verify_papermario_us(&jp), Err(RomVerifyError::WrongRegion {
+            region: Region::Jp,
+            expected: Region::Us,
+        }));
+        assert_eq!(verify_papermario(&jp, Region::Jp), Ok(()));
+        let error = verify_papermario(&Rom::from(baseroms.join("us-header.v64")), Region::Pal).unwrap_err();
+        assert_eq!(error.to_string(), "baserom is region US but package targets PAL");
+
+        let dir = TempDir::new()?;
+        let headered = Rom::from(dir.path().join("headered.z64"));
+        let mut bytes = vec![0; COPIER_HEADER_LEN];
+        bytes.extend(fs::read(baseroms.join("us-header.v64"))?);
+        fs::write(headered.path(), bytes)?;
+        assert_eq!(verify_papermario_us(&headered), Err(RomVerifyError::Headered));
+
+        let modified = Rom::from(dir.path().join("modified.z64"));
+        fs::write(modified.path(), b"pretend this is a ROM")?;
+        let error = verify_papermario_us(&modified).unwrap_err();
+        assert_eq!(error, RomVerifyError::WrongHash {
+            region: Region::Us,
+            expected: crate::baserom::PAPERMARIO_US_SHA1.to_owned(),
+            got: modified.sha1_string()?,
+        });
+        assert!(error.to_string().contains("is not an unmodified US-release"));
+
+        let missing = Rom::from(dir.path().join("missing.z64"));
+        assert!(matches!(verify_papermario_us(&missing), Err(RomVerifyError::Unreadable(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn byte_swapped_roms_are_normalized() -> Result<()> {
+        let baseroms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/baseroms");
+        let z64 = Rom::from(baseroms.join("jp-header.z64"));
+        assert_eq!(z64.byte_order()?, Some(ByteOrder::BigEndian));
+
+        let dir = TempDir::new()?;
+        let mut v64 = z64.read_bytes()?;
+        v64.chunks_exact_mut(2).for_each(<[u8]>::reverse);
+        fs::write(dir.path().join("rom.v64"), &v64)?;
+        let mut n64 = z64.read_bytes()?;
+        n64.chunks_exact_mut(4).for_each(<[u8]>::reverse);
+        fs::write(dir.path().join("rom.n64"), &n64)?;
+
+        for (file_name, byte_order) in [
+            ("rom.v64", ByteOrder::ByteSwapped),
+            ("rom.n64", ByteOrder::LittleEndian),
+        ] {
+            let rom = Rom::from(dir.path().join(file_name));
+            assert_eq!(rom.byte_order()?, Some(byte_order), "{}", file_name);
+            let normalized = rom.normalized_to_z64(&dir.path().join(file_name).with_extension("z64"))?;
+            assert_eq!(normalized.sha1_string()?, z64.sha1_string()?, "{}", file_name);
+        }
+
+        let not_a_rom = Rom::from(dir.path().join("not-a-rom.v64"));
+        fs::write(not_a_rom.path(), b"pretend this is a ROM")?;
+        assert_eq!(not_a_rom.byte_order()?, None);
+        assert!(not_a_rom.normalized_to_z64(&dir.path().join("not-a-rom.z64")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn temporary_rom_is_deleted_on_drop() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("patched.z64");
+        fs::write(&path, b"patched")?;
+
+        let rom = Rom::from(path.clone()).owning_temp_dir(temp_dir);
+        assert!(rom.is_temporary());
+        assert_eq!(rom.read_bytes()?, b"patched");
+        drop(rom);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_write_leaves_no_output() -> Result<()> {
+        let dir = TempDir::new()?;
+        let output = dir.path().join("rom.z64");
+        let result = write_atomically(&output, |temp_path| {
+            fs::write(temp_path, b"half a ROM")?;
+            bail!("disk full");
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn prepare_output_path_respects_policy() -> Result<()> {
+        let dir = TempDir::new()?;
+        let existing = dir.path().join("rom.z64");
+        fs::write(&existing, "")?;
+
+        assert!(prepare_output_path(&existing, EXTENSION, OverwritePolicy::Error).is_err());
+        assert_eq!(prepare_output_path(&existing, EXTENSION, OverwritePolicy::Overwrite)?, existing);
+        assert_eq!(prepare_output_path(&existing, EXTENSION, OverwritePolicy::Rename)?, dir.path().join("rom-1.z64"));
+
+        fs::write(dir.path().join("rom-1.z64"), "")?;
+        assert_eq!(prepare_output_path(&existing, EXTENSION, OverwritePolicy::Rename)?, dir.path().join("rom-2.z64"));
+        Ok(())
+    }
+}
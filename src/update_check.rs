@@ -0,0 +1,102 @@
+//! Checks GitHub for newer releases of Merlon.
+//!
+//! The check is opt-in, with `merlon config set update_check true`. It runs at most once a day, and the result is
+//! cached so the notice keeps showing in between checks. Setting the `MERLON_NO_UPDATE_CHECK` environment variable
+//! disables it even when enabled.
+
+use std::fs;
+use std::io::IsTerminal;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Result, Context};
+use merlon::config::{self, Config};
+use semver::Version;
+use serde::Deserialize;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/bates64/merlon/releases/latest";
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Prints a notice to stderr if a newer version of Merlon is available.
+/// Never fails; any errors are logged and otherwise ignored.
+pub fn notify_if_outdated() {
+    if !is_enabled() {
+        return;
+    }
+    match latest_version() {
+        Ok(latest) => {
+            let current = Version::parse(env!("CARGO_PKG_VERSION")).expect("invalid package version");
+            if latest > current {
+                eprintln!(
+                    "note: a newer version of Merlon ({}) is available, see https://github.com/bates64/merlon/releases",
+                    latest,
+                );
+            }
+        }
+        Err(error) => log::debug!("update check failed: {:#}", error),
+    }
+}
+
+fn is_enabled() -> bool {
+    if std::env::var_os("MERLON_NO_UPDATE_CHECK").is_some() {
+        return false;
+    }
+    // Don't add noise to scripts or CI
+    if !std::io::stderr().is_terminal() {
+        return false;
+    }
+    Config::load().map(|config| config.update_check).unwrap_or(false)
+}
+
+/// Returns the latest released version, from the cache if it was checked recently.
+/// The cache file contains the time of the last check and the version found.
+fn latest_version() -> Result<Version> {
+    let cache_path = config::dir().context("no config directory")?.join("last-update-check");
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+    // A cache that can't be parsed is treated as stale, so that it gets overwritten
+    if let Some(version) = fs::read_to_string(&cache_path).ok().and_then(|cache| cached_version(&cache, now)) {
+        return Ok(version);
+    }
+
+    let release: Release = ureq::get(LATEST_RELEASE_URL)
+        .timeout(TIMEOUT)
+        .call()?
+        .into_json()?;
+    let version = Version::parse(release.tag_name.trim_start_matches('v'))?;
+
+    if let Some(dir) = cache_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&cache_path, format!("{} {}", now.as_secs(), version))?;
+    Ok(version)
+}
+
+/// Returns the version in the cache file contents, if they are valid and the check was recent.
+fn cached_version(cache: &str, now: Duration) -> Option<Version> {
+    let (checked_at, version) = cache.trim().split_once(' ')?;
+    let checked_at = Duration::from_secs(checked_at.parse().ok()?);
+    if now.saturating_sub(checked_at) >= CHECK_INTERVAL {
+        return None;
+    }
+    Version::parse(version).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn corrupt_cache_is_stale() {
+        let now = Duration::from_secs(1_700_000_000);
+        assert_eq!(cached_version("1699999000 1.2.3", now), Some(Version::new(1, 2, 3)));
+        assert_eq!(cached_version("1600000000 1.2.3", now), None);
+        assert_eq!(cached_version("yesterday 1.2.3", now), None);
+        assert_eq!(cached_version("1699999000 not-a-version", now), None);
+        assert_eq!(cached_version("", now), None);
+    }
+}
@@ -22,7 +22,10 @@ fn initialising_package_gives_decomp_dependency() -> Result<()> {
     let package = registry.get_or_error(id)?;
     let _initialised = package.clone().to_initialised(InitialiseOptions {
         baserom: rom::baserom(),
+        region: None,
         rev: Some(DECOMP_REV.to_string()),
+        skip_baserom_check: false,
+        reference: None,
     })?;
     let all_dependencies = registry.all_dependencies()?;
     assert_eq!(all_dependencies.len(), 1);
@@ -69,10 +72,13 @@ fn sync_complex_dependency_graph_to_repo() -> Result<()> {
     let root_package = registry.get_or_error(root)?.clone();
     let mut initialised = root_package.clone().to_initialised(InitialiseOptions {
         baserom: rom::baserom(),
+        region: None,
         rev: Some(DECOMP_REV.to_string()),
+        skip_baserom_check: false,
+        reference: None,
     })?;
     initialised.set_registry(registry); // XXX
-    initialised.setup_git_branches()?;
+    initialised.sync_repo()?;
     initialised.update_patches_dir()?;
 
     // There should be 1 patch in the root package now
@@ -131,19 +137,28 @@ fn single_dependency() -> Result<()> {
     let root = Package::new("Root", tempdir.path().join("root"))?;
     let mut root = root.to_initialised(InitialiseOptions {
         baserom: rom::baserom(),
+        region: None,
         rev: Some(DECOMP_REV.to_string()),
+        skip_baserom_check: false,
+        reference: None,
     })?;
 
     // Dependency package with single commit
     let dependency = Package::new("Dependency", tempdir.path().join("dependency"))?;
+    dependency.edit_manifest(|manifest| {
+        manifest.metadata_mut().set_authors(vec!["Dependency Author".to_string()]);
+        Ok(())
+    })?;
     let mut file = File::create(dependency.path().join("patches/0001-set-bSkipIntro-to-true.patc"))?;
     write!(&mut file, "{}", skip_intro_patch())?;
 
     // Add dependency, sync repo, check skip intro commit was added
     root.add_dependency(AddDependencyOptions {
-        path: dependency.path().to_path_buf(),
+        path: Some(dependency.path().to_path_buf()),
+        git: None,
+        rev: None,
     })?;
-    root.setup_git_branches()?;
+    root.sync_repo()?;
     let output = Command::new("git")
         .arg("log")
         .arg("-1")
@@ -157,6 +172,12 @@ fn single_dependency() -> Result<()> {
     let distributable = root.package().export_distributable(ExportOptions {
         baserom: Some(rom::baserom()),
         output: Some(tempdir.path().join("output.merlon")),
+        if_exists: Default::default(),
+        dry_run: false,
+        exclude_patches: vec![],
+        verify_reproducible: false,
+        patch_context: None,
+        split_size: None,
     })?;
     distributable.open_scoped(rom::baserom(), |package| {
         let manifest = package.manifest()?;
@@ -170,6 +191,10 @@ fn single_dependency() -> Result<()> {
             .count();
         assert_eq!(patches_count, 0);
 
+        // Dependency authors should be credited
+        let attribution = std::fs::read_to_string(package.path().join("ATTRIBUTION.md"))?;
+        assert!(attribution.contains("Dependency Author"));
+
         Ok(())
     })?;
 
@@ -202,7 +227,10 @@ fn initialised_patches_maintained() -> Result<()> {
     // Initialise package
     let initialised = package.to_initialised(InitialiseOptions {
         baserom: rom::baserom(),
+        region: None,
         rev: Some(DECOMP_REV.to_string()),
+        skip_baserom_check: false,
+        reference: None,
     })?;
     assert!(patch_path.is_file());
 
@@ -219,7 +247,7 @@ fn initialised_patches_maintained() -> Result<()> {
     // Update, sync, check patch is still there
     initialised.update_decomp()?; // XXX: brittle; will fail if patch becomes unmergeable
     assert!(patch_path.is_file());
-    initialised.setup_git_branches()?;
+    initialised.sync_repo()?;
     assert!(patch_path.is_file());
 
     // Assert patch is applied to repo
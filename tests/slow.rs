@@ -16,9 +16,118 @@ fn new_init_build() -> Result<()> {
     let package = Package::new("Test", pkg_path)?;
     let initialised = package.to_initialised(InitialiseOptions {
         baserom: rom::baserom(),
+        region: None,
         rev: Some(DECOMP_REV.to_string()),
+        skip_baserom_check: false,
+        reference: None,
     })?;
     let rom = initialised.build_rom(BuildRomOptions::default())?;
     assert_eq!(rom.sha1_string()?, "e1f9c77fa35549897ace8b8627e821a27309d538");
     Ok(())
 }
+
+/// Export a package with a patch and apply it without keeping the output ROM.
+#[test]
+#[ignore]
+fn apply_distributable_to_temporary_rom() -> Result<()> {
+    let tempdir = TempDir::new()?;
+    let package = Package::new("Skip Intro", tempdir.path().join("package"))?;
+    std::fs::write(
+        package.path().join("patches/0001-set-bSkipIntro-to-true.patch"),
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dependencies/skip_intro_patch.patch")),
+    )?;
+    let distributable = package.export_distributable(distribute::ExportOptions {
+        output: Some(tempdir.path().join("skip-intro.merlon")),
+        baserom: Some(rom::baserom()),
+        if_exists: Default::default(),
+        dry_run: false,
+        exclude_patches: vec![],
+        verify_reproducible: false,
+        patch_context: None,
+        split_size: None,
+    })?;
+
+    let patched = merlon::rom::Rom::from_distributable_apply(&distributable, distribute::ApplyOptions {
+        baserom: rom::baserom(),
+        build_rom_options: BuildRomOptions::default(),
+    })?;
+    let patched_path = patched.path().to_owned();
+    let baserom_bytes = std::fs::read(rom::baserom())?;
+    let patched_bytes = patched.read_bytes()?;
+    let differing = baserom_bytes.iter().zip(&patched_bytes).filter(|(a, b)| a != b).count();
+    assert!(differing > 0);
+
+    drop(patched);
+    assert!(!patched_path.exists());
+    Ok(())
+}
+
+/// Check a distributable applies to exactly the expected ROM, and that a different ROM is reported as a mismatch.
+#[test]
+#[ignore]
+fn verify_apply_compares_against_expected_rom() -> Result<()> {
+    let tempdir = TempDir::new()?;
+    let package = Package::new("Skip Intro", tempdir.path().join("package"))?;
+    std::fs::write(
+        package.path().join("patches/0001-set-bSkipIntro-to-true.patch"),
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dependencies/skip_intro_patch.patch")),
+    )?;
+    let distributable = package.export_distributable(distribute::ExportOptions {
+        output: Some(tempdir.path().join("skip-intro.merlon")),
+        baserom: Some(rom::baserom()),
+        if_exists: Default::default(),
+        dry_run: false,
+        exclude_patches: vec![],
+        verify_reproducible: false,
+        patch_context: None,
+        split_size: None,
+    })?;
+    let options = distribute::ApplyOptions {
+        baserom: rom::baserom(),
+        build_rom_options: BuildRomOptions::default(),
+    };
+
+    let expected = tempdir.path().join("expected.z64");
+    std::fs::write(&expected, distributable.apply_to_bytes(options.clone())?)?;
+    distributable.verify_apply(options.clone(), Some(expected))?;
+
+    let error = distributable.verify_apply(options.clone(), Some(rom::baserom())).unwrap_err();
+    assert!(format!("{:#}", error).contains("bytes differ"), "{:#}", error);
+
+    // Without an expected ROM, the build manifest is needed, which is only embedded with --verify-reproducible
+    assert!(distributable.verify_apply(options, None).is_err());
+    Ok(())
+}
+
+/// Apply a distributable whose patch breaks the build, and check that no output ROM is left behind.
+#[test]
+#[ignore]
+fn failed_apply_leaves_no_output_rom() -> Result<()> {
+    let tempdir = TempDir::new()?;
+    let package = Package::new("Broken", tempdir.path().join("package"))?;
+    let patch = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dependencies/skip_intro_patch.patch"))
+        .replace("bSkipIntro = TRUE;", "bSkipIntro = TRUE"); // Missing semicolon
+    std::fs::write(package.path().join("patches/0001-break-the-build.patch"), patch)?;
+    let distributable = package.export_distributable(distribute::ExportOptions {
+        output: Some(tempdir.path().join("broken.merlon")),
+        baserom: Some(rom::baserom()),
+        if_exists: Default::default(),
+        dry_run: false,
+        exclude_patches: vec![],
+        verify_reproducible: false,
+        patch_context: None,
+        split_size: None,
+    })?;
+
+    let output_dir = tempdir.path().join("output");
+    let result = distributable.apply(distribute::ApplyOptions {
+        baserom: rom::baserom(),
+        build_rom_options: BuildRomOptions {
+            output: Some(output_dir.join("broken.z64")),
+            ..Default::default()
+        },
+    });
+    assert!(result.is_err());
+    assert!(!output_dir.exists() || std::fs::read_dir(&output_dir)?.next().is_none());
+    Ok(())
+}